@@ -0,0 +1,135 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use solaris::config::PerformanceConfig;
+use solaris::index::hnsw::HNSWIndex;
+use solaris::types::{CollectionConfig, DistanceMetric};
+use solaris::utils::distance::compare_distances;
+use solaris::utils::gen::random_vectors;
+use std::hint::black_box;
+
+const DIMENSION: usize = 32;
+
+fn config() -> CollectionConfig {
+    config_with_dimension(DIMENSION)
+}
+
+fn config_with_dimension(dimension: usize) -> CollectionConfig {
+    CollectionConfig {
+        name: "bench".to_string(),
+        dimension,
+        metric: DistanceMetric::Euclidean,
+        // Sequential sums, so only the scoring strategy under test touches rayon.
+        deterministic_distance: true,
+        ..Default::default()
+    }
+}
+
+fn index(size: usize, performance: &PerformanceConfig) -> HNSWIndex {
+    let mut index = HNSWIndex::new(config(), performance);
+    for (i, vector) in random_vectors(size, DIMENSION, 7).into_iter().enumerate() {
+        index.add_vector(i.to_string(), vector).unwrap();
+    }
+    index
+}
+
+/// Small searches score a few dozen candidates, where handing them to rayon costs more than
+/// scoring them in place.
+fn small_search_scoring(c: &mut Criterion) {
+    let query = random_vectors(1, DIMENSION, 8).remove(0);
+    let mut group = c.benchmark_group("small_search_scoring");
+    for (name, threshold) in [("sequential", usize::MAX), ("parallel", 0)] {
+        let performance = PerformanceConfig {
+            parallel_search_threshold: threshold,
+            ..Default::default()
+        };
+        let index = index(200, &performance);
+        group.bench_function(BenchmarkId::new(name, 10), |b| {
+            b.iter(|| index.search(black_box(query.clone()), 10, Some(20)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// Every insert selects neighbors from `ef_construction` candidates, comparing candidates
+/// pairwise; the cost of those distances dominates build time.
+fn inserts(c: &mut Criterion) {
+    let vectors = random_vectors(500, DIMENSION, 9);
+    let performance = PerformanceConfig::default();
+    let mut group = c.benchmark_group("inserts");
+    group.sample_size(10);
+    group.bench_function("500", |b| {
+        b.iter(|| {
+            let mut index = HNSWIndex::new(config(), &performance);
+            for (i, vector) in vectors.iter().enumerate() {
+                index.add_vector(i.to_string(), vector.clone()).unwrap();
+            }
+            index
+        })
+    });
+    group.finish();
+}
+
+/// The memo saves re-scoring nodes seen on the upper layers in the final pass, which pays
+/// off as each distance gets more expensive.
+fn search_distance_memo(c: &mut Criterion) {
+    let dimension = 768;
+    let vectors = random_vectors(1_000, dimension, 10);
+    let query = random_vectors(1, dimension, 11).remove(0);
+    let mut group = c.benchmark_group("search_distance_memo");
+    for memo in [true, false] {
+        let performance = PerformanceConfig {
+            search_distance_memo: memo,
+            ..Default::default()
+        };
+        let mut index = HNSWIndex::new(config_with_dimension(dimension), &performance);
+        for (i, vector) in vectors.iter().enumerate() {
+            index.add_vector(i.to_string(), vector.clone()).unwrap();
+        }
+        let name = if memo { "memo" } else { "no_memo" };
+        group.bench_function(BenchmarkId::new(name, dimension), |b| {
+            b.iter(|| index.search(black_box(query.clone()), 10, Some(100)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// The final ranking step of `HNSWIndex::search`: selecting the top `k` of the scored
+/// candidates and ordering only those, against sorting every candidate. Measured on its own,
+/// since on a real search the graph traversal before it dwarfs either.
+fn top_k_selection(c: &mut Criterion) {
+    let k = 10;
+    let mut rng = StdRng::seed_from_u64(12);
+    let compare = |a: &(u32, f32), b: &(u32, f32)| compare_distances(a.1, b.1).then(a.0.cmp(&b.0));
+    let mut group = c.benchmark_group("top_k_selection");
+    for size in [1_000, 50_000] {
+        let scored: Vec<(u32, f32)> = (0..size).map(|id| (id, rng.gen())).collect();
+        group.bench_function(BenchmarkId::new("select", size), |b| {
+            b.iter_batched(
+                || scored.clone(),
+                |mut result| {
+                    result.select_nth_unstable_by(k - 1, compare);
+                    result.truncate(k);
+                    result.sort_unstable_by(compare);
+                    result
+                },
+                BatchSize::SmallInput,
+            )
+        });
+        group.bench_function(BenchmarkId::new("full_sort", size), |b| {
+            b.iter_batched(
+                || scored.clone(),
+                |mut result| {
+                    result.sort_unstable_by(compare);
+                    result.truncate(k);
+                    result
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, small_search_scoring, inserts, search_distance_memo, top_k_selection);
+criterion_main!(benches);
@@ -1,8 +1,11 @@
 use crate::types::DistanceMetric;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::time::Duration;
 
+// Config files written before a field existed leave it out; missing fields take the defaults.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct DatabaseConfig {
     pub name: String,
     pub data_directory: PathBuf,
@@ -12,6 +15,45 @@ pub struct DatabaseConfig {
     pub memory_limit_mb: Option<usize>,
     pub thread_pool_size: Option<usize>,
     pub compression_enabled: bool,
+    pub persistence_format: PersistenceFormat,
+    /// Persist dense vectors as quantized deltas against a reference vector (lossy, ~half size).
+    pub delta_encoding: bool,
+    pub flush_policy: FlushPolicy,
+    /// Number of recent replication events kept for replicas reconnecting with `subscribe_after`.
+    pub replication_log_size: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PersistenceFormat {
+    #[default]
+    Jsonl,
+    Bincode,
+}
+
+/// When buffered log records are written out. Checked on every write, so `ByInterval` only
+/// fires when a write arrives after the oldest buffered record has waited that long.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FlushPolicy {
+    ByCount(usize),
+    /// Approximate in-memory size of the buffered records.
+    ByBytes(usize),
+    ByInterval(Duration),
+    Any(Vec<FlushPolicy>),
+}
+
+impl Default for FlushPolicy {
+    fn default() -> Self {
+        FlushPolicy::ByCount(1000)
+    }
+}
+
+/// What a search does when a candidate's distance is NaN (e.g. a vector stored without validation).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NanHandling {
+    #[default]
+    Error,
+    /// Ranks NaN distances after every real distance, ordered by id.
+    SinkLast,
 }
 
 impl Default for DatabaseConfig {
@@ -25,11 +67,16 @@ impl Default for DatabaseConfig {
             memory_limit_mb: None,
             thread_pool_size: None,
             compression_enabled: true,
+            persistence_format: PersistenceFormat::Jsonl,
+            delta_encoding: false,
+            flush_policy: FlushPolicy::default(),
+            replication_log_size: 0,
         }
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct CollectionSettings {
     pub default_dimension: usize,
     pub default_metric: DistanceMetric,
@@ -37,6 +84,8 @@ pub struct CollectionSettings {
     pub default_ef_construction: usize,
     pub max_vectors_per_collection: Option<usize>,
     pub enable_metadata_indexing: bool,
+    pub max_search_limit: usize,
+    pub max_batch_size: usize,
 }
 
 impl Default for CollectionSettings {
@@ -48,17 +97,24 @@ impl Default for CollectionSettings {
             default_ef_construction: 200,
             max_vectors_per_collection: None,
             enable_metadata_indexing: true,
+            max_search_limit: 10_000,
+            max_batch_size: 10_000,
         }
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct PerformanceConfig {
     pub search_timeout_ms: u64,
     pub batch_size: usize,
     pub parallel_search_threshold: usize,
     pub cache_size: usize,
     pub prefetch_enabled: bool,
+    pub search_distance_memo: bool,
+    /// Default `CollectionConfig.deterministic_distance` for collections this database creates.
+    pub deterministic_distance: bool,
+    pub nan_handling: NanHandling,
 }
 
 impl Default for PerformanceConfig {
@@ -69,6 +125,9 @@ impl Default for PerformanceConfig {
             parallel_search_threshold: 1000,
             cache_size: 10000,
             prefetch_enabled: true,
+            search_distance_memo: true,
+            deterministic_distance: false,
+            nan_handling: NanHandling::Error,
         }
     }
 }
@@ -103,39 +162,185 @@ impl SolarisConfig {
         Ok(())
     }
 
+    /// Defaults, overlaid by the fields present in the file at `path` (if it exists), overlaid
+    /// by any `SOLARIS_*` environment variables.
+    pub fn load_layered(path: Option<&std::path::Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut config = SolarisConfig::default();
+
+        if let Some(path) = path.filter(|path| path.exists()) {
+            let mut merged = serde_json::to_value(&config)?;
+            let overlay: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+            merge_json(&mut merged, overlay);
+            config = serde_json::from_value(merged)?;
+        }
+
+        config.apply_env();
+        Ok(config)
+    }
+
     pub fn from_env() -> Self {
         let mut config = SolarisConfig::default();
+        config.apply_env();
+        config
+    }
 
+    fn apply_env(&mut self) {
         if let Ok(name) = std::env::var("SOLARIS_DB_NAME") {
-            config.database.name = name;
+            self.database.name = name;
         }
 
         if let Ok(data_dir) = std::env::var("SOLARIS_DATA_DIR") {
-            config.database.data_directory = PathBuf::from(data_dir);
+            self.database.data_directory = PathBuf::from(data_dir);
         }
 
         if let Ok(max_collections) = std::env::var("SOLARIS_MAX_COLLECTIONS") {
             if let Ok(max) = max_collections.parse() {
-                config.database.max_collections = max;
+                self.database.max_collections = max;
             }
         }
 
         if let Ok(enable_persistence) = std::env::var("SOLARIS_ENABLE_PERSISTENCE") {
-            config.database.enable_persistence = enable_persistence.to_lowercase() == "true";
+            self.database.enable_persistence = enable_persistence.to_lowercase() == "true";
+        }
+
+        if let Ok(format) = std::env::var("SOLARIS_PERSISTENCE_FORMAT") {
+            match format.to_lowercase().as_str() {
+                "jsonl" => self.database.persistence_format = PersistenceFormat::Jsonl,
+                "bincode" => self.database.persistence_format = PersistenceFormat::Bincode,
+                _ => {}
+            }
+        }
+
+        if let Ok(delta_encoding) = std::env::var("SOLARIS_DELTA_ENCODING") {
+            self.database.delta_encoding = delta_encoding.to_lowercase() == "true";
+        }
+
+        if let Ok(replication_log_size) = std::env::var("SOLARIS_REPLICATION_LOG_SIZE") {
+            if let Ok(size) = replication_log_size.parse() {
+                self.database.replication_log_size = size;
+            }
         }
 
         if let Ok(memory_limit) = std::env::var("SOLARIS_MEMORY_LIMIT_MB") {
             if let Ok(limit) = memory_limit.parse() {
-                config.database.memory_limit_mb = Some(limit);
+                self.database.memory_limit_mb = Some(limit);
             }
         }
 
         if let Ok(threads) = std::env::var("SOLARIS_THREAD_POOL_SIZE") {
             if let Ok(size) = threads.parse() {
-                config.database.thread_pool_size = Some(size);
+                self.database.thread_pool_size = Some(size);
             }
         }
+    }
+}
 
-        config
+fn merge_json(base: &mut serde_json::Value, overlay: serde_json::Value) {
+    match (base, overlay) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_json(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layered_config_prefers_env_over_file_over_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("solaris.json");
+        std::fs::write(
+            &path,
+            r#"{"database": {"name": "from-file", "max_collections": 7, "thread_pool_size": 3}}"#,
+        )
+        .unwrap();
+        let defaults = SolarisConfig::default();
+
+        // The only test touching these variables, so there is no race with other tests.
+        std::env::set_var("SOLARIS_DB_NAME", "from-env");
+        std::env::set_var("SOLARIS_MEMORY_LIMIT_MB", "64");
+        let layered = SolarisConfig::load_layered(Some(&path));
+        let without_file = SolarisConfig::load_layered(Some(&dir.path().join("missing.json")));
+        std::env::remove_var("SOLARIS_DB_NAME");
+        std::env::remove_var("SOLARIS_MEMORY_LIMIT_MB");
+
+        let layered = layered.unwrap();
+        assert_eq!(layered.database.name, "from-env");
+        assert_eq!(layered.database.memory_limit_mb, Some(64));
+        assert_eq!(layered.database.max_collections, 7);
+        assert_eq!(layered.database.thread_pool_size, Some(3));
+        assert_eq!(layered.database.data_directory, defaults.database.data_directory);
+        assert_eq!(layered.database.persistence_format, defaults.database.persistence_format);
+
+        let without_file = without_file.unwrap();
+        assert_eq!(without_file.database.name, "from-env");
+        assert_eq!(without_file.database.max_collections, defaults.database.max_collections);
+
+        let file_only = SolarisConfig::load_layered(Some(&path)).unwrap();
+        assert_eq!(file_only.database.name, "from-file");
+        assert_eq!(file_only.database.memory_limit_mb, defaults.database.memory_limit_mb);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn config_files_from_before_the_newer_fields_still_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("solaris.json");
+        std::fs::write(
+            &path,
+            r#"{
+                "database": {
+                    "name": "legacy",
+                    "data_directory": "./legacy",
+                    "max_collections": 5,
+                    "enable_persistence": true,
+                    "auto_flush_interval_seconds": 30,
+                    "memory_limit_mb": null,
+                    "thread_pool_size": null,
+                    "compression_enabled": false
+                },
+                "collections": {
+                    "default_dimension": 8,
+                    "default_metric": "Euclidean",
+                    "default_m": 8,
+                    "default_ef_construction": 64,
+                    "max_vectors_per_collection": null,
+                    "enable_metadata_indexing": true
+                },
+                "performance": {
+                    "search_timeout_ms": 100,
+                    "batch_size": 10,
+                    "parallel_search_threshold": 50,
+                    "cache_size": 10,
+                    "prefetch_enabled": false
+                }
+            }"#,
+        )
+        .unwrap();
+        let defaults = SolarisConfig::default();
+
+        let config = SolarisConfig::load_from_file(&path).unwrap();
+        assert_eq!(config.database.name, "legacy");
+        assert_eq!(config.database.max_collections, 5);
+        assert_eq!(config.database.persistence_format, defaults.database.persistence_format);
+        assert_eq!(config.database.delta_encoding, defaults.database.delta_encoding);
+        assert_eq!(config.database.flush_policy, defaults.database.flush_policy);
+        assert_eq!(config.database.replication_log_size, defaults.database.replication_log_size);
+        assert_eq!(config.collections.default_m, 8);
+        assert_eq!(config.collections.max_search_limit, defaults.collections.max_search_limit);
+        assert_eq!(config.collections.max_batch_size, defaults.collections.max_batch_size);
+        assert_eq!(config.performance.parallel_search_threshold, 50);
+        assert_eq!(config.performance.search_distance_memo, defaults.performance.search_distance_memo);
+        assert_eq!(config.performance.deterministic_distance, defaults.performance.deterministic_distance);
+        assert_eq!(config.performance.nan_handling, defaults.performance.nan_handling);
+    }
+}
@@ -1 +1,6 @@
-pub mod database;
\ No newline at end of file
+pub mod database;
+pub mod document_iter;
+pub mod hooks;
+pub mod maintenance;
+pub mod recall_monitor;
+pub mod replication;
\ No newline at end of file
@@ -1,134 +1,5807 @@
+use crate::config::{
+    CollectionSettings, FlushPolicy, NanHandling, PerformanceConfig, PersistenceFormat, SolarisConfig,
+};
+use crate::core::document_iter::DocumentIter;
+use crate::core::hooks::{CollectionHook, CollectionHooks, QueryTransform};
+use crate::core::maintenance::{MaintenanceConfig, MaintenanceScheduler};
+use crate::core::recall_monitor::{RecallAlert, RecallMonitor, RecallMonitorConfig};
+use crate::core::replication::{ReplicationEvent, ReplicationLog, ReplicationOp};
+use crate::error::SolarisError;
 use crate::index::vector_index::VectorIndex;
 use crate::storage::memory_storage::MemoryStorage;
-use crate::types::{CollectionConfig, SearchResult, Vector, VectorMetadata};
-use std::collections::HashMap;
+#[cfg(feature = "persistence")]
+use crate::storage::persistent_storage::PersistentStorage;
+use crate::types::{
+    BatchFailure, BatchInsertRequest, BatchInsertResponse, BatchUpsertResponse,
+    BatchValidationReport, CollectionConfig, CollectionHealth, CollectionInfo, ConflictPolicy,
+    ConnectivityReport, DatabaseInfo, DistanceMatrix, DistanceMetric, DocumentMeta, EvictionPolicy,
+    FederatedHit, FilterStats, FusionMethod, HealthState, HealthStatus, IdType, IntVector,
+    MatchedResult, MetadataFilter, NumericSearchResult, RecallStats, SearchHit, SearchQuery,
+    SearchResponse, SearchResult, SortOrder, SparseVector, UpdateOutcome, UpsertOutcome, Vector,
+    VectorDocument, VectorMatrix, VectorMetadata, VectorType,
+};
+use crate::utils::cancellation::CancellationToken;
+use crate::utils::distance::{
+    calculate_distance_with, calculate_int_distance, check_nan_distances, compare_distances,
+    default_epsilon, distance_to_similarity, weighted_distance, within_epsilon, within_epsilon_int,
+};
+use crate::utils::filter::{evaluate_filter, matching_conditions};
+use crate::utils::validation::{
+    ValidationError, parse_id, validate_batch_size, validate_collection_config,
+    validate_dimension_range, validate_dimension_weights, validate_document_fields, validate_int_vector, validate_metadata, validate_search_params,
+    validate_sparse_vector, validate_vector, validate_vector_document, validate_vector_id,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+#[cfg(feature = "std")]
+use rayon::prelude::*;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
+#[cfg(feature = "persistence")]
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering as AtomicOrdering};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+const MEMORY_HEADROOM_PERCENT: usize = 10;
+const FILTER_STATS_SMOOTHING: f32 = 0.1;
+const CONFIDENCE_SAMPLE_SIZE: usize = 1000;
+const FULL_DISTANCE_MATRIX_LIMIT: usize = 1024;
+const WEIGHTED_RERANK_POOL: usize = 50;
+const RRF_K: f32 = 60.0;
 
 pub struct Database {
     name: String,
-    collections: HashMap<String, Collection>,
+    collections: RwLock<HashMap<String, Arc<RwLock<Collection>>>>,
+    performance: PerformanceConfig,
+    memory_limit_bytes: Option<usize>,
+    memory_pressure: AtomicBool,
+    data_directory: Option<PathBuf>,
+    persistence_format: PersistenceFormat,
+    delta_encoding: bool,
+    flush_policy: FlushPolicy,
+    collection_settings: CollectionSettings,
+    replication: Arc<ReplicationLog>,
 }
 
 impl Database {
     pub fn new(name: String) -> Self {
         Database {
             name,
-            collections: HashMap::new(),
+            collections: RwLock::new(HashMap::new()),
+            performance: PerformanceConfig::default(),
+            memory_limit_bytes: None,
+            memory_pressure: AtomicBool::new(false),
+            data_directory: None,
+            persistence_format: PersistenceFormat::default(),
+            delta_encoding: false,
+            flush_policy: FlushPolicy::default(),
+            collection_settings: CollectionSettings::default(),
+            replication: Arc::new(ReplicationLog::new(0)),
         }
     }
     
-    pub fn create_collection(&mut self, name: &str, dimension: usize) -> Result<(), Box<dyn Error>> {
-        if self.collections.contains_key(name) {
-            return Err(format!("Collection '{}' already exists", name).into());
+    pub fn with_config(config: &SolarisConfig) -> Self {
+        Database {
+            name: config.database.name.clone(),
+            collections: RwLock::new(HashMap::new()),
+            performance: config.performance.clone(),
+            memory_limit_bytes: config.database.memory_limit_mb.map(|mb| mb * 1024 * 1024),
+            memory_pressure: AtomicBool::new(false),
+            data_directory: config
+                .database
+                .enable_persistence
+                .then(|| config.database.data_directory.clone()),
+            persistence_format: config.database.persistence_format,
+            delta_encoding: config.database.delta_encoding,
+            flush_policy: config.database.flush_policy.clone(),
+            collection_settings: config.collections.clone(),
+            replication: Arc::new(ReplicationLog::new(config.database.replication_log_size)),
+        }
+    }
+    
+    /// JSON Schemas for the request and response types, keyed by type name.
+    #[cfg(feature = "schema")]
+    pub fn api_schema() -> serde_json::Value {
+        serde_json::json!({
+            "SearchQuery": schemars::schema_for!(SearchQuery),
+            "SearchResponse": schemars::schema_for!(SearchResponse),
+            "SearchHit": schemars::schema_for!(SearchHit),
+            "VectorDocument": schemars::schema_for!(VectorDocument),
+            "MetadataFilter": schemars::schema_for!(MetadataFilter),
+            "BatchInsertRequest": schemars::schema_for!(BatchInsertRequest),
+            "BatchInsertResponse": schemars::schema_for!(BatchInsertResponse),
+        })
+    }
+    
+    pub fn create_collection(&self, name: &str, dimension: usize) -> Result<(), Box<dyn Error>> {
+        let config = CollectionConfig {
+            name: name.to_string(),
+            dimension,
+            ..self.collection_defaults()
+        };
+        
+        self.create_collection_with_config(config)
+    }
+    
+    pub fn get_or_create_collection(
+        &self,
+        name: &str,
+        dimension: usize,
+        metric: DistanceMetric,
+    ) -> Result<(), Box<dyn Error>> {
+        let config = CollectionConfig {
+            name: name.to_string(),
+            dimension,
+            metric,
+            ..self.collection_defaults()
+        };
+        
+        if self.insert_collection(config)? {
+            return Ok(());
+        }
+        
+        let existing = self.get_collection(name)?;
+        let existing = existing.read().map_err(|_| "Failed to acquire read lock")?;
+        if existing.config.dimension != dimension || existing.config.metric != metric {
+            return Err(format!(
+                "Collection '{}' already exists with dimension {} and metric {:?}, requested dimension {} and metric {:?}",
+                name, existing.config.dimension, existing.config.metric, dimension, metric
+            )
+            .into());
         }
         
+        Ok(())
+    }
+    
+    pub fn create_sparse_collection(&self, name: &str, dimension: usize) -> Result<(), Box<dyn Error>> {
+        let config = CollectionConfig {
+            name: name.to_string(),
+            dimension,
+            vector_type: VectorType::Sparse,
+            ..self.collection_defaults()
+        };
+        
+        self.create_collection_with_config(config)
+    }
+    
+    pub fn create_integer_collection(
+        &self,
+        name: &str,
+        dimension: usize,
+        metric: DistanceMetric,
+    ) -> Result<(), Box<dyn Error>> {
         let config = CollectionConfig {
             name: name.to_string(),
             dimension,
+            metric,
+            vector_type: VectorType::Integer,
+            ..self.collection_defaults()
+        };
+        
+        self.create_collection_with_config(config)
+    }
+    
+    pub fn create_collection_with_config(&self, config: CollectionConfig) -> Result<(), Box<dyn Error>> {
+        let name = config.name.clone();
+        if !self.insert_collection(config)? {
+            return Err(format!("Collection '{}' already exists", name).into());
+        }
+        
+        Ok(())
+    }
+    
+    pub fn drop_collection(&self, name: &str) -> Result<bool, Box<dyn Error>> {
+        let collection = {
+            let collections = self.collections.read().map_err(|_| "Failed to acquire read lock")?;
+            match collections.get(name) {
+                Some(collection) => collection.clone(),
+                None => return Ok(false),
+            }
         };
         
-        let collection = Collection::new(config);
-        self.collections.insert(name.to_string(), collection);
+        // Deleted while the name is still taken, so a create of the same name cannot reopen
+        // the old files, and outside the collections lock, so the IO blocks no one else.
+        #[cfg(feature = "persistence")]
+        collection.write().map_err(|_| "Failed to acquire write lock")?.delete_storage()?;
+        
+        let mut collections = self.collections.write().map_err(|_| "Failed to acquire write lock")?;
+        let dropped = collections.get(name).is_some_and(|current| Arc::ptr_eq(current, &collection));
+        if dropped {
+            collections.remove(name);
+            self.replicate(name, || ReplicationOp::DropCollection)?;
+        }
+        Ok(dropped)
+    }
+    
+    /// Creates `target` with `source`'s config and contents, copying its index graph as is.
+    pub fn clone_collection(&self, source: &str, target: &str) -> Result<(), Box<dyn Error>> {
+        self.check_memory_limit()?;
+        {
+            let collections = self.collections.read().map_err(|_| "Failed to acquire read lock")?;
+            if collections.contains_key(target) {
+                return Err(format!("Collection '{}' already exists", target).into());
+            }
+        }
+        
+        // Copied in memory only; storage and replication are attached once the target name
+        // is claimed, so a failed copy or a lost race leaves no trace on disk or on replicas.
+        let source = self.get_collection(source)?;
+        let source = source.read().map_err(|_| "Failed to acquire read lock")?;
+        let mut clone = Collection::new(
+            CollectionConfig {
+                name: target.to_string(),
+                ..source.config.clone()
+            },
+            &self.performance,
+        );
+        let ids = source.copy_into(&mut clone)?;
+        drop(source);
+        
+        let clone = clone.into_shared();
+        let mut guard = clone.write().map_err(|_| "Failed to acquire write lock")?;
+        {
+            let mut collections = self.collections.write().map_err(|_| "Failed to acquire write lock")?;
+            if collections.contains_key(target) {
+                return Err(format!("Collection '{}' already exists", target).into());
+            }
+            self.attach_collection(&mut guard)?;
+            collections.insert(target.to_string(), clone.clone());
+            self.replicate(target, || ReplicationOp::CreateCollection(guard.config.clone()))?;
+        }
+        
+        // Still under the clone's write lock, so no other write to it can come first.
+        for id in &ids {
+            guard.persist(id)?;
+        }
+        Ok(())
+    }
+    
+    /// Re-keys `old` as `new` under the collections write lock, moving its storage
+    /// directory when persistence is on.
+    pub fn rename_collection(&self, old: &str, new: &str) -> Result<(), Box<dyn Error>> {
+        let mut collections = self.collections.write().map_err(|_| "Failed to acquire write lock")?;
+        if collections.contains_key(new) {
+            return Err(format!("Collection '{}' already exists", new).into());
+        }
+        let collection = collections
+            .get(old)
+            .cloned()
+            .ok_or_else(|| format!("Collection '{}' not found", old))?;
+        
+        collection.write().map_err(|_| "Failed to acquire write lock")?.rename(new)?;
+        collections.remove(old);
+        collections.insert(new.to_string(), collection);
+        self.replicate(old, || ReplicationOp::RenameCollection(new.to_string()))?;
+        Ok(())
+    }
+    
+    pub fn list_collections(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let collections = self.collections.read().map_err(|_| "Failed to acquire read lock")?;
+        Ok(collections.keys().cloned().collect())
+    }
+    
+    pub fn get_collection_info(&self, collection_name: &str) -> Result<CollectionInfo, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.info()
+    }
+    
+    pub fn get_database_info(&self) -> Result<DatabaseInfo, Box<dyn Error>> {
+        let mut collections = Vec::new();
+        for collection in self.collection_handles()? {
+            let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+            collections.push(collection.info()?);
+        }
+        collections.sort_by(|a, b| a.name.cmp(&b.name));
+        
+        Ok(DatabaseInfo {
+            name: self.name.clone(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            total_vectors: collections.iter().map(|info| info.vector_count).sum(),
+            memory_usage: collections.iter().map(|info| info.size_bytes).sum(),
+            collections,
+        })
+    }
+    
+    fn collection_defaults(&self) -> CollectionConfig {
+        CollectionConfig {
+            max_search_limit: self.collection_settings.max_search_limit,
+            max_batch_size: self.collection_settings.max_batch_size,
+            deterministic_distance: self.performance.deterministic_distance,
+            ..Default::default()
+        }
+    }
+    
+    fn insert_collection(&self, config: CollectionConfig) -> Result<bool, Box<dyn Error>> {
+        validate_collection_config(&config)?;
+        
+        {
+            let collections = self.collections.read().map_err(|_| "Failed to acquire read lock")?;
+            if collections.contains_key(&config.name) {
+                return Ok(false);
+            }
+        }
+        
+        let name = config.name.clone();
+        let collection = self.open_collection(config.clone())?.into_shared();
+        
+        let mut collections = self.collections.write().map_err(|_| "Failed to acquire write lock")?;
+        if collections.contains_key(&name) {
+            return Ok(false);
+        }
+        collections.insert(name.clone(), collection);
+        self.replicate(&name, || ReplicationOp::CreateCollection(config))?;
         
+        Ok(true)
+    }
+    
+    /// Live stream of every mutation applied to this database, starting now.
+    pub fn subscribe(&self) -> Result<Receiver<ReplicationEvent>, Box<dyn Error>> {
+        self.replication.subscribe()
+    }
+    
+    /// Like `subscribe`, but first replays the retained events after `seq`.
+    pub fn subscribe_after(&self, seq: u64) -> Result<Receiver<ReplicationEvent>, Box<dyn Error>> {
+        self.replication.subscribe_after(seq)
+    }
+    
+    /// Applies an event captured from another database's `subscribe` stream. Events must be
+    /// applied in seq order; documents get this database's own insert timestamps.
+    pub fn apply(&self, event: ReplicationEvent) -> Result<(), Box<dyn Error>> {
+        match event.op {
+            ReplicationOp::CreateCollection(config) => {
+                self.insert_collection(CollectionConfig {
+                    name: event.collection,
+                    ..config
+                })?;
+                Ok(())
+            }
+            ReplicationOp::DropCollection => {
+                self.drop_collection(&event.collection)?;
+                Ok(())
+            }
+            ReplicationOp::RenameCollection(new) => self.rename_collection(&event.collection, &new),
+            ReplicationOp::Put(document) => {
+                let collection = self.get_collection(&event.collection)?;
+                let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+                collection.apply_put(document)
+            }
+            ReplicationOp::UpdateMetadata(id, metadata) => {
+                let collection = self.get_collection(&event.collection)?;
+                let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+                collection.update_metadata(&id, metadata)?;
+                Ok(())
+            }
+            ReplicationOp::Delete(id) => {
+                let collection = self.get_collection(&event.collection)?;
+                let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+                collection.remove_vector(&id)?;
+                Ok(())
+            }
+        }
+    }
+    
+    fn replicate<F>(&self, collection: &str, op: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnOnce() -> ReplicationOp,
+    {
+        if self.replication.is_active() {
+            self.replication.record(collection, op())?;
+        }
         Ok(())
     }
     
+    /// `id` may be a `String` or a `CompositeId`.
     pub fn insert_vector(
-        &mut self,
+        &self,
         collection_name: &str,
-        id: String,
+        id: impl Into<String>,
         vector: Vector,
         metadata: Option<VectorMetadata>,
     ) -> Result<(), Box<dyn Error>> {
-        let collection = self.get_collection_mut(collection_name)?;
-        collection.insert_vector(id, vector, metadata)
+        self.check_memory_limit()?;
+        let collection = self.get_collection(collection_name)?;
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.insert_vector(id.into(), vector, metadata)
     }
     
-    pub fn search_vectors(
+    pub fn remove_vector(&self, collection_name: &str, id: &str) -> Result<bool, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.remove_vector(id)
+    }
+    
+    /// `insert_vector` for collections with `IdType::U64` ids.
+    pub fn insert_vector_u64(
         &self,
         collection_name: &str,
-        query_vector: Vector,
-        limit: usize,
-    ) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        id: u64,
+        vector: Vector,
+        metadata: Option<VectorMetadata>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.insert_vector(collection_name, id.to_string(), vector, metadata)
+    }
+    
+    pub fn remove_vector_u64(&self, collection_name: &str, id: u64) -> Result<bool, Box<dyn Error>> {
+        self.remove_vector(collection_name, &id.to_string())
+    }
+    
+    pub fn insert_pending(
+        &self,
+        collection_name: &str,
+        id: String,
+        metadata: Option<VectorMetadata>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.check_memory_limit()?;
         let collection = self.get_collection(collection_name)?;
-        collection.search_vectors(query_vector, limit)
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.insert_pending(id, metadata)
     }
     
-    fn get_collection(&self, name: &str) -> Result<&Collection, Box<dyn Error>> {
-        self.collections
-            .get(name)
-            .ok_or_else(|| format!("Collection '{}' not found", name).into())
+    pub fn update_metadata(
+        &self,
+        collection_name: &str,
+        id: &str,
+        metadata: Option<VectorMetadata>,
+    ) -> Result<UpdateOutcome, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.update_metadata(id, metadata)
     }
     
-    fn get_collection_mut(&mut self, name: &str) -> Result<&mut Collection, Box<dyn Error>> {
-        self.collections
-            .get_mut(name)
-            .ok_or_else(|| format!("Collection '{}' not found", name).into())
+    pub fn update_metadata_by_filter(
+        &self,
+        collection_name: &str,
+        filter: &MetadataFilter,
+        updates: VectorMetadata,
+    ) -> Result<usize, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.update_metadata_by_filter(filter, updates)
     }
-}
-
-pub struct Collection {
-    config: CollectionConfig,
-    storage: MemoryStorage,
-    index: VectorIndex,
-}
-
-impl Collection {
-    pub fn new(config: CollectionConfig) -> Self {
-        Collection {
-            config: config.clone(),
-            storage: MemoryStorage::new(config.clone()),
-            index: VectorIndex::new(config),
-        }
+    
+    pub fn update_vector(&self, collection_name: &str, id: &str, vector: Vector) -> Result<(), Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.update_vector(id, vector)
     }
     
-    pub fn insert_vector(
-        &mut self,
+    pub fn insert_sparse_vector(
+        &self,
+        collection_name: &str,
         id: String,
-        vector: Vector,
+        vector: SparseVector,
         metadata: Option<VectorMetadata>,
     ) -> Result<(), Box<dyn Error>> {
-        
-        if vector.len() != self.config.dimension {
-            return Err(format!(
-                "Vector dimension mismatch. Expected {}, got {}",
-                self.config.dimension,
-                vector.len()
-            )
-            .into());
-        }
-        
-        self.storage.store(id.clone(), vector.clone(), metadata.clone())?;
-        
-       
-        self.index.add_vector(id, vector)?;
-        
-        Ok(())
+        self.check_memory_limit()?;
+        let collection = self.get_collection(collection_name)?;
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.insert_sparse_vector(id, vector, metadata)
+    }
+    
+    pub fn batch_upsert(
+        &self,
+        collection_name: &str,
+        request: BatchInsertRequest,
+        policy: ConflictPolicy,
+    ) -> Result<BatchUpsertResponse, Box<dyn Error>> {
+        self.check_memory_limit()?;
+        let collection = self.get_collection(collection_name)?;
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.batch_upsert(request, policy)
+    }
+    
+    pub fn validate_batch(
+        &self,
+        collection_name: &str,
+        request: &BatchInsertRequest,
+    ) -> Result<BatchValidationReport, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.validate_batch(request)
+    }
+    
+    pub fn insert_stream<I>(&self, collection_name: &str, documents: I) -> Result<BatchInsertResponse, Box<dyn Error>>
+    where
+        I: IntoIterator<Item = VectorDocument>,
+    {
+        self.check_memory_limit()?;
+        let collection = self.get_collection(collection_name)?;
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.insert_stream(documents)
+    }
+    
+    pub fn insert_stream_cancellable<I>(
+        &self,
+        collection_name: &str,
+        documents: I,
+        cancel: &CancellationToken,
+    ) -> Result<BatchInsertResponse, Box<dyn Error>>
+    where
+        I: IntoIterator<Item = VectorDocument>,
+    {
+        self.check_memory_limit()?;
+        let collection = self.get_collection(collection_name)?;
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.insert_stream_cancellable(documents, cancel)
+    }
+    
+    pub fn batch_insert_with_progress<F>(
+        &self,
+        collection_name: &str,
+        request: BatchInsertRequest,
+        callback: F,
+    ) -> Result<BatchInsertResponse, Box<dyn Error>>
+    where
+        F: Fn(usize, usize),
+    {
+        self.check_memory_limit()?;
+        let collection = self.get_collection(collection_name)?;
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.batch_insert_with_progress(request, callback)
     }
     
     pub fn search_vectors(
         &self,
+        collection_name: &str,
         query_vector: Vector,
         limit: usize,
     ) -> Result<Vec<SearchResult>, Box<dyn Error>> {
-
-        if query_vector.len() != self.config.dimension {
-            return Err(format!(
-                "Query vector dimension mismatch. Expected {}, got {}",
-                self.config.dimension,
-                query_vector.len()
-            )
-            .into());
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.search_vectors(query_vector, limit)
+    }
+    
+    pub fn search_vectors_u64(
+        &self,
+        collection_name: &str,
+        query_vector: Vector,
+        limit: usize,
+    ) -> Result<Vec<NumericSearchResult>, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.search_vectors_u64(query_vector, limit)
+    }
+    
+    pub fn to_matrix(&self, collection_name: &str, ids: Option<&[String]>) -> Result<VectorMatrix, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.to_matrix(ids)
+    }
+    
+    pub fn multi_query_search(
+        &self,
+        collection_name: &str,
+        queries: &[Vector],
+        limit: usize,
+        fusion: FusionMethod,
+    ) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.multi_query_search(queries, limit, fusion)
+    }
+    
+    pub fn search_simple(&self, collection_name: &str, query: &[f32], k: usize) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.search_simple(query, k)
+    }
+    
+    pub fn search_detailed(&self, collection_name: &str, query: &SearchQuery) -> Result<Vec<SearchHit>, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.search_detailed(query)
+    }
+    
+    pub fn kth_distance(&self, collection_name: &str, query_vector: &Vector, k: usize) -> Result<Option<f32>, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.kth_distance(query_vector, k)
+    }
+    
+    pub fn search(&self, collection_name: &str, query: &SearchQuery) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.search(query)
+    }
+    
+    pub fn search_with_match_info(
+        &self,
+        collection_name: &str,
+        query: &SearchQuery,
+    ) -> Result<Vec<MatchedResult>, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.search_with_match_info(query)
+    }
+    
+    pub fn search_with_predicate<F>(
+        &self,
+        collection_name: &str,
+        query_vector: &Vector,
+        limit: usize,
+        predicate: F,
+    ) -> Result<Vec<SearchResult>, Box<dyn Error>>
+    where
+        F: Fn(&VectorDocument) -> bool,
+    {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.search_with_predicate(query_vector, limit, predicate)
+    }
+    
+    pub fn search_cancellable(
+        &self,
+        collection_name: &str,
+        query: &SearchQuery,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.search_cancellable(query, cancel)
+    }
+    
+    pub fn search_with_response(
+        &self,
+        collection_name: &str,
+        query: &SearchQuery,
+    ) -> Result<SearchResponse, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.search_with_response(query)
+    }
+    
+    pub fn search_across(
+        &self,
+        collection_names: &[String],
+        query_vector: Vector,
+        limit: usize,
+    ) -> Result<Vec<FederatedHit>, Box<dyn Error>> {
+        let handles = collection_names
+            .iter()
+            .map(|name| self.get_collection(name).map(|collection| (name.clone(), collection)))
+            .collect::<Result<Vec<_>, _>>()?;
+        
+        let mut expected: Option<(usize, DistanceMetric)> = None;
+        for (name, collection) in &handles {
+            let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+            let shape = (collection.config.dimension, collection.config.metric);
+            match expected {
+                None => expected = Some(shape),
+                Some((dimension, metric)) if (dimension, metric) != shape => {
+                    return Err(format!(
+                        "Collection '{}' has dimension {} and metric {:?}, expected dimension {} and metric {:?}",
+                        name, shape.0, shape.1, dimension, metric
+                    )
+                    .into());
+                }
+                Some(_) => {}
+            }
         }
         
-        let nearest_ids = self.index.search(query_vector, limit)?;
+        #[cfg(feature = "std")]
+        let handles = handles.into_par_iter();
+        #[cfg(not(feature = "std"))]
+        let handles = handles.into_iter();
+        let per_collection = handles
+            .map(|(name, collection)| {
+                let collection = collection.read().map_err(|_| "Failed to acquire read lock".to_string())?;
+                collection
+                    .search_vectors(query_vector.clone(), limit)
+                    .map(|results| (name, results))
+                    .map_err(|e| e.to_string())
+            })
+            .collect::<Result<Vec<_>, String>>()?;
         
-        let mut results = Vec::with_capacity(nearest_ids.len());
-        for (id, score) in nearest_ids {
-            let metadata = self.storage.get_metadata(&id)?;
-            results.push((id, score, metadata));
-        }
+        let mut hits: Vec<FederatedHit> = per_collection
+            .into_iter()
+            .flat_map(|(collection, results)| {
+                results.into_iter().map(move |(id, score, metadata)| FederatedHit {
+                    collection: collection.clone(),
+                    id,
+                    score,
+                    metadata,
+                })
+            })
+            .collect();
         
-        Ok(results)
+        hits.sort_by(|a, b| a.score.partial_cmp(&b.score).unwrap_or(Ordering::Equal));
+        hits.truncate(limit);
+        
+        Ok(hits)
     }
-}
\ No newline at end of file
+    
+    pub fn search_sparse_vectors(
+        &self,
+        collection_name: &str,
+        query_vector: SparseVector,
+        limit: usize,
+    ) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.search_sparse_vectors(&query_vector, limit)
+    }
+    
+    pub fn insert_int_vector(
+        &self,
+        collection_name: &str,
+        id: String,
+        vector: IntVector,
+        metadata: Option<VectorMetadata>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.check_memory_limit()?;
+        let collection = self.get_collection(collection_name)?;
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.insert_int_vector(id, vector, metadata)
+    }
+    
+    pub fn search_int_vectors(
+        &self,
+        collection_name: &str,
+        query_vector: IntVector,
+        limit: usize,
+    ) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.search_int_vectors(&query_vector, limit)
+    }
+    
+    pub fn search_within(
+        &self,
+        collection_name: &str,
+        query_vector: Vector,
+        ids: &[String],
+        limit: usize,
+        skip_missing: bool,
+    ) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.search_within(&query_vector, ids, limit, skip_missing)
+    }
+    
+    pub fn contains(&self, collection_name: &str, id: &str) -> Result<bool, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.contains(id)
+    }
+    
+    pub fn distance_between(&self, collection_name: &str, a: &str, b: &str) -> Result<Option<f32>, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.distance_between(a, b)
+    }
+    
+    pub fn distance_matrix(&self, collection_name: &str, ids: &[String]) -> Result<DistanceMatrix, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.distance_matrix(ids)
+    }
+    
+    pub fn is_duplicate(&self, collection_name: &str, a: &str, b: &str) -> Result<bool, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.is_duplicate(a, b)
+    }
+    
+    pub fn recent(&self, collection_name: &str, n: usize) -> Result<Vec<VectorDocument>, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.recent(n)
+    }
+    
+    pub fn sample(
+        &self,
+        collection_name: &str,
+        n: usize,
+        seed: Option<u64>,
+    ) -> Result<Vec<VectorDocument>, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.sample(n, seed)
+    }
+    
+    pub fn iter_documents(&self, collection_name: &str) -> Result<DocumentIter, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let ids = collection
+            .read()
+            .map_err(|_| "Failed to acquire read lock")?
+            .document_ids()?;
+        Ok(DocumentIter::new(collection, ids, self.performance.batch_size))
+    }
+    
+    pub fn get_document_meta(
+        &self,
+        collection_name: &str,
+        id: &str,
+    ) -> Result<Option<DocumentMeta>, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.get_document_meta(id)
+    }
+    
+    pub fn optimize_cancellable(&self, collection_name: &str, cancel: &CancellationToken) -> Result<(), Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.optimize_cancellable(cancel)
+    }
+    
+    pub fn get_collection_config(&self, collection_name: &str) -> Result<CollectionConfig, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        Ok(collection.config().clone())
+    }
+    
+    pub fn set_m(&self, collection_name: &str, m: usize) -> Result<(), Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.set_m(m)
+    }
+    
+    pub fn set_ef_construction(&self, collection_name: &str, ef_construction: usize) -> Result<(), Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.set_ef_construction(ef_construction)
+    }
+    
+    pub fn on_insert(&self, collection_name: &str, hook: CollectionHook) -> Result<(), Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.on_insert(hook);
+        Ok(())
+    }
+    
+    pub fn on_delete(&self, collection_name: &str, hook: CollectionHook) -> Result<(), Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.on_delete(hook);
+        Ok(())
+    }
+    
+    pub fn on_update(&self, collection_name: &str, hook: CollectionHook) -> Result<(), Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.on_update(hook);
+        Ok(())
+    }
+    
+    pub fn set_default_filter(&self, collection_name: &str, filter: MetadataFilter) -> Result<(), Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.set_default_filter(filter);
+        Ok(())
+    }
+    
+    pub fn clear_default_filter(&self, collection_name: &str) -> Result<(), Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.clear_default_filter();
+        Ok(())
+    }
+    
+    pub fn set_query_transform(&self, collection_name: &str, transform: QueryTransform) -> Result<(), Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.set_query_transform(transform);
+        Ok(())
+    }
+    
+    pub fn reindex(&self, collection_name: &str, m: usize, ef_construction: usize) -> Result<(), Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.reindex(m, ef_construction)
+    }
+    
+    pub fn prune(&self, collection_name: &str, max_degree: usize) -> Result<usize, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        collection.prune(max_degree)
+    }
+    
+    pub fn connectivity_report(&self, collection_name: &str) -> Result<ConnectivityReport, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        Ok(collection.connectivity_report())
+    }
+    
+    /// Starts running the collection's compaction, pruning and optimization in the
+    /// background; see `MaintenanceScheduler`.
+    pub fn start_maintenance(
+        &self,
+        collection_name: &str,
+        config: MaintenanceConfig,
+    ) -> Result<MaintenanceScheduler, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        Ok(MaintenanceScheduler::start(Arc::downgrade(&collection), config))
+    }
+    
+    /// Starts sampling the collection's recall in the background; see `RecallMonitor`.
+    pub fn monitor_recall(
+        &self,
+        collection_name: &str,
+        config: RecallMonitorConfig,
+        alert: RecallAlert,
+    ) -> Result<RecallMonitor, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        Ok(RecallMonitor::spawn(Arc::downgrade(&collection), config, alert))
+    }
+    
+    pub fn recall_stats(&self, collection_name: &str) -> Result<RecallStats, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.recall_stats()
+    }
+    
+    pub fn filter_stats(&self, collection_name: &str) -> Result<FilterStats, Box<dyn Error>> {
+        let collection = self.get_collection(collection_name)?;
+        let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+        collection.filter_stats()
+    }
+    
+    pub fn purge_expired(&self) -> Result<usize, Box<dyn Error>> {
+        let mut purged = 0;
+        for collection in self.collection_handles()? {
+            let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+            purged += collection.purge_expired()?;
+        }
+        Ok(purged)
+    }
+    
+    pub fn health(&self) -> HealthStatus {
+        let collections = self.collections.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+        
+        let mut memory_used_bytes = 0;
+        let mut collection_health = Vec::with_capacity(collections.len());
+        for (name, collection) in collections.iter() {
+            let lock_healthy = match collection.read() {
+                Ok(collection) => {
+                    memory_used_bytes += collection.size_bytes().unwrap_or(0);
+                    true
+                }
+                Err(_) => false,
+            };
+            collection_health.push(CollectionHealth {
+                name: name.clone(),
+                lock_healthy,
+            });
+        }
+        
+        let persistence_writable = self.data_directory.as_deref().map(|dir| {
+            let probe = dir.join(".solaris_health");
+            std::fs::create_dir_all(dir).is_ok()
+                && std::fs::write(&probe, b"ok").is_ok()
+                && std::fs::remove_file(&probe).is_ok()
+        });
+        
+        let healthy = !self.collections.is_poisoned()
+            && collection_health.iter().all(|collection| collection.lock_healthy)
+            && persistence_writable.unwrap_or(true)
+            && self.memory_limit_bytes.is_none_or(|limit| memory_used_bytes < limit);
+        
+        HealthStatus {
+            state: if healthy { HealthState::Healthy } else { HealthState::Degraded },
+            collections: collection_health,
+            persistence_writable,
+            memory_used_bytes,
+            memory_limit_bytes: self.memory_limit_bytes,
+        }
+    }
+    
+    pub fn memory_usage_bytes(&self) -> Result<usize, Box<dyn Error>> {
+        let mut used = 0;
+        for collection in self.collection_handles()? {
+            let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+            used += collection.size_bytes()?;
+        }
+        Ok(used)
+    }
+    
+    fn check_memory_limit(&self) -> Result<(), Box<dyn Error>> {
+        let limit = match self.memory_limit_bytes {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        
+        let used = self.memory_usage_bytes()?;
+        let threshold = if self.memory_pressure.load(AtomicOrdering::Relaxed) {
+            limit - limit * MEMORY_HEADROOM_PERCENT / 100
+        } else {
+            limit
+        };
+        
+        if used >= threshold {
+            self.memory_pressure.store(true, AtomicOrdering::Relaxed);
+            return Err(SolarisError::MemoryLimitExceeded { used, limit }.into());
+        }
+        
+        self.memory_pressure.store(false, AtomicOrdering::Relaxed);
+        Ok(())
+    }
+    
+    fn open_collection(&self, config: CollectionConfig) -> Result<Collection, Box<dyn Error>> {
+        #[cfg(feature = "persistence")]
+        let mut collection = match &self.data_directory {
+            Some(data_directory) => Collection::with_persistence(
+                config,
+                &self.performance,
+                data_directory,
+                self.persistence_format,
+                self.delta_encoding,
+                self.flush_policy.clone(),
+            )?,
+            None => Collection::new(config, &self.performance),
+        };
+        #[cfg(not(feature = "persistence"))]
+        let mut collection = Collection::new(config, &self.performance);
+        
+        collection.replication = Some(self.replication.clone());
+        Ok(collection)
+    }
+    
+    /// Connects a collection built in memory to this database's replication log and, with
+    /// persistence on, to a storage directory that must not hold data yet.
+    fn attach_collection(&self, collection: &mut Collection) -> Result<(), Box<dyn Error>> {
+        #[cfg(feature = "persistence")]
+        if let Some(data_directory) = &self.data_directory {
+            let persistent = PersistentStorage::with_format(
+                collection.config.clone(),
+                data_directory,
+                self.persistence_format,
+            )?
+            .with_delta_encoding(self.delta_encoding)
+            .with_flush_policy(self.flush_policy.clone());
+            if !persistent.load_all()?.is_empty() {
+                return Err(format!(
+                    "Storage for collection '{}' already holds documents",
+                    collection.config.name
+                )
+                .into());
+            }
+            collection.persistent = Some(persistent);
+        }
+        
+        collection.replication = Some(self.replication.clone());
+        Ok(())
+    }
+    
+    fn get_collection(&self, name: &str) -> Result<Arc<RwLock<Collection>>, Box<dyn Error>> {
+        let collections = self.collections.read().map_err(|_| "Failed to acquire read lock")?;
+        collections
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("Collection '{}' not found", name).into())
+    }
+    
+    fn collection_handles(&self) -> Result<Vec<Arc<RwLock<Collection>>>, Box<dyn Error>> {
+        let collections = self.collections.read().map_err(|_| "Failed to acquire read lock")?;
+        Ok(collections.values().cloned().collect())
+    }
+}
+
+pub struct Collection {
+    config: CollectionConfig,
+    storage: MemoryStorage,
+    index: VectorIndex,
+    batch_size: usize,
+    nan_handling: NanHandling,
+    filter_stats: Mutex<FilterStats>,
+    recall_stats: Mutex<RecallStats>,
+    operation_count: AtomicU64,
+    last_modified: AtomicU64,
+    hooks: CollectionHooks,
+    query_transform: Option<QueryTransform>,
+    default_filter: Option<MetadataFilter>,
+    replication: Option<Arc<ReplicationLog>>,
+    /// The lock this collection lives in once a database owns it; background compaction
+    /// needs it to build outside the caller's write lock.
+    handle: Weak<RwLock<Collection>>,
+    compacting: Arc<AtomicBool>,
+    /// Seconds since the epoch, as TTL expiry sees them.
+    clock: fn() -> Result<u64, Box<dyn Error>>,
+    #[cfg(feature = "persistence")]
+    persistent: Option<PersistentStorage>,
+}
+
+impl Collection {
+    pub fn new(config: CollectionConfig, performance: &PerformanceConfig) -> Self {
+        Collection {
+            config: config.clone(),
+            storage: MemoryStorage::new(config.clone()),
+            index: VectorIndex::new(config, performance),
+            batch_size: performance.batch_size,
+            nan_handling: performance.nan_handling,
+            filter_stats: Mutex::new(FilterStats::default()),
+            recall_stats: Mutex::new(RecallStats::default()),
+            operation_count: AtomicU64::new(0),
+            last_modified: AtomicU64::new(0),
+            hooks: CollectionHooks::default(),
+            query_transform: None,
+            default_filter: None,
+            replication: None,
+            handle: Weak::new(),
+            compacting: Arc::new(AtomicBool::new(false)),
+            clock: unix_time,
+            #[cfg(feature = "persistence")]
+            persistent: None,
+        }
+    }
+    
+    fn into_shared(mut self) -> Arc<RwLock<Collection>> {
+        Arc::new_cyclic(|handle| {
+            self.handle = handle.clone();
+            RwLock::new(self)
+        })
+    }
+    
+    /// Opens the collection's log under `data_dir` and replays it into memory and the index.
+    #[cfg(feature = "persistence")]
+    pub fn with_persistence(
+        config: CollectionConfig,
+        performance: &PerformanceConfig,
+        data_dir: &Path,
+        format: PersistenceFormat,
+        delta_encoding: bool,
+        flush_policy: FlushPolicy,
+    ) -> Result<Self, Box<dyn Error>> {
+        let start = Instant::now();
+        let persistent = PersistentStorage::with_format(config.clone(), data_dir, format)?
+            .with_delta_encoding(delta_encoding)
+            .with_flush_policy(flush_policy);
+        let documents = persistent.load_all()?;
+        
+        let mut collection = Collection::new(config, performance);
+        collection.recover(documents)?;
+        collection.persistent = Some(persistent);
+        
+        log::info!(
+            "Recovered collection '{}': {} documents in {} ms",
+            collection.config.name,
+            collection.storage.count()?,
+            start.elapsed().as_millis()
+        );
+        Ok(collection)
+    }
+    
+    #[cfg(feature = "persistence")]
+    fn recover(&mut self, documents: Vec<VectorDocument>) -> Result<(), Box<dyn Error>> {
+        if self.config.dimension == 0 {
+            if let Some(document) = documents.iter().find(|document| !document.vector.is_empty()) {
+                self.lock_dimension(document.vector.len())?;
+            }
+        }
+        
+        let mut dense = Vec::new();
+        for document in &documents {
+            if let Some(sparse_vector) = &document.sparse_vector {
+                self.index.add_sparse_vector(document.id.clone(), sparse_vector)?;
+            } else if document.int_vector.is_none() && !document.vector.is_empty() {
+                dense.push((document.id.clone(), document.vector.clone()));
+            }
+        }
+        
+        self.storage.batch_insert(documents)?;
+        self.index.par_build(dense)
+    }
+    
+    /// The stored document, with the exact vector when storage only holds an approximation.
+    fn exact_document(&self, id: &str) -> Result<Option<VectorDocument>, Box<dyn Error>> {
+        let mut document = self.storage.get(id)?;
+        if self.config.in_memory_compression {
+            if let (Some(document), Some(vector)) = (document.as_mut(), self.index.get_vector(id)) {
+                document.vector = vector.clone();
+            }
+        }
+        Ok(document)
+    }
+    
+    // Every insert, update and delete passes through one of the `persist*` methods below.
+    fn record_mutation(&self) -> Result<(), Box<dyn Error>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        self.operation_count.fetch_add(1, AtomicOrdering::Relaxed);
+        self.last_modified.fetch_max(now, AtomicOrdering::Relaxed);
+        Ok(())
+    }
+    
+    /// Writes the mutation to the persistent log, then to the replication log.
+    fn persist(&self, id: &str) -> Result<(), Box<dyn Error>> {
+        self.record_mutation()?;
+        #[cfg(feature = "persistence")]
+        if let Some(persistent) = &self.persistent {
+            if let Some(document) = self.exact_document(id)? {
+                persistent.store(document)?;
+                if self.config.sync_on_insert {
+                    persistent.sync()?;
+                }
+            }
+        }
+        
+        if let Some(log) = self.replication.as_ref().filter(|log| log.is_active()) {
+            if let Some(document) = self.exact_document(id)? {
+                log.record(&self.config.name, ReplicationOp::Put(document))?;
+            }
+        }
+        Ok(())
+    }
+    
+    fn persist_metadata(&self, id: &str) -> Result<(), Box<dyn Error>> {
+        self.record_mutation()?;
+        #[cfg(feature = "persistence")]
+        if let Some(persistent) = &self.persistent {
+            persistent.update_metadata(id, self.storage.get_metadata(id)?)?;
+        }
+        
+        if let Some(log) = self.replication.as_ref().filter(|log| log.is_active()) {
+            let metadata = self.storage.get_metadata(id)?;
+            log.record(&self.config.name, ReplicationOp::UpdateMetadata(id.to_string(), metadata))?;
+        }
+        Ok(())
+    }
+    
+    fn persist_delete(&self, id: &str) -> Result<(), Box<dyn Error>> {
+        self.record_mutation()?;
+        #[cfg(feature = "persistence")]
+        if let Some(persistent) = &self.persistent {
+            persistent.delete(id)?;
+        }
+        
+        if let Some(log) = self.replication.as_ref().filter(|log| log.is_active()) {
+            log.record(&self.config.name, ReplicationOp::Delete(id.to_string()))?;
+        }
+        Ok(())
+    }
+    
+    /// Stores a replicated document as-is, overwriting any existing one with the same id.
+    fn apply_put(&mut self, document: VectorDocument) -> Result<(), Box<dyn Error>> {
+        if document.is_pending() {
+            self.remove_document(&document.id)?;
+            return self.insert_pending(document.id, document.metadata);
+        }
+        
+        match self.upsert_document(document, ConflictPolicy::Overwrite)? {
+            UpsertOutcome::Failed(reason) => Err(reason.into()),
+            _ => Ok(()),
+        }
+    }
+    
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(collection = %self.config.name, id = %id)))]
+    pub fn insert_vector(
+        &mut self,
+        id: String,
+        vector: Vector,
+        metadata: Option<VectorMetadata>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.store_dense(id.clone(), vector, metadata)?;
+        self.persist(&id)?;
+        self.hooks.fire_insert(&id);
+        Ok(())
+    }
+    
+    fn store_dense(
+        &mut self,
+        id: String,
+        mut vector: Vector,
+        metadata: Option<VectorMetadata>,
+    ) -> Result<(), Box<dyn Error>> {
+        parse_id(&id, self.config.id_type)?;
+        self.check_dense_vector(&vector)?;
+        self.clamp_vector(&mut vector);
+        self.make_room(&id)?;
+        
+        self.storage.store(id.clone(), vector.clone(), metadata.clone())?;
+        self.index.add_vector(id, vector)?;
+        
+        Ok(())
+    }
+    
+    pub fn on_insert(&mut self, hook: CollectionHook) {
+        self.hooks.on_insert(hook);
+    }
+    
+    pub fn on_delete(&mut self, hook: CollectionHook) {
+        self.hooks.on_delete(hook);
+    }
+    
+    pub fn on_update(&mut self, hook: CollectionHook) {
+        self.hooks.on_update(hook);
+    }
+    
+    /// Applied to every dense query vector before searching. It must reproduce whatever
+    /// transform was applied to vectors before they were inserted, or queries and stored
+    /// vectors end up in different spaces.
+    pub fn set_query_transform(&mut self, transform: QueryTransform) {
+        self.query_transform = Some(transform);
+    }
+    
+    pub fn clear_query_transform(&mut self) {
+        self.query_transform = None;
+    }
+    
+    /// ANDed with the filter of every search; `SearchQuery.ignore_default_filter` opts out.
+    pub fn set_default_filter(&mut self, filter: MetadataFilter) {
+        self.default_filter = Some(filter);
+    }
+    
+    pub fn clear_default_filter(&mut self) {
+        self.default_filter = None;
+    }
+    
+    fn active_filters<'a>(&'a self, filter: Option<&'a MetadataFilter>, ignore_default: bool) -> Vec<&'a MetadataFilter> {
+        let default = self.default_filter.as_ref().filter(|_| !ignore_default);
+        default.into_iter().chain(filter).collect()
+    }
+    
+    fn prepare_query<'a>(&self, query_vector: &'a Vector) -> Cow<'a, Vector> {
+        match &self.query_transform {
+            Some(transform) => {
+                let mut query_vector = query_vector.clone();
+                transform(&mut query_vector);
+                Cow::Owned(query_vector)
+            }
+            None => Cow::Borrowed(query_vector),
+        }
+    }
+    
+    pub fn insert_pending(&mut self, id: String, metadata: Option<VectorMetadata>) -> Result<(), Box<dyn Error>> {
+        validate_vector_id(&id)?;
+        parse_id(&id, self.config.id_type)?;
+        
+        if self.storage.contains(&id)? {
+            return Err(SolarisError::DuplicateId(id).into());
+        }
+        
+        self.make_room(&id)?;
+        self.storage.store(id.clone(), Vec::new(), metadata)?;
+        self.persist(&id)?;
+        self.hooks.fire_insert(&id);
+        Ok(())
+    }
+    
+    pub fn update_metadata(
+        &mut self,
+        id: &str,
+        metadata: Option<VectorMetadata>,
+    ) -> Result<UpdateOutcome, Box<dyn Error>> {
+        if let Some(metadata) = &metadata {
+            validate_metadata(metadata)?;
+        }
+        
+        let outcome = self.storage.update_metadata(id, metadata)?;
+        if outcome == UpdateOutcome::Updated {
+            self.persist_metadata(id)?;
+            self.hooks.fire_update(id);
+        }
+        Ok(outcome)
+    }
+    
+    /// Upserts `updates` into the metadata of every live document matching `filter` and
+    /// returns how many documents actually changed.
+    pub fn update_metadata_by_filter(
+        &mut self,
+        filter: &MetadataFilter,
+        updates: VectorMetadata,
+    ) -> Result<usize, Box<dyn Error>> {
+        validate_metadata(&updates)?;
+        
+        let cutoff = self.expiry_cutoff()?;
+        let mut matches = Vec::new();
+        self.storage.for_each_metadata(|document| {
+            if cutoff.is_none_or(|cutoff| document.timestamp >= cutoff) && evaluate_filter(document, filter) {
+                matches.push((document.id.clone(), document.metadata.clone().unwrap_or_default()));
+            }
+        })?;
+        
+        let mut updated = 0;
+        for (id, mut metadata) in matches {
+            for (key, value) in &updates {
+                match metadata.iter_mut().find(|(existing, _)| existing == key) {
+                    Some(entry) => entry.1 = value.clone(),
+                    None => metadata.push((key.clone(), value.clone())),
+                }
+            }
+            
+            if self.update_metadata(&id, Some(metadata))? == UpdateOutcome::Updated {
+                updated += 1;
+            }
+        }
+        Ok(updated)
+    }
+    
+    pub fn update_vector(&mut self, id: &str, vector: Vector) -> Result<(), Box<dyn Error>> {
+        if !self.storage.contains(id)? {
+            return Err(format!("Vector '{}' not found", id).into());
+        }
+        
+        let metadata = self.storage.get_metadata(id)?;
+        self.store_dense(id.to_string(), vector, metadata)?;
+        self.persist(id)?;
+        self.hooks.fire_update(id);
+        Ok(())
+    }
+    
+    pub fn insert_stream<I>(&mut self, documents: I) -> Result<BatchInsertResponse, Box<dyn Error>>
+    where
+        I: IntoIterator<Item = VectorDocument>,
+    {
+        self.insert_stream_cancellable(documents, &CancellationToken::new())
+    }
+    
+    pub fn insert_stream_cancellable<I>(
+        &mut self,
+        documents: I,
+        cancel: &CancellationToken,
+    ) -> Result<BatchInsertResponse, Box<dyn Error>>
+    where
+        I: IntoIterator<Item = VectorDocument>,
+    {
+        self.insert_documents(documents, Some(cancel), |_| {})
+    }
+    
+    /// Inserts in chunks of the configured batch size, calling `callback(processed, total)`
+    /// after each chunk; the last call always reports `total`.
+    pub fn batch_insert_with_progress<F>(
+        &mut self,
+        request: BatchInsertRequest,
+        callback: F,
+    ) -> Result<BatchInsertResponse, Box<dyn Error>>
+    where
+        F: Fn(usize, usize),
+    {
+        validate_batch_size(request.vectors.len(), self.config.max_batch_size)?;
+        
+        let total = request.vectors.len();
+        self.insert_documents(request.vectors, None, |processed| callback(processed, total))
+    }
+    
+    fn insert_documents<I, P>(
+        &mut self,
+        documents: I,
+        cancel: Option<&CancellationToken>,
+        mut progress: P,
+    ) -> Result<BatchInsertResponse, Box<dyn Error>>
+    where
+        I: IntoIterator<Item = VectorDocument>,
+        P: FnMut(usize),
+    {
+        let start = Instant::now();
+        let mut response = BatchInsertResponse {
+            inserted: 0,
+            failed: Vec::new(),
+            duration_ms: 0,
+        };
+        
+        let mut processed = 0;
+        let mut documents = documents.into_iter();
+        loop {
+            if let Some(cancel) = cancel {
+                cancel.check()?;
+            }
+            let chunk: Vec<VectorDocument> = documents.by_ref().take(self.batch_size.max(1)).collect();
+            if chunk.is_empty() {
+                break;
+            }
+            processed += chunk.len();
+            self.insert_chunk(chunk, &mut response)?;
+            progress(processed);
+        }
+        
+        response.duration_ms = start.elapsed().as_millis() as u64;
+        Ok(response)
+    }
+    
+    fn insert_chunk(
+        &mut self,
+        chunk: Vec<VectorDocument>,
+        response: &mut BatchInsertResponse,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut dense: Vec<(String, Vector)> = Vec::with_capacity(chunk.len());
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        
+        for mut document in chunk {
+            if let Err(e) = validate_document_fields(&document) {
+                response.failed.push((document.id, BatchFailure::from_error(&e)));
+                continue;
+            }
+            
+            let result = match (document.sparse_vector, document.int_vector) {
+                (Some(sparse_vector), _) => {
+                    self.insert_sparse_vector(document.id.clone(), sparse_vector, document.metadata)
+                }
+                (None, Some(int_vector)) => {
+                    self.insert_int_vector(document.id.clone(), int_vector, document.metadata)
+                }
+                (None, None) => {
+                    let checked = parse_id(&document.id, self.config.id_type)
+                        .map_err(Into::into)
+                        .and_then(|_| self.check_dense_vector(&document.vector))
+                        .and_then(|_| self.make_room(&document.id));
+                    if let Err(e) = checked {
+                        response.failed.push((document.id, BatchFailure::from_error(&*e)));
+                        continue;
+                    }
+                    self.clamp_vector(&mut document.vector);
+                    
+                    self.storage.store(document.id.clone(), document.vector.clone(), document.metadata)?;
+                    response.inserted += 1;
+                    match positions.get(&document.id) {
+                        Some(&position) => dense[position].1 = document.vector,
+                        None => {
+                            positions.insert(document.id.clone(), dense.len());
+                            dense.push((document.id, document.vector));
+                        }
+                    }
+                    continue;
+                }
+            };
+            
+            match result {
+                Ok(()) => response.inserted += 1,
+                Err(e) => response.failed.push((document.id, BatchFailure::from_error(&*e))),
+            }
+        }
+        
+        // Evictions for later documents in the chunk may have removed earlier ones.
+        let mut stored = Vec::with_capacity(dense.len());
+        for (id, vector) in dense {
+            if self.storage.contains(&id)? {
+                stored.push((id, vector));
+            }
+        }
+        let dense = stored;
+        
+        let ids: Vec<String> = dense.iter().map(|(id, _)| id.clone()).collect();
+        self.index.par_build(dense)?;
+        for id in &ids {
+            self.persist(id)?;
+            self.hooks.fire_insert(id);
+        }
+        Ok(())
+    }
+    
+    fn unsupported_vector_type(&self, kind: &'static str) -> SolarisError {
+        SolarisError::UnsupportedVectorType {
+            collection: self.config.name.clone(),
+            kind,
+        }
+    }
+    
+    /// A collection created with dimension 0 takes the dimension of its first accepted vector.
+    fn check_dense_vector(&mut self, vector: &Vector) -> Result<(), Box<dyn Error>> {
+        if self.config.vector_type != VectorType::Dense {
+            return Err(self.unsupported_vector_type("dense").into());
+        }
+        
+        let dimension = match self.config.dimension {
+            0 => vector.len(),
+            dimension => dimension,
+        };
+        
+        if vector.len() != dimension || dimension == 0 {
+            return Err(ValidationError::DimensionMismatch {
+                expected: self.config.dimension,
+                actual: vector.len(),
+            }
+            .into());
+        }
+        
+        if !self.config.skip_value_validation {
+            validate_vector(vector, dimension)?;
+        }
+        
+        if self.config.dimension == 0 {
+            self.lock_dimension(dimension)?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Applies the eviction policy before `id` is stored, if it is new and the collection is full.
+    fn make_room(&mut self, id: &str) -> Result<(), Box<dyn Error>> {
+        let Some(capacity) = self.config.max_elements else {
+            return Ok(());
+        };
+        if self.storage.contains(id)? {
+            return Ok(());
+        }
+        
+        let mut evicted = false;
+        while self.storage.count()? >= capacity {
+            let victim = match self.config.eviction_policy {
+                EvictionPolicy::Reject => None,
+                EvictionPolicy::LruEvict => self.storage.least_recently_accessed()?,
+                EvictionPolicy::OldestEvict => self.storage.oldest_written()?,
+            };
+            let Some(victim) = victim else {
+                return Err(SolarisError::CollectionFull {
+                    collection: self.config.name.clone(),
+                    capacity,
+                }
+                .into());
+            };
+            
+            log::debug!("Collection '{}' evicting '{}' to make room for '{}'", self.config.name, victim, id);
+            self.remove_document(&victim)?;
+            evicted = true;
+        }
+        
+        if evicted {
+            self.schedule_compaction();
+        }
+        Ok(())
+    }
+    
+    fn distance(&self, a: &Vector, b: &Vector) -> f32 {
+        calculate_distance_with(a, b, self.config.metric, self.config.deterministic_distance)
+    }
+    
+    fn clamp_vector(&self, vector: &mut Vector) {
+        if let Some((min, max)) = self.config.clamp_range {
+            for value in vector.iter_mut() {
+                *value = value.clamp(min, max);
+            }
+        }
+    }
+    
+    /// Copies documents and graph into `target` without persisting them; returns the copied ids.
+    fn copy_into(&self, target: &mut Collection) -> Result<Vec<String>, Box<dyn Error>> {
+        if target.storage.count()? > 0 {
+            return Err(format!("Collection '{}' is not empty", target.config.name).into());
+        }
+        
+        let documents = self.storage.get_all_documents()?;
+        let ids: Vec<String> = documents.iter().map(|document| document.id.clone()).collect();
+        target.storage.batch_insert(documents)?;
+        target.index.clone_from(&self.index)?;
+        Ok(ids)
+    }
+    
+    fn lock_dimension(&mut self, dimension: usize) -> Result<(), Box<dyn Error>> {
+        let config = CollectionConfig {
+            dimension,
+            ..self.config.clone()
+        };
+        validate_collection_config(&config)?;
+        
+        log::info!("Collection '{}' inferred dimension {}", self.config.name, dimension);
+        self.config = config;
+        Ok(())
+    }
+    
+    fn check_dense_query(&self) -> Result<(), Box<dyn Error>> {
+        if self.config.vector_type != VectorType::Dense {
+            return Err(format!("Collection '{}' does not accept dense queries", self.config.name).into());
+        }
+        
+        if self.config.dimension == 0 {
+            return Err(format!(
+                "Collection '{}' has no dimension yet; it is inferred from the first inserted vector",
+                self.config.name
+            )
+            .into());
+        }
+        
+        Ok(())
+    }
+    
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(
+                collection = %self.config.name,
+                documents = request.vectors.len(),
+                results = tracing::field::Empty,
+                duration_ms = tracing::field::Empty,
+            )
+        )
+    )]
+    pub fn batch_upsert(
+        &mut self,
+        request: BatchInsertRequest,
+        policy: ConflictPolicy,
+    ) -> Result<BatchUpsertResponse, Box<dyn Error>> {
+        validate_batch_size(request.vectors.len(), self.config.max_batch_size)?;
+        
+        let start = Instant::now();
+        let mut response = BatchUpsertResponse {
+            outcomes: Vec::with_capacity(request.vectors.len()),
+            inserted: 0,
+            updated: 0,
+            skipped: 0,
+            failed: 0,
+            duration_ms: 0,
+        };
+        
+        for document in request.vectors {
+            let id = document.id.clone();
+            let outcome = self.upsert_document(document, policy)?;
+            
+            match &outcome {
+                UpsertOutcome::Inserted => response.inserted += 1,
+                UpsertOutcome::Updated => response.updated += 1,
+                UpsertOutcome::Skipped => response.skipped += 1,
+                UpsertOutcome::Failed(_) => response.failed += 1,
+            }
+            response.outcomes.push((id, outcome));
+        }
+        
+        response.duration_ms = start.elapsed().as_millis() as u64;
+        #[cfg(feature = "tracing")]
+        record_span(response.inserted + response.updated, start);
+        Ok(response)
+    }
+    
+    pub fn validate_batch(&self, request: &BatchInsertRequest) -> Result<BatchValidationReport, Box<dyn Error>> {
+        validate_batch_size(request.vectors.len(), self.config.max_batch_size)?;
+        
+        let invalid: Vec<(String, String)> = request
+            .vectors
+            .iter()
+            .filter_map(|document| {
+                validate_vector_document(document, self.config.dimension)
+                    .err()
+                    .map(|e| (document.id.clone(), e.to_string()))
+            })
+            .collect();
+        
+        Ok(BatchValidationReport {
+            total: request.vectors.len(),
+            valid: request.vectors.len() - invalid.len(),
+            invalid,
+        })
+    }
+    
+    fn upsert_document(
+        &mut self,
+        document: VectorDocument,
+        policy: ConflictPolicy,
+    ) -> Result<UpsertOutcome, Box<dyn Error>> {
+        if let Err(e) = validate_document_fields(&document) {
+            return Ok(UpsertOutcome::Failed(e.to_string()));
+        }
+        
+        let exists = self.storage.contains(&document.id)?;
+        if exists {
+            match policy {
+                ConflictPolicy::Skip => return Ok(UpsertOutcome::Skipped),
+                ConflictPolicy::Error => {
+                    return Ok(UpsertOutcome::Failed(SolarisError::DuplicateId(document.id).to_string()));
+                }
+                ConflictPolicy::Overwrite => {}
+            }
+        }
+        
+        let result = match (document.sparse_vector, document.int_vector) {
+            (Some(sparse_vector), _) => self.insert_sparse_vector(document.id, sparse_vector, document.metadata),
+            (None, Some(int_vector)) => self.insert_int_vector(document.id, int_vector, document.metadata),
+            (None, None) => self.insert_vector(document.id, document.vector, document.metadata),
+        };
+        
+        Ok(match result {
+            Ok(()) if exists => UpsertOutcome::Updated,
+            Ok(()) => UpsertOutcome::Inserted,
+            Err(e) => UpsertOutcome::Failed(e.to_string()),
+        })
+    }
+    
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(
+                collection = %self.config.name,
+                limit = limit,
+                results = tracing::field::Empty,
+                duration_ms = tracing::field::Empty,
+            )
+        )
+    )]
+    pub fn search_vectors(
+        &self,
+        query_vector: Vector,
+        limit: usize,
+    ) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        #[cfg(feature = "tracing")]
+        let start = Instant::now();
+
+        self.check_dense_query()?;
+        
+        let mut query_vector = query_vector;
+        if let Some(transform) = &self.query_transform {
+            transform(&mut query_vector);
+        }
+        validate_search_params(&query_vector, self.config.dimension, limit, None, self.config.max_search_limit)?;
+        
+        let filters = self.active_filters(None, false);
+        let nearest_ids = self.search_live(limit, &filters, None, None, None, |k| self.index.search(query_vector.clone(), k))?;
+        let results = self.attach_metadata(nearest_ids)?;
+        
+        #[cfg(feature = "tracing")]
+        record_span(results.len(), start);
+        Ok(results)
+    }
+    
+    /// `search_vectors` with the ids parsed back, for collections with `IdType::U64` ids.
+    pub fn search_vectors_u64(
+        &self,
+        query_vector: Vector,
+        limit: usize,
+    ) -> Result<Vec<NumericSearchResult>, Box<dyn Error>> {
+        if self.config.id_type != IdType::U64 {
+            return Err(format!("Collection '{}' does not use u64 ids", self.config.name).into());
+        }
+        
+        self.search_vectors(query_vector, limit)?
+            .into_iter()
+            .map(|(id, distance, metadata)| Ok((id.parse()?, distance, metadata)))
+            .collect()
+    }
+    
+    /// Runs each query on its own and fuses the `limit`-sized result lists into one ranking.
+    /// The distance-based methods rescore every candidate against every query.
+    pub fn multi_query_search(
+        &self,
+        queries: &[Vector],
+        limit: usize,
+        fusion: FusionMethod,
+    ) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        if queries.is_empty() {
+            return Err("multi_query_search requires at least one query".into());
+        }
+        self.check_dense_query()?;
+        
+        let queries: Vec<Cow<Vector>> = queries.iter().map(|query| self.prepare_query(query)).collect();
+        for query in &queries {
+            validate_search_params(query, self.config.dimension, limit, None, self.config.max_search_limit)?;
+        }
+        
+        let filters = self.active_filters(None, false);
+        let mut lists = Vec::with_capacity(queries.len());
+        for query in &queries {
+            lists.push(self.search_live(limit, &filters, None, None, None, |k| self.index.search(query.to_vec(), k))?);
+        }
+        
+        let mut fused: Vec<(String, f32)> = match fusion {
+            FusionMethod::Rrf => {
+                let mut scores: HashMap<String, f32> = HashMap::new();
+                for list in lists {
+                    for (rank, (id, _)) in list.into_iter().enumerate() {
+                        *scores.entry(id).or_default() += 1.0 / (RRF_K + rank as f32 + 1.0);
+                    }
+                }
+                let mut fused: Vec<(String, f32)> = scores.into_iter().collect();
+                fused.sort_by(|a, b| compare_distances(b.1, a.1).then_with(|| a.0.cmp(&b.0)));
+                fused
+            }
+            FusionMethod::MinDistance | FusionMethod::MeanDistance => {
+                let candidates: HashSet<String> = lists.into_iter().flatten().map(|(id, _)| id).collect();
+                let mut fused: Vec<(String, f32)> = candidates
+                    .into_iter()
+                    .filter_map(|id| {
+                        let vector = self.index.get_vector(&id)?;
+                        let distances = queries
+                            .iter()
+                            .map(|query| self.distance(query, vector));
+                        let score = match fusion {
+                            FusionMethod::MinDistance => distances.fold(f32::INFINITY, f32::min),
+                            _ => distances.sum::<f32>() / queries.len() as f32,
+                        };
+                        Some((id, score))
+                    })
+                    .collect();
+                fused.sort_by(|a, b| compare_distances(a.1, b.1).then_with(|| a.0.cmp(&b.0)));
+                fused
+            }
+        };
+        fused.truncate(limit);
+        
+        self.attach_metadata(fused)
+    }
+    
+    pub fn search_simple(&self, query: &[f32], k: usize) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        self.search(&SearchQuery {
+            vector: query.to_vec(),
+            limit: k,
+            ..Default::default()
+        })
+    }
+    
+    pub fn search_detailed(&self, query: &SearchQuery) -> Result<Vec<SearchHit>, Box<dyn Error>> {
+        Ok(self
+            .search(query)?
+            .into_iter()
+            .map(|(id, distance, metadata)| SearchHit {
+                id,
+                distance,
+                similarity: distance_to_similarity(distance, self.config.metric),
+                metadata,
+            })
+            .collect())
+    }
+    
+    pub fn kth_distance(&self, query_vector: &Vector, k: usize) -> Result<Option<f32>, Box<dyn Error>> {
+        if k == 0 {
+            return Ok(None);
+        }
+        
+        self.check_dense_query()?;
+        
+        let query_vector = self.prepare_query(query_vector);
+        validate_search_params(&query_vector, self.config.dimension, k, None, self.config.max_search_limit)?;
+        
+        let filters = self.active_filters(None, false);
+        let nearest_ids = self.search_live(k, &filters, None, None, None, |n| self.index.search(query_vector.to_vec(), n))?;
+        Ok(nearest_ids.get(k - 1).map(|(_, distance)| *distance))
+    }
+    
+    /// Like `search`, but pairs each result with the conditions of `query.filter` it
+    /// satisfied (empty when the query has no filter).
+    pub fn search_with_match_info(
+        &self,
+        query: &SearchQuery,
+    ) -> Result<Vec<MatchedResult>, Box<dyn Error>> {
+        let results = self.search(query)?;
+        let Some(filter) = &query.filter else {
+            return Ok(results.into_iter().map(|result| (result, Vec::new())).collect());
+        };
+        
+        results
+            .into_iter()
+            .map(|result| {
+                let matched = self
+                    .storage
+                    .with_metadata(&result.0, |doc| {
+                        matching_conditions(doc, filter).into_iter().cloned().collect()
+                    })?
+                    .unwrap_or_default();
+                Ok((result, matched))
+            })
+            .collect()
+    }
+    
+    /// Like `search_vectors`, but only keeps candidates the predicate accepts, fetching
+    /// deeper into the index until `limit` pass or it runs out.
+    pub fn search_with_predicate<F>(
+        &self,
+        query_vector: &Vector,
+        limit: usize,
+        predicate: F,
+    ) -> Result<Vec<SearchResult>, Box<dyn Error>>
+    where
+        F: Fn(&VectorDocument) -> bool,
+    {
+        self.check_dense_query()?;
+        
+        let query_vector = self.prepare_query(query_vector);
+        validate_search_params(&query_vector, self.config.dimension, limit, None, self.config.max_search_limit)?;
+        
+        let filters = self.active_filters(None, false);
+        let nearest_ids = self.search_live(limit, &filters, None, Some(&predicate), None, |k| {
+            self.index.search(query_vector.to_vec(), k)
+        })?;
+        self.attach_metadata(nearest_ids)
+    }
+    
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(
+                collection = %self.config.name,
+                limit = query.limit,
+                results = tracing::field::Empty,
+                duration_ms = tracing::field::Empty,
+            )
+        )
+    )]
+    pub fn search(&self, query: &SearchQuery) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        #[cfg(feature = "tracing")]
+        let start = Instant::now();
+        
+        let mut results = self.search_ranked(query, query.limit, None)?;
+        if query.sort_order == SortOrder::WorstFirst {
+            results.reverse();
+        }
+        
+        #[cfg(feature = "tracing")]
+        record_span(results.len(), start);
+        Ok(results)
+    }
+    
+    pub fn search_with_response(&self, query: &SearchQuery) -> Result<SearchResponse, Box<dyn Error>> {
+        let mut results = self.search_ranked(query, query.limit.saturating_add(1), None)?;
+        let truncated = results.len() > query.limit;
+        results.truncate(query.limit);
+        
+        let confidence = if query.estimate_confidence {
+            Some(self.estimate_confidence(query, &results)?)
+        } else {
+            None
+        };
+        
+        if query.sort_order == SortOrder::WorstFirst {
+            results.reverse();
+        }
+        
+        Ok(SearchResponse {
+            results,
+            truncated,
+            confidence,
+        })
+    }
+    
+    fn estimate_confidence(&self, query: &SearchQuery, results: &[SearchResult]) -> Result<f32, Box<dyn Error>> {
+        let worst_distance = match results.last() {
+            Some((_, distance, _)) => *distance,
+            None => return Ok(1.0),
+        };
+        
+        let query_vector = self.prepare_query(&query.vector);
+        // Score the sample the way the results were ranked, so the estimate matches them.
+        let weights = rerank_weights(query.dimension_weights.as_deref(), query.dimension_range, self.config.dimension);
+        let filters = self.active_filters(query.filter.as_ref(), query.ignore_default_filter);
+        let returned: HashSet<&str> = results.iter().map(|(id, _, _)| id.as_str()).collect();
+        let cutoff = self.expiry_cutoff()?;
+        let step = self.storage.count()?.div_ceil(CONFIDENCE_SAMPLE_SIZE).max(1);
+        
+        let mut position = 0;
+        let mut missed = 0;
+        self.storage.for_each_document(|document| {
+            position += 1;
+            if (position - 1) % step != 0
+                || document.is_pending()
+                || returned.contains(document.id.as_str())
+                || cutoff.is_some_and(|cutoff| document.timestamp < cutoff)
+                || !filters.iter().all(|filter| evaluate_filter(document, filter))
+            {
+                return;
+            }
+            
+            let distance = match &weights {
+                Some(weights) => weighted_distance(&query_vector, &document.vector, weights, self.config.metric),
+                None => self.distance(&query_vector, &document.vector),
+            };
+            if distance < worst_distance {
+                missed += 1;
+            }
+        })?;
+        
+        let estimated_missed = (missed * step) as f32;
+        Ok(results.len() as f32 / (results.len() as f32 + estimated_missed))
+    }
+    
+    pub fn search_cancellable(
+        &self,
+        query: &SearchQuery,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        let mut results = self.search_ranked(query, query.limit, Some(cancel))?;
+        if query.sort_order == SortOrder::WorstFirst {
+            results.reverse();
+        }
+        
+        Ok(results)
+    }
+    
+    fn search_ranked(
+        &self,
+        query: &SearchQuery,
+        fetch: usize,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        self.check_dense_query()?;
+        
+        let query_vector = self.prepare_query(&query.vector);
+        validate_search_params(
+            &query_vector,
+            self.config.dimension,
+            query.limit,
+            query.ef,
+            self.config.max_search_limit,
+        )?;
+        if let Some(weights) = &query.dimension_weights {
+            validate_dimension_weights(weights, self.config.dimension)?;
+        }
+        if let Some(range) = query.dimension_range {
+            validate_dimension_range(range, self.config.dimension)?;
+        }
+        let weights = rerank_weights(query.dimension_weights.as_deref(), query.dimension_range, self.config.dimension);
+        
+        let dedup = query.dedup_by.as_deref().map(|key| (key, query.dedup_keep_missing));
+        let filters = self.active_filters(query.filter.as_ref(), query.ignore_default_filter);
+        let nearest_ids = self.search_live(fetch, &filters, dedup, None, cancel, |k| {
+            let ef = query.ef.map(|ef| ef.max(k));
+            let Some(weights) = &weights else {
+                return self.index.search_with_params(query_vector.to_vec(), k, ef, query.upper_layer_ef);
+            };
+            
+            // Rerank a wider pool so weighting can promote candidates outside the unweighted top k.
+            let pool = ef.unwrap_or(WEIGHTED_RERANK_POOL).max(k);
+            let candidates = self.index.search_with_params(query_vector.to_vec(), pool, ef, query.upper_layer_ef)?;
+            Ok(self.rerank_weighted(&query_vector, weights, candidates, k))
+        })?;
+        
+        let mut results = self.attach_metadata(nearest_ids)?;
+        if let Some(fields) = &query.metadata_fields {
+            for (_, _, metadata) in results.iter_mut() {
+                *metadata = project_metadata(metadata.take(), fields);
+            }
+        }
+        
+        Ok(results)
+    }
+    
+    pub fn insert_sparse_vector(
+        &mut self,
+        id: String,
+        vector: SparseVector,
+        metadata: Option<VectorMetadata>,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.config.vector_type != VectorType::Sparse {
+            return Err(self.unsupported_vector_type("sparse").into());
+        }
+        
+        validate_sparse_vector(&vector, self.config.dimension)?;
+        parse_id(&id, self.config.id_type)?;
+        self.make_room(&id)?;
+        
+        if let Some(previous) = self.storage.get(&id)?.and_then(|doc| doc.sparse_vector) {
+            self.index.remove_sparse_vector(&id, &previous);
+        }
+        
+        self.storage.store_sparse(id.clone(), vector.clone(), metadata)?;
+        self.index.add_sparse_vector(id.clone(), &vector)?;
+        
+        self.persist(&id)?;
+        self.hooks.fire_insert(&id);
+        Ok(())
+    }
+    
+    pub fn search_sparse_vectors(
+        &self,
+        query_vector: &SparseVector,
+        limit: usize,
+    ) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        if self.config.vector_type != VectorType::Sparse {
+            return Err(format!("Collection '{}' does not accept sparse queries", self.config.name).into());
+        }
+        
+        validate_sparse_vector(query_vector, self.config.dimension)?;
+        
+        let filters = self.active_filters(None, false);
+        let nearest_ids = self.search_live(limit, &filters, None, None, None, |k| self.index.search_sparse(query_vector, k))?;
+        self.attach_metadata(nearest_ids)
+    }
+    
+    pub fn insert_int_vector(
+        &mut self,
+        id: String,
+        vector: IntVector,
+        metadata: Option<VectorMetadata>,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.config.vector_type != VectorType::Integer {
+            return Err(self.unsupported_vector_type("integer").into());
+        }
+        
+        validate_int_vector(&vector, self.config.dimension)?;
+        parse_id(&id, self.config.id_type)?;
+        self.make_room(&id)?;
+        
+        self.storage.store_integer(id.clone(), vector, metadata)?;
+        self.persist(&id)?;
+        self.hooks.fire_insert(&id);
+        Ok(())
+    }
+    
+    pub fn search_int_vectors(
+        &self,
+        query_vector: &IntVector,
+        limit: usize,
+    ) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        if self.config.vector_type != VectorType::Integer {
+            return Err(format!("Collection '{}' does not accept integer queries", self.config.name).into());
+        }
+        
+        validate_int_vector(query_vector, self.config.dimension)?;
+        
+        let cutoff = self.expiry_cutoff()?;
+        let filters = self.active_filters(None, false);
+        let mut scored = Vec::new();
+        self.storage.for_each_metadata(|document| {
+            if cutoff.is_some_and(|cutoff| document.timestamp < cutoff)
+                || !filters.iter().all(|filter| evaluate_filter(document, filter))
+            {
+                return;
+            }
+            if let Some(vector) = &document.int_vector {
+                let distance = calculate_int_distance(query_vector, vector, self.config.metric);
+                scored.push((document.id.clone(), distance));
+            }
+        })?;
+        
+        check_nan_distances(scored.iter().map(|(id, distance)| (id.as_str(), *distance)), self.nan_handling)?;
+        scored.sort_by(|a, b| compare_distances(a.1, b.1).then_with(|| a.0.cmp(&b.0)));
+        scored.truncate(limit);
+        
+        self.attach_metadata(scored)
+    }
+    
+    pub fn search_within(
+        &self,
+        query_vector: &Vector,
+        ids: &[String],
+        limit: usize,
+        skip_missing: bool,
+    ) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        self.check_dense_query()?;
+        
+        let query_vector = self.prepare_query(query_vector);
+        validate_vector(&query_vector, self.config.dimension)?;
+        
+        let cutoff = self.expiry_cutoff()?;
+        let filters = self.active_filters(None, false);
+        let mut scored = Vec::with_capacity(ids.len());
+        
+        for id in ids {
+            let document = match self.storage.get(id)? {
+                Some(document) => document,
+                None if skip_missing => continue,
+                None => return Err(format!("Vector '{}' not found", id).into()),
+            };
+            
+            if document.is_pending()
+                || cutoff.is_some_and(|cutoff| document.timestamp < cutoff)
+                || !filters.iter().all(|filter| evaluate_filter(&document, filter))
+            {
+                continue;
+            }
+            
+            let distance = self.distance(&query_vector, &document.vector);
+            scored.push((document.id, distance, document.metadata));
+        }
+        
+        check_nan_distances(scored.iter().map(|(id, distance, _)| (id.as_str(), *distance)), self.nan_handling)?;
+        scored.sort_by(|a, b| compare_distances(a.1, b.1).then_with(|| a.0.cmp(&b.0)));
+        scored.truncate(limit);
+        
+        Ok(scored)
+    }
+    
+    /// Stored documents plus the index's full-precision copy of the dense vectors.
+    pub fn size_bytes(&self) -> Result<usize, Box<dyn Error>> {
+        Ok(self.storage.size_bytes()? + self.index.vector_bytes())
+    }
+    
+    pub fn info(&self) -> Result<CollectionInfo, Box<dyn Error>> {
+        Ok(CollectionInfo {
+            name: self.config.name.clone(),
+            dimension: self.config.dimension,
+            metric: self.config.metric,
+            vector_count: self.storage.count()?,
+            size_bytes: self.size_bytes()?,
+            operation_count: self.operation_count.load(AtomicOrdering::Relaxed),
+            last_modified: self.last_modified.load(AtomicOrdering::Relaxed),
+        })
+    }
+    
+    pub fn filter_stats(&self) -> Result<FilterStats, Box<dyn Error>> {
+        let stats = self.filter_stats.lock().map_err(|_| "Failed to acquire filter stats lock")?;
+        Ok(stats.clone())
+    }
+    
+    pub fn recall_stats(&self) -> Result<RecallStats, Box<dyn Error>> {
+        let stats = self.recall_stats.lock().map_err(|_| "Failed to acquire recall stats lock")?;
+        Ok(stats.clone())
+    }
+    
+    pub(crate) fn record_recall(&self, recall: f32, alerted: bool) -> Result<(), Box<dyn Error>> {
+        let mut stats = self.recall_stats.lock().map_err(|_| "Failed to acquire recall stats lock")?;
+        stats.measurements += 1;
+        stats.last_recall = Some(recall);
+        if alerted {
+            stats.alerts += 1;
+        }
+        Ok(())
+    }
+    
+    /// Fraction of the exact `k` nearest dense vectors to `query` that the index returns,
+    /// or `None` when there is nothing to compare against. Scans every stored document.
+    pub fn query_recall(&self, query: &Vector, k: usize) -> Result<Option<f32>, Box<dyn Error>> {
+        if k == 0 {
+            return Ok(None);
+        }
+        
+        let mut nearest: Vec<(f32, String)> = Vec::with_capacity(k + 1);
+        self.storage.for_each_document(|document| {
+            if document.sparse_vector.is_some() || document.int_vector.is_some() || document.vector.is_empty() {
+                return;
+            }
+            let distance = self.distance(query, &document.vector);
+            if nearest.len() < k || compare_distances(distance, nearest[k - 1].0) == Ordering::Less {
+                let position = nearest.partition_point(|(d, _)| compare_distances(*d, distance) != Ordering::Greater);
+                nearest.insert(position, (distance, document.id.clone()));
+                nearest.truncate(k);
+            }
+        })?;
+        if nearest.is_empty() {
+            return Ok(None);
+        }
+        
+        let truth: HashSet<&str> = nearest.iter().map(|(_, id)| id.as_str()).collect();
+        let found = self
+            .index
+            .search(query.clone(), nearest.len())?
+            .iter()
+            .filter(|(id, _)| truth.contains(id.as_str()))
+            .count();
+        Ok(Some(found as f32 / nearest.len() as f32))
+    }
+    
+    pub fn contains(&self, id: &str) -> Result<bool, Box<dyn Error>> {
+        match self.expiry_cutoff()? {
+            Some(cutoff) => Ok(self.storage.with_metadata(id, |doc| doc.timestamp >= cutoff)? == Some(true)),
+            None => self.storage.contains(id),
+        }
+    }
+    
+    pub fn epsilon(&self) -> f32 {
+        self.config.epsilon.unwrap_or_else(|| default_epsilon(self.config.metric))
+    }
+    
+    pub fn distance_between(&self, a: &str, b: &str) -> Result<Option<f32>, Box<dyn Error>> {
+        let (Some(a), Some(b)) = (self.storage.get(a)?, self.storage.get(b)?) else {
+            return Ok(None);
+        };
+        
+        match (&a.int_vector, &b.int_vector, &a.sparse_vector) {
+            (_, _, Some(_)) => Err(format!("Collection '{}' does not support sparse distances", self.config.name).into()),
+            (Some(x), Some(y), None) => Ok(Some(calculate_int_distance(x, y, self.config.metric))),
+            _ if a.is_pending() || b.is_pending() => Ok(None),
+            _ => Ok(Some(self.distance(&a.vector, &b.vector))),
+        }
+    }
+    
+    /// True when both documents exist and their vectors are within the collection's epsilon.
+    pub fn is_duplicate(&self, a: &str, b: &str) -> Result<bool, Box<dyn Error>> {
+        let (Some(a), Some(b)) = (self.storage.get(a)?, self.storage.get(b)?) else {
+            return Ok(false);
+        };
+        
+        let epsilon = self.epsilon();
+        Ok(match (&a.int_vector, &b.int_vector) {
+            (Some(x), Some(y)) => within_epsilon_int(x, y, self.config.metric, epsilon),
+            _ if a.is_pending() || b.is_pending() || a.sparse_vector.is_some() => false,
+            _ => within_epsilon(&a.vector, &b.vector, self.config.metric, epsilon),
+        })
+    }
+    
+    /// Pairwise distances with a zero diagonal; sets larger than `FULL_DISTANCE_MATRIX_LIMIT`
+    /// come back in upper-triangular form.
+    pub fn distance_matrix(&self, ids: &[String]) -> Result<DistanceMatrix, Box<dyn Error>> {
+        let mut documents = Vec::with_capacity(ids.len());
+        let mut missing = Vec::new();
+        for id in ids {
+            match self.storage.get(id)? {
+                Some(document) if !document.is_pending() && document.sparse_vector.is_none() => {
+                    documents.push(document)
+                }
+                _ => missing.push(id.as_str()),
+            }
+        }
+        
+        if !missing.is_empty() {
+            return Err(format!("Vectors not found: {}", missing.join(", ")).into());
+        }
+        
+        let metric = self.config.metric;
+        let deterministic = self.config.deterministic_distance;
+        let distance = |a: &VectorDocument, b: &VectorDocument| match (&a.int_vector, &b.int_vector) {
+            (Some(x), Some(y)) => calculate_int_distance(x, y, metric),
+            _ => calculate_distance_with(&a.vector, &b.vector, metric, deterministic),
+        };
+        
+        let size = documents.len();
+        #[cfg(feature = "std")]
+        let indices = (0..size).into_par_iter();
+        #[cfg(not(feature = "std"))]
+        let indices = 0..size;
+        let rows: Vec<Vec<f32>> = indices
+            .map(|i| ((i + 1)..size).map(|j| distance(&documents[i], &documents[j])).collect())
+            .collect();
+        
+        if size > FULL_DISTANCE_MATRIX_LIMIT {
+            return Ok(DistanceMatrix::UpperTriangular {
+                size,
+                values: rows.into_iter().flatten().collect(),
+            });
+        }
+        
+        let mut matrix = vec![vec![0.0; size]; size];
+        for (i, row) in rows.into_iter().enumerate() {
+            for (offset, value) in row.into_iter().enumerate() {
+                let j = i + 1 + offset;
+                matrix[i][j] = value;
+                matrix[j][i] = value;
+            }
+        }
+        
+        Ok(DistanceMatrix::Full(matrix))
+    }
+    
+    pub fn for_each_document<F>(&self, f: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&VectorDocument),
+    {
+        self.storage.for_each_document(f)
+    }
+    
+    /// The `n` most recently written live documents, newest first. Ordered by storage write
+    /// order rather than the one-second `timestamp`, so inserts within a second keep their order.
+    pub fn recent(&self, n: usize) -> Result<Vec<VectorDocument>, Box<dyn Error>> {
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        
+        let cutoff = self.expiry_cutoff()?;
+        let mut stamped = Vec::new();
+        self.storage.for_each_written(|written, document| {
+            if cutoff.is_none_or(|cutoff| document.timestamp >= cutoff) {
+                stamped.push((written, document.id.clone()));
+            }
+        })?;
+        
+        let newest_first = |a: &(u64, String), b: &(u64, String)| b.0.cmp(&a.0);
+        if n < stamped.len() {
+            stamped.select_nth_unstable_by(n - 1, newest_first);
+            stamped.truncate(n);
+        }
+        stamped.sort_unstable_by(newest_first);
+        
+        let ids: Vec<String> = stamped.into_iter().map(|(_, id)| id).collect();
+        self.storage.get_many(&ids)
+    }
+    
+    /// Reservoir sample of up to `n` live documents in a single pass, skipping pending and
+    /// expired ones as search does. A seed makes the sample reproducible for a given
+    /// collection state within one process (storage iteration order is not stable across
+    /// restarts).
+    pub fn sample(&self, n: usize, seed: Option<u64>) -> Result<Vec<VectorDocument>, Box<dyn Error>> {
+        let mut rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        
+        let cutoff = self.expiry_cutoff()?;
+        let mut reservoir: Vec<VectorDocument> = Vec::with_capacity(n.min(self.storage.count()?));
+        let mut seen = 0usize;
+        self.storage.for_each_document(|document| {
+            if document.is_pending() || cutoff.is_some_and(|cutoff| document.timestamp < cutoff) {
+                return;
+            }
+            if reservoir.len() < n {
+                reservoir.push(document.clone());
+            } else {
+                let slot = rng.gen_range(0..=seen);
+                if slot < n {
+                    reservoir[slot] = document.clone();
+                }
+            }
+            seen += 1;
+        })?;
+        
+        Ok(reservoir)
+    }
+    
+    /// The dense vectors of `ids` (every document, sorted by id, when `None`) as one row-major
+    /// matrix. Unknown, pending and expired ids are left out, so rows follow the returned ids.
+    /// Does not count as an access for LRU eviction.
+    pub fn to_matrix(&self, ids: Option<&[String]>) -> Result<VectorMatrix, Box<dyn Error>> {
+        if self.config.vector_type != VectorType::Dense {
+            return Err(self.unsupported_vector_type("dense").into());
+        }
+        
+        let wanted: Option<HashSet<&str>> = ids.map(|ids| ids.iter().map(String::as_str).collect());
+        let cutoff = self.expiry_cutoff()?;
+        let mut vectors: HashMap<String, Vector> = HashMap::new();
+        self.storage.for_each_document(|document| {
+            if document.vector.is_empty()
+                || cutoff.is_some_and(|cutoff| document.timestamp < cutoff)
+                || wanted.as_ref().is_some_and(|wanted| !wanted.contains(document.id.as_str()))
+            {
+                return;
+            }
+            // Compressed storage only holds an approximation; the index has the exact vector.
+            let vector = match self.index.get_vector(&document.id) {
+                Some(vector) if self.config.in_memory_compression => vector.clone(),
+                _ => document.vector.clone(),
+            };
+            vectors.insert(document.id.clone(), vector);
+        })?;
+        
+        let order: Vec<String> = match ids {
+            Some(ids) => ids.to_vec(),
+            None => {
+                let mut ids: Vec<String> = vectors.keys().cloned().collect();
+                ids.sort();
+                ids
+            }
+        };
+        
+        let dimension = self.config.dimension;
+        let mut rows = Vec::with_capacity(vectors.len());
+        let mut data = Vec::with_capacity(vectors.len() * dimension);
+        for id in order {
+            if let Some(vector) = vectors.remove(&id) {
+                data.extend_from_slice(&vector);
+                rows.push(id);
+            }
+        }
+        Ok((rows, data, dimension))
+    }
+    
+    pub fn document_ids(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        self.storage.list_ids()
+    }
+    
+    pub fn get_documents(&self, ids: &[String]) -> Result<Vec<VectorDocument>, Box<dyn Error>> {
+        self.storage.get_many(ids)
+    }
+    
+    /// Metadata (`None` when the document has none, as opposed to empty) and timestamp.
+    pub fn get_document_meta(&self, id: &str) -> Result<Option<DocumentMeta>, Box<dyn Error>> {
+        let cutoff = self.expiry_cutoff()?;
+        let meta = self.storage.with_metadata(id, |doc| {
+            if cutoff.is_some_and(|cutoff| doc.timestamp < cutoff) {
+                return None;
+            }
+            Some((doc.metadata.clone(), doc.timestamp))
+        })?;
+        Ok(meta.flatten())
+    }
+    
+    pub fn remove_vector(&mut self, id: &str) -> Result<bool, Box<dyn Error>> {
+        let removed = self.remove_document(id)?;
+        if removed {
+            self.schedule_compaction();
+        }
+        Ok(removed)
+    }
+    
+    pub fn config(&self) -> &CollectionConfig {
+        &self.config
+    }
+    
+    /// Deletes the persisted files; the collection carries on in memory only.
+    #[cfg(feature = "persistence")]
+    fn delete_storage(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.persistent.take() {
+            Some(persistent) => persistent.destroy(),
+            None => Ok(()),
+        }
+    }
+    
+    fn rename(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        let config = CollectionConfig {
+            name: name.to_string(),
+            ..self.config.clone()
+        };
+        validate_collection_config(&config)?;
+        
+        #[cfg(feature = "persistence")]
+        if let Some(persistent) = &mut self.persistent {
+            persistent.rename(name)?;
+        }
+        self.config = config;
+        Ok(())
+    }
+    
+    /// Applies to future inserts only; existing nodes keep their connections until `reindex`.
+    pub fn set_m(&mut self, m: usize) -> Result<(), Box<dyn Error>> {
+        self.set_construction_params(m, self.config.ef_construction)
+    }
+    
+    /// Applies to future inserts only; existing nodes keep their connections until `reindex`.
+    pub fn set_ef_construction(&mut self, ef_construction: usize) -> Result<(), Box<dyn Error>> {
+        self.set_construction_params(self.config.m, ef_construction)
+    }
+    
+    fn set_construction_params(&mut self, m: usize, ef_construction: usize) -> Result<(), Box<dyn Error>> {
+        let config = CollectionConfig {
+            m,
+            ef_construction,
+            ..self.config.clone()
+        };
+        validate_collection_config(&config)?;
+        
+        self.index.set_construction_params(m, ef_construction);
+        self.config = config;
+        Ok(())
+    }
+    
+    pub fn prune(&mut self, max_degree: usize) -> Result<usize, Box<dyn Error>> {
+        self.index.prune_connections(max_degree)
+    }
+    
+    pub fn connectivity_report(&self) -> ConnectivityReport {
+        self.index.connectivity_report()
+    }
+    
+    pub fn reindex(&mut self, m: usize, ef_construction: usize) -> Result<(), Box<dyn Error>> {
+        let config = CollectionConfig {
+            m,
+            ef_construction,
+            ..self.config.clone()
+        };
+        validate_collection_config(&config)?;
+        
+        self.index.reconfigure(config.clone())?;
+        self.config = config;
+        Ok(())
+    }
+    
+    pub fn optimize_cancellable(&mut self, cancel: &CancellationToken) -> Result<(), Box<dyn Error>> {
+        self.index.optimize_cancellable(cancel)
+    }
+    
+    pub fn needs_compaction(&self) -> bool {
+        self.index.deleted_ratio() > self.config.compaction_threshold
+    }
+    
+    /// Rebuilds the graph right away, under the caller's write lock, if it needs compaction.
+    pub fn maybe_compact(&mut self) -> Result<bool, Box<dyn Error>> {
+        if !self.needs_compaction() {
+            return Ok(false);
+        }
+        
+        self.index.optimize()?;
+        Ok(true)
+    }
+    
+    /// Compacts without blocking searches or writes while the new graph is built: only
+    /// collecting the live vectors and the final swap take a lock.
+    pub fn compact(collection: &RwLock<Collection>) -> Result<bool, Box<dyn Error>> {
+        let (mut graph, live, operations) = {
+            let collection = collection.read().map_err(|_| "Failed to acquire read lock")?;
+            if !collection.needs_compaction() {
+                return Ok(false);
+            }
+            let (graph, live) = collection.index.compaction_seed();
+            (graph, live, collection.operation_count.load(AtomicOrdering::Relaxed))
+        };
+        graph.par_build(live)?;
+        
+        let mut collection = collection.write().map_err(|_| "Failed to acquire write lock")?;
+        let changed = collection.operation_count.load(AtomicOrdering::Relaxed) != operations;
+        collection.index.install_compacted(graph, changed)
+    }
+    
+    /// Starts a background `compact` once deletions pass `compaction_threshold`, unless one
+    /// is already running. Collections not owned by a database only compact on request.
+    fn schedule_compaction(&self) {
+        if !self.needs_compaction()
+            || self.handle.strong_count() == 0
+            || self.compacting.swap(true, AtomicOrdering::AcqRel)
+        {
+            return;
+        }
+        
+        let handle = self.handle.clone();
+        let compacting = self.compacting.clone();
+        thread::spawn(move || {
+            if let Some(collection) = handle.upgrade() {
+                if let Err(e) = Collection::compact(&collection) {
+                    log::warn!("Background compaction failed: {}", e);
+                }
+            }
+            compacting.store(false, AtomicOrdering::Release);
+        });
+    }
+    
+    fn remove_document(&mut self, id: &str) -> Result<bool, Box<dyn Error>> {
+        let document = match self.storage.get(id)? {
+            Some(document) => document,
+            None => return Ok(false),
+        };
+        
+        match (&document.sparse_vector, &document.int_vector) {
+            (Some(sparse_vector), _) => {
+                self.index.remove_sparse_vector(id, sparse_vector);
+            }
+            (None, Some(_)) => {}
+            (None, None) if self.config.repair_on_delete => {
+                self.index.remove_vector_with_repair(id)?;
+            }
+            (None, None) => {
+                self.index.mark_deleted(id);
+            }
+        }
+        
+        let removed = self.storage.remove(id)?;
+        if removed {
+            self.persist_delete(id)?;
+            self.hooks.fire_delete(id);
+        }
+        Ok(removed)
+    }
+    
+    pub fn purge_expired(&mut self) -> Result<usize, Box<dyn Error>> {
+        let cutoff = match self.expiry_cutoff()? {
+            Some(cutoff) => cutoff,
+            None => return Ok(0),
+        };
+        
+        let mut purged = 0;
+        for id in self.storage.ids_older_than(cutoff)? {
+            if self.remove_document(&id)? {
+                purged += 1;
+            }
+        }
+        
+        if purged > 0 {
+            self.schedule_compaction();
+        }
+        
+        Ok(purged)
+    }
+    
+    fn expiry_cutoff(&self) -> Result<Option<u64>, Box<dyn Error>> {
+        match self.config.ttl_seconds {
+            Some(ttl) => Ok(Some((self.clock)()?.saturating_sub(ttl))),
+            None => Ok(None),
+        }
+    }
+    
+    fn rerank_weighted(
+        &self,
+        query_vector: &Vector,
+        weights: &[f32],
+        candidates: Vec<(String, f32)>,
+        k: usize,
+    ) -> Vec<(String, f32)> {
+        let mut scored: Vec<(String, f32)> = candidates
+            .into_iter()
+            .filter_map(|(id, _)| {
+                let vector = self.index.get_vector(&id)?;
+                let distance = weighted_distance(query_vector, vector, weights, self.config.metric);
+                Some((id, distance))
+            })
+            .collect();
+        scored.sort_by(|a, b| compare_distances(a.1, b.1).then_with(|| a.0.cmp(&b.0)));
+        scored.truncate(k);
+        scored
+    }
+    
+    fn search_live<F>(
+        &self,
+        limit: usize,
+        filters: &[&MetadataFilter],
+        dedup: Option<(&str, bool)>,
+        predicate: Option<&dyn Fn(&VectorDocument) -> bool>,
+        cancel: Option<&CancellationToken>,
+        search: F,
+    ) -> Result<Vec<(String, f32)>, Box<dyn Error>>
+    where
+        F: Fn(usize) -> Result<Vec<(String, f32)>, Box<dyn Error>>,
+    {
+        if let Some(cancel) = cancel {
+            cancel.check()?;
+        }
+        
+        let cutoff = self.expiry_cutoff()?;
+        if cutoff.is_none() && filters.is_empty() && dedup.is_none() && predicate.is_none() {
+            return search(limit);
+        }
+        
+        let mut fetch = limit;
+        loop {
+            let nearest_ids = search(fetch)?;
+            let exhausted = nearest_ids.len() < fetch;
+            
+            let mut live = Vec::with_capacity(nearest_ids.len());
+            let mut seen = HashSet::new();
+            let mut candidates = 0;
+            let mut passed = 0;
+            for (id, score) in nearest_ids {
+                let accepted = self.storage.with_document(&id, |doc| {
+                    if cutoff.is_some_and(|cutoff| doc.timestamp < cutoff) {
+                        return None;
+                    }
+                    let passes = filters.iter().all(|filter| evaluate_filter(doc, filter))
+                        && predicate.is_none_or(|predicate| predicate(doc));
+                    let dedup_value = dedup.and_then(|(key, _)| {
+                        doc.metadata
+                            .iter()
+                            .flatten()
+                            .find(|(k, _)| k == key)
+                            .map(|(_, v)| v.clone())
+                    });
+                    Some((passes, dedup_value))
+                })?;
+                
+                let Some((passes, dedup_value)) = accepted.flatten() else {
+                    continue;
+                };
+                candidates += 1;
+                if !passes {
+                    continue;
+                }
+                passed += 1;
+                
+                let keep = match (dedup, dedup_value) {
+                    (None, _) => true,
+                    (Some(_), Some(value)) => seen.insert(value),
+                    (Some((_, keep_missing)), None) => keep_missing,
+                };
+                
+                if keep {
+                    live.push((id, score));
+                }
+            }
+            
+            if live.len() >= limit || exhausted {
+                if !filters.is_empty() {
+                    self.record_filter_stats(candidates, passed)?;
+                }
+                live.truncate(limit);
+                return Ok(live);
+            }
+            
+            if let Some(cancel) = cancel {
+                cancel.check()?;
+            }
+            fetch *= 2;
+        }
+    }
+    
+    fn record_filter_stats(&self, candidates: u64, passed: u64) -> Result<(), Box<dyn Error>> {
+        let mut stats = self.filter_stats.lock().map_err(|_| "Failed to acquire filter stats lock")?;
+        stats.filtered_searches += 1;
+        stats.candidates_fetched += candidates;
+        stats.candidates_passed += passed;
+        
+        if candidates > 0 {
+            let pass_rate = passed as f32 / candidates as f32;
+            stats.rolling_pass_rate = if stats.filtered_searches == 1 {
+                pass_rate
+            } else {
+                stats.rolling_pass_rate + FILTER_STATS_SMOOTHING * (pass_rate - stats.rolling_pass_rate)
+            };
+        }
+        
+        Ok(())
+    }
+    
+    fn attach_metadata(&self, nearest_ids: Vec<(String, f32)>) -> Result<Vec<SearchResult>, Box<dyn Error>> {
+        let mut results = Vec::with_capacity(nearest_ids.len());
+        for (id, score) in nearest_ids {
+            let metadata = self.storage.get_metadata(&id)?;
+            results.push((id, score, metadata));
+        }
+        
+        Ok(results)
+    }
+}
+
+// A dimension range reranks as weights of zero outside it, which gives the same distance as
+// slicing both vectors for every metric.
+fn rerank_weights(
+    weights: Option<&[f32]>,
+    range: Option<(usize, usize)>,
+    dimension: usize,
+) -> Option<Cow<'_, [f32]>> {
+    let Some((start, end)) = range else {
+        return weights.map(Cow::Borrowed);
+    };
+    
+    let mut masked = weights.map_or_else(|| vec![1.0; dimension], <[f32]>::to_vec);
+    for (i, weight) in masked.iter_mut().enumerate() {
+        if i < start || i >= end {
+            *weight = 0.0;
+        }
+    }
+    Some(Cow::Owned(masked))
+}
+
+fn project_metadata(metadata: Option<VectorMetadata>, fields: &[String]) -> Option<VectorMetadata> {
+    if fields.is_empty() {
+        return None;
+    }
+    
+    metadata.map(|metadata| {
+        metadata
+            .into_iter()
+            .filter(|(key, _)| fields.contains(key))
+            .collect()
+    })
+}
+
+fn unix_time() -> Result<u64, Box<dyn Error>> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+#[cfg(feature = "tracing")]
+fn record_span(results: usize, start: Instant) {
+    let span = tracing::Span::current();
+    span.record("results", results);
+    span.record("duration_ms", start.elapsed().as_millis() as u64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{CompositeId, ErrorCode, FilterCondition, FilterOperation, FilterOperator};
+    use crate::utils::gen::{clustered_vectors, random_vectors};
+    use crate::utils::validation::{BATCH_SIZE_CEILING, SEARCH_LIMIT_CEILING};
+    use std::time::Duration;
+    
+    fn config(dimension: usize) -> CollectionConfig {
+        CollectionConfig {
+            name: "test".to_string(),
+            dimension,
+            metric: DistanceMetric::Euclidean,
+            // Sequential sums: a parallel reduction per distance dominates on vectors this small.
+            deterministic_distance: true,
+            ..Default::default()
+        }
+    }
+    
+    fn collection(config: CollectionConfig) -> Collection {
+        Collection::new(config, &PerformanceConfig::default())
+    }
+    
+    fn document(id: &str, vector: Vector) -> VectorDocument {
+        VectorDocument {
+            id: id.to_string(),
+            vector,
+            sparse_vector: None,
+            int_vector: None,
+            metadata: None,
+            timestamp: 0,
+        }
+    }
+    
+    fn ids(results: &[SearchResult]) -> Vec<&str> {
+        results.iter().map(|result| result.0.as_str()).collect()
+    }
+    
+    fn equals(key: &str, value: &str) -> MetadataFilter {
+        MetadataFilter {
+            conditions: vec![FilterCondition {
+                key: key.to_string(),
+                value: value.to_string(),
+                operation: FilterOperation::Equals,
+            }],
+            operator: FilterOperator::And,
+        }
+    }
+    
+    #[test]
+    fn compressed_collections_search_the_same_and_report_less_memory() {
+        let mut plain = collection(config(32));
+        let mut compressed = collection(CollectionConfig {
+            in_memory_compression: true,
+            ..config(32)
+        });
+        for (i, vector) in random_vectors(50, 32, 1).into_iter().enumerate() {
+            plain.insert_vector(i.to_string(), vector.clone(), None).unwrap();
+            compressed.insert_vector(i.to_string(), vector, None).unwrap();
+        }
+        
+        for query in random_vectors(5, 32, 2) {
+            let expected = plain.search_vectors(query.clone(), 5).unwrap();
+            let actual = compressed.search_vectors(query, 5).unwrap();
+            assert_eq!(ids(&actual), ids(&expected));
+        }
+        
+        let index_copy = 50 * 32 * std::mem::size_of::<f32>();
+        let (plain, compressed) = (plain.size_bytes().unwrap(), compressed.size_bytes().unwrap());
+        assert!(compressed < plain, "{} vs {}", compressed, plain);
+        assert!(compressed > index_copy, "{} does not count the index's vectors", compressed);
+    }
+    
+    #[test]
+    fn u64_keyed_collections_round_trip_integer_ids() {
+        let database = Database::new("test".to_string());
+        database
+            .create_collection_with_config(CollectionConfig {
+                id_type: IdType::U64,
+                ..config(8)
+            })
+            .unwrap();
+        let vectors = random_vectors(30, 8, 1);
+        for (i, vector) in vectors.iter().enumerate() {
+            database.insert_vector_u64("test", 1_000_000 + i as u64, vector.clone(), None).unwrap();
+        }
+        
+        for (i, vector) in vectors.iter().enumerate().step_by(7) {
+            let results = database.search_vectors_u64("test", vector.clone(), 1).unwrap();
+            assert_eq!(results[0].0, 1_000_000 + i as u64);
+        }
+        
+        assert!(database.remove_vector_u64("test", 1_000_000).unwrap());
+        let results = database.search_vectors_u64("test", vectors[0].clone(), 30).unwrap();
+        assert_eq!(results.len(), 29);
+        assert!(results.iter().all(|(id, _, _)| *id != 1_000_000));
+    }
+    
+    #[test]
+    fn u64_keyed_collections_reject_other_ids() {
+        let mut collection = collection(CollectionConfig {
+            id_type: IdType::U64,
+            ..config(2)
+        });
+        for id in ["doc", "042", "-1"] {
+            assert!(collection.insert_vector(id.to_string(), vec![1.0, 0.0], None).is_err(), "{}", id);
+        }
+        collection.insert_vector("42".to_string(), vec![1.0, 0.0], None).unwrap();
+        assert_eq!(collection.document_ids().unwrap(), vec!["42".to_string()]);
+    }
+    
+    #[test]
+    fn sparse_collections_return_the_top_k_by_dot_product() {
+        let database = Database::new("test".to_string());
+        database.create_sparse_collection("sparse", 100).unwrap();
+        let documents = [
+            ("a", vec![1, 10], vec![0.5, 0.5]),
+            ("b", vec![10, 20], vec![1.0, 1.0]),
+            ("c", vec![50], vec![1.0]),
+        ];
+        for (id, indices, values) in documents {
+            database
+                .insert_sparse_vector("sparse", id.to_string(), SparseVector { indices, values }, None)
+                .unwrap();
+        }
+        
+        let query = SparseVector {
+            indices: vec![10, 20],
+            values: vec![1.0, 0.5],
+        };
+        let results = database.search_sparse_vectors("sparse", query, 2).unwrap();
+        assert_eq!(ids(&results), vec!["b", "a"]);
+        assert!(database.insert_vector("sparse", "dense", vec![0.0; 100], None).is_err());
+    }
+    
+    #[test]
+    fn expired_vectors_leave_search_before_and_after_the_purge() {
+        let database = Database::new("test".to_string());
+        database
+            .create_collection_with_config(CollectionConfig {
+                ttl_seconds: Some(60),
+                ..config(2)
+            })
+            .unwrap();
+        database.insert_vector("test", "a", vec![1.0, 0.0], None).unwrap();
+        database.insert_vector("test", "b", vec![0.0, 1.0], None).unwrap();
+        assert_eq!(database.search_vectors("test", vec![1.0, 0.0], 2).unwrap().len(), 2);
+        
+        let collection = database.get_collection("test").unwrap();
+        collection.write().unwrap().clock = || Ok(unix_time()? + 61);
+        assert!(database.search_vectors("test", vec![1.0, 0.0], 2).unwrap().is_empty());
+        assert!(!database.contains("test", "a").unwrap());
+        
+        assert_eq!(database.purge_expired().unwrap(), 2);
+        assert!(collection.read().unwrap().index.search(vec![1.0, 0.0], 2).unwrap().is_empty());
+        assert_eq!(database.purge_expired().unwrap(), 0);
+    }
+    
+    #[test]
+    fn collections_without_a_ttl_never_expire() {
+        let mut collection = collection(config(2));
+        collection.insert_vector("a".to_string(), vec![1.0, 0.0], None).unwrap();
+        assert_eq!(collection.purge_expired().unwrap(), 0);
+        assert_eq!(ids(&collection.search_vectors(vec![1.0, 0.0], 1).unwrap()), ["a"]);
+    }
+    
+    #[test]
+    fn get_or_create_collection_creates_then_reuses_matching_configs() {
+        let database = Database::new("test".to_string());
+        database.get_or_create_collection("test", 2, DistanceMetric::Cosine).unwrap();
+        database.insert_vector("test", "a", vec![1.0, 0.0], None).unwrap();
+        
+        database.get_or_create_collection("test", 2, DistanceMetric::Cosine).unwrap();
+        assert_eq!(database.list_collections().unwrap(), ["test"]);
+        assert!(database.contains("test", "a").unwrap());
+    }
+    
+    #[test]
+    fn get_or_create_collection_rejects_a_mismatched_existing_collection() {
+        let database = Database::new("test".to_string());
+        database.get_or_create_collection("test", 2, DistanceMetric::Cosine).unwrap();
+        
+        assert!(database.get_or_create_collection("test", 3, DistanceMetric::Cosine).is_err());
+        assert!(database.get_or_create_collection("test", 2, DistanceMetric::Euclidean).is_err());
+        assert_eq!(database.get_collection_info("test").unwrap().dimension, 2);
+    }
+    
+    #[test]
+    fn batch_upsert_twice_follows_each_conflict_policy() {
+        let document = |id: &str, x: f32| VectorDocument {
+            id: id.to_string(),
+            vector: vec![x, 1.0],
+            sparse_vector: None,
+            int_vector: None,
+            metadata: None,
+            timestamp: 0,
+        };
+        let batch = |x: f32| BatchInsertRequest {
+            vectors: vec![document("a", x), document("b", x), document("c", x)],
+        };
+        let outcomes = |response: &BatchUpsertResponse| -> Vec<UpsertOutcome> {
+            response.outcomes.iter().map(|(_, outcome)| outcome.clone()).collect()
+        };
+        
+        for policy in [ConflictPolicy::Skip, ConflictPolicy::Overwrite, ConflictPolicy::Error] {
+            let mut collection = collection(config(2));
+            let first = collection.batch_upsert(batch(0.0), policy).unwrap();
+            assert_eq!(first.inserted, 3);
+            assert!(outcomes(&first).iter().all(|outcome| *outcome == UpsertOutcome::Inserted));
+            
+            let second = collection.batch_upsert(batch(5.0), policy).unwrap();
+            let expected_x = match policy {
+                ConflictPolicy::Skip => {
+                    assert!(outcomes(&second).iter().all(|outcome| *outcome == UpsertOutcome::Skipped));
+                    0.0
+                }
+                ConflictPolicy::Overwrite => {
+                    assert!(outcomes(&second).iter().all(|outcome| *outcome == UpsertOutcome::Updated));
+                    5.0
+                }
+                ConflictPolicy::Error => {
+                    assert_eq!(second.failed, 3);
+                    assert!(outcomes(&second).iter().all(|outcome| matches!(outcome, UpsertOutcome::Failed(_))));
+                    0.0
+                }
+            };
+            
+            let (nodes, _) = collection.index.get_stats();
+            assert_eq!(nodes - collection.index.deleted_count(), 3, "{:?}", policy);
+            let results = collection.search_vectors(vec![expected_x, 1.0], 10).unwrap();
+            assert_eq!(results.len(), 3, "{:?}", policy);
+            assert!(results.iter().all(|result| result.1 < 1e-6), "{:?}", policy);
+        }
+    }
+    
+    #[test]
+    fn search_within_returns_the_exact_top_k_of_the_subset() {
+        let mut collection = collection(config(8));
+        let vectors = random_vectors(60, 8, 3);
+        for (i, vector) in vectors.iter().enumerate() {
+            collection.insert_vector(i.to_string(), vector.clone(), None).unwrap();
+        }
+        let subset: Vec<String> = (0..60).step_by(3).map(|i: usize| i.to_string()).collect();
+        let query = random_vectors(1, 8, 4).remove(0);
+        
+        let mut expected: Vec<(String, f32)> = subset
+            .iter()
+            .map(|id| {
+                let vector = &vectors[id.parse::<usize>().unwrap()];
+                (id.clone(), calculate_distance_with(&query, vector, DistanceMetric::Euclidean, true))
+            })
+            .collect();
+        expected.sort_by(|a, b| compare_distances(a.1, b.1).then_with(|| a.0.cmp(&b.0)));
+        expected.truncate(5);
+        
+        let results = collection.search_within(&query, &subset, 5, false).unwrap();
+        assert_eq!(ids(&results), expected.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>());
+    }
+    
+    #[test]
+    fn search_within_errors_on_unknown_ids_unless_skipping() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(2)).unwrap();
+        database.insert_vector("test", "a", vec![1.0, 0.0], None).unwrap();
+        let subset = ["a".to_string(), "missing".to_string()];
+        
+        assert!(database.search_within("test", vec![1.0, 0.0], &subset, 5, false).is_err());
+        assert_eq!(ids(&database.search_within("test", vec![1.0, 0.0], &subset, 5, true).unwrap()), ["a"]);
+        assert!(database.search_within("missing", vec![1.0, 0.0], &subset, 5, true).is_err());
+    }
+    
+    #[test]
+    fn maybe_compact_fires_past_the_threshold_and_reclaims_nodes() {
+        let mut collection = collection(config(4));
+        for (i, vector) in random_vectors(20, 4, 7).into_iter().enumerate() {
+            collection.insert_vector(i.to_string(), vector, None).unwrap();
+        }
+        for i in 0..3 {
+            collection.remove_vector(&i.to_string()).unwrap();
+        }
+        assert!(!collection.maybe_compact().unwrap(), "3/20 is under the 0.2 default");
+        
+        let memory = collection.index.estimated_memory_bytes();
+        for i in 3..10 {
+            collection.remove_vector(&i.to_string()).unwrap();
+        }
+        assert!(collection.maybe_compact().unwrap());
+        assert_eq!(collection.index.get_stats().0, 10);
+        assert_eq!(collection.index.deleted_count(), 0);
+        assert!(collection.index.estimated_memory_bytes() < memory);
+        assert_eq!(collection.search_vectors(vec![0.0; 4], 20).unwrap().len(), 10);
+    }
+    
+    #[test]
+    fn deletes_past_the_threshold_compact_in_the_background() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(4)).unwrap();
+        for (i, vector) in random_vectors(20, 4, 7).into_iter().enumerate() {
+            database.insert_vector("test", i.to_string(), vector, None).unwrap();
+        }
+        for i in 0..10 {
+            database.remove_vector("test", &i.to_string()).unwrap();
+        }
+        
+        let collection = database.get_collection("test").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while collection.read().unwrap().index.get_stats().0 != 10 {
+            assert!(Instant::now() < deadline, "background compaction never finished");
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(database.search_vectors("test", vec![0.0; 4], 20).unwrap().len(), 10);
+    }
+    
+    #[test]
+    fn concurrent_creates_and_drops_leave_a_consistent_set_of_collections() {
+        let database = Arc::new(Database::new("test".to_string()));
+        let workers: Vec<_> = (0..8)
+            .map(|worker| {
+                let database = database.clone();
+                thread::spawn(move || {
+                    for round in 0..50 {
+                        let shared = format!("shared-{}", round % 4);
+                        if (worker + round) % 2 == 0 {
+                            let _ = database.create_collection(&shared, 4);
+                        } else {
+                            database.drop_collection(&shared).unwrap();
+                        }
+                    }
+                    database.create_collection(&format!("own-{}", worker), 4).unwrap();
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        
+        for name in database.list_collections().unwrap() {
+            assert_eq!(database.get_collection_info(&name).unwrap().dimension, 4);
+            if name.starts_with("shared-") {
+                assert!(database.drop_collection(&name).unwrap());
+            }
+        }
+        let mut names = database.list_collections().unwrap();
+        names.sort();
+        assert_eq!(names, (0..8).map(|worker| format!("own-{}", worker)).collect::<Vec<_>>());
+    }
+    
+    #[test]
+    fn racing_creates_of_one_name_succeed_exactly_once() {
+        let database = Arc::new(Database::new("test".to_string()));
+        let workers: Vec<_> = (0..8)
+            .map(|_| {
+                let database = database.clone();
+                thread::spawn(move || database.create_collection("test", 4).is_ok())
+            })
+            .collect();
+        let created = workers.into_iter().map(|worker| worker.join().unwrap()).filter(|&ok| ok).count();
+        assert_eq!(created, 1);
+        assert_eq!(database.list_collections().unwrap(), ["test"]);
+    }
+    
+    #[test]
+    fn metadata_fields_project_each_hit_to_the_requested_keys() {
+        let mut collection = collection(config(2));
+        let metadata = vec![
+            ("title".to_string(), "a".to_string()),
+            ("body".to_string(), "long".to_string()),
+            ("lang".to_string(), "en".to_string()),
+        ];
+        collection.insert_vector("a".to_string(), vec![1.0, 0.0], Some(metadata.clone())).unwrap();
+        let query = |metadata_fields: Option<Vec<String>>| SearchQuery {
+            vector: vec![1.0, 0.0],
+            metadata_fields,
+            ..Default::default()
+        };
+        
+        let all = collection.search(&query(None)).unwrap();
+        assert_eq!(all[0].2, Some(metadata));
+        
+        let projected = collection.search(&query(Some(vec!["lang".to_string(), "title".to_string()]))).unwrap();
+        assert_eq!(
+            projected[0].2,
+            Some(vec![("title".to_string(), "a".to_string()), ("lang".to_string(), "en".to_string())])
+        );
+        
+        let none = collection.search(&query(Some(Vec::new()))).unwrap();
+        assert_eq!(none[0].2, None);
+    }
+    
+    #[test]
+    fn skip_value_validation_admits_non_finite_values_but_still_checks_dimension() {
+        let mut validated = collection(config(2));
+        assert!(validated.insert_vector("nan".to_string(), vec![f32::NAN, 0.0], None).is_err());
+        assert!(validated.insert_vector("inf".to_string(), vec![f32::INFINITY, 0.0], None).is_err());
+        
+        let mut trusted = collection(CollectionConfig {
+            skip_value_validation: true,
+            ..config(2)
+        });
+        trusted.insert_vector("nan".to_string(), vec![f32::NAN, 0.0], None).unwrap();
+        assert!(trusted.contains("nan").unwrap());
+        assert!(trusted.insert_vector("short".to_string(), vec![1.0], None).is_err());
+    }
+    
+    #[test]
+    fn best_first_puts_the_nearest_vector_first_for_every_metric() {
+        for metric in [
+            DistanceMetric::Cosine,
+            DistanceMetric::Euclidean,
+            DistanceMetric::Manhattan,
+            DistanceMetric::DotProduct,
+            DistanceMetric::JensenShannon,
+        ] {
+            let mut collection = collection(CollectionConfig { metric, ..config(2) });
+            collection.insert_vector("far".to_string(), vec![0.0, 1.0], None).unwrap();
+            collection.insert_vector("near".to_string(), vec![1.0, 0.0], None).unwrap();
+            collection.insert_vector("mid".to_string(), vec![0.5, 0.5], None).unwrap();
+            let query = |sort_order| SearchQuery {
+                vector: vec![1.0, 0.0],
+                sort_order,
+                ..Default::default()
+            };
+            
+            let best = collection.search(&query(SortOrder::BestFirst)).unwrap();
+            assert_eq!(ids(&best), ["near", "mid", "far"], "{:?}", metric);
+            let worst = collection.search(&query(SortOrder::WorstFirst)).unwrap();
+            assert_eq!(ids(&worst), ["far", "mid", "near"], "{:?}", metric);
+        }
+    }
+    
+    #[test]
+    fn dedup_by_keeps_the_best_hit_per_metadata_value() {
+        let mut collection = collection(config(2));
+        let author = |name: &str| Some(vec![("author".to_string(), name.to_string())]);
+        for i in 0..6 {
+            let x = i as f32 * 0.01;
+            collection.insert_vector(format!("a{}", i), vec![x, 0.0], author("ann")).unwrap();
+        }
+        collection.insert_vector("b0".to_string(), vec![0.0, 0.5], author("bob")).unwrap();
+        collection.insert_vector("b1".to_string(), vec![0.0, 0.6], author("bob")).unwrap();
+        collection.insert_vector("c0".to_string(), vec![0.0, 0.7], author("cy")).unwrap();
+        collection.insert_vector("anon".to_string(), vec![0.0, 0.3], None).unwrap();
+        let query = |dedup_keep_missing| SearchQuery {
+            vector: vec![0.0, 0.0],
+            limit: 4,
+            dedup_by: Some("author".to_string()),
+            dedup_keep_missing,
+            ..Default::default()
+        };
+        
+        assert_eq!(ids(&collection.search(&query(true)).unwrap()), ["a0", "anon", "b0", "c0"]);
+        assert_eq!(ids(&collection.search(&query(false)).unwrap()), ["a0", "b0", "c0"]);
+    }
+    
+    #[test]
+    fn contains_reports_present_and_absent_ids() {
+        let database = Database::new("test".to_string());
+        database.create_collection("test", 2).unwrap();
+        database.insert_vector("test", "present", vec![1.0, 0.0], None).unwrap();
+        database.insert_vector("test", "removed", vec![0.0, 1.0], None).unwrap();
+        database.remove_vector("test", "removed").unwrap();
+        
+        assert!(database.contains("test", "present").unwrap());
+        assert!(!database.contains("test", "removed").unwrap());
+        assert!(!database.contains("test", "absent").unwrap());
+        assert!(database.contains("missing", "present").is_err());
+    }
+    
+    #[cfg(feature = "tracing")]
+    type RecordedSpan = (&'static tracing::Metadata<'static>, HashMap<String, String>);
+    
+    /// Spans opened on this thread, in order, with every field recorded on them so far.
+    #[cfg(feature = "tracing")]
+    #[derive(Clone, Default)]
+    struct SpanRecorder {
+        spans: Arc<Mutex<Vec<RecordedSpan>>>,
+        stack: Arc<Mutex<Vec<u64>>>,
+    }
+    
+    #[cfg(feature = "tracing")]
+    struct FieldValues<'a>(&'a mut HashMap<String, String>);
+    
+    #[cfg(feature = "tracing")]
+    impl tracing::field::Visit for FieldValues<'_> {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+    
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for SpanRecorder {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+        
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut fields = HashMap::new();
+            span.record(&mut FieldValues(&mut fields));
+            let mut spans = self.spans.lock().unwrap();
+            spans.push((span.metadata(), fields));
+            tracing::span::Id::from_u64(spans.len() as u64)
+        }
+        
+        fn record(&self, span: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+            let mut spans = self.spans.lock().unwrap();
+            values.record(&mut FieldValues(&mut spans[span.into_u64() as usize - 1].1));
+        }
+        
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+        
+        fn event(&self, _: &tracing::Event<'_>) {}
+        
+        fn enter(&self, span: &tracing::span::Id) {
+            self.stack.lock().unwrap().push(span.into_u64());
+        }
+        
+        fn exit(&self, _: &tracing::span::Id) {
+            self.stack.lock().unwrap().pop();
+        }
+        
+        fn current_span(&self) -> tracing_core::span::Current {
+            match self.stack.lock().unwrap().last() {
+                Some(&id) => {
+                    let metadata = self.spans.lock().unwrap()[id as usize - 1].0;
+                    tracing_core::span::Current::new(tracing::span::Id::from_u64(id), metadata)
+                }
+                None => tracing_core::span::Current::none(),
+            }
+        }
+    }
+    
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn searches_record_a_span_with_their_fields() {
+        let mut collection = collection(config(2));
+        for i in 0..4 {
+            collection.insert_vector(i.to_string(), vec![i as f32, 0.0], None).unwrap();
+        }
+        
+        let recorder = SpanRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            collection.search_vectors(vec![0.0, 0.0], 3).unwrap();
+        });
+        
+        let spans = recorder.spans.lock().unwrap();
+        let (_, fields) = spans
+            .iter()
+            .find(|(metadata, _)| metadata.name() == "search_vectors")
+            .expect("search_vectors opened no span");
+        assert_eq!(fields["collection"], "test");
+        assert_eq!(fields["limit"], "3");
+        assert_eq!(fields["results"], "3");
+        assert!(fields.contains_key("duration_ms"));
+    }
+    
+    #[test]
+    fn inserts_past_the_memory_limit_are_rejected_while_reads_still_work() {
+        let mut database = Database::new("test".to_string());
+        database.memory_limit_bytes = Some(64 * 1024);
+        database.create_collection_with_config(config(16)).unwrap();
+        
+        let vectors = random_vectors(1000, 16, 1);
+        let rejected = vectors
+            .iter()
+            .enumerate()
+            .map(|(i, vector)| database.insert_vector("test", i.to_string(), vector.clone(), None))
+            .position(|result| result.is_err())
+            .expect("the limit was never reached");
+        let error = database.insert_vector("test", "over", vectors[0].clone(), None).unwrap_err();
+        assert!(matches!(error.downcast_ref::<SolarisError>(), Some(SolarisError::MemoryLimitExceeded { .. })));
+        
+        assert!(database.contains("test", "0").unwrap());
+        assert_eq!(database.search_vectors("test", vectors[0].clone(), 1).unwrap()[0].0, "0");
+        
+        // Freeing one vector is not enough: inserts resume only below the headroom.
+        database.remove_vector("test", "0").unwrap();
+        assert!(database.insert_vector("test", "over", vectors[0].clone(), None).is_err());
+        for i in 1..rejected / 2 {
+            database.remove_vector("test", &i.to_string()).unwrap();
+        }
+        // Removed nodes hold their vectors until compaction drops them from the graph.
+        database.get_collection("test").unwrap().write().unwrap().maybe_compact().unwrap();
+        database.insert_vector("test", "over", vectors[0].clone(), None).unwrap();
+    }
+    
+    #[test]
+    fn reindexing_with_richer_params_improves_recall() {
+        let database = Database::new("test".to_string());
+        database
+            .create_collection_with_config(CollectionConfig {
+                m: 2,
+                ef_construction: 4,
+                ..config(8)
+            })
+            .unwrap();
+        for (i, vector) in clustered_vectors(500, 8, 10, 3).into_iter().enumerate() {
+            database.insert_vector("test", i.to_string(), vector, None).unwrap();
+        }
+        let collection = database.get_collection("test").unwrap();
+        let queries = clustered_vectors(20, 8, 10, 4);
+        let recall = || {
+            let collection = collection.read().unwrap();
+            let total: f32 = queries.iter().map(|query| collection.query_recall(query, 10).unwrap().unwrap()).sum();
+            total / queries.len() as f32
+        };
+        
+        let before = recall();
+        database.reindex("test", 16, 200).unwrap();
+        let after = recall();
+        assert!(after > before, "recall {} before reindexing, {} after", before, after);
+        let params = |collection: &Collection| (collection.config().m, collection.config().ef_construction);
+        assert_eq!(params(&collection.read().unwrap()), (16, 200));
+        assert_eq!(database.search_vectors("test", queries[0].clone(), 500).unwrap().len(), 500);
+        
+        assert!(database.reindex("test", 0, 200).is_err());
+        assert_eq!(params(&collection.read().unwrap()), (16, 200));
+        assert!(database.reindex("missing", 16, 200).is_err());
+    }
+    
+    #[test]
+    fn insert_stream_inserts_every_valid_document_from_an_iterator() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(4)).unwrap();
+        database.get_collection("test").unwrap().write().unwrap().batch_size = 32;
+        let documents = (0..300).map(|i| VectorDocument {
+            id: i.to_string(),
+            // Every fiftieth document has the wrong dimension.
+            vector: if i % 50 == 49 { vec![1.0; 3] } else { vec![i as f32, 1.0, 0.0, 0.0] },
+            sparse_vector: None,
+            int_vector: None,
+            metadata: None,
+            timestamp: 0,
+        });
+        
+        let response = database.insert_stream("test", documents).unwrap();
+        assert_eq!(response.inserted, 294);
+        let failed: Vec<&str> = response.failed.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(failed, ["49", "99", "149", "199", "249", "299"]);
+        assert_eq!(database.get_collection_info("test").unwrap().vector_count, 294);
+        assert!(database.insert_stream("missing", std::iter::empty()).is_err());
+    }
+    
+    #[test]
+    fn filter_stats_report_the_pass_rate_of_filtered_searches() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(4)).unwrap();
+        for (i, vector) in random_vectors(400, 4, 9).into_iter().enumerate() {
+            let group = Some(vec![("group".to_string(), (i % 4).to_string())]);
+            database.insert_vector("test", i.to_string(), vector, group).unwrap();
+        }
+        
+        database.search_vectors("test", vec![0.0; 4], 10).unwrap();
+        assert_eq!(database.filter_stats("test").unwrap().filtered_searches, 0);
+        
+        for query in random_vectors(20, 4, 10) {
+            let query = SearchQuery {
+                vector: query,
+                limit: 10,
+                filter: Some(equals("group", "0")),
+                ..Default::default()
+            };
+            database.search("test", &query).unwrap();
+        }
+        
+        let stats = database.filter_stats("test").unwrap();
+        assert_eq!(stats.filtered_searches, 20);
+        let overall = stats.candidates_passed as f32 / stats.candidates_fetched as f32;
+        assert!((overall - 0.25).abs() < 0.1, "overall pass rate {}", overall);
+        assert!((stats.rolling_pass_rate - 0.25).abs() < 0.1, "rolling pass rate {}", stats.rolling_pass_rate);
+    }
+    
+    #[test]
+    fn search_responses_report_truncation_only_when_more_matches_exist() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(2)).unwrap();
+        for i in 0..5 {
+            database.insert_vector("test", i.to_string(), vec![i as f32, 0.0], None).unwrap();
+        }
+        let query = |limit| SearchQuery {
+            vector: vec![0.0, 0.0],
+            limit,
+            ..Default::default()
+        };
+        
+        let more_than_exist = database.search_with_response("test", &query(10)).unwrap();
+        assert_eq!(more_than_exist.results.len(), 5);
+        assert!(!more_than_exist.truncated);
+        
+        let exactly_all = database.search_with_response("test", &query(5)).unwrap();
+        assert_eq!(exactly_all.results.len(), 5);
+        assert!(!exactly_all.truncated);
+        
+        let within = database.search_with_response("test", &query(3)).unwrap();
+        assert_eq!(ids(&within.results), ["0", "1", "2"]);
+        assert!(within.truncated);
+        
+        let at_the_cap = database.search_with_response("test", &query(config(2).max_search_limit)).unwrap();
+        assert_eq!(at_the_cap.results.len(), 5);
+        assert!(database.search_with_response("test", &query(usize::MAX)).is_err());
+    }
+    
+    #[test]
+    fn integer_collections_search_by_manhattan_distance() {
+        let database = Database::new("test".to_string());
+        database.create_integer_collection("ints", 3, DistanceMetric::Manhattan).unwrap();
+        database.insert_int_vector("ints", "far".to_string(), vec![10, 10, 10], None).unwrap();
+        database.insert_int_vector("ints", "near".to_string(), vec![1, 2, 3], None).unwrap();
+        database.insert_int_vector("ints", "mid".to_string(), vec![4, 0, 3], None).unwrap();
+        
+        let results = database.search_int_vectors("ints", vec![1, 1, 3], 3).unwrap();
+        assert_eq!(ids(&results), ["near", "mid", "far"]);
+        assert_eq!(results.iter().map(|result| result.1).collect::<Vec<_>>(), [1.0, 4.0, 25.0]);
+        
+        assert!(database.insert_vector("ints", "dense", vec![1.0, 2.0, 3.0], None).is_err());
+        assert!(database.insert_int_vector("ints", "short".to_string(), vec![1, 2], None).is_err());
+        assert!(database.search_vectors("ints", vec![1.0, 1.0, 3.0], 3).is_err());
+        
+        database.create_collection("dense", 3).unwrap();
+        assert!(database.insert_int_vector("dense", "a".to_string(), vec![1, 2, 3], None).is_err());
+    }
+    
+    #[test]
+    fn health_names_collections_with_poisoned_locks() {
+        let database = Database::new("test".to_string());
+        database.create_collection("good", 2).unwrap();
+        database.create_collection("poisoned", 2).unwrap();
+        let health = database.health();
+        assert_eq!(health.state, HealthState::Healthy);
+        assert_eq!(health.persistence_writable, None);
+        
+        let collection = database.get_collection("poisoned").unwrap();
+        thread::spawn(move || {
+            let _guard = collection.write().unwrap();
+            panic!("poisoning the collection lock");
+        })
+        .join()
+        .unwrap_err();
+        
+        let health = database.health();
+        assert_eq!(health.state, HealthState::Degraded);
+        let unhealthy: Vec<&str> = health
+            .collections
+            .iter()
+            .filter(|collection| !collection.lock_healthy)
+            .map(|collection| collection.name.as_str())
+            .collect();
+        assert_eq!(unhealthy, ["poisoned"]);
+    }
+    
+    #[test]
+    fn cancelling_a_stream_stops_it_between_chunks() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(4)).unwrap();
+        database.get_collection("test").unwrap().write().unwrap().batch_size = 16;
+        let cancel = CancellationToken::new();
+        let trigger = cancel.clone();
+        let documents = random_vectors(200, 4, 11).into_iter().enumerate().map(move |(i, vector)| {
+            if i == 40 {
+                trigger.cancel();
+            }
+            VectorDocument {
+                id: i.to_string(),
+                vector,
+                sparse_vector: None,
+                int_vector: None,
+                metadata: None,
+                timestamp: 0,
+            }
+        });
+        
+        let error = database.insert_stream_cancellable("test", documents, &cancel).unwrap_err();
+        assert!(matches!(error.downcast_ref::<SolarisError>(), Some(SolarisError::Cancelled)));
+        
+        // The chunk that pulled document 40 finishes; nothing after it starts.
+        assert_eq!(database.get_collection_info("test").unwrap().vector_count, 48);
+        assert_eq!(database.get_collection("test").unwrap().read().unwrap().index.get_stats().0, 48);
+        assert_eq!(database.search_vectors("test", vec![0.0; 4], 100).unwrap().len(), 48);
+    }
+    
+    #[test]
+    fn cancelled_optimize_and_search_leave_the_collection_usable() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(4)).unwrap();
+        for (i, vector) in random_vectors(50, 4, 12).into_iter().enumerate() {
+            database.insert_vector("test", i.to_string(), vector, None).unwrap();
+        }
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        
+        let error = database.optimize_cancellable("test", &cancel).unwrap_err();
+        assert!(matches!(error.downcast_ref::<SolarisError>(), Some(SolarisError::Cancelled)));
+        let query = SearchQuery {
+            vector: vec![0.0; 4],
+            ..Default::default()
+        };
+        let error = database.search_cancellable("test", &query, &cancel).unwrap_err();
+        assert!(matches!(error.downcast_ref::<SolarisError>(), Some(SolarisError::Cancelled)));
+        
+        assert_eq!(database.search_vectors("test", vec![0.0; 4], 100).unwrap().len(), 50);
+        let live = CancellationToken::new();
+        assert_eq!(database.search_cancellable("test", &query, &live).unwrap().len(), 10);
+        database.optimize_cancellable("test", &live).unwrap();
+        assert_eq!(database.search_vectors("test", vec![0.0; 4], 100).unwrap().len(), 50);
+    }
+    
+    #[test]
+    fn federated_search_matches_a_single_collection_baseline() {
+        let database = Database::new("test".to_string());
+        for name in ["all", "even", "odd"] {
+            database.create_collection_with_config(CollectionConfig { name: name.to_string(), ..config(8) }).unwrap();
+        }
+        for (i, vector) in random_vectors(80, 8, 13).into_iter().enumerate() {
+            database.insert_vector("all", i.to_string(), vector.clone(), None).unwrap();
+            let shard = if i % 2 == 0 { "even" } else { "odd" };
+            database.insert_vector(shard, i.to_string(), vector, None).unwrap();
+        }
+        let shards = ["even".to_string(), "odd".to_string()];
+        
+        for query in random_vectors(5, 8, 14) {
+            let baseline = database.search_vectors("all", query.clone(), 10).unwrap();
+            let federated = database.search_across(&shards, query, 10).unwrap();
+            let federated_ids: Vec<&str> = federated.iter().map(|hit| hit.id.as_str()).collect();
+            assert_eq!(federated_ids, ids(&baseline));
+            for hit in &federated {
+                let shard = if hit.id.parse::<usize>().unwrap() % 2 == 0 { "even" } else { "odd" };
+                assert_eq!(hit.collection, shard);
+            }
+        }
+    }
+    
+    #[test]
+    fn federated_search_rejects_mismatched_collections() {
+        let database = Database::new("test".to_string());
+        database.create_collection("a", 4).unwrap();
+        database.create_collection("b", 8).unwrap();
+        database
+            .create_collection_with_config(CollectionConfig {
+                name: "c".to_string(),
+                metric: DistanceMetric::Euclidean,
+                ..config(4)
+            })
+            .unwrap();
+        
+        assert!(database.search_across(&["a".to_string(), "b".to_string()], vec![0.0; 4], 5).is_err());
+        assert!(database.search_across(&["a".to_string(), "c".to_string()], vec![0.0; 4], 5).is_err());
+        assert!(database.search_across(&["a".to_string(), "missing".to_string()], vec![0.0; 4], 5).is_err());
+    }
+    
+    #[test]
+    fn pending_documents_become_searchable_once_their_vector_is_set() {
+        let mut collection = collection(config(2));
+        collection.insert_vector("a".to_string(), vec![0.0, 1.0], None).unwrap();
+        let metadata = Some(vec![("stage".to_string(), "draft".to_string())]);
+        collection.insert_pending("pending".to_string(), metadata.clone()).unwrap();
+        
+        assert!(collection.contains("pending").unwrap());
+        assert_eq!(ids(&collection.search_vectors(vec![1.0, 0.0], 10).unwrap()), ["a"]);
+        assert!(collection.insert_pending("pending".to_string(), None).is_err());
+        
+        collection.update_vector("pending", vec![1.0, 0.0]).unwrap();
+        let results = collection.search_vectors(vec![1.0, 0.0], 10).unwrap();
+        assert_eq!(ids(&results), ["pending", "a"]);
+        assert_eq!(results[0].2, metadata);
+        
+        assert!(collection.update_vector("missing", vec![1.0, 0.0]).is_err());
+    }
+    
+    #[test]
+    fn collections_enforce_their_own_search_and_batch_caps() {
+        let mut settings = SolarisConfig::default();
+        settings.collections.max_search_limit = 5;
+        settings.collections.max_batch_size = 3;
+        let database = Database::with_config(&settings);
+        database.create_collection("capped", 2).unwrap();
+        database
+            .create_collection_with_config(CollectionConfig {
+                name: "roomy".to_string(),
+                max_search_limit: 50_000,
+                ..config(2)
+            })
+            .unwrap();
+        
+        for name in ["capped", "roomy"] {
+            for i in 0..10 {
+                database.insert_vector(name, i.to_string(), vec![i as f32, 1.0], None).unwrap();
+            }
+        }
+        
+        assert!(database.search_vectors("capped", vec![0.0, 1.0], 6).is_err());
+        let query = SearchQuery {
+            vector: vec![0.0, 1.0],
+            limit: 6,
+            ..Default::default()
+        };
+        assert!(database.search("capped", &query).is_err());
+        assert_eq!(database.search_vectors("capped", vec![0.0, 1.0], 5).unwrap().len(), 5);
+        assert_eq!(database.search_vectors("roomy", vec![0.0, 1.0], 20_000).unwrap().len(), 10);
+        
+        let batch = |n: usize| BatchInsertRequest {
+            vectors: (0..n).map(|i| document(&format!("batch-{}", i), vec![1.0, 0.0])).collect(),
+        };
+        assert!(database.batch_upsert("capped", batch(4), ConflictPolicy::Error).is_err());
+        assert_eq!(database.batch_upsert("capped", batch(3), ConflictPolicy::Error).unwrap().inserted, 3);
+    }
+    
+    #[test]
+    fn caps_outside_the_ceilings_are_rejected() {
+        let database = Database::new("test".to_string());
+        let caps = [(0, 10), (10, 0), (SEARCH_LIMIT_CEILING + 1, 10), (10, BATCH_SIZE_CEILING + 1)];
+        for (max_search_limit, max_batch_size) in caps {
+            let config = CollectionConfig {
+                max_search_limit,
+                max_batch_size,
+                ..config(2)
+            };
+            assert!(database.create_collection_with_config(config).is_err());
+        }
+    }
+    
+    #[test]
+    fn update_metadata_reports_each_outcome() {
+        let mut collection = collection(config(2));
+        let tagged = |tag: &str| Some(vec![("tag".to_string(), tag.to_string())]);
+        collection.insert_vector("a".to_string(), vec![1.0, 0.0], tagged("old")).unwrap();
+        
+        assert_eq!(collection.update_metadata("missing", tagged("new")).unwrap(), UpdateOutcome::NotFound);
+        assert_eq!(collection.update_metadata("a", tagged("old")).unwrap(), UpdateOutcome::Unchanged);
+        assert_eq!(collection.update_metadata("a", tagged("new")).unwrap(), UpdateOutcome::Updated);
+        assert_eq!(collection.search_vectors(vec![1.0, 0.0], 1).unwrap()[0].2, tagged("new"));
+        
+        // Clearing is a change; clearing again is not.
+        assert_eq!(collection.update_metadata("a", None).unwrap(), UpdateOutcome::Updated);
+        assert_eq!(collection.update_metadata("a", None).unwrap(), UpdateOutcome::Unchanged);
+    }
+    
+    #[test]
+    fn confidence_is_full_on_exact_results_and_drops_on_a_degraded_graph() {
+        let mut collection = collection(config(8));
+        for (i, vector) in clustered_vectors(300, 8, 10, 17).into_iter().enumerate() {
+            collection.insert_vector(i.to_string(), vector, None).unwrap();
+        }
+        let queries = clustered_vectors(10, 8, 10, 18);
+        let confidence = |collection: &Collection, ef: usize| {
+            let total: f32 = queries
+                .iter()
+                .map(|query| {
+                    let query = SearchQuery {
+                        vector: query.clone(),
+                        limit: 10,
+                        ef: Some(ef),
+                        estimate_confidence: true,
+                        ..Default::default()
+                    };
+                    collection.search_with_response(&query).unwrap().confidence.unwrap()
+                })
+                .sum();
+            total / queries.len() as f32
+        };
+        
+        // An ef covering the whole collection makes the search exact on a graph this small.
+        assert_eq!(confidence(&collection, 300), 1.0);
+        
+        collection.prune(1).unwrap();
+        let degraded = confidence(&collection, 10);
+        assert!(degraded < 1.0, "confidence {} on a graph pruned to one link per node", degraded);
+        
+        let plain = SearchQuery {
+            vector: queries[0].clone(),
+            ..Default::default()
+        };
+        assert_eq!(collection.search_with_response(&plain).unwrap().confidence, None);
+    }
+    
+    #[test]
+    fn collection_configs_read_back_and_tune_only_with_valid_params() {
+        let database = Database::new("test".to_string());
+        database
+            .create_collection_with_config(CollectionConfig {
+                m: 8,
+                ef_construction: 100,
+                ..config(3)
+            })
+            .unwrap();
+        
+        let config = database.get_collection_config("test").unwrap();
+        assert_eq!((config.dimension, config.metric), (3, DistanceMetric::Euclidean));
+        assert_eq!((config.m, config.ef_construction), (8, 100));
+        
+        database.set_m("test", 12).unwrap();
+        database.set_ef_construction("test", 300).unwrap();
+        assert!(database.set_m("test", 0).is_err());
+        assert!(database.set_ef_construction("test", 0).is_err());
+        let config = database.get_collection_config("test").unwrap();
+        assert_eq!((config.m, config.ef_construction), (12, 300));
+        assert!(database.get_collection_config("missing").is_err());
+    }
+    
+    #[test]
+    fn validate_batch_flags_exactly_the_bad_documents_without_inserting() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(3)).unwrap();
+        
+        let request = BatchInsertRequest {
+            vectors: vec![
+                document("good-1", vec![1.0, 0.0, 0.0]),
+                document("short", vec![1.0, 0.0]),
+                document("good-2", vec![0.0, 1.0, 0.0]),
+                document("not-a-number", vec![f32::NAN, 0.0, 0.0]),
+                document("", vec![0.0, 0.0, 1.0]),
+            ],
+        };
+        let report = database.validate_batch("test", &request).unwrap();
+        
+        assert_eq!(report.total, 5);
+        assert_eq!(report.valid, 2);
+        let expected: Vec<(String, String)> = request.vectors[1..]
+            .iter()
+            .filter_map(|document| {
+                validate_vector_document(document, 3).err().map(|e| (document.id.clone(), e.to_string()))
+            })
+            .collect();
+        assert_eq!(expected.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>(), ["short", "not-a-number", ""]);
+        assert_eq!(report.invalid, expected);
+        
+        assert!(database.get_collection("test").unwrap().read().unwrap().document_ids().unwrap().is_empty());
+        assert!(database.search_vectors("test", vec![1.0, 0.0, 0.0], 5).unwrap().is_empty());
+    }
+    
+    #[test]
+    fn validate_batch_reports_a_clean_batch_and_enforces_the_batch_cap() {
+        let database = Database::new("test".to_string());
+        database
+            .create_collection_with_config(CollectionConfig { max_batch_size: 2, ..config(2) })
+            .unwrap();
+        
+        let batch = |n: usize| BatchInsertRequest {
+            vectors: (0..n).map(|i| document(&format!("doc-{}", i), vec![i as f32, 1.0])).collect(),
+        };
+        let report = database.validate_batch("test", &batch(2)).unwrap();
+        assert_eq!((report.total, report.valid), (2, 2));
+        assert!(report.invalid.is_empty());
+        
+        assert!(database.validate_batch("test", &batch(3)).is_err());
+        assert!(database.validate_batch("missing", &batch(1)).is_err());
+    }
+    
+    #[test]
+    fn hooks_fire_after_each_successful_write() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(2)).unwrap();
+        
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let record = |tag: &'static str| -> CollectionHook {
+            let events = Arc::clone(&events);
+            Box::new(move |id| events.lock().unwrap().push(format!("{}:{}", tag, id)))
+        };
+        database.on_insert("test", record("insert")).unwrap();
+        database.on_delete("test", record("delete")).unwrap();
+        database.on_update("test", record("update")).unwrap();
+        let counter = Arc::new(AtomicU64::new(0));
+        let second = Arc::clone(&counter);
+        database
+            .on_insert("test", Box::new(move |_| {
+                second.fetch_add(1, AtomicOrdering::SeqCst);
+            }))
+            .unwrap();
+        
+        for id in ["a", "b", "c"] {
+            database.insert_vector("test", id.to_string(), vec![1.0, 0.0], None).unwrap();
+        }
+        assert!(database.insert_vector("test", "d".to_string(), vec![1.0], None).is_err());
+        database.update_vector("test", "a", vec![0.0, 1.0]).unwrap();
+        database.update_metadata("test", "b", Some(vec![("k".to_string(), "v".to_string())])).unwrap();
+        assert_eq!(database.update_metadata("test", "missing", None).unwrap(), UpdateOutcome::NotFound);
+        assert!(database.remove_vector("test", "c").unwrap());
+        assert!(!database.remove_vector("test", "c").unwrap());
+        
+        assert_eq!(
+            *events.lock().unwrap(),
+            ["insert:a", "insert:b", "insert:c", "update:a", "update:b", "delete:c"]
+        );
+        assert_eq!(counter.load(AtomicOrdering::SeqCst), 3);
+    }
+    
+    #[test]
+    fn a_panicking_hook_leaves_the_collection_consistent() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(2)).unwrap();
+        database.on_insert("test", Box::new(|_| panic!("hook failure"))).unwrap();
+        
+        database.insert_vector("test", "a".to_string(), vec![1.0, 0.0], None).unwrap();
+        database.insert_vector("test", "b".to_string(), vec![0.0, 1.0], None).unwrap();
+        
+        assert_eq!(ids(&database.search_vectors("test", vec![1.0, 0.1], 2).unwrap()), ["a", "b"]);
+        assert!(database.remove_vector("test", "a").unwrap());
+    }
+    
+    #[test]
+    fn get_document_meta_returns_metadata_and_timestamp() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(4)).unwrap();
+        
+        let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let metadata = vec![("kind".to_string(), "report".to_string()), ("lang".to_string(), "en".to_string())];
+        database.insert_vector("test", "tagged".to_string(), vec![1.0; 4], Some(metadata.clone())).unwrap();
+        database.insert_vector("test", "bare".to_string(), vec![0.5; 4], None).unwrap();
+        database.insert_pending("test", "pending".to_string(), Some(metadata.clone())).unwrap();
+        let after = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        
+        let (found, timestamp) = database.get_document_meta("test", "tagged").unwrap().unwrap();
+        assert_eq!(found, Some(metadata.clone()));
+        assert!((before..=after).contains(&timestamp));
+        
+        let (found, timestamp) = database.get_document_meta("test", "bare").unwrap().unwrap();
+        assert!(found.is_none());
+        assert!((before..=after).contains(&timestamp));
+        
+        assert_eq!(database.get_document_meta("test", "pending").unwrap().unwrap().0, Some(metadata));
+        
+        assert!(database.get_document_meta("test", "missing").unwrap().is_none());
+        database.remove_vector("test", "tagged").unwrap();
+        assert!(database.get_document_meta("test", "tagged").unwrap().is_none());
+        assert!(database.get_document_meta("missing", "bare").is_err());
+    }
+    
+    #[cfg(feature = "persistence")]
+    fn persistent_database(dir: &std::path::Path) -> Database {
+        let mut settings = SolarisConfig::default();
+        settings.database.enable_persistence = true;
+        settings.database.data_directory = dir.to_path_buf();
+        Database::with_config(&settings)
+    }
+    
+    #[cfg(feature = "persistence")]
+    #[test]
+    fn reopening_a_persistent_collection_recovers_its_documents() {
+        let dir = tempfile::tempdir().unwrap();
+        let vectors = random_vectors(50, 8, 7);
+        {
+            let database = persistent_database(dir.path());
+            database.create_collection_with_config(config(8)).unwrap();
+            for (i, vector) in vectors.iter().enumerate() {
+                let metadata = vec![("n".to_string(), i.to_string())];
+                database.insert_vector("test", i.to_string(), vector.clone(), Some(metadata)).unwrap();
+            }
+            database.update_vector("test", "0", vectors[1].clone()).unwrap();
+            database.remove_vector("test", "2").unwrap();
+        }
+        
+        let database = persistent_database(dir.path());
+        database.create_collection_with_config(config(8)).unwrap();
+        
+        let collection = database.get_collection("test").unwrap();
+        let collection = collection.read().unwrap();
+        assert_eq!(collection.document_ids().unwrap().len(), 49);
+        let documents = collection.get_documents(&["0".to_string(), "5".to_string()]).unwrap();
+        assert_eq!(documents[0].vector, vectors[1]);
+        assert_eq!(documents[1].metadata, Some(vec![("n".to_string(), "5".to_string())]));
+        drop(collection);
+        
+        for (i, vector) in vectors.iter().enumerate().skip(3) {
+            let results = database.search_vectors("test", vector.clone(), 1).unwrap();
+            assert_eq!(ids(&results), [i.to_string()]);
+        }
+        let results = database.search_vectors("test", vectors[2].clone(), 50).unwrap();
+        assert!(!ids(&results).contains(&"2"));
+        assert_eq!(results.len(), 49);
+    }
+    
+    #[cfg(feature = "persistence")]
+    #[test]
+    fn recovering_a_larger_collection_keeps_search_quality() {
+        let dir = tempfile::tempdir().unwrap();
+        let tuned = CollectionConfig {
+            m: 8,
+            ef_construction: 64,
+            ..config(16)
+        };
+        let vectors = clustered_vectors(1_000, 16, 20, 5);
+        {
+            let database = persistent_database(dir.path());
+            database.create_collection_with_config(tuned.clone()).unwrap();
+            let request = BatchInsertRequest {
+                vectors: vectors.iter().enumerate().map(|(i, v)| document(&i.to_string(), v.clone())).collect(),
+            };
+            assert_eq!(database.batch_upsert("test", request, ConflictPolicy::Error).unwrap().inserted, 1_000);
+        }
+        
+        let database = persistent_database(dir.path());
+        let start = Instant::now();
+        database.create_collection_with_config(tuned.clone()).unwrap();
+        let recovery = start.elapsed();
+        
+        let collection = database.get_collection("test").unwrap();
+        let collection = collection.read().unwrap();
+        assert_eq!(collection.document_ids().unwrap().len(), 1_000);
+        let queries = clustered_vectors(20, 16, 20, 6);
+        let recall: f32 = queries.iter().map(|query| collection.query_recall(query, 10).unwrap().unwrap()).sum::<f32>()
+            / queries.len() as f32;
+        assert!(recall >= 0.9, "recall {} after a {:?} recovery", recall, recovery);
+    }
+    
+    #[test]
+    fn is_duplicate_follows_the_collection_epsilon() {
+        let database = Database::new("test".to_string());
+        database
+            .create_collection_with_config(CollectionConfig { epsilon: Some(0.01), ..config(2) })
+            .unwrap();
+        database.create_collection_with_config(CollectionConfig { name: "strict".to_string(), ..config(2) }).unwrap();
+        for name in ["test", "strict"] {
+            database.insert_vector(name, "a".to_string(), vec![1.0, 1.0], None).unwrap();
+            database.insert_vector(name, "near".to_string(), vec![1.0, 1.005], None).unwrap();
+            database.insert_vector(name, "far".to_string(), vec![1.0, 1.02], None).unwrap();
+            database.insert_vector(name, "copy".to_string(), vec![1.0, 1.0], None).unwrap();
+        }
+        
+        assert!(database.is_duplicate("test", "a", "near").unwrap());
+        assert!(!database.is_duplicate("test", "a", "far").unwrap());
+        assert!(!database.is_duplicate("test", "a", "missing").unwrap());
+        
+        // The default epsilon only absorbs rounding noise.
+        assert!(database.is_duplicate("strict", "a", "copy").unwrap());
+        assert!(!database.is_duplicate("strict", "a", "near").unwrap());
+        
+        let distance = database.distance_between("test", "a", "far").unwrap().unwrap();
+        assert!((distance - 0.02).abs() < 1e-4);
+        assert_eq!(database.distance_between("test", "a", "missing").unwrap(), None);
+    }
+    
+    #[test]
+    fn negative_or_non_finite_epsilons_are_rejected() {
+        let database = Database::new("test".to_string());
+        for epsilon in [-0.1, f32::NAN, f32::INFINITY] {
+            assert!(database
+                .create_collection_with_config(CollectionConfig { epsilon: Some(epsilon), ..config(2) })
+                .is_err());
+        }
+        database.create_collection_with_config(CollectionConfig { epsilon: Some(0.0), ..config(2) }).unwrap();
+    }
+    
+    #[test]
+    fn failed_batch_inserts_report_distinguishable_codes() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(3)).unwrap();
+        
+        let sparse = VectorDocument {
+            sparse_vector: Some(SparseVector { indices: vec![0], values: vec![1.0] }),
+            ..document("sparse", Vec::new())
+        };
+        let request = BatchInsertRequest {
+            vectors: vec![
+                document("good", vec![1.0, 0.0, 0.0]),
+                document("short", vec![1.0, 0.0]),
+                document("not-a-number", vec![f32::NAN, 0.0, 0.0]),
+                document("", vec![0.0, 1.0, 0.0]),
+                sparse,
+            ],
+        };
+        let response = database.batch_insert_with_progress("test", request, |_, _| {}).unwrap();
+        
+        assert_eq!(response.inserted, 1);
+        let codes: Vec<(&str, ErrorCode)> = response.failed.iter().map(|(id, failure)| (id.as_str(), failure.code)).collect();
+        assert_eq!(
+            codes,
+            [
+                ("short", ErrorCode::DimensionMismatch),
+                ("not-a-number", ErrorCode::InvalidVector),
+                ("", ErrorCode::InvalidDocument),
+                ("sparse", ErrorCode::UnsupportedVectorType),
+            ]
+        );
+        assert!(response.failed.iter().all(|(_, failure)| !failure.message.is_empty()));
+    }
+    
+    #[test]
+    fn kth_distance_matches_the_kth_result_of_a_full_search() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(8)).unwrap();
+        for (i, vector) in random_vectors(60, 8, 11).into_iter().enumerate() {
+            database.insert_vector("test", i.to_string(), vector, None).unwrap();
+        }
+        
+        for query in random_vectors(5, 8, 12) {
+            let full = database.search_vectors("test", query.clone(), 60).unwrap();
+            for k in [1, 5, 17, 60] {
+                assert_eq!(database.kth_distance("test", &query, k).unwrap(), Some(full[k - 1].1), "k {}", k);
+            }
+            assert_eq!(database.kth_distance("test", &query, 61).unwrap(), None);
+            assert_eq!(database.kth_distance("test", &query, 0).unwrap(), None);
+        }
+        assert!(database.kth_distance("test", &vec![1.0; 3], 1).is_err());
+        assert!(database.kth_distance("missing", &vec![1.0; 8], 1).is_err());
+    }
+    
+    #[test]
+    fn search_detailed_pairs_each_distance_with_its_similarity() {
+        let metrics = [
+            DistanceMetric::Cosine,
+            DistanceMetric::Euclidean,
+            DistanceMetric::Manhattan,
+            DistanceMetric::DotProduct,
+            DistanceMetric::JensenShannon,
+        ];
+        // Positive components keep the vectors valid distributions for Jensen-Shannon.
+        let vectors: Vec<Vector> = random_vectors(30, 4, 13)
+            .into_iter()
+            .map(|v| v.into_iter().map(|x| x.abs() + 0.01).collect())
+            .collect();
+        let query = SearchQuery {
+            vector: vec![0.4, 0.3, 0.2, 0.1],
+            limit: 10,
+            ..Default::default()
+        };
+        
+        for metric in metrics {
+            let database = Database::new("test".to_string());
+            database.create_collection_with_config(CollectionConfig { metric, ..config(4) }).unwrap();
+            for (i, vector) in vectors.iter().enumerate() {
+                database.insert_vector("test", i.to_string(), vector.clone(), None).unwrap();
+            }
+            
+            let results = database.search("test", &query).unwrap();
+            let hits = database.search_detailed("test", &query).unwrap();
+            assert_eq!(hits.len(), results.len());
+            for (hit, (id, distance, _)) in hits.iter().zip(&results) {
+                assert_eq!((&hit.id, hit.distance), (id, *distance));
+                let expected = match metric {
+                    DistanceMetric::Euclidean | DistanceMetric::Manhattan => 1.0 / (1.0 + distance),
+                    _ => 1.0 - distance,
+                };
+                assert_eq!(hit.similarity, expected, "{:?}", metric);
+            }
+            assert!(hits.windows(2).all(|pair| pair[0].similarity >= pair[1].similarity), "{:?}", metric);
+        }
+    }
+    
+    #[test]
+    fn distance_matrix_is_symmetric_with_a_zero_diagonal() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(3)).unwrap();
+        let vectors = random_vectors(5, 3, 14);
+        for (i, vector) in vectors.iter().enumerate() {
+            database.insert_vector("test", i.to_string(), vector.clone(), None).unwrap();
+        }
+        let order: Vec<String> = ["3", "0", "4", "1", "2"].iter().map(|id| id.to_string()).collect();
+        
+        let DistanceMatrix::Full(matrix) = database.distance_matrix("test", &order).unwrap() else {
+            panic!("small id sets return the full matrix");
+        };
+        assert_eq!(matrix.len(), 5);
+        for (i, row) in matrix.iter().enumerate() {
+            assert_eq!(row[i], 0.0);
+            for (j, &distance) in row.iter().enumerate() {
+                assert_eq!(distance, matrix[j][i]);
+                let a = &vectors[order[i].parse::<usize>().unwrap()];
+                let b = &vectors[order[j].parse::<usize>().unwrap()];
+                assert!((distance - calculate_distance_with(a, b, DistanceMetric::Euclidean, true)).abs() < 1e-6);
+            }
+        }
+        
+        let error = database
+            .distance_matrix("test", &["0".to_string(), "missing".to_string(), "gone".to_string()])
+            .unwrap_err();
+        assert!(error.to_string().contains("missing, gone"), "{}", error);
+        assert!(database.distance_matrix("missing", &order).is_err());
+    }
+    
+    #[test]
+    fn large_distance_matrices_are_returned_upper_triangular() {
+        let mut collection = collection(CollectionConfig {
+            m: 4,
+            ef_construction: 16,
+            ..config(2)
+        });
+        let size = FULL_DISTANCE_MATRIX_LIMIT + 1;
+        let request = BatchInsertRequest {
+            vectors: (0..size).map(|i| document(&i.to_string(), vec![i as f32, 0.0])).collect(),
+        };
+        collection.batch_upsert(request, ConflictPolicy::Error).unwrap();
+        let ids: Vec<String> = (0..size).map(|i| i.to_string()).collect();
+        
+        let DistanceMatrix::UpperTriangular { size: n, values } = collection.distance_matrix(&ids).unwrap() else {
+            panic!("id sets above the limit return the upper triangle");
+        };
+        assert_eq!(n, size);
+        assert_eq!(values.len(), size * (size - 1) / 2);
+        // Row i starts after the (size - 1) + (size - 2) + ... entries of the rows before it.
+        let at = |i: usize, j: usize| values[i * (2 * size - i - 1) / 2 + (j - i - 1)];
+        assert_eq!(at(0, 1), 1.0);
+        assert_eq!(at(0, size - 1), (size - 1) as f32);
+        assert_eq!(at(7, 300), 293.0);
+        assert_eq!(at(size - 2, size - 1), 1.0);
+    }
+    
+    #[test]
+    fn search_simple_matches_the_equivalent_full_query() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(6)).unwrap();
+        for (i, vector) in random_vectors(80, 6, 15).into_iter().enumerate() {
+            let metadata = vec![("parity".to_string(), (i % 2).to_string())];
+            database.insert_vector("test", i.to_string(), vector, Some(metadata)).unwrap();
+        }
+        
+        let full = |vector: &Vector, k: usize| {
+            let query = SearchQuery {
+                vector: vector.clone(),
+                limit: k,
+                ..Default::default()
+            };
+            database.search("test", &query).unwrap()
+        };
+        let queries = random_vectors(4, 6, 16);
+        for query in &queries {
+            for k in [1, 10, 80] {
+                assert_eq!(database.search_simple("test", query, k).unwrap(), full(query, k));
+            }
+        }
+        
+        database.set_default_filter("test", equals("parity", "1")).unwrap();
+        let filtered = database.search_simple("test", &queries[0], 10).unwrap();
+        assert_eq!(filtered, full(&queries[0], 10));
+        assert!(ids(&filtered).iter().all(|id| id.parse::<usize>().unwrap() % 2 == 1));
+        
+        assert!(database.search_simple("test", &[1.0; 3], 5).is_err());
+    }
+    
+    #[cfg(feature = "persistence")]
+    #[test]
+    fn metadata_updates_and_deletes_are_recovered() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let database = persistent_database(dir.path());
+            database.create_collection_with_config(config(2)).unwrap();
+            for i in 0..6 {
+                let metadata = vec![("group".to_string(), (i % 2).to_string())];
+                database.insert_vector("test", i.to_string(), vec![i as f32, 1.0], Some(metadata)).unwrap();
+            }
+            database.update_metadata("test", "0", Some(vec![("group".to_string(), "solo".to_string())])).unwrap();
+            let flagged = vec![("flag".to_string(), "yes".to_string())];
+            assert_eq!(database.update_metadata_by_filter("test", &equals("group", "1"), flagged).unwrap(), 3);
+            database.remove_vector("test", "5").unwrap();
+        }
+        
+        let database = persistent_database(dir.path());
+        database.create_collection_with_config(config(2)).unwrap();
+        let meta = |id: &str| database.get_document_meta("test", id).unwrap().map(|(metadata, _)| metadata.unwrap());
+        
+        assert_eq!(meta("0").unwrap(), [("group".to_string(), "solo".to_string())]);
+        assert_eq!(
+            meta("3").unwrap(),
+            [("group".to_string(), "1".to_string()), ("flag".to_string(), "yes".to_string())]
+        );
+        assert_eq!(meta("2").unwrap(), [("group".to_string(), "0".to_string())]);
+        assert!(meta("5").is_none());
+        assert_eq!(database.search_vectors("test", vec![5.0, 1.0], 10).unwrap().len(), 5);
+    }
+    
+    #[test]
+    fn iter_documents_runs_alongside_concurrent_inserts() {
+        let database = Arc::new(Database::new("test".to_string()));
+        database.create_collection_with_config(config(2)).unwrap();
+        for i in 0..300 {
+            database.insert_vector("test", format!("before-{}", i), vec![i as f32, 0.0], None).unwrap();
+        }
+        
+        let documents = database.iter_documents("test").unwrap();
+        let writer = {
+            let database = Arc::clone(&database);
+            std::thread::spawn(move || {
+                for i in 0..300 {
+                    database.insert_vector("test", format!("during-{}", i), vec![0.0, i as f32], None).unwrap();
+                }
+            })
+        };
+        let mut visited: Vec<String> = documents.map(|document| document.id).collect();
+        writer.join().unwrap();
+        
+        let mut expected: Vec<String> = (0..300).map(|i| format!("before-{}", i)).collect();
+        expected.sort();
+        visited.sort();
+        assert_eq!(visited, expected);
+        assert_eq!(database.iter_documents("test").unwrap().count(), 600);
+    }
+    
+    #[test]
+    fn dimension_zero_locks_to_the_first_inserted_vector() {
+        let database = Database::new("test".to_string());
+        database.create_collection("inferred", 0).unwrap();
+        
+        let error = database.search_vectors("inferred", vec![1.0; 256], 5).unwrap_err();
+        assert!(error.to_string().contains("no dimension yet"), "{}", error);
+        
+        // A rejected first vector does not fix the dimension.
+        assert!(database.insert_vector("inferred", "bad".to_string(), vec![f32::NAN; 64], None).is_err());
+        assert!(database.insert_vector("inferred", "empty".to_string(), Vec::new(), None).is_err());
+        assert_eq!(database.get_collection_config("inferred").unwrap().dimension, 0);
+        
+        let vectors = random_vectors(3, 256, 18);
+        database.insert_vector("inferred", "first".to_string(), vectors[0].clone(), None).unwrap();
+        assert_eq!(database.get_collection_config("inferred").unwrap().dimension, 256);
+        
+        assert!(database.insert_vector("inferred", "short".to_string(), vec![1.0; 128], None).is_err());
+        database.insert_vector("inferred", "second".to_string(), vectors[1].clone(), None).unwrap();
+        assert_eq!(ids(&database.search_vectors("inferred", vectors[0].clone(), 1).unwrap()), ["first"]);
+        assert!(database.search_vectors("inferred", vec![1.0; 128], 1).is_err());
+    }
+    
+    #[test]
+    fn a_batch_locks_an_inferred_dimension_from_its_first_vector() {
+        let database = Database::new("test".to_string());
+        database.create_collection("inferred", 0).unwrap();
+        
+        let request = BatchInsertRequest {
+            vectors: vec![
+                document("a", vec![1.0, 0.0, 0.0]),
+                document("b", vec![1.0, 0.0]),
+                document("c", vec![0.0, 1.0, 0.0]),
+            ],
+        };
+        let response = database.batch_upsert("inferred", request, ConflictPolicy::Error).unwrap();
+        assert_eq!((response.inserted, response.failed), (2, 1));
+        assert_eq!(database.get_collection_config("inferred").unwrap().dimension, 3);
+        
+        assert!(database.create_sparse_collection("sparse", 0).is_err());
+    }
+    
+    #[test]
+    fn sample_returns_min_n_count_and_is_reproducible_with_a_seed() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(2)).unwrap();
+        for i in 0..100 {
+            database.insert_vector("test", i.to_string(), vec![i as f32, 0.0], None).unwrap();
+        }
+        database.insert_pending("test", "pending".to_string(), None).unwrap();
+        let sampled = |n: usize, seed: u64| -> Vec<String> {
+            database.sample("test", n, Some(seed)).unwrap().into_iter().map(|document| document.id).collect()
+        };
+        
+        let first = sampled(10, 7);
+        assert_eq!(first.len(), 10);
+        assert_eq!(first.iter().collect::<HashSet<_>>().len(), 10);
+        assert_eq!(sampled(10, 7), first);
+        assert_ne!(sampled(10, 8), first);
+        
+        assert_eq!(sampled(500, 7).len(), 100);
+        assert!(!sampled(500, 7).contains(&"pending".to_string()));
+        assert!(sampled(0, 7).is_empty());
+        assert_eq!(database.sample("test", 10, None).unwrap().len(), 10);
+        assert!(database.sample("missing", 10, None).is_err());
+    }
+    
+    #[test]
+    fn sampling_picks_every_document_about_equally_often() {
+        let mut collection = collection(config(2));
+        for i in 0..50 {
+            collection.insert_vector(i.to_string(), vec![i as f32, 0.0], None).unwrap();
+        }
+        
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for seed in 0..2_000 {
+            for document in collection.sample(5, Some(seed)).unwrap() {
+                *counts.entry(document.id).or_default() += 1;
+            }
+        }
+        // Each document is expected 200 times; the bounds sit about seven standard deviations out.
+        assert_eq!(counts.len(), 50);
+        assert!(counts.values().all(|&count| (100..=300).contains(&count)), "{:?}", counts);
+    }
+    
+    #[test]
+    fn query_transform_puts_raw_queries_in_the_stored_space() {
+        fn normalize(vector: &mut Vector) {
+            let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+            if norm > 0.0 {
+                vector.iter_mut().for_each(|x| *x /= norm);
+            }
+        }
+        
+        let mut collection = collection(config(4));
+        for (i, mut vector) in random_vectors(60, 4, 19).into_iter().enumerate() {
+            normalize(&mut vector);
+            collection.insert_vector(i.to_string(), vector, None).unwrap();
+        }
+        let raw: Vec<Vector> = random_vectors(5, 4, 20).into_iter().map(|v| v.iter().map(|x| x * 40.0).collect()).collect();
+        let normalized: Vec<Vector> = raw
+            .iter()
+            .map(|v| {
+                let mut v = v.clone();
+                normalize(&mut v);
+                v
+            })
+            .collect();
+        
+        let expected: Vec<_> = normalized.iter().map(|q| collection.search_vectors(q.clone(), 5).unwrap()).collect();
+        let expected_kth: Vec<_> = normalized.iter().map(|q| collection.kth_distance(q, 3).unwrap()).collect();
+        let expected_fused = collection.multi_query_search(&normalized, 5, FusionMethod::MinDistance).unwrap();
+        assert_ne!(collection.search_vectors(raw[0].clone(), 5).unwrap(), expected[0]);
+        
+        collection.set_query_transform(Box::new(normalize));
+        for (i, query) in raw.iter().enumerate() {
+            assert_eq!(collection.search_vectors(query.clone(), 5).unwrap(), expected[i]);
+            let full = SearchQuery {
+                vector: query.clone(),
+                limit: 5,
+                ..Default::default()
+            };
+            assert_eq!(collection.search(&full).unwrap(), expected[i]);
+            assert_eq!(collection.kth_distance(query, 3).unwrap(), expected_kth[i]);
+        }
+        assert_eq!(collection.multi_query_search(&raw, 5, FusionMethod::MinDistance).unwrap(), expected_fused);
+        
+        collection.clear_query_transform();
+        assert_ne!(collection.search_vectors(raw[0].clone(), 5).unwrap(), expected[0]);
+    }
+    
+    #[test]
+    fn query_transforms_set_through_the_database_apply_to_its_searches() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(2)).unwrap();
+        database.insert_vector("test", "right", vec![1.0, 0.0], None).unwrap();
+        database.insert_vector("test", "up", vec![0.0, 1.0], None).unwrap();
+        assert_eq!(ids(&database.search_vectors("test", vec![0.9, 0.1], 1).unwrap()), ["right"]);
+        
+        database.set_query_transform("test", Box::new(|vector: &mut Vector| vector.reverse())).unwrap();
+        assert_eq!(ids(&database.search_vectors("test", vec![0.9, 0.1], 1).unwrap()), ["up"]);
+        assert!(database.set_query_transform("missing", Box::new(|_: &mut Vector| {})).is_err());
+    }
+    
+    #[test]
+    fn batch_progress_rises_monotonically_to_the_total() {
+        let mut collection = collection(config(2));
+        collection.batch_size = 16;
+        let request = BatchInsertRequest {
+            vectors: (0..100)
+                .map(|i| document(&i.to_string(), if i % 30 == 0 { vec![1.0] } else { vec![i as f32, 1.0] }))
+                .collect(),
+        };
+        
+        let calls = Mutex::new(Vec::new());
+        let response = collection
+            .batch_insert_with_progress(request, |processed, total| calls.lock().unwrap().push((processed, total)))
+            .unwrap();
+        
+        let calls = calls.into_inner().unwrap();
+        assert_eq!(response.inserted, 96);
+        assert_eq!(response.failed.len(), 4);
+        assert!(calls.iter().all(|&(_, total)| total == 100));
+        let processed: Vec<usize> = calls.iter().map(|&(processed, _)| processed).collect();
+        assert_eq!(processed, [16, 32, 48, 64, 80, 96, 100]);
+        
+        let called = AtomicBool::new(false);
+        let oversized = BatchInsertRequest {
+            vectors: (0..collection.config().max_batch_size + 1).map(|i| document(&i.to_string(), vec![0.0, 1.0])).collect(),
+        };
+        assert!(collection.batch_insert_with_progress(oversized, |_, _| called.store(true, AtomicOrdering::SeqCst)).is_err());
+        assert!(!called.load(AtomicOrdering::SeqCst));
+    }
+    
+    #[test]
+    fn default_filter_scopes_every_search_unless_opted_out() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(2)).unwrap();
+        for i in 0..40 {
+            let metadata = vec![
+                ("tenant".to_string(), if i % 2 == 0 { "a" } else { "b" }.to_string()),
+                ("kind".to_string(), if i % 4 < 2 { "doc" } else { "image" }.to_string()),
+            ];
+            database.insert_vector("test", i.to_string(), vec![i as f32, 0.0], Some(metadata)).unwrap();
+        }
+        let tenant = |results: &[SearchResult]| -> HashSet<String> {
+            results.iter().map(|(_, _, metadata)| metadata.as_ref().unwrap()[0].1.clone()).collect()
+        };
+        let kinds = |results: &[SearchResult]| -> HashSet<String> {
+            results.iter().map(|(_, _, metadata)| metadata.as_ref().unwrap()[1].1.clone()).collect()
+        };
+        
+        database.set_default_filter("test", equals("tenant", "a")).unwrap();
+        let scoped = database.search_vectors("test", vec![21.0, 0.0], 40).unwrap();
+        assert_eq!(scoped.len(), 20);
+        assert_eq!(tenant(&scoped), HashSet::from(["a".to_string()]));
+        
+        let mut query = SearchQuery {
+            vector: vec![21.0, 0.0],
+            limit: 40,
+            filter: Some(equals("kind", "image")),
+            ..Default::default()
+        };
+        let combined = database.search("test", &query).unwrap();
+        assert_eq!(combined.len(), 10);
+        assert_eq!(tenant(&combined), HashSet::from(["a".to_string()]));
+        assert_eq!(kinds(&combined), HashSet::from(["image".to_string()]));
+        
+        query.ignore_default_filter = true;
+        let unscoped = database.search("test", &query).unwrap();
+        assert_eq!(unscoped.len(), 20);
+        assert_eq!(tenant(&unscoped).len(), 2);
+        
+        database.clear_default_filter("test").unwrap();
+        assert_eq!(database.search_vectors("test", vec![21.0, 0.0], 40).unwrap().len(), 40);
+    }
+    
+    /// Checks the subset of draft-07 that schemars emits for these types.
+    #[cfg(feature = "schema")]
+    fn conforms(schema: &serde_json::Value, value: &serde_json::Value, root: &serde_json::Value) -> bool {
+        use serde_json::Value;
+        
+        if let Some(Value::String(reference)) = schema.get("$ref") {
+            let name = reference.trim_start_matches("#/definitions/");
+            return conforms(&root["definitions"][name], value, root);
+        }
+        if let Some(Value::Array(options)) = schema.get("anyOf") {
+            return options.iter().any(|option| conforms(option, value, root));
+        }
+        
+        let type_matches = |name: &str| match name {
+            "object" => value.is_object(),
+            "array" => value.is_array(),
+            "string" => value.is_string(),
+            "integer" => value.is_u64() || value.is_i64(),
+            "number" => value.is_number(),
+            "boolean" => value.is_boolean(),
+            "null" => value.is_null(),
+            _ => true,
+        };
+        let typed = match schema.get("type") {
+            Some(Value::String(name)) => type_matches(name),
+            Some(Value::Array(names)) => names.iter().filter_map(Value::as_str).any(type_matches),
+            _ => true,
+        };
+        if !typed {
+            return false;
+        }
+        if let (Some(minimum), Some(number)) = (schema.get("minimum").and_then(Value::as_f64), value.as_f64()) {
+            if number < minimum {
+                return false;
+            }
+        }
+        if let Some(enumerated) = schema.get("enum").and_then(Value::as_array) {
+            if !enumerated.contains(value) {
+                return false;
+            }
+        }
+        
+        match value {
+            Value::Object(fields) => {
+                let required = schema.get("required").and_then(Value::as_array).into_iter().flatten();
+                let properties = schema.get("properties").and_then(Value::as_object);
+                required.filter_map(Value::as_str).all(|key| fields.contains_key(key))
+                    && fields.iter().all(|(key, field)| {
+                        properties.and_then(|properties| properties.get(key)).is_none_or(|p| conforms(p, field, root))
+                    })
+            }
+            Value::Array(elements) => match schema.get("items") {
+                Some(Value::Array(tuple)) => {
+                    elements.len() == tuple.len() && tuple.iter().zip(elements).all(|(item, e)| conforms(item, e, root))
+                }
+                Some(item) => elements.iter().all(|element| conforms(item, element, root)),
+                None => true,
+            },
+            _ => true,
+        }
+    }
+    
+    #[cfg(feature = "schema")]
+    #[test]
+    fn api_schema_accepts_good_payloads_and_rejects_bad_ones() {
+        use serde_json::json;
+        
+        let schemas = Database::api_schema();
+        let names = [
+            "SearchQuery",
+            "SearchResponse",
+            "SearchHit",
+            "VectorDocument",
+            "MetadataFilter",
+            "BatchInsertRequest",
+            "BatchInsertResponse",
+        ];
+        for name in names {
+            assert_eq!(schemas[name]["title"], name);
+        }
+        let check = |name: &str, payload: serde_json::Value| conforms(&schemas[name], &payload, &schemas[name]);
+        
+        let document = serde_json::to_value(VectorDocument {
+            metadata: Some(vec![("lang".to_string(), "en".to_string())]),
+            ..document("a", vec![0.5, 1.0])
+        })
+        .unwrap();
+        assert!(check("VectorDocument", document.clone()));
+        assert!(check("BatchInsertRequest", json!({ "vectors": [document] })));
+        let query = SearchQuery {
+            vector: vec![1.0, 0.0],
+            limit: 5,
+            filter: Some(equals("lang", "en")),
+            ..Default::default()
+        };
+        assert!(check("SearchQuery", serde_json::to_value(query).unwrap()));
+        
+        assert!(!check("VectorDocument", json!({ "id": "a", "vector": [0.5, 1.0] })));
+        assert!(!check("VectorDocument", json!({ "id": "a", "vector": ["x"], "timestamp": 0 })));
+        assert!(!check("VectorDocument", json!({ "id": "a", "vector": [], "timestamp": -1 })));
+        assert!(!check("VectorDocument", json!({ "id": "a", "vector": [], "timestamp": 0, "metadata": [["lang"]] })));
+        assert!(!check("BatchInsertRequest", json!({ "vectors": [{ "id": 7, "vector": [], "timestamp": 0 }] })));
+        assert!(!check("MetadataFilter", json!({ "conditions": [], "operator": "Xor" })));
+    }
+    
+    #[test]
+    fn update_metadata_by_filter_retags_only_the_matching_documents() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(2)).unwrap();
+        for i in 0..30 {
+            let metadata = vec![
+                ("year".to_string(), (2010 + i % 15).to_string()),
+                ("status".to_string(), "active".to_string()),
+            ];
+            database.insert_vector("test", i.to_string(), vec![i as f32, 0.0], Some(metadata)).unwrap();
+        }
+        database.insert_vector("test", "untagged".to_string(), vec![0.0, 1.0], None).unwrap();
+        let old = MetadataFilter {
+            conditions: vec![FilterCondition {
+                key: "year".to_string(),
+                value: "201".to_string(),
+                operation: FilterOperation::StartsWith,
+            }],
+            operator: FilterOperator::And,
+        };
+        
+        let updates = vec![("status".to_string(), "archived".to_string()), ("reviewed".to_string(), "yes".to_string())];
+        assert_eq!(database.update_metadata_by_filter("test", &old, updates.clone()).unwrap(), 20);
+        assert_eq!(database.update_metadata_by_filter("test", &old, updates).unwrap(), 0);
+        
+        let query = |filter: MetadataFilter| {
+            let query = SearchQuery {
+                vector: vec![0.0, 0.0],
+                limit: 50,
+                filter: Some(filter),
+                ..Default::default()
+            };
+            let mut found: Vec<usize> = ids(&database.search("test", &query).unwrap()).iter().map(|id| id.parse().unwrap()).collect();
+            found.sort();
+            found
+        };
+        let archived = query(equals("status", "archived"));
+        assert_eq!(archived, (0..30).filter(|i| i % 15 < 10).collect::<Vec<_>>());
+        assert_eq!(query(equals("reviewed", "yes")), archived);
+        assert_eq!(query(equals("status", "active")), (0..30).filter(|i| i % 15 >= 10).collect::<Vec<_>>());
+        
+        let (metadata, _) = database.get_document_meta("test", "0").unwrap().unwrap();
+        assert_eq!(
+            metadata.unwrap(),
+            [
+                ("year".to_string(), "2010".to_string()),
+                ("status".to_string(), "archived".to_string()),
+                ("reviewed".to_string(), "yes".to_string()),
+            ]
+        );
+        assert_eq!(database.get_document_meta("test", "untagged").unwrap().unwrap().0, None);
+        assert!(database.update_metadata_by_filter("test", &old, vec![(String::new(), "x".to_string())]).is_err());
+    }
+    
+    #[test]
+    fn nan_distances_error_by_default_and_sink_last_when_configured() {
+        let trusted = CollectionConfig {
+            skip_value_validation: true,
+            ..config(2)
+        };
+        let populate = |collection: &mut Collection| {
+            for i in 0..10 {
+                collection.insert_vector(i.to_string(), vec![i as f32, 0.0], None).unwrap();
+            }
+            collection.insert_vector("nan".to_string(), vec![f32::NAN, 0.0], None).unwrap();
+        };
+        
+        let mut strict = collection(trusted.clone());
+        populate(&mut strict);
+        let error = strict.search_vectors(vec![3.0, 0.0], 11).unwrap_err();
+        assert!(
+            matches!(error.downcast_ref::<SolarisError>(), Some(SolarisError::NanDistance(id)) if id == "nan"),
+            "{}",
+            error
+        );
+        
+        let performance = PerformanceConfig {
+            nan_handling: NanHandling::SinkLast,
+            ..Default::default()
+        };
+        let mut lenient = Collection::new(trusted, &performance);
+        populate(&mut lenient);
+        let results = lenient.search_vectors(vec![3.0, 0.0], 11).unwrap();
+        assert_eq!(ids(&results), ["3", "2", "4", "1", "5", "0", "6", "7", "8", "9", "nan"]);
+        assert!(results[10].1.is_nan());
+        assert_eq!(ids(&lenient.search_vectors(vec![3.0, 0.0], 3).unwrap()), ["3", "2", "4"]);
+    }
+    
+    #[test]
+    fn clone_collection_copies_documents_and_search_behaviour() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(8)).unwrap();
+        for (i, vector) in random_vectors(100, 8, 25).into_iter().enumerate() {
+            let metadata = vec![("n".to_string(), i.to_string())];
+            database.insert_vector("test", i.to_string(), vector, Some(metadata)).unwrap();
+        }
+        
+        database.clone_collection("test", "copy").unwrap();
+        assert_eq!(database.get_collection_config("copy").unwrap().name, "copy");
+        for query in random_vectors(5, 8, 26) {
+            assert_eq!(
+                database.search_vectors("copy", query.clone(), 10).unwrap(),
+                database.search_vectors("test", query, 10).unwrap()
+            );
+        }
+        
+        database.remove_vector("copy", "0").unwrap();
+        assert!(database.get_document_meta("test", "0").unwrap().is_some());
+        assert!(database.clone_collection("test", "copy").is_err());
+        assert!(database.clone_collection("missing", "other").is_err());
+    }
+    
+    #[test]
+    fn dot_product_ranks_unnormalized_vectors_by_descending_inner_product() {
+        let config = CollectionConfig {
+            metric: DistanceMetric::DotProduct,
+            ..config(6)
+        };
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config).unwrap();
+        // Spread the norms so the largest inner product is often not the closest direction.
+        let vectors: Vec<Vector> = random_vectors(150, 6, 21)
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| v.into_iter().map(|x| x * (1.0 + (i % 7) as f32)).collect())
+            .collect();
+        for (i, vector) in vectors.iter().enumerate() {
+            database.insert_vector("test", i.to_string(), vector.clone(), None).unwrap();
+        }
+        
+        let dot = |a: &Vector, b: &Vector| a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>();
+        for query in random_vectors(10, 6, 22) {
+            let search = SearchQuery {
+                vector: query.clone(),
+                limit: 10,
+                ef: Some(150),
+                ..Default::default()
+            };
+            let results = database.search("test", &search).unwrap();
+            
+            let best = (0..vectors.len())
+                .max_by(|&a, &b| dot(&query, &vectors[a]).total_cmp(&dot(&query, &vectors[b])))
+                .unwrap();
+            assert_eq!(results[0].0, best.to_string());
+            
+            let dots: Vec<f32> = results.iter().map(|r| dot(&query, &vectors[r.0.parse::<usize>().unwrap()])).collect();
+            assert!(dots.windows(2).all(|w| w[0] >= w[1]), "not descending: {:?}", dots);
+            for (result, expected) in results.iter().zip(&dots) {
+                assert!((result.1 - (1.0 - expected)).abs() < 1e-4);
+            }
+        }
+    }
+    
+    #[test]
+    fn dimension_weights_reorder_results_and_are_validated() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(2)).unwrap();
+        database.insert_vector("test", "a".to_string(), vec![1.0, 4.5], None).unwrap();
+        database.insert_vector("test", "b".to_string(), vec![4.0, 1.0], None).unwrap();
+        database.insert_vector("test", "c".to_string(), vec![6.0, 6.0], None).unwrap();
+        
+        let search = |weights: Option<Vec<f32>>| {
+            let query = SearchQuery {
+                vector: vec![0.0, 0.0],
+                limit: 3,
+                dimension_weights: weights,
+                ..Default::default()
+            };
+            database.search("test", &query)
+        };
+        assert_eq!(ids(&search(None).unwrap()), vec!["b", "a", "c"]);
+        assert_eq!(ids(&search(Some(vec![1.0, 0.1])).unwrap()), vec!["a", "b", "c"]);
+        assert_eq!(ids(&search(Some(vec![0.1, 1.0])).unwrap()), vec!["b", "a", "c"]);
+        
+        let weighted = search(Some(vec![1.0, 0.1])).unwrap();
+        assert!((weighted[0].1 - (1.0f32 + 0.1 * 4.5 * 4.5).sqrt()).abs() < 1e-5);
+        
+        assert!(search(Some(vec![1.0])).is_err());
+        assert!(search(Some(vec![1.0, -1.0])).is_err());
+        assert!(search(Some(vec![1.0, f32::NAN])).is_err());
+    }
+    
+    #[cfg(feature = "persistence")]
+    #[test]
+    fn sync_on_insert_puts_each_document_on_disk_before_returning() {
+        fn on_disk(dir: &std::path::Path) -> String {
+            let mut contents = String::new();
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    contents.push_str(&on_disk(&path));
+                } else if path.extension().is_some_and(|ext| ext == "jsonl") {
+                    contents.push_str(&std::fs::read_to_string(&path).unwrap());
+                }
+            }
+            contents
+        }
+        
+        let dir = tempfile::tempdir().unwrap();
+        let database = persistent_database(dir.path());
+        database.create_collection_with_config(CollectionConfig {
+            name: "synced".to_string(),
+            sync_on_insert: true,
+            ..config(2)
+        }).unwrap();
+        database.create_collection_with_config(config(2)).unwrap();
+        
+        database.insert_vector("synced", "durable".to_string(), vec![1.0, 2.0], None).unwrap();
+        database.insert_vector("test", "buffered".to_string(), vec![1.0, 2.0], None).unwrap();
+        let contents = on_disk(dir.path());
+        assert!(contents.contains("durable"));
+        assert!(!contents.contains("buffered"));
+    }
+    
+    #[test]
+    fn search_with_predicate_fills_the_limit_from_accepted_documents() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(4)).unwrap();
+        let vectors = random_vectors(100, 4, 41);
+        for (i, vector) in vectors.iter().enumerate() {
+            database.insert_vector("test", i.to_string(), vector.clone(), None).unwrap();
+        }
+        
+        // Only ids 10..=99 have an even length; the query sits on id 3, which is rejected.
+        let even_length = |document: &VectorDocument| document.id.len().is_multiple_of(2);
+        let results = database.search_with_predicate("test", &vectors[3], 10, even_length).unwrap();
+        assert_eq!(results.len(), 10);
+        assert!(ids(&results).iter().all(|id| id.len() == 2), "{:?}", ids(&results));
+        
+        let mut exact: Vec<(usize, f32)> = (10..100)
+            .map(|i| (i, calculate_distance_with(&vectors[3], &vectors[i], DistanceMetric::Euclidean, true)))
+            .collect();
+        exact.sort_by(|a, b| compare_distances(a.1, b.1));
+        assert_eq!(results[0].0, exact[0].0.to_string());
+        
+        let results = database.search_with_predicate("test", &vectors[3], 10, |document| document.id == "7").unwrap();
+        assert_eq!(ids(&results), vec!["7"]);
+        assert!(database.search_with_predicate("test", &vectors[3], 10, |_| false).unwrap().is_empty());
+    }
+    
+    #[test]
+    fn rename_collection_moves_a_populated_collection() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(3)).unwrap();
+        database.create_collection_with_config(CollectionConfig { name: "taken".to_string(), ..config(3) }).unwrap();
+        let vectors = random_vectors(20, 3, 51);
+        for (i, vector) in vectors.iter().enumerate() {
+            database.insert_vector("test", i.to_string(), vector.clone(), None).unwrap();
+        }
+        
+        assert!(database.rename_collection("test", "taken").is_err());
+        assert!(database.rename_collection("missing", "other").is_err());
+        assert!(database.get_collection("test").is_ok());
+        
+        database.rename_collection("test", "renamed").unwrap();
+        assert!(database.get_collection("test").is_err());
+        let collection = database.get_collection("renamed").unwrap();
+        assert_eq!(collection.read().unwrap().config().name, "renamed");
+        assert_eq!(collection.read().unwrap().document_ids().unwrap().len(), 20);
+        drop(collection);
+        
+        let results = database.search_vectors("renamed", vectors[4].clone(), 1).unwrap();
+        assert_eq!(ids(&results), ["4"]);
+        database.insert_vector("renamed", "new".to_string(), vec![0.0; 3], None).unwrap();
+        database.create_collection_with_config(config(3)).unwrap();
+    }
+    
+    #[cfg(feature = "persistence")]
+    #[test]
+    fn renamed_persistent_collections_reopen_under_the_new_name() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let database = persistent_database(dir.path());
+            database.create_collection_with_config(config(2)).unwrap();
+            database.insert_vector("test", "a".to_string(), vec![1.0, 2.0], None).unwrap();
+            database.rename_collection("test", "renamed").unwrap();
+            database.insert_vector("renamed", "b".to_string(), vec![3.0, 4.0], None).unwrap();
+        }
+        
+        let database = persistent_database(dir.path());
+        database.create_collection_with_config(CollectionConfig { name: "renamed".to_string(), ..config(2) }).unwrap();
+        database.create_collection_with_config(config(2)).unwrap();
+        let mut renamed = database.get_collection("renamed").unwrap().read().unwrap().document_ids().unwrap();
+        renamed.sort();
+        assert_eq!(renamed, vec!["a", "b"]);
+        assert!(database.get_collection("test").unwrap().read().unwrap().document_ids().unwrap().is_empty());
+    }
+    
+    #[test]
+    fn match_info_reports_the_or_branches_each_hit_satisfied() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(2)).unwrap();
+        let documents = [
+            ("red-large", "red", "large"),
+            ("red-small", "red", "small"),
+            ("blue-large", "blue", "large"),
+            ("blue-small", "blue", "small"),
+        ];
+        for (i, (id, color, size)) in documents.iter().enumerate() {
+            let metadata = vec![("color".to_string(), color.to_string()), ("size".to_string(), size.to_string())];
+            database.insert_vector("test", id.to_string(), vec![i as f32, 0.0], Some(metadata)).unwrap();
+        }
+        
+        let mut filter = equals("color", "red");
+        filter.conditions.extend(equals("size", "large").conditions);
+        filter.operator = FilterOperator::Or;
+        let query = SearchQuery {
+            vector: vec![0.0, 0.0],
+            limit: 4,
+            filter: Some(filter),
+            ..Default::default()
+        };
+        let matched: Vec<(String, Vec<String>)> = database
+            .search_with_match_info("test", &query)
+            .unwrap()
+            .into_iter()
+            .map(|(result, conditions)| (result.0, conditions.into_iter().map(|c| c.key).collect()))
+            .collect();
+        assert_eq!(
+            matched,
+            vec![
+                ("red-large".to_string(), vec!["color".to_string(), "size".to_string()]),
+                ("red-small".to_string(), vec!["color".to_string()]),
+                ("blue-large".to_string(), vec!["size".to_string()]),
+            ]
+        );
+        
+        let unfiltered = SearchQuery { filter: None, ..query };
+        let results = database.search_with_match_info("test", &unfiltered).unwrap();
+        assert_eq!(results.len(), 4);
+        assert!(results.iter().all(|(_, conditions)| conditions.is_empty()));
+    }
+    
+    #[test]
+    fn recent_returns_the_latest_writes_newest_first() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(2)).unwrap();
+        for i in 0..20 {
+            database.insert_vector("test", format!("doc-{:02}", i), vec![i as f32, 0.0], None).unwrap();
+        }
+        let recent_ids = |n: usize| -> Vec<String> {
+            database.recent("test", n).unwrap().into_iter().map(|document| document.id).collect()
+        };
+        
+        // All twenty land within the same second, so only write order can tell them apart.
+        assert_eq!(recent_ids(3), vec!["doc-19", "doc-18", "doc-17"]);
+        assert!(recent_ids(0).is_empty());
+        let all = recent_ids(100);
+        assert_eq!(all.len(), 20);
+        assert_eq!(all.last().unwrap(), "doc-00");
+        
+        database.remove_vector("test", "doc-19").unwrap();
+        database.update_vector("test", "doc-05", vec![5.0, 1.0]).unwrap();
+        assert_eq!(recent_ids(3), vec!["doc-05", "doc-18", "doc-17"]);
+        assert_eq!(database.recent("test", 1).unwrap()[0].vector, vec![5.0, 1.0]);
+    }
+    
+    #[test]
+    fn connectivity_report_finds_no_duplicate_edges_after_many_inserts() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(4)).unwrap();
+        for (i, vector) in random_vectors(500, 4, 61).into_iter().enumerate() {
+            if i < 400 {
+                database.insert_vector("test", i.to_string(), vector, None).unwrap();
+            } else {
+                database.update_vector("test", &(i - 400).to_string(), vector).unwrap();
+            }
+        }
+        assert_eq!(database.connectivity_report("test").unwrap().duplicate_edges, 0);
+        assert!(database.connectivity_report("missing").is_err());
+    }
+    
+    #[test]
+    fn applying_the_event_stream_makes_a_replica_converge() {
+        let primary = Database::new("primary".to_string());
+        let events = primary.subscribe().unwrap();
+        
+        primary.create_collection_with_config(config(3)).unwrap();
+        primary.create_collection_with_config(CollectionConfig { name: "scratch".to_string(), ..config(3) }).unwrap();
+        for (i, vector) in random_vectors(30, 3, 71).into_iter().enumerate() {
+            let metadata = vec![("n".to_string(), i.to_string())];
+            primary.insert_vector("test", i.to_string(), vector, Some(metadata)).unwrap();
+        }
+        primary.update_vector("test", "3", vec![9.0, 9.0, 9.0]).unwrap();
+        primary.update_metadata("test", "4", None).unwrap();
+        primary.remove_vector("test", "5").unwrap();
+        primary.insert_vector("scratch", "x".to_string(), vec![1.0; 3], None).unwrap();
+        primary.drop_collection("scratch").unwrap();
+        primary.rename_collection("test", "live").unwrap();
+        
+        let replica = Database::new("replica".to_string());
+        let mut last_seq = 0;
+        for event in events.try_iter() {
+            assert!(event.seq > last_seq);
+            last_seq = event.seq;
+            replica.apply(event).unwrap();
+        }
+        
+        let mut names = replica.list_collections().unwrap();
+        names.sort();
+        assert_eq!(names, vec!["live"]);
+        let snapshot = |database: &Database| {
+            let collection = database.get_collection("live").unwrap();
+            let collection = collection.read().unwrap();
+            let mut ids = collection.document_ids().unwrap();
+            ids.sort();
+            collection.get_documents(&ids).unwrap()
+        };
+        let (expected, actual) = (snapshot(&primary), snapshot(&replica));
+        assert_eq!(actual.len(), 29);
+        for (expected, actual) in expected.iter().zip(&actual) {
+            assert_eq!((&expected.id, &expected.vector, &expected.metadata), (&actual.id, &actual.vector, &actual.metadata));
+        }
+    }
+    
+    #[test]
+    fn subscribe_after_replays_only_the_missed_events() {
+        let mut settings = SolarisConfig::default();
+        settings.database.replication_log_size = 4;
+        let primary = Database::with_config(&settings);
+        primary.create_collection_with_config(config(2)).unwrap();
+        for i in 0..3 {
+            primary.insert_vector("test", i.to_string(), vec![i as f32, 0.0], None).unwrap();
+        }
+        
+        let caught_up: Vec<u64> = primary.subscribe_after(2).unwrap().try_iter().map(|event| event.seq).collect();
+        assert_eq!(caught_up, vec![3, 4]);
+        
+        primary.insert_vector("test", "3".to_string(), vec![3.0, 0.0], None).unwrap();
+        primary.insert_vector("test", "4".to_string(), vec![4.0, 0.0], None).unwrap();
+        // Only seqs 3..=6 are retained now, so a replica stuck at 1 has to resync.
+        assert!(primary.subscribe_after(1).is_err());
+        let caught_up: Vec<u64> = primary.subscribe_after(4).unwrap().try_iter().map(|event| event.seq).collect();
+        assert_eq!(caught_up, vec![5, 6]);
+    }
+    
+    #[test]
+    fn recall_monitor_alerts_once_when_the_graph_degrades() {
+        use crate::core::recall_monitor::RecallMonitorConfig;
+        use std::sync::Mutex;
+        
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(8)).unwrap();
+        for (i, vector) in clustered_vectors(600, 8, 6, 81).into_iter().enumerate() {
+            database.insert_vector("test", i.to_string(), vector, None).unwrap();
+        }
+        
+        let alerts = Arc::new(Mutex::new(Vec::new()));
+        let recorded = alerts.clone();
+        let monitor_config = RecallMonitorConfig {
+            interval: Duration::from_millis(20),
+            queries: 10,
+            k: 10,
+            threshold: 0.9,
+        };
+        let monitor = database
+            .monitor_recall("test", monitor_config, Box::new(move |name, recall| {
+                recorded.lock().unwrap().push((name.to_string(), recall));
+            }))
+            .unwrap();
+        let wait_for = |done: &dyn Fn(&RecallStats) -> bool| {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            loop {
+                let stats = database.recall_stats("test").unwrap();
+                if done(&stats) || Instant::now() > deadline {
+                    return stats;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        };
+        
+        let healthy = wait_for(&|stats| stats.measurements >= 1);
+        assert!(healthy.last_recall.unwrap() >= 0.9, "{:?}", healthy);
+        assert_eq!(healthy.alerts, 0);
+        
+        // A single link per node leaves most of the graph unreachable from the entry point.
+        database.prune("test", 1).unwrap();
+        let degraded = wait_for(&|stats| stats.alerts >= 1);
+        assert!(degraded.last_recall.unwrap() < 0.9, "{:?}", degraded);
+        let measured = degraded.measurements;
+        let later = wait_for(&|stats| stats.measurements >= measured + 3);
+        assert_eq!(later.alerts, 1);
+        
+        drop(monitor);
+        let alerts = alerts.lock().unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].0, "test");
+        assert!(alerts[0].1 < 0.9);
+        let stopped = database.recall_stats("test").unwrap().measurements;
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(database.recall_stats("test").unwrap().measurements, stopped);
+    }
+    
+    #[test]
+    fn composite_ids_survive_insert_get_search_and_delete() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(2)).unwrap();
+        let first = CompositeId::new(["tenant/a", "doc-1"]);
+        let second = CompositeId::new(["tenant", "a/doc-1"]);
+        database.insert_vector("test", first.clone(), vec![0.0, 0.0], None).unwrap();
+        database.insert_vector("test", second.clone(), vec![5.0, 5.0], None).unwrap();
+        
+        let results = database.search_vectors("test", vec![0.1, 0.0], 2).unwrap();
+        let found: Vec<CompositeId> = results.iter().map(|result| result.0.parse().unwrap()).collect();
+        assert_eq!(found, vec![first.clone(), second.clone()]);
+        assert_eq!(found[0].parts(), ["tenant/a", "doc-1"]);
+        
+        let collection = database.get_collection("test").unwrap();
+        let documents = collection.read().unwrap().get_documents(&[second.to_string()]).unwrap();
+        assert_eq!(documents[0].vector, vec![5.0, 5.0]);
+        drop(collection);
+        
+        assert!(database.remove_vector("test", &first.to_string()).unwrap());
+        let results = database.search_vectors("test", vec![0.1, 0.0], 2).unwrap();
+        assert_eq!(ids(&results), [second.to_string().as_str()]);
+    }
+    
+    #[test]
+    fn dimension_range_ranks_by_the_selected_slice_only() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(4)).unwrap();
+        database.insert_vector("test", "text", vec![1.0, 1.0, 9.0, 9.0], None).unwrap();
+        database.insert_vector("test", "image", vec![8.0, 8.0, 1.0, 1.0], None).unwrap();
+        database.insert_vector("test", "neither", vec![5.0, 5.0, 5.0, 5.0], None).unwrap();
+        
+        let search = |range: Option<(usize, usize)>, weights: Option<Vec<f32>>| {
+            let query = SearchQuery {
+                vector: vec![1.0, 1.0, 1.0, 1.0],
+                limit: 3,
+                dimension_range: range,
+                dimension_weights: weights,
+                ..Default::default()
+            };
+            database.search("test", &query)
+        };
+        assert_eq!(ids(&search(None, None).unwrap()), vec!["neither", "image", "text"]);
+        
+        let text = search(Some((0, 2)), None).unwrap();
+        assert_eq!(ids(&text), vec!["text", "neither", "image"]);
+        assert_eq!(text[0].1, 0.0);
+        assert!((text[1].1 - 32.0f32.sqrt()).abs() < 1e-5);
+        assert_eq!(ids(&search(Some((2, 4)), None).unwrap()), vec!["image", "neither", "text"]);
+        
+        // Weights outside the range are ignored; inside it they still apply.
+        let weighted = search(Some((2, 4)), Some(vec![100.0, 100.0, 1.0, 0.0])).unwrap();
+        assert_eq!(ids(&weighted)[0], "image");
+        assert!((weighted[1].1 - 4.0).abs() < 1e-5);
+        
+        for range in [(2, 2), (3, 1), (2, 5)] {
+            assert!(search(Some(range), None).is_err());
+        }
+    }
+    
+    #[test]
+    fn maintenance_compacts_and_prunes_while_the_collection_stays_searchable() {
+        use crate::core::maintenance::MaintenanceConfig;
+        
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(8)).unwrap();
+        let vectors = clustered_vectors(300, 8, 6, 91);
+        for (i, vector) in vectors.iter().enumerate() {
+            database.insert_vector("test", i.to_string(), vector.clone(), None).unwrap();
+        }
+        {
+            // Tombstones without the automatic background compaction `remove_vector` would start.
+            let collection = database.get_collection("test").unwrap();
+            let mut collection = collection.write().unwrap();
+            for i in (0..300).filter(|i| i % 3 == 0) {
+                collection.remove_document(&i.to_string()).unwrap();
+            }
+            assert!(collection.needs_compaction());
+        }
+        
+        let scheduler = database
+            .start_maintenance("test", MaintenanceConfig {
+                compact_interval: Some(Duration::from_millis(30)),
+                prune_interval: Some(Duration::from_millis(50)),
+                prune_max_degree: 6,
+                optimize_interval: None,
+                purge_interval: None,
+            })
+            .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            for i in (1..300).step_by(37).filter(|i| i % 3 != 0) {
+                let results = database.search_vectors("test", vectors[i].clone(), 10).unwrap();
+                assert_eq!(results.len(), 10);
+                assert!(ids(&results).iter().all(|id| id.parse::<usize>().unwrap() % 3 != 0));
+            }
+            let status = scheduler.status().unwrap();
+            if (status.compactions >= 1 && status.prunes >= 2) || Instant::now() > deadline {
+                break;
+            }
+        }
+        
+        let status = scheduler.stop();
+        assert!(status.stopped);
+        assert_eq!(status.running, None);
+        assert_eq!(status.compactions, 1);
+        assert!(status.prunes >= 2);
+        assert_eq!(status.last_error, None);
+        
+        let collection = database.get_collection("test").unwrap();
+        let collection = collection.read().unwrap();
+        assert!(!collection.needs_compaction());
+        assert_eq!(collection.document_ids().unwrap().len(), 200);
+        let mut found = 0;
+        for i in (0..300).filter(|i| i % 3 != 0) {
+            let results = collection.search_vectors(vectors[i].clone(), 1).unwrap();
+            found += usize::from(results[0].0 == i.to_string());
+        }
+        assert!(found >= 190, "{} of 200 found themselves", found);
+    }
+    
+    #[test]
+    fn maintenance_stops_when_its_collection_is_dropped() {
+        use crate::core::maintenance::MaintenanceConfig;
+        
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(2)).unwrap();
+        let scheduler = database
+            .start_maintenance("test", MaintenanceConfig {
+                compact_interval: Some(Duration::from_millis(10)),
+                ..Default::default()
+            })
+            .unwrap();
+        database.drop_collection("test").unwrap();
+        
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !scheduler.status().unwrap().stopped && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(scheduler.status().unwrap().stopped);
+    }
+    
+    #[test]
+    fn maintenance_purges_expired_documents_from_storage_and_index() {
+        use crate::core::maintenance::MaintenanceConfig;
+        
+        let database = Database::new("test".to_string());
+        database
+            .create_collection_with_config(CollectionConfig {
+                ttl_seconds: Some(60),
+                ..config(2)
+            })
+            .unwrap();
+        for i in 0..3 {
+            database.insert_vector("test", i.to_string(), vec![i as f32, 0.0], None).unwrap();
+        }
+        let collection = database.get_collection("test").unwrap();
+        collection.write().unwrap().clock = || Ok(unix_time()? + 61);
+        
+        let scheduler = database
+            .start_maintenance("test", MaintenanceConfig {
+                compact_interval: None,
+                prune_interval: None,
+                purge_interval: Some(Duration::from_millis(10)),
+                ..Default::default()
+            })
+            .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while scheduler.status().unwrap().documents_purged < 3 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        
+        let status = scheduler.stop();
+        assert_eq!(status.documents_purged, 3);
+        assert!(status.purges >= 1);
+        assert_eq!(status.last_error, None);
+        let collection = collection.read().unwrap();
+        assert!(collection.document_ids().unwrap().is_empty());
+        assert!(collection.index.search(vec![0.0, 0.0], 3).unwrap().is_empty());
+    }
+    
+    #[test]
+    fn absent_and_empty_metadata_stay_distinct_through_reads_and_updates() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(2)).unwrap();
+        database.insert_vector("test", "absent", vec![0.0, 0.0], None).unwrap();
+        database.insert_vector("test", "empty", vec![1.0, 0.0], Some(Vec::new())).unwrap();
+        database.insert_vector("test", "tagged", vec![2.0, 0.0], Some(vec![("lang".to_string(), "rust".to_string())])).unwrap();
+        
+        let documents = |ids: &[&str]| {
+            let collection = database.get_collection("test").unwrap();
+            let collection = collection.read().unwrap();
+            let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+            for id in &ids {
+                let document = collection.get_documents(std::slice::from_ref(id)).unwrap().remove(0);
+                assert_eq!(collection.get_document_meta(id).unwrap().unwrap().0, document.metadata);
+            }
+            collection.get_documents(&ids).unwrap()
+        };
+        let metadata = |id: &str| documents(&[id]).remove(0).metadata;
+        assert_eq!(metadata("absent"), None);
+        assert_eq!(metadata("empty"), Some(Vec::new()));
+        
+        let json = serde_json::to_string(&documents(&["absent", "empty"])).unwrap();
+        let parsed: Vec<VectorDocument> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed[0].metadata, None);
+        assert_eq!(parsed[1].metadata, Some(Vec::new()));
+        
+        // A missing key fails even `NotEquals`, so neither untagged document matches.
+        let mut not_go = equals("lang", "go");
+        not_go.conditions[0].operation = FilterOperation::NotEquals;
+        let query = SearchQuery {
+            vector: vec![0.0, 0.0],
+            limit: 3,
+            filter: Some(not_go),
+            ..Default::default()
+        };
+        assert_eq!(ids(&database.search("test", &query).unwrap()), vec!["tagged"]);
+        
+        database.update_metadata("test", "tagged", Some(Vec::new())).unwrap();
+        assert_eq!(metadata("tagged"), Some(Vec::new()));
+        assert!(database.search("test", &query).unwrap().is_empty());
+        database.update_metadata("test", "empty", None).unwrap();
+        assert_eq!(metadata("empty"), None);
+    }
+    
+    #[cfg(feature = "persistence")]
+    #[test]
+    fn absent_and_empty_metadata_survive_a_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let database = persistent_database(dir.path());
+            database.create_collection_with_config(config(2)).unwrap();
+            database.insert_vector("test", "absent", vec![0.0, 0.0], None).unwrap();
+            database.insert_vector("test", "empty", vec![1.0, 0.0], Some(Vec::new())).unwrap();
+            database.insert_vector("test", "cleared", vec![2.0, 0.0], Some(vec![("k".to_string(), "v".to_string())])).unwrap();
+            database.update_metadata("test", "cleared", Some(Vec::new())).unwrap();
+        }
+        
+        let database = persistent_database(dir.path());
+        database.create_collection_with_config(config(2)).unwrap();
+        let collection = database.get_collection("test").unwrap();
+        let collection = collection.read().unwrap();
+        let meta = |id: &str| collection.get_document_meta(id).unwrap().unwrap().0;
+        assert_eq!(meta("absent"), None);
+        assert_eq!(meta("empty"), Some(Vec::new()));
+        assert_eq!(meta("cleared"), Some(Vec::new()));
+    }
+    
+    #[test]
+    fn clamp_range_clamps_inserted_and_updated_vectors() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(CollectionConfig {
+            clamp_range: Some((-1.0, 1.0)),
+            ..config(3)
+        }).unwrap();
+        
+        database.insert_vector("test", "single", vec![-3.0, 0.5, 7.0], None).unwrap();
+        let request = BatchInsertRequest {
+            vectors: vec![document("batched", vec![2.0, -2.0, 1.0])],
+        };
+        database.batch_insert_with_progress("test", request, |_, _| {}).unwrap();
+        database.insert_vector("test", "updated", vec![0.0; 3], None).unwrap();
+        database.update_vector("test", "updated", vec![0.25, 5.0, -0.25]).unwrap();
+        
+        let collection = database.get_collection("test").unwrap();
+        let stored: Vec<Vector> = collection
+            .read()
+            .unwrap()
+            .get_documents(&["single".to_string(), "batched".to_string(), "updated".to_string()])
+            .unwrap()
+            .into_iter()
+            .map(|document| document.vector)
+            .collect();
+        assert_eq!(stored, vec![vec![-1.0, 0.5, 1.0], vec![1.0, -1.0, 1.0], vec![0.25, 1.0, -0.25]]);
+        drop(collection);
+        
+        // The index holds the clamped vector too, so it is an exact match for itself.
+        let results = database.search_vectors("test", vec![-1.0, 0.5, 1.0], 1).unwrap();
+        assert_eq!(results[0].0, "single");
+        assert_eq!(results[0].1, 0.0);
+        
+        // Clamping happens after validation, so non-finite values are still rejected.
+        assert!(database.insert_vector("test", "nan", vec![f32::NAN, 0.0, 0.0], None).is_err());
+        assert!(database.insert_vector("test", "inf", vec![f32::INFINITY, 0.0, 0.0], None).is_err());
+        
+        for clamp_range in [(1.0, -1.0), (f32::NEG_INFINITY, 0.0), (0.0, f32::NAN)] {
+            let config = CollectionConfig {
+                name: "invalid".to_string(),
+                clamp_range: Some(clamp_range),
+                ..config(3)
+            };
+            assert!(database.create_collection_with_config(config).is_err());
+        }
+    }
+    
+    #[test]
+    fn multi_query_fusion_combines_both_neighborhoods() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(2)).unwrap();
+        for i in 1..=3 {
+            database.insert_vector("test", format!("a{}", i), vec![0.0, i as f32], None).unwrap();
+            database.insert_vector("test", format!("b{}", i), vec![10.0, i as f32], None).unwrap();
+        }
+        // Fourth-nearest to each query, but the only point close to both.
+        database.insert_vector("test", "middle", vec![5.0, 0.0], None).unwrap();
+        
+        let queries = [vec![0.0, 0.0], vec![10.0, 0.0]];
+        let search = |fusion| database.multi_query_search("test", &queries, 4, fusion).unwrap();
+        assert_eq!(ids(&database.search_vectors("test", queries[0].clone(), 4).unwrap()), vec!["a1", "a2", "a3", "middle"]);
+        
+        let min = search(FusionMethod::MinDistance);
+        assert_eq!(ids(&min), vec!["a1", "b1", "a2", "b2"]);
+        assert_eq!(min[0].1, 1.0);
+        
+        let mean = search(FusionMethod::MeanDistance);
+        assert_eq!(ids(&mean), vec!["middle", "a1", "b1", "a2"]);
+        assert_eq!(mean[0].1, 5.0);
+        assert!((mean[1].1 - (1.0 + 101.0f32.sqrt()) / 2.0).abs() < 1e-5);
+        
+        let rrf = search(FusionMethod::Rrf);
+        assert_eq!(ids(&rrf), vec!["middle", "a1", "b1", "a2"]);
+        assert!((rrf[0].1 - 2.0 / 64.0).abs() < 1e-6);
+        assert!((rrf[1].1 - 1.0 / 61.0).abs() < 1e-6);
+        
+        assert!(database.multi_query_search("test", &[], 4, FusionMethod::Rrf).is_err());
+        assert!(database.multi_query_search("test", &[vec![0.0, 0.0], vec![1.0]], 4, FusionMethod::MinDistance).is_err());
+    }
+    
+    #[test]
+    fn collection_info_counts_mutations_but_not_reads() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(config(2)).unwrap();
+        database.create_collection_with_config(CollectionConfig { name: "other".to_string(), ..config(2) }).unwrap();
+        let now = || SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let info = || database.get_collection_info("test").unwrap();
+        
+        let fresh = info();
+        assert_eq!((fresh.operation_count, fresh.last_modified), (0, 0));
+        
+        let before = now();
+        database.insert_vector("test", "a", vec![0.0, 0.0], None).unwrap();
+        database.insert_vector("test", "b", vec![1.0, 0.0], None).unwrap();
+        let inserted = info();
+        assert_eq!(inserted.operation_count, 2);
+        assert!(inserted.last_modified >= before && inserted.last_modified <= now());
+        
+        database.search_vectors("test", vec![0.0, 0.0], 2).unwrap();
+        database.get_collection("test").unwrap().read().unwrap().get_documents(&["a".to_string()]).unwrap();
+        database.recent("test", 1).unwrap();
+        assert!(!database.remove_vector("test", "missing").unwrap());
+        let read = info();
+        assert_eq!((read.operation_count, read.last_modified), (inserted.operation_count, inserted.last_modified));
+        
+        database.update_vector("test", "a", vec![0.5, 0.0]).unwrap();
+        database.update_metadata("test", "b", Some(vec![("k".to_string(), "v".to_string())])).unwrap();
+        database.remove_vector("test", "b").unwrap();
+        let mutated = info();
+        assert_eq!(mutated.operation_count, 5);
+        assert!(mutated.last_modified >= inserted.last_modified);
+        assert_eq!(mutated.vector_count, 1);
+        
+        let database_info = database.get_database_info().unwrap();
+        let names: Vec<&str> = database_info.collections.iter().map(|info| info.name.as_str()).collect();
+        assert_eq!(names, vec!["other", "test"]);
+        assert_eq!(database_info.collections[1].operation_count, 5);
+        assert_eq!(database_info.collections[0].operation_count, 0);
+        assert_eq!(database_info.total_vectors, 1);
+    }
+    
+    #[test]
+    fn lru_eviction_removes_the_least_recently_used_document() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(CollectionConfig {
+            max_elements: Some(3),
+            eviction_policy: EvictionPolicy::LruEvict,
+            ..config(2)
+        }).unwrap();
+        for (i, id) in ["a", "b", "c"].into_iter().enumerate() {
+            database.insert_vector("test", id, vec![i as f32 * 10.0, 0.0], None).unwrap();
+        }
+        let stored = || {
+            let collection = database.get_collection("test").unwrap();
+            let mut ids = collection.read().unwrap().document_ids().unwrap();
+            ids.sort();
+            ids
+        };
+        
+        database.get_collection("test").unwrap().read().unwrap().get_documents(&["a".to_string()]).unwrap();
+        database.insert_vector("test", "d", vec![30.0, 0.0], None).unwrap();
+        assert_eq!(stored(), vec!["a", "c", "d"]);
+        
+        // Being returned by a search counts as a use too.
+        assert_eq!(ids(&database.search_vectors("test", vec![20.0, 0.0], 1).unwrap()), vec!["c"]);
+        database.insert_vector("test", "e", vec![40.0, 0.0], None).unwrap();
+        assert_eq!(stored(), vec!["c", "d", "e"]);
+        
+        // Evicted documents leave the index as well.
+        let results = database.search_vectors("test", vec![0.0, 0.0], 3).unwrap();
+        assert_eq!(ids(&results), vec!["c", "d", "e"]);
+        
+        // Overwriting an existing id needs no room.
+        database.insert_vector("test", "c", vec![21.0, 0.0], None).unwrap();
+        assert_eq!(stored(), vec!["c", "d", "e"]);
+    }
+    
+    #[test]
+    fn oldest_eviction_ignores_reads_and_reject_refuses_new_ids() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(CollectionConfig {
+            max_elements: Some(2),
+            eviction_policy: EvictionPolicy::OldestEvict,
+            ..config(2)
+        }).unwrap();
+        database.create_collection_with_config(CollectionConfig {
+            name: "capped".to_string(),
+            max_elements: Some(2),
+            ..config(2)
+        }).unwrap();
+        
+        database.insert_vector("test", "a", vec![0.0, 0.0], None).unwrap();
+        database.insert_vector("test", "b", vec![1.0, 0.0], None).unwrap();
+        database.search_vectors("test", vec![0.0, 0.0], 1).unwrap();
+        database.insert_vector("test", "c", vec![2.0, 0.0], None).unwrap();
+        let collection = database.get_collection("test").unwrap();
+        let mut remaining = collection.read().unwrap().document_ids().unwrap();
+        remaining.sort();
+        assert_eq!(remaining, vec!["b", "c"]);
+        
+        database.insert_vector("capped", "a", vec![0.0, 0.0], None).unwrap();
+        database.insert_vector("capped", "b", vec![1.0, 0.0], None).unwrap();
+        let error = database.insert_vector("capped", "c", vec![2.0, 0.0], None).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<SolarisError>(),
+            Some(SolarisError::CollectionFull { capacity: 2, .. })
+        ));
+        database.update_vector("capped", "a", vec![0.5, 0.0]).unwrap();
+        assert_eq!(database.get_collection_info("capped").unwrap().vector_count, 2);
+    }
+    
+    #[test]
+    fn to_matrix_rows_reshape_into_the_stored_vectors() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(CollectionConfig {
+            in_memory_compression: true,
+            ..config(5)
+        }).unwrap();
+        let vectors = random_vectors(10, 5, 101);
+        for (i, vector) in vectors.iter().enumerate() {
+            database.insert_vector("test", i.to_string(), vector.clone(), None).unwrap();
+        }
+        
+        let (rows, data, dimension) = database.to_matrix("test", None).unwrap();
+        assert_eq!(dimension, 5);
+        assert_eq!(rows, (0..10).map(|i| i.to_string()).collect::<Vec<_>>());
+        assert_eq!(data.len(), rows.len() * dimension);
+        // Compressed storage still exports the exact vectors.
+        for (id, row) in rows.iter().zip(data.chunks(dimension)) {
+            assert_eq!(row, vectors[id.parse::<usize>().unwrap()].as_slice());
+        }
+        
+        let subset = ["7".to_string(), "missing".to_string(), "2".to_string()];
+        let (rows, data, _) = database.to_matrix("test", Some(&subset)).unwrap();
+        assert_eq!(rows, vec!["7", "2"]);
+        assert_eq!(&data[..5], vectors[7].as_slice());
+        assert_eq!(&data[5..], vectors[2].as_slice());
+        
+        let (rows, data, dimension) = database.to_matrix("test", Some(&[])).unwrap();
+        assert!(rows.is_empty() && data.is_empty());
+        assert_eq!(dimension, 5);
+    }
+    
+    #[test]
+    fn to_matrix_is_not_an_lru_access() {
+        let database = Database::new("test".to_string());
+        database.create_collection_with_config(CollectionConfig {
+            max_elements: Some(2),
+            eviction_policy: EvictionPolicy::LruEvict,
+            ..config(2)
+        }).unwrap();
+        database.insert_vector("test", "a", vec![0.0, 0.0], None).unwrap();
+        database.insert_vector("test", "b", vec![1.0, 0.0], None).unwrap();
+        database.to_matrix("test", Some(&["a".to_string()])).unwrap();
+        database.insert_vector("test", "c", vec![2.0, 0.0], None).unwrap();
+        assert_eq!(database.to_matrix("test", None).unwrap().0, vec!["b", "c"]);
+        
+        database.create_collection_with_config(CollectionConfig {
+            name: "sparse".to_string(),
+            vector_type: VectorType::Sparse,
+            ..config(8)
+        }).unwrap();
+        assert!(database.to_matrix("sparse", None).is_err());
+    }
+    
+    #[test]
+    fn repair_on_delete_unlinks_instead_of_tombstoning() {
+        let database = Database::new("test".to_string());
+        database
+            .create_collection_with_config(CollectionConfig {
+                repair_on_delete: true,
+                ..config(8)
+            })
+            .unwrap();
+        let vectors = clustered_vectors(300, 8, 6, 93);
+        for (i, vector) in vectors.iter().enumerate() {
+            database.insert_vector("test", i.to_string(), vector.clone(), None).unwrap();
+        }
+        for i in (0..300).filter(|i| i % 3 == 0) {
+            assert!(database.remove_vector("test", &i.to_string()).unwrap());
+        }
+        
+        let collection = database.get_collection("test").unwrap();
+        let collection = collection.read().unwrap();
+        assert_eq!(collection.index.deleted_count(), 0);
+        assert_eq!(collection.index.get_stats().0, 200);
+        let mut found = 0;
+        for i in (0..300).filter(|i| i % 3 != 0) {
+            let results = collection.search_vectors(vectors[i].clone(), 10).unwrap();
+            assert!(ids(&results).iter().all(|id| id.parse::<usize>().unwrap() % 3 != 0));
+            found += usize::from(results[0].0 == i.to_string());
+        }
+        assert!(found >= 190, "{} of 200 found themselves", found);
+    }
+    
+    #[test]
+    fn confidence_scores_weighted_and_partial_queries_as_they_were_ranked() {
+        let mut collection = collection(config(8));
+        for (i, vector) in clustered_vectors(300, 8, 10, 19).into_iter().enumerate() {
+            collection.insert_vector(i.to_string(), vector, None).unwrap();
+        }
+        let weights = vec![4.0, 0.1, 1.0, 0.0, 2.0, 0.5, 3.0, 1.0];
+        for query in clustered_vectors(10, 8, 10, 20) {
+            for (dimension_weights, dimension_range) in [(Some(weights.clone()), None), (None, Some((0, 3)))] {
+                // The weighted pool spans the whole collection, so these results are exact.
+                let query = SearchQuery {
+                    vector: query.clone(),
+                    limit: 10,
+                    ef: Some(300),
+                    dimension_weights,
+                    dimension_range,
+                    estimate_confidence: true,
+                    ..Default::default()
+                };
+                assert_eq!(collection.search_with_response(&query).unwrap().confidence, Some(1.0));
+            }
+        }
+    }
+    
+    #[cfg(feature = "persistence")]
+    #[test]
+    fn dropping_a_persistent_collection_deletes_its_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = persistent_database(dir.path());
+        database.create_collection_with_config(config(2)).unwrap();
+        database.insert_vector("test", "a", vec![1.0, 0.0], None).unwrap();
+        database.insert_vector("test", "b", vec![0.0, 1.0], None).unwrap();
+        {
+        let collection = database.get_collection("test").unwrap();
+        collection.read().unwrap().persistent.as_ref().unwrap().flush().unwrap();
+    }
+        database.insert_vector("test", "buffered", vec![1.0, 1.0], None).unwrap();
+        
+        assert!(database.drop_collection("test").unwrap());
+        assert!(!database.drop_collection("test").unwrap());
+        assert!(!dir.path().join("test").exists());
+        
+        database.create_collection_with_config(config(2)).unwrap();
+        assert!(database.search_vectors("test", vec![1.0, 0.0], 3).unwrap().is_empty());
+        assert!(!database.contains("test", "a").unwrap());
+        drop(database);
+        
+        let reopened = persistent_database(dir.path());
+        reopened.create_collection_with_config(config(2)).unwrap();
+        assert!(reopened.search_vectors("test", vec![1.0, 0.0], 3).unwrap().is_empty());
+    }
+}
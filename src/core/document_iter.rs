@@ -0,0 +1,121 @@
+use crate::core::database::Collection;
+use crate::types::VectorDocument;
+use std::sync::{Arc, RwLock};
+
+/// Walks the ids present when iteration started, re-taking the collection's read lock once per
+/// chunk so writers are never blocked for the whole scan. Documents removed mid-scan are skipped;
+/// documents inserted mid-scan are not visited.
+pub struct DocumentIter {
+    collection: Arc<RwLock<Collection>>,
+    ids: Vec<String>,
+    chunk_size: usize,
+    chunk: std::vec::IntoIter<VectorDocument>,
+}
+
+impl DocumentIter {
+    pub fn new(collection: Arc<RwLock<Collection>>, mut ids: Vec<String>, chunk_size: usize) -> Self {
+        ids.reverse();
+        DocumentIter {
+            collection,
+            ids,
+            chunk_size: chunk_size.max(1),
+            chunk: Vec::new().into_iter(),
+        }
+    }
+
+    fn next_chunk(&mut self) -> Option<Vec<VectorDocument>> {
+        let split = self.ids.len().saturating_sub(self.chunk_size);
+        let mut ids = self.ids.split_off(split);
+        ids.reverse();
+
+        let collection = match self.collection.read() {
+            Ok(collection) => collection,
+            Err(_) => {
+                log::warn!("Failed to acquire read lock; stopping document iteration");
+                self.ids.clear();
+                return None;
+            }
+        };
+
+        match collection.get_documents(&ids) {
+            Ok(documents) => Some(documents),
+            Err(e) => {
+                log::warn!("Failed to read documents; stopping iteration: {}", e);
+                self.ids.clear();
+                None
+            }
+        }
+    }
+}
+
+impl Iterator for DocumentIter {
+    type Item = VectorDocument;
+
+    fn next(&mut self) -> Option<VectorDocument> {
+        loop {
+            if let Some(document) = self.chunk.next() {
+                return Some(document);
+            }
+            if self.ids.is_empty() {
+                return None;
+            }
+            self.chunk = self.next_chunk()?.into_iter();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PerformanceConfig;
+    use crate::types::CollectionConfig;
+    use std::collections::HashSet;
+
+    fn populated(count: usize) -> Arc<RwLock<Collection>> {
+        let config = CollectionConfig {
+            name: "test".to_string(),
+            dimension: 2,
+            deterministic_distance: true,
+            ..Default::default()
+        };
+        let mut collection = Collection::new(config, &PerformanceConfig::default());
+        for i in 0..count {
+            collection.insert_vector(i.to_string(), vec![i as f32, 1.0], None).unwrap();
+        }
+        Arc::new(RwLock::new(collection))
+    }
+
+    fn iter(collection: &Arc<RwLock<Collection>>, chunk_size: usize) -> DocumentIter {
+        let ids = collection.read().unwrap().document_ids().unwrap();
+        DocumentIter::new(Arc::clone(collection), ids, chunk_size)
+    }
+
+    #[test]
+    fn every_document_is_visited_exactly_once() {
+        let collection = populated(50);
+        for chunk_size in [0, 1, 7, 50, 64] {
+            let visited: Vec<String> = iter(&collection, chunk_size).map(|document| document.id).collect();
+            let unique: HashSet<&String> = visited.iter().collect();
+            assert_eq!(visited.len(), 50, "chunk size {}", chunk_size);
+            assert_eq!(unique.len(), 50, "chunk size {}", chunk_size);
+        }
+        assert_eq!(iter(&populated(0), 8).count(), 0);
+    }
+
+    #[test]
+    fn writes_between_chunks_neither_block_nor_leak_into_the_scan() {
+        let collection = populated(20);
+        let ids = collection.read().unwrap().document_ids().unwrap();
+        let mut documents = DocumentIter::new(Arc::clone(&collection), ids.clone(), 5);
+        assert_eq!(documents.next().unwrap().id, ids[0]);
+
+        // ids[2] was fetched with the first chunk; ids[10] is still ahead of the scan.
+        collection.write().unwrap().insert_vector("late".to_string(), vec![0.0, 0.0], None).unwrap();
+        collection.write().unwrap().remove_vector(&ids[2]).unwrap();
+        collection.write().unwrap().remove_vector(&ids[10]).unwrap();
+
+        let rest: Vec<String> = documents.map(|document| document.id).collect();
+        let expected: Vec<String> = ids[1..].iter().filter(|id| **id != ids[10]).cloned().collect();
+        assert_eq!(rest, expected);
+    }
+}
@@ -0,0 +1,88 @@
+use crate::types::Vector;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+pub type CollectionHook = Box<dyn Fn(&str) + Send + Sync>;
+pub type QueryTransform = Box<dyn Fn(&mut Vector) + Send + Sync>;
+
+#[derive(Default)]
+pub struct CollectionHooks {
+    insert: Vec<CollectionHook>,
+    delete: Vec<CollectionHook>,
+    update: Vec<CollectionHook>,
+}
+
+impl CollectionHooks {
+    pub fn on_insert(&mut self, hook: CollectionHook) {
+        self.insert.push(hook);
+    }
+
+    pub fn on_delete(&mut self, hook: CollectionHook) {
+        self.delete.push(hook);
+    }
+
+    pub fn on_update(&mut self, hook: CollectionHook) {
+        self.update.push(hook);
+    }
+
+    pub fn fire_insert(&self, id: &str) {
+        fire(&self.insert, "insert", id);
+    }
+
+    pub fn fire_delete(&self, id: &str) {
+        fire(&self.delete, "delete", id);
+    }
+
+    pub fn fire_update(&self, id: &str) {
+        fire(&self.update, "update", id);
+    }
+}
+
+// Hooks run after the operation has been applied, so a panicking hook is
+// contained and logged rather than unwinding through the collection.
+fn fire(hooks: &[CollectionHook], event: &str, id: &str) {
+    for hook in hooks {
+        if catch_unwind(AssertUnwindSafe(|| hook(id))).is_err() {
+            log::warn!("{} hook panicked for vector '{}'", event, id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn recorder(events: &Arc<Mutex<Vec<String>>>, tag: &'static str) -> CollectionHook {
+        let events = Arc::clone(events);
+        Box::new(move |id| events.lock().unwrap().push(format!("{}:{}", tag, id)))
+    }
+
+    #[test]
+    fn every_registered_hook_fires_in_registration_order() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut hooks = CollectionHooks::default();
+        hooks.on_insert(recorder(&events, "first"));
+        hooks.on_insert(recorder(&events, "second"));
+        hooks.on_delete(recorder(&events, "delete"));
+        hooks.on_update(recorder(&events, "update"));
+
+        hooks.fire_insert("a");
+        hooks.fire_update("a");
+        hooks.fire_delete("a");
+
+        assert_eq!(*events.lock().unwrap(), ["first:a", "second:a", "update:a", "delete:a"]);
+    }
+
+    #[test]
+    fn a_panicking_hook_does_not_stop_the_others() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut hooks = CollectionHooks::default();
+        hooks.on_insert(Box::new(|_| panic!("hook failure")));
+        hooks.on_insert(recorder(&events, "after"));
+
+        hooks.fire_insert("a");
+        hooks.fire_insert("b");
+
+        assert_eq!(*events.lock().unwrap(), ["after:a", "after:b"]);
+    }
+}
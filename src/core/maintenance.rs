@@ -0,0 +1,195 @@
+use crate::core::database::Collection;
+use crate::utils::cancellation::CancellationToken;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::sync::{Arc, Mutex, RwLock, RwLockWriteGuard, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Which maintenance operations run, and how often. A task left at `None` never runs.
+#[derive(Debug, Clone)]
+pub struct MaintenanceConfig {
+    /// Compacts when deleted nodes exceed the collection's `compaction_threshold`.
+    pub compact_interval: Option<Duration>,
+    pub prune_interval: Option<Duration>,
+    /// Connection lists longer than this are trimmed by each prune.
+    pub prune_max_degree: usize,
+    /// Rebuilds the whole graph; the most expensive task, so usually the longest interval.
+    pub optimize_interval: Option<Duration>,
+    /// Removes documents past the collection's `ttl_seconds`; does nothing without a TTL.
+    pub purge_interval: Option<Duration>,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            compact_interval: Some(Duration::from_secs(60)),
+            prune_interval: Some(Duration::from_secs(600)),
+            prune_max_degree: 32,
+            optimize_interval: None,
+            purge_interval: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MaintenanceTask {
+    Compact,
+    Prune,
+    Optimize,
+    Purge,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MaintenanceStatus {
+    pub running: Option<MaintenanceTask>,
+    /// Compaction checks that found enough deletions to compact.
+    pub compactions: u64,
+    pub prunes: u64,
+    pub lists_pruned: u64,
+    pub optimizations: u64,
+    pub purges: u64,
+    pub documents_purged: u64,
+    pub last_error: Option<String>,
+    pub stopped: bool,
+}
+
+/// Runs a collection's maintenance tasks on one background thread, one at a time. Prune,
+/// optimize and purge hold the collection's write lock throughout; compaction builds its new graph
+/// without it and only takes it to swap. Stops on `stop`, on drop, or when the collection is
+/// dropped; stopping cancels a running optimize.
+pub struct MaintenanceScheduler {
+    stop: CancellationToken,
+    status: Arc<Mutex<MaintenanceStatus>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MaintenanceScheduler {
+    pub(crate) fn start(collection: Weak<RwLock<Collection>>, config: MaintenanceConfig) -> Self {
+        let stop = CancellationToken::new();
+        let status = Arc::new(Mutex::new(MaintenanceStatus::default()));
+
+        let stopped = stop.clone();
+        let shared = status.clone();
+        let handle = thread::spawn(move || {
+            let now = Instant::now();
+            let mut schedule: Vec<(MaintenanceTask, Duration, Instant)> = [
+                (MaintenanceTask::Compact, config.compact_interval),
+                (MaintenanceTask::Prune, config.prune_interval),
+                (MaintenanceTask::Optimize, config.optimize_interval),
+                (MaintenanceTask::Purge, config.purge_interval),
+            ]
+            .into_iter()
+            .filter_map(|(task, interval)| interval.map(|interval| (task, interval, now + interval)))
+            .collect();
+
+            while let Some(&(_, _, due)) = schedule.iter().min_by_key(|(_, _, due)| *due) {
+                if !stopped.park_until(due) {
+                    break;
+                }
+                let Some(collection) = collection.upgrade() else {
+                    break;
+                };
+
+                for (task, interval, next) in schedule.iter_mut() {
+                    if *next > Instant::now() || stopped.is_cancelled() {
+                        continue;
+                    }
+                    run(&collection, *task, &config, &stopped, &shared);
+                    *next = Instant::now() + *interval;
+                }
+            }
+
+            if let Ok(mut status) = shared.lock() {
+                status.stopped = true;
+            }
+        });
+
+        MaintenanceScheduler {
+            stop,
+            status,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn status(&self) -> Result<MaintenanceStatus, Box<dyn Error>> {
+        let status = self.status.lock().map_err(|_| "Failed to acquire maintenance status lock")?;
+        Ok(status.clone())
+    }
+
+    /// Stops the scheduler, waiting for a running task to finish or be cancelled.
+    pub fn stop(mut self) -> MaintenanceStatus {
+        self.shutdown();
+        self.status().unwrap_or_default()
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.cancel();
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for MaintenanceScheduler {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn run(
+    collection: &RwLock<Collection>,
+    task: MaintenanceTask,
+    config: &MaintenanceConfig,
+    cancel: &CancellationToken,
+    status: &Mutex<MaintenanceStatus>,
+) {
+    set_status(status, |status| status.running = Some(task));
+
+    let result = match task {
+        MaintenanceTask::Compact => Collection::compact(collection).map(usize::from),
+        MaintenanceTask::Prune => write(collection).and_then(|mut collection| collection.prune(config.prune_max_degree)),
+        MaintenanceTask::Optimize => {
+            write(collection).and_then(|mut collection| collection.optimize_cancellable(cancel).map(|_| 0))
+        }
+        MaintenanceTask::Purge => write(collection).and_then(|mut collection| collection.purge_expired()),
+    };
+
+    set_status(status, |status| {
+        status.running = None;
+        match result {
+            Ok(count) => match task {
+                MaintenanceTask::Compact => status.compactions += count as u64,
+                MaintenanceTask::Prune => {
+                    status.prunes += 1;
+                    status.lists_pruned += count as u64;
+                }
+                MaintenanceTask::Optimize => status.optimizations += 1,
+                MaintenanceTask::Purge => {
+                    status.purges += 1;
+                    status.documents_purged += count as u64;
+                }
+            },
+            // A cancelled optimize is a requested stop, not a failure.
+            Err(_) if cancel.is_cancelled() => {}
+            Err(e) => {
+                log::warn!("Maintenance task {:?} failed: {}", task, e);
+                status.last_error = Some(format!("{:?}: {}", task, e));
+            }
+        }
+    });
+}
+
+fn write(collection: &RwLock<Collection>) -> Result<RwLockWriteGuard<'_, Collection>, Box<dyn Error>> {
+    collection.write().map_err(|_| "Failed to acquire write lock".into())
+}
+
+fn set_status<F>(status: &Mutex<MaintenanceStatus>, update: F)
+where
+    F: FnOnce(&mut MaintenanceStatus),
+{
+    if let Ok(mut status) = status.lock() {
+        update(&mut status);
+    }
+}
@@ -0,0 +1,117 @@
+use crate::core::database::Collection;
+use crate::types::Vector;
+use crate::utils::cancellation::CancellationToken;
+use std::sync::{RwLock, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Called with the collection name and the measured recall when recall falls below the threshold.
+pub type RecallAlert = Box<dyn Fn(&str, f32) + Send + 'static>;
+
+#[derive(Debug, Clone)]
+pub struct RecallMonitorConfig {
+    pub interval: Duration,
+    /// Size of the ground-truth query set, sampled from the collection once it has vectors.
+    pub queries: usize,
+    pub k: usize,
+    pub threshold: f32,
+}
+
+impl Default for RecallMonitorConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            queries: 10,
+            k: 10,
+            threshold: 0.9,
+        }
+    }
+}
+
+/// Background sampler measuring a collection's recall@k every `interval`. Each query takes
+/// the collection read lock on its own and is skipped if a writer holds it, so a writer
+/// waits for at most one exact scan. Stops when dropped or when the collection is dropped.
+pub struct RecallMonitor {
+    stop: CancellationToken,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl RecallMonitor {
+    pub(crate) fn spawn(collection: Weak<RwLock<Collection>>, config: RecallMonitorConfig, alert: RecallAlert) -> Self {
+        let stop = CancellationToken::new();
+        let stopped = stop.clone();
+        let handle = thread::spawn(move || {
+            let mut queries: Vec<Vector> = Vec::new();
+            let mut below = false;
+
+            while stopped.park_until(Instant::now() + config.interval) {
+                let Some(collection) = collection.upgrade() else {
+                    break;
+                };
+                if queries.is_empty() {
+                    queries = sample_queries(&collection, config.queries);
+                }
+                let Some(recall) = measure(&collection, &queries, config.k) else {
+                    continue;
+                };
+
+                // Alert once per drop below the threshold rather than on every measurement.
+                let alerted = recall < config.threshold && !below;
+                below = recall < config.threshold;
+                let name = {
+                    let Ok(collection) = collection.read() else {
+                        break;
+                    };
+                    if let Err(e) = collection.record_recall(recall, alerted) {
+                        log::warn!("Failed to record recall: {}", e);
+                    }
+                    collection.config().name.clone()
+                };
+                // Called without the lock held, so the alert may trigger `optimize` directly.
+                if alerted {
+                    alert(&name, recall);
+                }
+            }
+        });
+
+        RecallMonitor {
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for RecallMonitor {
+    fn drop(&mut self) {
+        self.stop.cancel();
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+fn sample_queries(collection: &RwLock<Collection>, n: usize) -> Vec<Vector> {
+    let Ok(collection) = collection.try_read() else {
+        return Vec::new();
+    };
+    collection
+        .sample(n, None)
+        .map(|documents| {
+            documents
+                .into_iter()
+                .filter(|document| !document.vector.is_empty())
+                .map(|document| document.vector)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn measure(collection: &RwLock<Collection>, queries: &[Vector], k: usize) -> Option<f32> {
+    let recalls: Vec<f32> = queries
+        .iter()
+        .filter_map(|query| collection.try_read().ok()?.query_recall(query, k).ok().flatten())
+        .collect();
+
+    (!recalls.is_empty()).then(|| recalls.iter().sum::<f32>() / recalls.len() as f32)
+}
@@ -0,0 +1,159 @@
+use crate::types::{CollectionConfig, VectorDocument, VectorMetadata};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReplicationOp {
+    CreateCollection(CollectionConfig),
+    DropCollection,
+    RenameCollection(String),
+    /// The full document as stored after an insert or vector update.
+    Put(VectorDocument),
+    UpdateMetadata(String, Option<VectorMetadata>),
+    Delete(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicationEvent {
+    pub seq: u64,
+    pub collection: String,
+    pub op: ReplicationOp,
+}
+
+/// Mutations in the order they were applied, fanned out to subscribers. The last
+/// `capacity` events are kept so a reconnecting replica can catch up from its last seq.
+pub struct ReplicationLog {
+    state: Mutex<LogState>,
+    capacity: usize,
+}
+
+struct LogState {
+    next_seq: u64,
+    retained: VecDeque<ReplicationEvent>,
+    subscribers: Vec<Sender<ReplicationEvent>>,
+}
+
+impl ReplicationLog {
+    pub fn new(capacity: usize) -> Self {
+        ReplicationLog {
+            state: Mutex::new(LogState {
+                next_seq: 1,
+                retained: VecDeque::new(),
+                subscribers: Vec::new(),
+            }),
+            capacity,
+        }
+    }
+
+    /// Skips building the event when nobody could ever read it.
+    pub fn is_active(&self) -> bool {
+        self.capacity > 0 || self.state.lock().is_ok_and(|state| !state.subscribers.is_empty())
+    }
+
+    pub fn record(&self, collection: &str, op: ReplicationOp) -> Result<u64, Box<dyn Error>> {
+        let mut state = self.state.lock().map_err(|_| "Failed to acquire replication lock")?;
+        let event = ReplicationEvent {
+            seq: state.next_seq,
+            collection: collection.to_string(),
+            op,
+        };
+        state.next_seq += 1;
+
+        // Receivers that have been dropped are unsubscribed on the next send.
+        state.subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+        if self.capacity > 0 {
+            if state.retained.len() == self.capacity {
+                state.retained.pop_front();
+            }
+            state.retained.push_back(event.clone());
+        }
+        Ok(event.seq)
+    }
+
+    pub fn subscribe(&self) -> Result<Receiver<ReplicationEvent>, Box<dyn Error>> {
+        let (sender, receiver) = mpsc::channel();
+        let mut state = self.state.lock().map_err(|_| "Failed to acquire replication lock")?;
+        state.subscribers.push(sender);
+        Ok(receiver)
+    }
+
+    /// Subscribes with every retained event after `seq` already queued, so nothing is
+    /// missed or repeated between catch-up and live events. Fails when events after
+    /// `seq` have already been evicted; the replica then has to resync from a snapshot.
+    pub fn subscribe_after(&self, seq: u64) -> Result<Receiver<ReplicationEvent>, Box<dyn Error>> {
+        let (sender, receiver) = mpsc::channel();
+        let mut state = self.state.lock().map_err(|_| "Failed to acquire replication lock")?;
+
+        let oldest = state.retained.front().map_or(state.next_seq, |event| event.seq);
+        if seq + 1 < oldest && seq + 1 < state.next_seq {
+            return Err(format!(
+                "Replication events after seq {} are no longer retained (oldest is {})",
+                seq, oldest
+            )
+            .into());
+        }
+
+        for event in state.retained.iter().filter(|event| event.seq > seq) {
+            sender.send(event.clone())?;
+        }
+        state.subscribers.push(sender);
+        Ok(receiver)
+    }
+
+    pub fn last_seq(&self) -> Result<u64, Box<dyn Error>> {
+        let state = self.state.lock().map_err(|_| "Failed to acquire replication lock")?;
+        Ok(state.next_seq - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delete(id: &str) -> ReplicationOp {
+        ReplicationOp::Delete(id.to_string())
+    }
+
+    #[test]
+    fn subscribers_receive_events_with_increasing_seqs() {
+        let log = ReplicationLog::new(0);
+        assert!(!log.is_active());
+        let first = log.subscribe().unwrap();
+        let second = log.subscribe().unwrap();
+        assert!(log.is_active());
+
+        assert_eq!(log.record("docs", delete("a")).unwrap(), 1);
+        assert_eq!(log.record("docs", delete("b")).unwrap(), 2);
+        assert_eq!(log.last_seq().unwrap(), 2);
+
+        for receiver in [&first, &second] {
+            let seqs: Vec<u64> = receiver.try_iter().map(|event| event.seq).collect();
+            assert_eq!(seqs, vec![1, 2]);
+        }
+
+        drop(first);
+        drop(second);
+        log.record("docs", delete("c")).unwrap();
+        assert!(!log.is_active());
+    }
+
+    #[test]
+    fn subscribe_after_queues_retained_events_before_live_ones() {
+        let log = ReplicationLog::new(3);
+        for id in ["a", "b", "c", "d", "e"] {
+            log.record("docs", delete(id)).unwrap();
+        }
+
+        assert!(log.subscribe_after(1).is_err());
+        let receiver = log.subscribe_after(3).unwrap();
+        log.record("docs", delete("f")).unwrap();
+        let seqs: Vec<u64> = receiver.try_iter().map(|event| event.seq).collect();
+        assert_eq!(seqs, vec![4, 5, 6]);
+
+        assert_eq!(log.subscribe_after(6).unwrap().try_iter().count(), 0);
+        assert!(log.subscribe_after(2).is_err());
+    }
+}
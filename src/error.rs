@@ -0,0 +1,107 @@
+use crate::types::{BatchFailure, ErrorCode};
+use crate::utils::validation::ValidationError;
+use std::error::Error;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SolarisError {
+    #[error("Memory limit exceeded: {used} bytes in use, limit is {limit} bytes")]
+    MemoryLimitExceeded { used: usize, limit: usize },
+    
+    #[error("Collection '{collection}' is full: capacity is {capacity} documents")]
+    CollectionFull { collection: String, capacity: usize },
+    
+    #[error(
+        "Storage in '{path}' uses format version {found}, but this build reads versions 1 to {supported}; \
+         open it with the release that wrote it, or a newer one, and export the vectors to re-import here"
+    )]
+    UnsupportedFormatVersion { path: String, found: u32, supported: u32 },
+    
+    #[error("Operation cancelled")]
+    Cancelled,
+    
+    #[error("Collection '{collection}' does not accept {kind} vectors")]
+    UnsupportedVectorType { collection: String, kind: &'static str },
+    
+    #[error("Vector '{0}' already exists")]
+    DuplicateId(String),
+    
+    #[error("Distance to vector '{0}' is NaN")]
+    NanDistance(String),
+    
+    #[error(transparent)]
+    Validation(#[from] ValidationError),
+}
+
+impl SolarisError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            SolarisError::MemoryLimitExceeded { .. } => ErrorCode::MemoryLimitExceeded,
+            SolarisError::CollectionFull { .. } => ErrorCode::CollectionFull,
+            SolarisError::UnsupportedFormatVersion { .. } => ErrorCode::Internal,
+            SolarisError::Cancelled => ErrorCode::Cancelled,
+            SolarisError::UnsupportedVectorType { .. } => ErrorCode::UnsupportedVectorType,
+            SolarisError::DuplicateId(_) => ErrorCode::DuplicateId,
+            SolarisError::NanDistance(_) => ErrorCode::InvalidVector,
+            SolarisError::Validation(e) => validation_code(e),
+        }
+    }
+}
+
+fn validation_code(error: &ValidationError) -> ErrorCode {
+    match error {
+        ValidationError::DimensionMismatch { .. }
+        | ValidationError::SparseIndexOutOfBounds { .. } => ErrorCode::DimensionMismatch,
+        ValidationError::InvalidValues
+        | ValidationError::SparseLengthMismatch { .. }
+        | ValidationError::UnsortedSparseIndices => ErrorCode::InvalidVector,
+        _ => ErrorCode::InvalidDocument,
+    }
+}
+
+pub fn error_code(error: &(dyn Error + 'static)) -> ErrorCode {
+    if let Some(e) = error.downcast_ref::<SolarisError>() {
+        return e.code();
+    }
+    if let Some(e) = error.downcast_ref::<ValidationError>() {
+        return validation_code(e);
+    }
+    ErrorCode::Internal
+}
+
+impl BatchFailure {
+    pub fn from_error(error: &(dyn Error + 'static)) -> Self {
+        BatchFailure {
+            code: error_code(error),
+            message: error.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn boxed_errors_keep_their_code() {
+        let dimension: Box<dyn Error> = ValidationError::DimensionMismatch { expected: 3, actual: 2 }.into();
+        assert_eq!(error_code(&*dimension), ErrorCode::DimensionMismatch);
+        
+        let wrapped: Box<dyn Error> = SolarisError::from(ValidationError::InvalidValues).into();
+        assert_eq!(error_code(&*wrapped), ErrorCode::InvalidVector);
+        
+        let duplicate: Box<dyn Error> = SolarisError::DuplicateId("a".to_string()).into();
+        assert_eq!(error_code(&*duplicate), ErrorCode::DuplicateId);
+        
+        let untyped: Box<dyn Error> = "Failed to acquire write lock".into();
+        assert_eq!(error_code(&*untyped), ErrorCode::Internal);
+    }
+    
+    #[test]
+    fn batch_failures_carry_the_code_and_the_message() {
+        let error = SolarisError::MemoryLimitExceeded { used: 10, limit: 5 };
+        let failure = BatchFailure::from_error(&error);
+        assert_eq!(failure.code, ErrorCode::MemoryLimitExceeded);
+        assert_eq!(failure.message, error.to_string());
+    }
+}
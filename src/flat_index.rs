@@ -1,5 +1,12 @@
+use crate::types::{DistanceMetric, Vector};
+use crate::utils::distance::{calculate_distance, compare_distances};
+use crate::utils::validation::validate_vector;
+#[cfg(feature = "std")]
 use rayon::prelude::*;
 use std::collections::HashMap;
+use std::error::Error;
+
+pub type FlatHit = (String, f32, Option<HashMap<String, String>>);
 
 #[derive(Clone)]
 pub struct VectorEntry {
@@ -10,12 +17,13 @@ pub struct VectorEntry {
 
 pub struct BruteIndex {
     dim: usize,
+    metric: DistanceMetric,
     entries: Vec<VectorEntry>,
 }
 
 impl BruteIndex {
-    pub fn new(dim: usize) -> Self {
-        Self { dim, entries: Vec::new() }
+    pub fn new(dim: usize, metric: DistanceMetric) -> Self {
+        Self { dim, metric, entries: Vec::new() }
     }
 
     pub fn insert(
@@ -23,39 +31,42 @@ impl BruteIndex {
         id: String,
         vector: Vec<f32>,
         metadata: Option<HashMap<String, String>>,
-    ) -> anyhow::Result<()> {
-        if vector.len() != self.dim {
-            anyhow::bail!("invalid dimension");
-        }
+    ) -> Result<(), Box<dyn Error>> {
+        validate_vector(&vector, self.dim)?;
         self.entries.push(VectorEntry { id, vector, metadata });
         Ok(())
     }
 
-    fn cosine(a: &[f32], b: &[f32]) -> f32 {
-        let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
-        let na = a.iter().map(|x| x * x).sum::<f32>().sqrt();
-        let nb = b.iter().map(|x| x * x).sum::<f32>().sqrt();
-        if na == 0.0 || nb == 0.0 { 0.0 } else { dot / (na * nb) }
-    }
-
     pub fn search(
         &self,
         query: &[f32],
         k: usize,
-    ) -> anyhow::Result<Vec<(String, f32, Option<HashMap<String, String>>)>> {
-        if query.len() != self.dim {
-            anyhow::bail!("invalid dimension");
+    ) -> Result<Vec<FlatHit>, Box<dyn Error>> {
+        let query: Vector = query.to_vec();
+        validate_vector(&query, self.dim)?;
+        if k == 0 {
+            return Err("Search limit must be greater than 0".into());
         }
-        let mut scored: Vec<(usize, f32)> = self
-            .entries
-            .par_iter()
+
+        #[cfg(feature = "std")]
+        let entries = self.entries.par_iter();
+        #[cfg(not(feature = "std"))]
+        let entries = self.entries.iter();
+        let mut scored: Vec<(usize, f32)> = entries
             .enumerate()
-            .map(|(i, e)| (i, Self::cosine(&e.vector, query)))
+            .map(|(i, e)| (i, calculate_distance(&e.vector, &query, self.metric)))
             .collect();
-        scored.par_sort_unstable_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        let compare = |a: &(usize, f32), b: &(usize, f32)| compare_distances(a.1, b.1).then(a.0.cmp(&b.0));
+        if k < scored.len() {
+            scored.select_nth_unstable_by(k - 1, compare);
+            scored.truncate(k);
+        }
+        #[cfg(feature = "std")]
+        scored.par_sort_unstable_by(compare);
+        #[cfg(not(feature = "std"))]
+        scored.sort_unstable_by(compare);
         Ok(scored
             .into_iter()
-            .take(k)
             .map(|(i, s)| {
                 let e = &self.entries[i];
                 (e.id.clone(), s, e.metadata.clone())
@@ -63,3 +74,77 @@ impl BruteIndex {
             .collect())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PerformanceConfig;
+    use crate::index::hnsw::HNSWIndex;
+    use crate::types::CollectionConfig;
+    use crate::utils::gen::random_vectors;
+    use crate::utils::validation::ValidationError;
+
+    fn hits(index: &BruteIndex, query: &[f32], k: usize) -> Vec<(String, f32)> {
+        index.search(query, k).unwrap().into_iter().map(|(id, distance, _)| (id, distance)).collect()
+    }
+
+    #[test]
+    fn euclidean_and_manhattan_return_ascending_distances() {
+        for (metric, expected) in [(DistanceMetric::Euclidean, [0.0, 5.0]), (DistanceMetric::Manhattan, [0.0, 7.0])] {
+            let mut index = BruteIndex::new(2, metric);
+            index.insert("far".to_string(), vec![3.0, 4.0], None).unwrap();
+            index.insert("origin".to_string(), vec![0.0, 0.0], None).unwrap();
+
+            let results = hits(&index, &[0.0, 0.0], 2);
+            assert_eq!(results[0].0, "origin", "{:?}", metric);
+            assert_eq!(results[1].0, "far", "{:?}", metric);
+            assert!((results[0].1 - expected[0]).abs() < 1e-6, "{:?}", metric);
+            assert!((results[1].1 - expected[1]).abs() < 1e-6, "{:?}", metric);
+        }
+    }
+
+    #[test]
+    fn ordering_matches_hnsw_on_the_same_data() {
+        let config = CollectionConfig {
+            dimension: 8,
+            metric: DistanceMetric::Euclidean,
+            deterministic_distance: true,
+            ..Default::default()
+        };
+        let mut hnsw = HNSWIndex::new(config, &PerformanceConfig::default());
+        let mut flat = BruteIndex::new(8, DistanceMetric::Euclidean);
+        for (i, vector) in random_vectors(50, 8, 5).into_iter().enumerate() {
+            hnsw.add_vector(i.to_string(), vector.clone()).unwrap();
+            flat.insert(i.to_string(), vector, None).unwrap();
+        }
+
+        // With ef as large as the index, the graph search visits every node of a graph this small.
+        for query in random_vectors(5, 8, 6) {
+            let expected = hnsw.search(query.clone(), 10, Some(50)).unwrap();
+            let actual = hits(&flat, &query, 10);
+            let ids = |results: &[(String, f32)]| results.iter().map(|(id, _)| id.clone()).collect::<Vec<_>>();
+            assert_eq!(ids(&actual), ids(&expected));
+        }
+    }
+
+    #[test]
+    fn search_validates_k_and_the_query_dimension() {
+        let mut index = BruteIndex::new(2, DistanceMetric::Euclidean);
+        for i in 0..3 {
+            index.insert(i.to_string(), vec![i as f32, 0.0], None).unwrap();
+        }
+
+        assert!(index.search(&[0.0, 0.0], 0).is_err());
+        let all = hits(&index, &[0.0, 0.0], 10);
+        assert_eq!(all.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>(), ["0", "1", "2"]);
+
+        let error = index.search(&[0.0, 0.0, 0.0], 1).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<ValidationError>(),
+            Some(ValidationError::DimensionMismatch { expected: 2, actual: 3 })
+        ));
+        assert!(index.insert("short".to_string(), vec![1.0], None).is_err());
+        assert!(index.search(&[f32::NAN, 0.0], 1).is_err());
+        assert!(BruteIndex::new(2, DistanceMetric::Euclidean).search(&[0.0, 0.0], 5).unwrap().is_empty());
+    }
+}
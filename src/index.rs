@@ -1,2 +1,3 @@
 pub mod hnsw;
+pub mod sparse_index;
 pub mod vector_index;
\ No newline at end of file
@@ -1,22 +1,24 @@
-use crate::types::{CollectionConfig, DistanceMetric, Vector};
-use crate::utils::distance::calculate_distance;
+use crate::config::{NanHandling, PerformanceConfig};
+use crate::types::{CollectionConfig, ConnectivityReport, DistanceMetric, NeighborSelection, Vector};
+use crate::utils::cancellation::CancellationToken;
+use crate::utils::distance::{calculate_distance_with, check_nan_distances, compare_distances};
 use std::collections::{BinaryHeap, HashMap, HashSet};
-use std::cmp::Ordering;
+use std::cmp::{Ordering, Reverse};
 use std::error::Error;
+#[cfg(feature = "std")]
 use rayon::prelude::*;
 use rand::Rng;
 
 #[derive(Clone)]
 struct Node {
-    id: String,
     vector: Vector,
-    connections: Vec<Vec<String>>,
+    connections: Vec<Vec<u32>>,
     level: usize,
 }
 
 #[derive(PartialEq)]
 struct SearchCandidate {
-    id: String,
+    id: u32,
     distance: f32,
 }
 
@@ -35,132 +37,355 @@ impl Ord for SearchCandidate {
 }
 
 pub struct HNSWIndex {
-    nodes: HashMap<String, Node>,
-    entry_point: Option<String>,
+    nodes: HashMap<u32, Node>,
+    ids: Vec<String>,
+    id_map: HashMap<String, u32>,
+    entry_point: Option<u32>,
     max_level: usize,
     level_multiplier: f64,
     config: CollectionConfig,
-    rng: rand::rngs::ThreadRng,
+    parallel_search_threshold: usize,
+    distance_memo: bool,
+    nan_handling: NanHandling,
+    deleted: HashSet<u32>,
 }
 
 impl HNSWIndex {
-    pub fn new(config: CollectionConfig) -> Self {
+    pub fn new(config: CollectionConfig, performance: &PerformanceConfig) -> Self {
         HNSWIndex {
             nodes: HashMap::new(),
+            ids: Vec::new(),
+            id_map: HashMap::new(),
             entry_point: None,
             max_level: 0,
             level_multiplier: 1.0 / (2.0_f64).ln(),
             config,
-            rng: rand::thread_rng(),
+            parallel_search_threshold: performance.parallel_search_threshold,
+            distance_memo: performance.search_distance_memo,
+            nan_handling: performance.nan_handling,
+            deleted: HashSet::new(),
         }
     }
 
     pub fn add_vector(&mut self, id: String, vector: Vector) -> Result<(), Box<dyn Error>> {
-        let level = self.get_random_level();
-        
-        let mut connections = vec![Vec::new(); level + 1];
-        
-        let node = Node {
-            id: id.clone(),
-            vector: vector.clone(),
-            connections,
-            level,
-        };
+        let level = Self::get_random_level();
+        self.add_vector_at_level(id, vector, level)
+    }
 
-        if self.entry_point.is_none() {
-            self.entry_point = Some(id.clone());
-            self.max_level = level;
-            self.nodes.insert(id, node);
-            return Ok(());
+    #[doc(hidden)]
+    pub fn add_vector_at_level(&mut self, id: String, vector: Vector, level: usize) -> Result<(), Box<dyn Error>> {
+        if let Some(&idx) = self.id_map.get(&id) {
+            self.unlink(idx);
         }
 
-        let mut current_closest = vec![self.entry_point.as_ref().unwrap().clone()];
-        
-        for lc in (level + 1..=self.max_level).rev() {
-            current_closest = self.search_layer(&vector, &current_closest, 1, lc)?;
+        let neighbors = self.find_neighbors(&vector, level)?;
+        self.link_node(id, vector, level, neighbors)?;
+        Ok(())
+    }
+
+    pub fn par_build(&mut self, vectors: Vec<(String, Vector)>) -> Result<(), Box<dyn Error>> {
+        self.par_build_cancellable(vectors, None)
+    }
+
+    pub fn par_build_cancellable(
+        &mut self,
+        vectors: Vec<(String, Vector)>,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<(), Box<dyn Error>> {
+        for (id, _) in &vectors {
+            if let Some(&idx) = self.id_map.get(id) {
+                self.unlink(idx);
+            }
         }
 
-        for lc in (0..=level.min(self.max_level)).rev() {
-            let candidates = self.search_layer(&vector, &current_closest, self.config.ef_construction, lc)?;
-            
-            let selected = self.select_neighbors_heuristic(&vector, &candidates, self.config.m)?;
-            
-            for neighbor_id in &selected {
+        #[cfg(feature = "std")]
+        let vectors = vectors.into_par_iter();
+        #[cfg(not(feature = "std"))]
+        let vectors = vectors.into_iter();
+        let mut pending = vectors
+            .map(|(id, vector)| (id, vector, Self::get_random_level()))
+            .collect::<Vec<_>>()
+            .into_iter();
+
+        loop {
+            if let Some(cancel) = cancel {
+                cancel.check()?;
+            }
+
+            let batch: Vec<_> = pending.by_ref().take(self.nodes.len().max(1)).collect();
+            if batch.is_empty() {
+                break;
+            }
+
+            #[cfg(feature = "std")]
+            let batch = batch.into_par_iter();
+            #[cfg(not(feature = "std"))]
+            let batch = batch.into_iter();
+            let linked = batch
+                .map(|(id, vector, level)| {
+                    self.find_neighbors(&vector, level)
+                        .map(|neighbors| (id, vector, level, neighbors))
+                        .map_err(|e| e.to_string())
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+
+            let mut batch = Vec::with_capacity(linked.len());
+            for (id, vector, level, neighbors) in linked {
+                batch.push(self.link_node(id, vector, level, neighbors)?);
+            }
+            self.stitch(&batch)?;
+        }
+
+        Ok(())
+    }
+
+    /// Nodes of one parallel batch only searched the graph as it was before the batch, so
+    /// they cannot have found each other. Each one re-selects its neighbors from its current
+    /// ones plus the batch mates linked from those, which finds mates that chose nearby nodes.
+    fn stitch(&mut self, batch: &[u32]) -> Result<(), Box<dyn Error>> {
+        let members: HashSet<u32> = batch.iter().copied().collect();
+        #[cfg(feature = "std")]
+        let batch = batch.par_iter();
+        #[cfg(not(feature = "std"))]
+        let batch = batch.iter();
+        let reselected = batch
+            .map(|&idx| {
+                let node = &self.nodes[&idx];
+                let lists = (0..=node.level)
+                    .map(|lc| {
+                        let mut candidates = node.connections[lc].clone();
+                        for neighbor_id in &node.connections[lc] {
+                            let Some(neighbor) = self.nodes.get(neighbor_id).filter(|n| n.level >= lc) else {
+                                continue;
+                            };
+                            candidates.extend(
+                                neighbor.connections[lc]
+                                    .iter()
+                                    .filter(|&&mate| mate != idx && members.contains(&mate)),
+                            );
+                        }
+                        candidates.sort_unstable();
+                        candidates.dedup();
+                        self.select_neighbors(&node.vector, &candidates, self.config.m)
+                            .map_err(|e| e.to_string())
+                    })
+                    .collect::<Result<Vec<_>, String>>()?;
+                Ok((idx, lists))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        for (idx, lists) in reselected {
+            for (lc, selected) in lists.into_iter().enumerate() {
+                self.add_back_links(idx, lc, &selected)?;
+                if let Some(node) = self.nodes.get_mut(&idx) {
+                    node.connections[lc] = selected;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Layer 0 holds twice as many links as the upper layers, as in the HNSW paper.
+    fn max_degree(&self, level: usize) -> usize {
+        if level == 0 {
+            2 * self.config.m
+        } else {
+            self.config.m
+        }
+    }
+
+    /// Links each of `neighbors` back to `idx` at `level`, re-selecting any list that grows
+    /// past the layer's maximum degree.
+    fn add_back_links(&mut self, idx: u32, level: usize, neighbors: &[u32]) -> Result<(), Box<dyn Error>> {
+        let max_degree = self.max_degree(level);
+        for neighbor_id in neighbors {
+            let overfull = match self.nodes.get_mut(neighbor_id) {
+                Some(neighbor) if neighbor.level >= level && !neighbor.connections[level].contains(&idx) => {
+                    neighbor.connections[level].push(idx);
+                    neighbor.connections[level].len() > max_degree
+                }
+                _ => false,
+            };
+            if overfull {
+                let neighbor = &self.nodes[neighbor_id];
+                let selected = self.select_neighbors(&neighbor.vector, &neighbor.connections[level], max_degree)?;
                 if let Some(neighbor) = self.nodes.get_mut(neighbor_id) {
-                    if neighbor.level >= lc {
-                        neighbor.connections[lc].push(id.clone());
-                    }
+                    neighbor.connections[level] = selected;
                 }
             }
+        }
+        Ok(())
+    }
+
+    fn find_neighbors(&self, vector: &Vector, level: usize) -> Result<Vec<Vec<u32>>, Box<dyn Error>> {
+        let mut neighbors = vec![Vec::new(); level + 1];
+
+        let entry_point = match self.entry_point {
+            Some(entry_point) => entry_point,
+            None => return Ok(neighbors),
+        };
+
+        let mut current_closest = vec![entry_point];
+        
+        for lc in (level + 1..=self.max_level).rev() {
+            current_closest = self.search_layer(vector, &current_closest, 1, lc, &mut None)?;
+        }
+
+        for lc in (0..=level.min(self.max_level)).rev() {
+            let candidates =
+                self.search_layer(vector, &current_closest, self.config.ef_construction, lc, &mut None)?;
             
-            if let Some(node) = self.nodes.get_mut(&id) {
-                node.connections[lc] = selected.clone();
-            }
+            let selected = self.select_neighbors(vector, &candidates, self.config.m)?;
             
+            neighbors[lc] = selected.clone();
             current_closest = selected;
         }
 
-        if level > self.max_level {
+        Ok(neighbors)
+    }
+
+    fn link_node(
+        &mut self,
+        id: String,
+        vector: Vector,
+        level: usize,
+        mut neighbors: Vec<Vec<u32>>,
+    ) -> Result<u32, Box<dyn Error>> {
+        let idx = self.intern(id);
+
+        for selected in neighbors.iter_mut() {
+            let mut seen = HashSet::new();
+            selected.retain(|neighbor_id| seen.insert(*neighbor_id));
+        }
+
+        if self.entry_point.is_none() || level > self.max_level {
             self.max_level = level;
-            self.entry_point = Some(id.clone());
+            self.entry_point = Some(idx);
         }
 
-        self.nodes.insert(id, node);
-        Ok(())
+        // Inserted before the back-links so an overfull neighbor can weigh it when re-selecting.
+        let node = Node {
+            vector,
+            connections: neighbors.clone(),
+            level,
+        };
+        self.nodes.insert(idx, node);
+
+        for (lc, selected) in neighbors.iter().enumerate() {
+            self.add_back_links(idx, lc, selected)?;
+        }
+        Ok(idx)
+    }
+
+    #[doc(hidden)]
+    pub fn neighbor_ids(&self, id: &str, level: usize) -> Option<Vec<&str>> {
+        let node = self.id_map.get(id).and_then(|idx| self.nodes.get(idx))?;
+        let connections = node.connections.get(level)?;
+        Some(connections.iter().map(|&idx| self.ids[idx as usize].as_str()).collect())
+    }
+
+    /// Known ids keep their slot, so updates do not grow the id table.
+    fn intern(&mut self, id: String) -> u32 {
+        if let Some(&idx) = self.id_map.get(&id) {
+            return idx;
+        }
+        let idx = self.ids.len() as u32;
+        self.id_map.insert(id.clone(), idx);
+        self.ids.push(id);
+        idx
     }
 
     pub fn search(&self, query: Vector, k: usize, ef: Option<usize>) -> Result<Vec<(String, f32)>, Box<dyn Error>> {
+        self.search_with_beam(query, k, ef, 1)
+    }
+
+    pub fn search_with_beam(
+        &self,
+        query: Vector,
+        k: usize,
+        ef: Option<usize>,
+        upper_layer_ef: usize,
+    ) -> Result<Vec<(String, f32)>, Box<dyn Error>> {
         if self.entry_point.is_none() {
             return Ok(Vec::new());
         }
 
-        let ef = ef.unwrap_or(k.max(50));
-        let mut current_closest = vec![self.entry_point.as_ref().unwrap().clone()];
+        let ef = ef.unwrap_or(50).max(k);
+        let mut current_closest = vec![self.entry_point.unwrap()];
+        let mut memo = self.distance_memo.then(HashMap::new);
 
         for lc in (1..=self.max_level).rev() {
-            current_closest = self.search_layer(&query, &current_closest, 1, lc)?;
+            current_closest = self.search_layer(&query, &current_closest, upper_layer_ef, lc, &mut memo)?;
         }
 
-        let candidates = self.search_layer(&query, &current_closest, ef, 0)?;
+        let candidates = self.search_layer(&query, &current_closest, ef, 0, &mut memo)?;
         
-        let mut result: Vec<_> = candidates.into_par_iter()
-            .filter_map(|id| {
-                self.nodes.get(&id).map(|node| {
-                    let distance = calculate_distance(&query, &node.vector, self.config.metric);
-                    (id, distance)
-                })
+        let score = |id: u32| {
+            if self.deleted.contains(&id) {
+                return None;
+            }
+            self.nodes.get(&id).map(|node| {
+                let distance = memo
+                    .as_ref()
+                    .and_then(|memo| memo.get(&id).copied())
+                    .unwrap_or_else(|| self.distance(&query, &node.vector));
+                (id, distance)
             })
-            .collect();
+        };
+        let compare = |a: &(u32, f32), b: &(u32, f32)| {
+            compare_distances(a.1, b.1).then(a.0.cmp(&b.0))
+        };
         
-        result.par_sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
-        result.truncate(k);
+        let mut result: Vec<_> = if candidates.len() < self.parallel_search_threshold {
+            candidates.into_iter().filter_map(score).collect()
+        } else {
+            #[cfg(feature = "std")]
+            let candidates = candidates.into_par_iter();
+            #[cfg(not(feature = "std"))]
+            let candidates = candidates.into_iter();
+            candidates.filter_map(score).collect()
+        };
+        
+        check_nan_distances(
+            result.iter().map(|&(id, distance)| (self.ids[id as usize].as_str(), distance)),
+            self.nan_handling,
+        )?;
+        
+        if k == 0 {
+            return Ok(Vec::new());
+        }
+        if k < result.len() {
+            result.select_nth_unstable_by(k - 1, compare);
+            result.truncate(k);
+        }
+        result.sort_unstable_by(compare);
         
-        Ok(result)
+        Ok(result
+            .into_iter()
+            .map(|(id, distance)| (self.ids[id as usize].clone(), distance))
+            .collect())
     }
 
     fn search_layer(
         &self,
         query: &Vector,
-        entry_points: &[String],
+        entry_points: &[u32],
         num_closest: usize,
         level: usize,
-    ) -> Result<Vec<String>, Box<dyn Error>> {
+        memo: &mut Option<HashMap<u32, f32>>,
+    ) -> Result<Vec<u32>, Box<dyn Error>> {
+        let num_closest = num_closest.max(1);
         let mut visited = HashSet::new();
         let mut candidates = BinaryHeap::new();
-        let mut w = BinaryHeap::new();
+        let mut w = BinaryHeap::with_capacity(num_closest + 1);
 
         for ep in entry_points {
+            if !visited.insert(*ep) {
+                continue;
+            }
             if let Some(node) = self.nodes.get(ep) {
-                let distance = calculate_distance(query, &node.vector, self.config.metric);
-                candidates.push(SearchCandidate {
-                    id: ep.clone(),
-                    distance: -distance,
-                });
-                w.push(SearchCandidate {
-                    id: ep.clone(),
-                    distance,
-                });
-                visited.insert(ep.clone());
+                let distance = self.memoized_distance(query, *ep, node, memo);
+                Self::push_bounded(&mut candidates, &mut w, *ep, distance, num_closest);
             }
         }
 
@@ -168,109 +393,171 @@ impl HNSWIndex {
             let current_id = current.id;
             let current_distance = -current.distance;
 
-            if let Some(furthest) = w.peek() {
-                if current_distance > furthest.distance {
-                    break;
+            if w.len() >= num_closest {
+                if let Some(furthest) = w.peek() {
+                    if current_distance > furthest.distance {
+                        break;
+                    }
                 }
             }
 
             if let Some(current_node) = self.nodes.get(&current_id) {
                 if level < current_node.connections.len() {
                     for neighbor_id in &current_node.connections[level] {
-                        if !visited.contains(neighbor_id) {
-                            visited.insert(neighbor_id.clone());
-                            
+                        if visited.insert(*neighbor_id) {
                             if let Some(neighbor_node) = self.nodes.get(neighbor_id) {
-                                let distance = calculate_distance(query, &neighbor_node.vector, self.config.metric);
-                                
-                                if w.len() < num_closest {
-                                    candidates.push(SearchCandidate {
-                                        id: neighbor_id.clone(),
-                                        distance: -distance,
-                                    });
-                                    w.push(SearchCandidate {
-                                        id: neighbor_id.clone(),
-                                        distance,
-                                    });
-                                } else if let Some(furthest) = w.peek() {
-                                    if distance < furthest.distance {
-                                        candidates.push(SearchCandidate {
-                                            id: neighbor_id.clone(),
-                                            distance: -distance,
-                                        });
-                                        w.pop();
-                                        w.push(SearchCandidate {
-                                            id: neighbor_id.clone(),
-                                            distance,
-                                        });
-                                    }
-                                }
+                                let distance = self.memoized_distance(query, *neighbor_id, neighbor_node, memo);
+                                Self::push_bounded(&mut candidates, &mut w, *neighbor_id, distance, num_closest);
                             }
                         }
                     }
                 }
             }
+
+            if candidates.len() > 2 * num_closest && w.len() >= num_closest {
+                if let Some(bound) = w.peek().map(|furthest| furthest.distance) {
+                    candidates.retain(|c| -c.distance <= bound);
+                }
+            }
         }
 
         Ok(w.into_iter().map(|c| c.id).collect())
     }
 
+    fn push_bounded(
+        candidates: &mut BinaryHeap<SearchCandidate>,
+        w: &mut BinaryHeap<SearchCandidate>,
+        id: u32,
+        distance: f32,
+        num_closest: usize,
+    ) {
+        if w.len() >= num_closest {
+            match w.peek() {
+                Some(furthest) if distance < furthest.distance => {
+                    w.pop();
+                }
+                _ => return,
+            }
+        }
+
+        candidates.push(SearchCandidate {
+            id,
+            distance: -distance,
+        });
+        w.push(SearchCandidate { id, distance });
+    }
+
+    fn distance(&self, a: &Vector, b: &Vector) -> f32 {
+        calculate_distance_with(a, b, self.config.metric, self.config.deterministic_distance)
+    }
+
+    fn memoized_distance(&self, query: &Vector, id: u32, node: &Node, memo: &mut Option<HashMap<u32, f32>>) -> f32 {
+        match memo {
+            Some(memo) => *memo
+                .entry(id)
+                .or_insert_with(|| self.distance(query, &node.vector)),
+            None => self.distance(query, &node.vector),
+        }
+    }
+
+    fn select_neighbors(&self, vector: &Vector, candidates: &[u32], m: usize) -> Result<Vec<u32>, Box<dyn Error>> {
+        match self.config.neighbor_selection {
+            NeighborSelection::Simple => self.select_neighbors_simple(vector, candidates, m),
+            NeighborSelection::Heuristic => self.select_neighbors_heuristic(vector, candidates, m),
+        }
+    }
+
+    fn select_neighbors_simple(
+        &self,
+        vector: &Vector,
+        candidates: &[u32],
+        m: usize,
+    ) -> Result<Vec<u32>, Box<dyn Error>> {
+        if candidates.len() <= m {
+            return Ok(candidates.to_vec());
+        }
+
+        let mut scored: Vec<(u32, f32)> = candidates
+            .iter()
+            .filter_map(|&id| {
+                self.nodes
+                    .get(&id)
+                    .map(|node| (id, self.distance(vector, &node.vector)))
+            })
+            .collect();
+        scored.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+
+        Ok(scored.into_iter().take(m).map(|(id, _)| id).collect())
+    }
+
     fn select_neighbors_heuristic(
         &self,
         vector: &Vector,
-        candidates: &[String],
+        candidates: &[u32],
         m: usize,
-    ) -> Result<Vec<String>, Box<dyn Error>> {
+    ) -> Result<Vec<u32>, Box<dyn Error>> {
         if candidates.len() <= m {
             return Ok(candidates.to_vec());
         }
 
+        // The diversity test compares candidates with each other, which assumes a metric;
+        // dot product only ranks candidates against the query, so keep the top `m` instead.
+        if self.config.metric == DistanceMetric::DotProduct {
+            return self.select_neighbors_simple(vector, candidates, m);
+        }
+
+        let mut remaining: Vec<(u32, &Vector, f32, f32)> = candidates
+            .iter()
+            .filter_map(|&id| {
+                self.nodes.get(&id).map(|node| {
+                    (id, &node.vector, self.distance(vector, &node.vector), f32::INFINITY)
+                })
+            })
+            .collect();
         let mut selected = Vec::new();
-        let mut remaining: Vec<_> = candidates.iter().collect();
 
         while selected.len() < m && !remaining.is_empty() {
             let mut best_idx = 0;
             let mut best_score = f32::INFINITY;
 
-            for (idx, candidate_id) in remaining.iter().enumerate() {
-                if let Some(candidate_node) = self.nodes.get(*candidate_id) {
-                    let distance_to_query = calculate_distance(vector, &candidate_node.vector, self.config.metric);
-                    
-                    let mut min_distance_to_selected = f32::INFINITY;
-                    for selected_id in &selected {
-                        if let Some(selected_node) = self.nodes.get(selected_id) {
-                            let distance = calculate_distance(&candidate_node.vector, &selected_node.vector, self.config.metric);
-                            min_distance_to_selected = min_distance_to_selected.min(distance);
-                        }
-                    }
-
-                    let score = if selected.is_empty() {
-                        distance_to_query
-                    } else {
-                        distance_to_query - min_distance_to_selected
-                    };
+            for (idx, &(_, _, distance_to_query, min_distance_to_selected)) in remaining.iter().enumerate() {
+                let score = if selected.is_empty() {
+                    distance_to_query
+                } else {
+                    distance_to_query - min_distance_to_selected
+                };
 
-                    if score < best_score {
-                        best_score = score;
-                        best_idx = idx;
-                    }
+                if score < best_score {
+                    best_score = score;
+                    best_idx = idx;
                 }
             }
 
-            selected.push(remaining.remove(best_idx).clone());
+            let (chosen_id, chosen_vector, _, _) = remaining.remove(best_idx);
+            for (_, candidate_vector, _, min_distance_to_selected) in remaining.iter_mut() {
+                let distance = self.distance(candidate_vector, chosen_vector);
+                *min_distance_to_selected = min_distance_to_selected.min(distance);
+            }
+            selected.push(chosen_id);
         }
 
         Ok(selected)
     }
 
-    fn get_random_level(&mut self) -> usize {
+    fn get_random_level() -> usize {
+        let mut rng = rand::thread_rng();
         let mut level = 0;
-        while self.rng.gen::<f64>() < 0.5 && level < 16 {
+        while rng.gen::<f64>() < 0.5 && level < 16 {
             level += 1;
         }
         level
     }
 
+    pub fn get_vector(&self, id: &str) -> Option<&Vector> {
+        let idx = self.id_map.get(id)?;
+        self.nodes.get(idx).map(|node| &node.vector)
+    }
+
     pub fn get_stats(&self) -> (usize, usize) {
         let total_connections: usize = self.nodes.values()
             .map(|node| node.connections.iter().map(|level| level.len()).sum::<usize>())
@@ -279,30 +566,1030 @@ impl HNSWIndex {
         (self.nodes.len(), total_connections)
     }
 
+    pub fn estimated_memory_bytes(&self) -> usize {
+        let ids: usize = self.id_map.keys().map(|id| 2 * id.len()).sum::<usize>()
+            + self.ids.len() * std::mem::size_of::<String>()
+            + self.id_map.len() * (std::mem::size_of::<String>() + std::mem::size_of::<u32>());
+
+        let nodes: usize = self.nodes.values()
+            .map(|node| {
+                std::mem::size_of::<u32>()
+                    + std::mem::size_of::<Node>()
+                    + node.vector.len() * std::mem::size_of::<f32>()
+                    + node.connections.iter()
+                        .map(|level| std::mem::size_of::<Vec<u32>>() + level.len() * std::mem::size_of::<u32>())
+                        .sum::<usize>()
+            })
+            .sum();
+
+        ids + nodes
+    }
+
+    /// Bytes held by the nodes' vectors, without walking the graph.
+    pub fn vector_bytes(&self) -> usize {
+        self.nodes.len() * self.config.dimension * std::mem::size_of::<f32>()
+    }
+
+    pub fn mark_deleted(&mut self, id: &str) -> bool {
+        let Some(&idx) = self.id_map.get(id) else {
+            return false;
+        };
+        let marked = self.deleted.insert(idx);
+        // Tombstones still route searches, but a live entry point keeps results one hop closer.
+        if marked && self.entry_point == Some(idx) {
+            self.reselect_entry_point();
+        }
+        marked
+    }
+
+    pub fn deleted_count(&self) -> usize {
+        self.deleted.len()
+    }
+
+    /// Tombstones plus slots freed by `remove_vector`, over all slots; both are reclaimed
+    /// only by a rebuild, which renumbers the live ids compactly.
+    pub fn deleted_ratio(&self) -> f32 {
+        if self.ids.is_empty() {
+            0.0
+        } else {
+            let vacant = self.ids.len() - self.id_map.len();
+            (self.deleted.len() + vacant) as f32 / self.ids.len() as f32
+        }
+    }
+
+    pub fn rebuilt(&self) -> Result<HNSWIndex, Box<dyn Error>> {
+        self.rebuilt_with(self.config.clone(), None)
+    }
+
+    pub fn rebuilt_with(
+        &self,
+        config: CollectionConfig,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<HNSWIndex, Box<dyn Error>> {
+        let live = self.live_vectors();
+        let mut index = self.empty_like(config, live.len());
+        index.par_build_cancellable(live, cancel)?;
+
+        Ok(index)
+    }
+
+    /// An empty graph with this one's settings and the live vectors to build it from, so a
+    /// compacted copy can be built without holding on to this graph.
+    pub fn compaction_seed(&self) -> (HNSWIndex, Vec<(String, Vector)>) {
+        let live = self.live_vectors();
+        (self.empty_like(self.config.clone(), live.len()), live)
+    }
+
+    fn live_vectors(&self) -> Vec<(String, Vector)> {
+        self.nodes
+            .iter()
+            .filter(|(idx, _)| !self.deleted.contains(idx))
+            .map(|(idx, node)| (self.ids[*idx as usize].clone(), node.vector.clone()))
+            .collect()
+    }
+
+    fn empty_like(&self, config: CollectionConfig, capacity: usize) -> HNSWIndex {
+        HNSWIndex {
+            nodes: HashMap::with_capacity(capacity),
+            ids: Vec::with_capacity(capacity),
+            id_map: HashMap::with_capacity(capacity),
+            entry_point: None,
+            max_level: 0,
+            level_multiplier: self.level_multiplier,
+            config,
+            parallel_search_threshold: self.parallel_search_threshold,
+            distance_memo: self.distance_memo,
+            nan_handling: self.nan_handling,
+            deleted: HashSet::new(),
+        }
+    }
+
+    /// Whether `other` was built with the same construction parameters as this graph.
+    pub fn built_like(&self, other: &HNSWIndex) -> bool {
+        self.config.m == other.config.m
+            && self.config.ef_construction == other.config.ef_construction
+            && self.config.neighbor_selection == other.config.neighbor_selection
+    }
+
+    /// Brings a graph built from an older snapshot of `current` up to date: vectors added or
+    /// changed since are inserted, and ones removed since are tombstoned.
+    pub fn catch_up(&mut self, current: &HNSWIndex) -> Result<(), Box<dyn Error>> {
+        let is_live = |index: &HNSWIndex, id: &str| {
+            index.id_map.get(id).is_some_and(|idx| !index.deleted.contains(idx))
+        };
+
+        for (id, idx) in &current.id_map {
+            let Some(node) = current.nodes.get(idx).filter(|_| !current.deleted.contains(idx)) else {
+                continue;
+            };
+            if !is_live(self, id) || self.get_vector(id) != Some(&node.vector) {
+                self.add_vector(id.clone(), node.vector.clone())?;
+            }
+        }
+
+        let removed: Vec<String> = self
+            .id_map
+            .keys()
+            .filter(|id| !is_live(current, id))
+            .cloned()
+            .collect();
+        for id in removed {
+            self.mark_deleted(&id);
+        }
+        Ok(())
+    }
+
+    pub fn optimize(&mut self) -> Result<(), Box<dyn Error>> {
+        *self = self.rebuilt()?;
+        Ok(())
+    }
+
+    pub fn optimize_cancellable(&mut self, cancel: &CancellationToken) -> Result<(), Box<dyn Error>> {
+        *self = self.rebuilt_with(self.config.clone(), Some(cancel))?;
+        Ok(())
+    }
+
+    /// Re-selects, with the heuristic, every connection list longer than `max_degree`.
+    /// Returns how many lists were trimmed.
+    pub fn prune_connections(&mut self, max_degree: usize) -> Result<usize, Box<dyn Error>> {
+        if max_degree == 0 {
+            return Err("max_degree must be greater than 0".into());
+        }
+
+        let ids: Vec<u32> = self.nodes.keys().copied().collect();
+        #[cfg(feature = "std")]
+        let ids = ids.par_iter();
+        #[cfg(not(feature = "std"))]
+        let ids = ids.iter();
+        let pruned: Vec<(u32, usize, Vec<u32>)> = ids
+            .map(|id| {
+                let node = &self.nodes[id];
+                let mut lists = Vec::new();
+                for (level, connections) in node.connections.iter().enumerate() {
+                    if connections.len() > max_degree {
+                        let selected = self
+                            .select_neighbors_heuristic(&node.vector, connections, max_degree)
+                            .map_err(|e| e.to_string())?;
+                        lists.push((*id, level, selected));
+                    }
+                }
+                Ok(lists)
+            })
+            .collect::<Result<Vec<_>, String>>()?
+            .into_iter()
+            .flatten()
+            .collect();
+
+        let count = pruned.len();
+        for (id, level, connections) in pruned {
+            if let Some(node) = self.nodes.get_mut(&id) {
+                node.connections[level] = connections;
+            }
+        }
+        if self.config.symmetric_links {
+            self.enforce_symmetry_within(max_degree);
+        }
+        self.reselect_entry_point();
+
+        Ok(count)
+    }
+
+    /// Moves the entry point to the best-connected live node on the top layer, a cheap proxy
+    /// for the most central one. Ties go to the lowest internal id.
+    pub fn reselect_entry_point(&mut self) {
+        let top_level = self.nodes.values().map(|node| node.level).max();
+        self.entry_point = top_level.and_then(|level| {
+            self.nodes
+                .iter()
+                .filter(|(_, node)| node.level == level)
+                .max_by_key(|&(id, node)| {
+                    (
+                        !self.deleted.contains(id),
+                        node.connections[level].len(),
+                        node.connections[0].len(),
+                        Reverse(*id),
+                    )
+                })
+                .map(|(&id, _)| id)
+        });
+        self.max_level = top_level.unwrap_or(0);
+    }
+
+    /// Adds the missing reverse of every one-way link, returning how many were added. Links
+    /// the target cannot return (it was removed, re-added at a lower level, or is already at
+    /// its level's max degree) are dropped.
+    pub fn enforce_symmetry(&mut self) -> usize {
+        self.enforce_symmetry_within(usize::MAX)
+    }
+
+    /// `enforce_symmetry`, with every level's degree also capped at `max_degree`.
+    fn enforce_symmetry_within(&mut self, max_degree: usize) -> usize {
+        let mut missing = Vec::new();
+        let mut stale = Vec::new();
+        for (&id, node) in &self.nodes {
+            for (level, connections) in node.connections.iter().enumerate() {
+                for neighbor_id in connections {
+                    match self.nodes.get(neighbor_id) {
+                        Some(neighbor) if neighbor.level >= level => {
+                            if !neighbor.connections[level].contains(&id) {
+                                missing.push((*neighbor_id, level, id));
+                            }
+                        }
+                        _ => stale.push((id, level, *neighbor_id)),
+                    }
+                }
+            }
+        }
+
+        for (id, level, neighbor_id) in stale {
+            if let Some(node) = self.nodes.get_mut(&id) {
+                node.connections[level].retain(|&x| x != neighbor_id);
+            }
+        }
+        let mut added = 0;
+        for (neighbor_id, level, id) in missing {
+            let cap = max_degree.min(self.max_degree(level));
+            let Some(neighbor) = self.nodes.get_mut(&neighbor_id) else {
+                continue;
+            };
+            if neighbor.connections[level].contains(&id) {
+                continue;
+            }
+            if neighbor.connections[level].len() < cap {
+                neighbor.connections[level].push(id);
+                added += 1;
+            } else if let Some(node) = self.nodes.get_mut(&id) {
+                node.connections[level].retain(|&x| x != neighbor_id);
+            }
+        }
+        added
+    }
+
+    pub fn connectivity_report(&self) -> ConnectivityReport {
+        let mut report = ConnectivityReport::default();
+        for (&id, node) in &self.nodes {
+            for (level, connections) in node.connections.iter().enumerate() {
+                let unique: HashSet<u32> = connections.iter().copied().collect();
+                report.duplicate_edges += connections.len() - unique.len();
+                report.asymmetric_edges += unique
+                    .iter()
+                    .filter(|neighbor_id| {
+                        !self
+                            .nodes
+                            .get(neighbor_id)
+                            .and_then(|neighbor| neighbor.connections.get(level))
+                            .is_some_and(|links| links.contains(&id))
+                    })
+                    .count();
+            }
+        }
+        report
+    }
+
+    /// External id and level of the current entry point.
+    pub fn entry_point(&self) -> Option<(&str, usize)> {
+        let idx = self.entry_point?;
+        let node = self.nodes.get(&idx)?;
+        Some((self.ids[idx as usize].as_str(), node.level))
+    }
+
+    pub fn set_construction_params(&mut self, m: usize, ef_construction: usize) {
+        self.config.m = m;
+        self.config.ef_construction = ef_construction;
+    }
+
+    pub fn reconfigure(&mut self, config: CollectionConfig) -> Result<(), Box<dyn Error>> {
+        *self = self.rebuilt_with(config, None)?;
+        Ok(())
+    }
+
+    /// Replaces this graph with a copy of `source`'s, keeping its internal ids and links
+    /// instead of re-running neighbor selection.
+    pub fn clone_from(&mut self, source: &HNSWIndex) -> Result<(), Box<dyn Error>> {
+        if self.config.dimension != source.config.dimension || self.config.metric != source.config.metric {
+            return Err(format!(
+                "Cannot clone index with dimension {} and metric {:?} into one with dimension {} and metric {:?}",
+                source.config.dimension, source.config.metric, self.config.dimension, self.config.metric
+            )
+            .into());
+        }
+
+        self.nodes = source.nodes.clone();
+        self.ids = source.ids.clone();
+        self.id_map = source.id_map.clone();
+        self.entry_point = source.entry_point;
+        self.max_level = source.max_level;
+        self.deleted = source.deleted.clone();
+        Ok(())
+    }
+
     pub fn remove_vector(&mut self, id: &str) -> Result<bool, Box<dyn Error>> {
-        if let Some(node) = self.nodes.remove(id) {
+        let idx = match self.id_map.remove(id) {
+            Some(idx) => idx,
+            None => return Ok(false),
+        };
+        self.ids[idx as usize] = String::new();
+        Ok(self.unlink(idx))
+    }
+
+    /// Drops the node at `idx` and the links its neighbors hold to it, keeping its id slot.
+    fn unlink(&mut self, idx: u32) -> bool {
+        self.deleted.remove(&idx);
+
+        if let Some(node) = self.nodes.remove(&idx) {
             for level in 0..=node.level {
                 for neighbor_id in &node.connections[level] {
                     if let Some(neighbor) = self.nodes.get_mut(neighbor_id) {
                         if level < neighbor.connections.len() {
-                            neighbor.connections[level].retain(|x| x != id);
+                            neighbor.connections[level].retain(|&x| x != idx);
                         }
                     }
                 }
             }
 
-            if self.entry_point.as_ref() == Some(&id.to_string()) {
-                self.entry_point = self.nodes.keys().next().cloned();
-                if let Some(new_entry) = &self.entry_point {
-                    if let Some(new_entry_node) = self.nodes.get(new_entry) {
-                        self.max_level = new_entry_node.level;
+            if self.entry_point == Some(idx) {
+                self.reselect_entry_point();
+            }
+
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn remove_vector_with_repair(&mut self, id: &str) -> Result<bool, Box<dyn Error>> {
+        let former_neighbors = match self.id_map.get(id).and_then(|idx| self.nodes.get(idx)) {
+            Some(node) => node.connections.clone(),
+            None => return Ok(false),
+        };
+
+        if !self.remove_vector(id)? {
+            return Ok(false);
+        }
+
+        for (lc, neighbors) in former_neighbors.iter().enumerate() {
+            for &neighbor_id in neighbors {
+                let (vector, mut candidates) = match self.nodes.get(&neighbor_id) {
+                    Some(node) if node.level >= lc => (node.vector.clone(), node.connections[lc].clone()),
+                    _ => continue,
+                };
+
+                for &other_id in neighbors {
+                    if other_id != neighbor_id
+                        && !candidates.contains(&other_id)
+                        && self.nodes.get(&other_id).is_some_and(|other| other.level >= lc)
+                    {
+                        candidates.push(other_id);
                     }
                 }
+
+                let selected = self.select_neighbors_heuristic(&vector, &candidates, self.max_degree(lc))?;
+                if let Some(node) = self.nodes.get_mut(&neighbor_id) {
+                    node.connections[lc] = selected;
+                }
             }
+        }
+        if self.config.symmetric_links {
+            self.enforce_symmetry();
+        }
 
-            Ok(true)
-        } else {
-            Ok(false)
+        Ok(true)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::gen::clustered_vectors;
+
+    fn index(dimension: usize) -> HNSWIndex {
+        let config = CollectionConfig {
+            dimension,
+            metric: DistanceMetric::Euclidean,
+            // Sequential sums: a parallel reduction per distance dominates on vectors this small.
+            deterministic_distance: true,
+            ..Default::default()
+        };
+        HNSWIndex::new(config, &PerformanceConfig::default())
+    }
+
+    fn recall(index: &HNSWIndex, vectors: &[Vector], queries: &[Vector], k: usize) -> f32 {
+        let mut found = 0;
+        for query in queries {
+            let mut exact: Vec<(usize, f32)> = vectors
+                .iter()
+                .enumerate()
+                .map(|(i, v)| (i, calculate_distance_with(query, v, DistanceMetric::Euclidean, false)))
+                .collect();
+            exact.sort_by(|a, b| compare_distances(a.1, b.1));
+            let truth: HashSet<String> = exact.iter().take(k).map(|(i, _)| i.to_string()).collect();
+
+            let results = index.search(query.clone(), k, Some(64)).unwrap();
+            found += results.iter().filter(|(id, _)| truth.contains(id)).count();
+        }
+        found as f32 / (queries.len() * k) as f32
+    }
+
+    #[test]
+    fn par_build_recall_matches_serial_build() {
+        let vectors = clustered_vectors(400, 8, 10, 7);
+        let queries = clustered_vectors(20, 8, 10, 8);
+        let items: Vec<(String, Vector)> = vectors.iter().cloned().enumerate().map(|(i, v)| (i.to_string(), v)).collect();
+
+        let mut serial = index(8);
+        for (id, vector) in items.clone() {
+            serial.add_vector(id, vector).unwrap();
         }
+        let mut parallel = index(8);
+        parallel.par_build(items).unwrap();
+
+        let serial_recall = recall(&serial, &vectors, &queries, 10);
+        let parallel_recall = recall(&parallel, &vectors, &queries, 10);
+        assert!(parallel_recall >= serial_recall - 0.05, "parallel {} vs serial {}", parallel_recall, serial_recall);
+        assert!(parallel_recall > 0.8, "parallel recall {}", parallel_recall);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn back_links_respect_max_degree() {
+        let mut index = index(8);
+        let items: Vec<(String, Vector)> = clustered_vectors(300, 8, 1, 3)
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i.to_string(), v))
+            .collect();
+        index.par_build(items).unwrap();
+
+        for node in index.nodes.values() {
+            for (level, connections) in node.connections.iter().enumerate() {
+                assert!(connections.len() <= index.max_degree(level));
+            }
+        }
+    }
+
+    #[test]
+    fn sequential_and_parallel_final_scoring_agree() {
+        let mut index = index(8);
+        let items: Vec<(String, Vector)> = clustered_vectors(300, 8, 5, 11)
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i.to_string(), v))
+            .collect();
+        index.par_build(items).unwrap();
+
+        for query in clustered_vectors(10, 8, 5, 12) {
+            index.parallel_search_threshold = usize::MAX;
+            let sequential = index.search(query.clone(), 10, Some(64)).unwrap();
+            index.parallel_search_threshold = 0;
+            let parallel = index.search(query, 10, Some(64)).unwrap();
+            assert_eq!(sequential, parallel);
+        }
+    }
+    #[test]
+    fn reinserting_an_id_reuses_its_slot() {
+        let mut index = index(4);
+        let vectors = clustered_vectors(10, 4, 2, 5);
+        for round in 0..3 {
+            for (i, vector) in vectors.iter().enumerate() {
+                let shifted: Vector = vector.iter().map(|x| x + round as f32).collect();
+                index.add_vector(format!("doc-{}", i), shifted).unwrap();
+            }
+        }
+        assert_eq!(index.ids.len(), 10);
+        assert_eq!(index.nodes.len(), 10);
+
+        for (i, vector) in vectors.iter().enumerate() {
+            let shifted: Vector = vector.iter().map(|x| x + 2.0).collect();
+            let results = index.search(shifted, 1, Some(32)).unwrap();
+            assert_eq!(results[0].0, format!("doc-{}", i));
+        }
+    }
+
+    #[test]
+    fn freed_slots_count_toward_the_deleted_ratio() {
+        let mut index = index(4);
+        for (i, vector) in clustered_vectors(10, 4, 2, 5).into_iter().enumerate() {
+            index.add_vector(i.to_string(), vector).unwrap();
+        }
+        for i in 0..3 {
+            index.remove_vector(&i.to_string()).unwrap();
+        }
+        index.mark_deleted("3");
+        assert!((index.deleted_ratio() - 0.4).abs() < 1e-6, "{}", index.deleted_ratio());
+    }
+
+    #[test]
+    fn interned_connections_take_less_memory_than_string_ids() {
+        let mut index = index(8);
+        let items: Vec<(String, Vector)> = clustered_vectors(500, 8, 5, 9)
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (format!("document-{:08}", i), v))
+            .collect();
+        index.par_build(items).unwrap();
+
+        let links: usize = index.nodes.values().map(|node| node.connections.iter().map(Vec::len).sum::<usize>()).sum();
+        let as_strings = links * (std::mem::size_of::<String>() + "document-00000000".len());
+        let as_indices = links * std::mem::size_of::<u32>();
+        let interned = index.estimated_memory_bytes();
+        let with_string_links = interned - as_indices + as_strings;
+        assert!(interned * 2 < with_string_links, "{} vs {}", interned, with_string_links);
+    }
+
+    /// The selection loop before distances were cached: every score recomputed from scratch.
+    fn select_neighbors_uncached(index: &HNSWIndex, vector: &Vector, candidates: &[u32], m: usize) -> Vec<u32> {
+        let mut selected: Vec<u32> = Vec::new();
+        let mut remaining = candidates.to_vec();
+        while selected.len() < m && !remaining.is_empty() {
+            let mut best_idx = 0;
+            let mut best_score = f32::INFINITY;
+            for (idx, candidate) in remaining.iter().enumerate() {
+                let candidate = &index.nodes[candidate].vector;
+                let distance_to_query = index.distance(vector, candidate);
+                let min_distance_to_selected = selected
+                    .iter()
+                    .map(|selected| index.distance(candidate, &index.nodes[selected].vector))
+                    .fold(f32::INFINITY, f32::min);
+                let score = if selected.is_empty() {
+                    distance_to_query
+                } else {
+                    distance_to_query - min_distance_to_selected
+                };
+                if score < best_score {
+                    best_score = score;
+                    best_idx = idx;
+                }
+            }
+            selected.push(remaining.remove(best_idx));
+        }
+        selected
+    }
+
+    #[test]
+    fn cached_neighbor_selection_matches_the_uncached_loop() {
+        let mut index = index(8);
+        for (i, vector) in clustered_vectors(200, 8, 4, 13).into_iter().enumerate() {
+            index.add_vector(i.to_string(), vector).unwrap();
+        }
+        let candidates: Vec<u32> = (0..100).collect();
+
+        for query in clustered_vectors(10, 8, 4, 14) {
+            for m in [4, 16, 32] {
+                let cached = index.select_neighbors_heuristic(&query, &candidates, m).unwrap();
+                assert_eq!(cached, select_neighbors_uncached(&index, &query, &candidates, m));
+            }
+        }
+    }
+
+    #[test]
+    fn repairing_removals_keeps_recall_after_heavy_deletion() {
+        let vectors = clustered_vectors(600, 8, 6, 21);
+        let mut plain = index(8);
+        let items: Vec<(String, Vector)> = vectors.iter().cloned().enumerate().map(|(i, v)| (i.to_string(), v)).collect();
+        plain.par_build(items).unwrap();
+        let mut repaired = index(8);
+        repaired.clone_from(&plain).unwrap();
+
+        for i in (0..600).filter(|i| i % 4 != 0) {
+            plain.remove_vector(&i.to_string()).unwrap();
+            repaired.remove_vector_with_repair(&i.to_string()).unwrap();
+        }
+
+        let survivors: Vec<(usize, &Vector)> = vectors.iter().enumerate().step_by(4).collect();
+        let recall = |index: &HNSWIndex| {
+            let mut found = 0;
+            let queries = clustered_vectors(30, 8, 6, 22);
+            for query in &queries {
+                let mut exact: Vec<(usize, f32)> = survivors
+                    .iter()
+                    .map(|&(i, v)| (i, calculate_distance_with(query, v, DistanceMetric::Euclidean, false)))
+                    .collect();
+                exact.sort_by(|a, b| compare_distances(a.1, b.1));
+                let truth: HashSet<String> = exact.iter().take(10).map(|(i, _)| i.to_string()).collect();
+                let results = index.search(query.clone(), 10, Some(16)).unwrap();
+                found += results.iter().filter(|(id, _)| truth.contains(id)).count();
+            }
+            found as f32 / (queries.len() * 10) as f32
+        };
+
+        let (plain_recall, repaired_recall) = (recall(&plain), recall(&repaired));
+        assert!(repaired_recall >= plain_recall, "repaired {} vs plain {}", repaired_recall, plain_recall);
+        assert!(repaired_recall > 0.8, "repaired recall {}", repaired_recall);
+        assert!(repaired.nodes.values().all(|node| !node.connections[0].is_empty()));
+    }
+
+    #[test]
+    fn repair_keeps_the_base_layer_at_its_full_degree() {
+        let mut index = index(8);
+        for (i, vector) in clustered_vectors(400, 8, 4, 23).into_iter().enumerate() {
+            index.add_vector(i.to_string(), vector).unwrap();
+        }
+        let former: Vec<String> = index.neighbor_ids("0", 0).unwrap().into_iter().map(str::to_string).collect();
+        assert!(index.remove_vector_with_repair("0").unwrap());
+
+        let degrees: Vec<usize> = former.iter().map(|id| index.neighbor_ids(id, 0).unwrap().len()).collect();
+        assert!(degrees.iter().all(|&degree| degree <= 2 * index.config.m), "{:?}", degrees);
+        assert!(degrees.iter().any(|&degree| degree > index.config.m), "{:?}", degrees);
+    }
+
+    #[test]
+    fn simple_selection_keeps_the_m_nearest_and_heuristic_prefers_diversity() {
+        let mut index = index(1);
+        for (id, x) in [("near", 1.0), ("beside", 1.1), ("opposite", -2.0)] {
+            index.add_vector_at_level(id.to_string(), vec![x], 0).unwrap();
+        }
+        let candidates: Vec<u32> = ["near", "beside", "opposite"].iter().map(|id| index.id_map[*id]).collect();
+        let names = |selected: Vec<u32>| -> Vec<String> {
+            selected.iter().map(|&idx| index.ids[idx as usize].clone()).collect()
+        };
+
+        index.config.neighbor_selection = NeighborSelection::Simple;
+        assert_eq!(names(index.select_neighbors(&vec![0.0], &candidates, 2).unwrap()), ["near", "beside"]);
+
+        // "beside" sits right next to "near", so the heuristic picks the diverse "opposite" instead.
+        index.config.neighbor_selection = NeighborSelection::Heuristic;
+        assert_eq!(names(index.select_neighbors(&vec![0.0], &candidates, 2).unwrap()), ["near", "opposite"]);
+    }
+
+    #[test]
+    fn simple_selection_matches_a_brute_force_top_m() {
+        let mut index = index(8);
+        for (i, vector) in clustered_vectors(200, 8, 4, 15).into_iter().enumerate() {
+            index.add_vector(i.to_string(), vector).unwrap();
+        }
+        index.config.neighbor_selection = NeighborSelection::Simple;
+        let candidates: Vec<u32> = (0..150).collect();
+
+        for query in clustered_vectors(5, 8, 4, 16) {
+            let mut exact: Vec<(u32, f32)> = candidates
+                .iter()
+                .map(|&idx| (idx, index.distance(&query, &index.nodes[&idx].vector)))
+                .collect();
+            exact.sort_by(|a, b| compare_distances(a.1, b.1));
+            let expected: Vec<u32> = exact.iter().take(12).map(|&(idx, _)| idx).collect();
+            assert_eq!(index.select_neighbors(&query, &candidates, 12).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn explicit_levels_build_a_known_graph() {
+        let mut index = index(1);
+        index.add_vector_at_level("a".to_string(), vec![0.0], 2).unwrap();
+        index.add_vector_at_level("b".to_string(), vec![1.0], 0).unwrap();
+        index.add_vector_at_level("c".to_string(), vec![10.0], 1).unwrap();
+        index.add_vector_at_level("d".to_string(), vec![11.0], 0).unwrap();
+        assert_eq!(index.entry_point(), Some(("a", 2)));
+
+        fn sorted(mut ids: Vec<&str>) -> Vec<&str> {
+            ids.sort();
+            ids
+        }
+        assert_eq!(sorted(index.neighbor_ids("a", 0).unwrap()), ["b", "c", "d"]);
+        assert_eq!(sorted(index.neighbor_ids("d", 0).unwrap()), ["a", "b", "c"]);
+        assert_eq!(index.neighbor_ids("a", 1).unwrap(), ["c"]);
+        assert_eq!(index.neighbor_ids("c", 1).unwrap(), ["a"]);
+        assert!(index.neighbor_ids("a", 2).unwrap().is_empty());
+        assert_eq!(index.neighbor_ids("b", 1), None);
+
+        index.add_vector_at_level("top".to_string(), vec![5.0], 3).unwrap();
+        assert_eq!(index.entry_point(), Some(("top", 3)));
+        assert_eq!(index.neighbor_ids("top", 2).unwrap(), ["a"]);
+        assert_eq!(index.neighbor_ids("a", 2).unwrap(), ["top"]);
+    }
+
+    #[test]
+    fn memoized_searches_return_identical_results() {
+        let mut index = index(16);
+        let items: Vec<(String, Vector)> = clustered_vectors(300, 16, 5, 19)
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i.to_string(), v))
+            .collect();
+        index.par_build(items).unwrap();
+
+        for query in clustered_vectors(10, 16, 5, 20) {
+            index.distance_memo = false;
+            let plain = index.search(query.clone(), 10, Some(32)).unwrap();
+            index.distance_memo = true;
+            let memoized = index.search(query, 10, Some(32)).unwrap();
+            assert_eq!(memoized, plain);
+        }
+    }
+
+    #[test]
+    fn construction_params_apply_to_later_inserts_only() {
+        let mut index = index(4);
+        let vectors = clustered_vectors(100, 4, 2, 23);
+        for (i, vector) in vectors.iter().take(60).enumerate() {
+            index.add_vector(i.to_string(), vector.clone()).unwrap();
+        }
+        index.set_construction_params(2, 200);
+        for (i, vector) in vectors.iter().enumerate().skip(60) {
+            index.add_vector(i.to_string(), vector.clone()).unwrap();
+        }
+
+        let degree = |i: usize| index.neighbor_ids(&i.to_string(), 0).unwrap().len();
+        assert!((60..100).all(|i| degree(i) <= 4));
+        assert!((0..60).any(|i| degree(i) > 4), "earlier nodes were relinked under the new m");
+    }
+
+    /// Nodes `0..len` at `[i]` on level 0, each linked only to `i - 1` and `i + 1`.
+    fn chain(len: u32) -> HNSWIndex {
+        let mut index = index(1);
+        for i in 0..len {
+            index.add_vector_at_level(i.to_string(), vec![i as f32], 0).unwrap();
+        }
+        for i in 0..len {
+            let links = [i.checked_sub(1), Some(i + 1).filter(|&next| next < len)];
+            index.nodes.get_mut(&i).unwrap().connections[0] = links.into_iter().flatten().collect();
+        }
+        index
+    }
+
+    #[test]
+    fn search_layer_returns_exactly_the_nearest_at_the_level() {
+        let index = chain(20);
+        let query = vec![12.2];
+        let by_distance = |a: &u32, b: &u32| compare_distances((*a as f32 - 12.2).abs(), (*b as f32 - 12.2).abs());
+        let mut nearest: Vec<u32> = (0..20).collect();
+        nearest.sort_by(by_distance);
+
+        for entry in [0, 19] {
+            for num_closest in 1..=20 {
+                let mut found = index.search_layer(&query, &[entry], num_closest, 0, &mut None).unwrap();
+                found.sort_by(by_distance);
+                assert_eq!(found, nearest[..num_closest], "entry {} num_closest {}", entry, num_closest);
+            }
+        }
+    }
+
+    #[test]
+    fn top_k_selection_matches_the_fully_sorted_result() {
+        let mut index = index(2);
+        // Ten distinct points, each inserted four times, so most distances tie.
+        for i in 0..40u32 {
+            let point = (i % 10) as f32;
+            index.add_vector(i.to_string(), vec![point, 0.5 * point]).unwrap();
+        }
+        
+        let query = vec![3.3, 1.1];
+        let ef = Some(40);
+        let full = index.search(query.clone(), 40, ef).unwrap();
+        
+        let mut exact: Vec<(u32, f32)> = (0..40u32)
+            .map(|i| (i, index.distance(&query, &index.nodes[&i].vector)))
+            .collect();
+        exact.sort_by(|a, b| compare_distances(a.1, b.1).then(a.0.cmp(&b.0)));
+        let exact: Vec<(String, f32)> = exact.into_iter().map(|(i, d)| (i.to_string(), d)).collect();
+        assert_eq!(full, exact);
+        
+        for k in 0..=45 {
+            let top = index.search(query.clone(), k, ef).unwrap();
+            assert_eq!(top, full[..k.min(full.len())], "k {}", k);
+        }
+    }
+
+    #[test]
+    fn pruning_caps_every_connection_list_and_keeps_recall() {
+        let vectors = clustered_vectors(500, 8, 10, 21);
+        let queries = clustered_vectors(20, 8, 10, 22);
+        let max_degree = |index: &HNSWIndex| {
+            index.nodes.values().flat_map(|node| node.connections.iter().map(Vec::len)).max().unwrap()
+        };
+        // Symmetric links add reverse edges after pruning; they must not push a list past the bound.
+        for symmetric_links in [false, true] {
+            let mut index = HNSWIndex::new(
+                CollectionConfig {
+                    symmetric_links,
+                    ..index(8).config.clone()
+                },
+                &PerformanceConfig::default(),
+            );
+            for (i, vector) in vectors.iter().enumerate() {
+                index.add_vector(i.to_string(), vector.clone()).unwrap();
+            }
+            assert!(max_degree(&index) > 8);
+            let before = recall(&index, &vectors, &queries, 10);
+
+            let pruned = index.prune_connections(8).unwrap();
+            assert!(pruned > 0);
+            assert!(max_degree(&index) <= 8, "symmetric_links: {}", symmetric_links);
+            let after = recall(&index, &vectors, &queries, 10);
+            assert!(after >= 0.9 && after >= before - 0.1, "recall {} before pruning, {} after", before, after);
+
+            assert_eq!(index.prune_connections(8).unwrap(), 0);
+            assert!(index.prune_connections(0).is_err());
+        }
+    }
+
+    #[test]
+    fn a_wider_upper_layer_beam_escapes_a_greedy_trap() {
+        // Query at 10. On level 1 the entry E (0) sees a trap T (3) that is closer than the
+        // detour D (-1), which alone leads to N (9.5). Level 0 repeats the same links.
+        let mut index = index(1);
+        let points = [("E", 0.0), ("T", 3.0), ("D", -1.0), ("N", 9.5)];
+        for (id, x) in points {
+            index.add_vector_at_level(id.to_string(), vec![x], 1).unwrap();
+        }
+        let links: [&[u32]; 4] = [&[1, 2], &[0], &[0, 3], &[2]];
+        for (id, neighbors) in links.iter().enumerate() {
+            let node = index.nodes.get_mut(&(id as u32)).unwrap();
+            node.connections[0] = neighbors.to_vec();
+            node.connections[1] = neighbors.to_vec();
+        }
+        index.entry_point = Some(0);
+        index.max_level = 1;
+
+        let greedy = index.search_with_beam(vec![10.0], 1, Some(1), 1).unwrap();
+        assert_eq!(greedy, [("T".to_string(), 7.0)]);
+
+        let wide = index.search_with_beam(vec![10.0], 1, Some(1), 3).unwrap();
+        assert_eq!(wide, [("N".to_string(), 0.5)]);
+        assert_eq!(index.search(vec![10.0], 1, Some(1)).unwrap(), greedy);
+    }
+
+    #[test]
+    fn cloned_graphs_are_structurally_equal_and_search_identically() {
+        let mut source = index(8);
+        for (i, vector) in clustered_vectors(200, 8, 5, 23).into_iter().enumerate() {
+            source.add_vector(i.to_string(), vector).unwrap();
+        }
+        source.mark_deleted("7");
+
+        let mut clone = index(8);
+        clone.clone_from(&source).unwrap();
+        assert_eq!(clone.entry_point(), source.entry_point());
+        assert_eq!(clone.ids, source.ids);
+        assert_eq!(clone.deleted, source.deleted);
+        for (id, node) in &source.nodes {
+            let copied = &clone.nodes[id];
+            assert_eq!((&copied.vector, &copied.connections, copied.level), (&node.vector, &node.connections, node.level));
+        }
+        for query in clustered_vectors(10, 8, 5, 24) {
+            assert_eq!(clone.search(query.clone(), 10, None).unwrap(), source.search(query, 10, None).unwrap());
+        }
+
+        // The copy is independent of its source.
+        clone.add_vector("new".to_string(), vec![0.0; 8]).unwrap();
+        assert!(source.get_vector("new").is_none());
+    }
+
+    #[test]
+    fn cloning_between_incompatible_indexes_fails() {
+        let mut source = index(8);
+        source.add_vector("a".to_string(), vec![1.0; 8]).unwrap();
+
+        assert!(index(4).clone_from(&source).is_err());
+        let cosine = CollectionConfig {
+            dimension: 8,
+            metric: DistanceMetric::Cosine,
+            ..Default::default()
+        };
+        let mut other = HNSWIndex::new(cosine, &PerformanceConfig::default());
+        assert!(other.clone_from(&source).is_err());
+        assert!(other.get_vector("a").is_none());
+    }
+
+    #[test]
+    fn heuristic_selection_keeps_the_top_inner_products_under_dot_product() {
+        let config = |neighbor_selection| CollectionConfig {
+            dimension: 4,
+            metric: DistanceMetric::DotProduct,
+            neighbor_selection,
+            deterministic_distance: true,
+            ..Default::default()
+        };
+        let mut heuristic = HNSWIndex::new(config(NeighborSelection::Heuristic), &PerformanceConfig::default());
+        let mut simple = HNSWIndex::new(config(NeighborSelection::Simple), &PerformanceConfig::default());
+        for (i, vector) in clustered_vectors(60, 4, 3, 11).into_iter().enumerate() {
+            heuristic.add_vector_at_level(i.to_string(), vector.clone(), 0).unwrap();
+            simple.add_vector_at_level(i.to_string(), vector, 0).unwrap();
+        }
+        
+        let query = vec![3.0, -1.0, 2.0, 0.5];
+        let candidates: Vec<u32> = heuristic.nodes.keys().copied().collect();
+        assert_eq!(
+            heuristic.select_neighbors(&query, &candidates, 8).unwrap(),
+            simple.select_neighbors(&query, &candidates, 8).unwrap()
+        );
+    }
+
+    #[test]
+    fn search_returns_k_results_when_ef_is_below_k() {
+        let mut index = index(4);
+        for (i, vector) in clustered_vectors(100, 4, 5, 13).into_iter().enumerate() {
+            index.add_vector(i.to_string(), vector).unwrap();
+        }
+        
+        let query = vec![0.5; 4];
+        let short_ef = index.search(query.clone(), 30, Some(2)).unwrap();
+        assert_eq!(short_ef.len(), 30);
+        assert_eq!(short_ef, index.search(query.clone(), 30, Some(30)).unwrap());
+        assert_eq!(index.search(query, 200, Some(1)).unwrap().len(), 100);
+    }
+
+    #[test]
+    fn removing_the_entry_point_promotes_the_best_connected_top_level_node() {
+        let vectors = clustered_vectors(200, 8, 5, 17);
+        let mut index = index(8);
+        for (i, vector) in vectors.iter().enumerate() {
+            let level = if i < 4 { 2 } else { 0 };
+            index.add_vector_at_level(i.to_string(), vector.clone(), level).unwrap();
+        }
+
+        let removed = index.entry_point.unwrap();
+        let removed_id = index.ids[removed as usize].clone();
+        assert!(index.remove_vector(&removed_id).unwrap());
+
+        let entry = index.entry_point.unwrap();
+        assert_eq!(index.nodes[&entry].level, 2);
+        assert_eq!(index.max_level, 2);
+        let best_links = index
+            .nodes
+            .values()
+            .filter(|node| node.level == 2)
+            .map(|node| node.connections[2].len())
+            .max()
+            .unwrap();
+        assert_eq!(index.nodes[&entry].connections[2].len(), best_links);
+
+        let mut found = 0;
+        for (i, vector) in vectors.iter().enumerate().filter(|(i, _)| i.to_string() != removed_id) {
+            let results = index.search(vector.clone(), 1, Some(64)).unwrap();
+            found += usize::from(results[0].0 == i.to_string());
+        }
+        assert!(found as f32 / 199.0 >= 0.95, "recall {}", found as f32 / 199.0);
+
+        // Tombstoning the entry point also moves it to a live node.
+        let tombstoned = index.ids[entry as usize].clone();
+        assert!(index.mark_deleted(&tombstoned));
+        assert_ne!(index.entry_point, Some(entry));
+        assert!(!index.deleted.contains(&index.entry_point.unwrap()));
+
+        // Once every upper-layer node is gone, the graph shrinks back to layer 0.
+        for i in 0..4 {
+            index.remove_vector(&i.to_string()).unwrap();
+        }
+        assert_eq!(index.max_level, 0);
+        assert_eq!(index.nodes[&index.entry_point.unwrap()].level, 0);
+    }
+
+    #[test]
+    fn links_stay_deduplicated_and_symmetry_can_be_enforced() {
+        let vectors = clustered_vectors(200, 6, 4, 23);
+        let mut plain = index(6);
+        for (i, vector) in vectors.iter().enumerate() {
+            plain.add_vector(i.to_string(), vector.clone()).unwrap();
+        }
+        // Re-adding an id relinks it at a new level; neither path may leave a neighbor listed twice.
+        for (i, vector) in vectors.iter().enumerate().take(50) {
+            plain.add_vector(i.to_string(), vector.clone()).unwrap();
+        }
+        let report = plain.connectivity_report();
+        assert_eq!(report.duplicate_edges, 0);
+
+        plain.prune_connections(4).unwrap();
+        let asymmetric = plain.connectivity_report().asymmetric_edges;
+        assert!(asymmetric > 0);
+        let added = plain.enforce_symmetry();
+        assert!(added > 0 && added <= asymmetric);
+        assert_eq!(plain.connectivity_report().asymmetric_edges, 0);
+        assert_eq!(plain.enforce_symmetry(), 0);
+
+        let mut symmetric = HNSWIndex::new(
+            CollectionConfig {
+                symmetric_links: true,
+                ..plain.config.clone()
+            },
+            &PerformanceConfig::default(),
+        );
+        for (i, vector) in vectors.iter().enumerate() {
+            symmetric.add_vector(i.to_string(), vector.clone()).unwrap();
+        }
+        symmetric.prune_connections(4).unwrap();
+        assert_eq!(symmetric.connectivity_report().asymmetric_edges, 0);
+        symmetric.remove_vector_with_repair("7").unwrap();
+        let report = symmetric.connectivity_report();
+        assert_eq!(report.asymmetric_edges, 0);
+        assert_eq!(report.duplicate_edges, 0);
+    }
+
+    #[test]
+    fn symmetric_pruning_keeps_lists_within_the_bound() {
+        let vectors = clustered_vectors(200, 6, 4, 29);
+        let mut index = HNSWIndex::new(
+            CollectionConfig {
+                symmetric_links: true,
+                ..index(6).config.clone()
+            },
+            &PerformanceConfig::default(),
+        );
+        for (i, vector) in vectors.iter().enumerate() {
+            index.add_vector(i.to_string(), vector.clone()).unwrap();
+        }
+        index.prune_connections(3).unwrap();
+        let report = index.connectivity_report();
+        assert_eq!(report.asymmetric_edges, 0);
+        for node in index.nodes.values() {
+            assert!(node.connections.iter().all(|connections| connections.len() <= 3));
+        }
+    }
+}
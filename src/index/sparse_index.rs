@@ -0,0 +1,104 @@
+use crate::types::SparseVector;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::error::Error;
+
+#[derive(Clone, Default)]
+pub struct SparseIndex {
+    postings: HashMap<u32, Vec<(String, f32)>>,
+}
+
+impl SparseIndex {
+    pub fn new() -> Self {
+        SparseIndex {
+            postings: HashMap::new(),
+        }
+    }
+
+    pub fn add_vector(&mut self, id: String, vector: &SparseVector) -> Result<(), Box<dyn Error>> {
+        for (&dimension, &value) in vector.indices.iter().zip(&vector.values) {
+            if value != 0.0 {
+                self.postings
+                    .entry(dimension)
+                    .or_default()
+                    .push((id.clone(), value));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove_vector(&mut self, id: &str, vector: &SparseVector) -> bool {
+        let mut removed = false;
+
+        for dimension in &vector.indices {
+            if let Some(list) = self.postings.get_mut(dimension) {
+                let before = list.len();
+                list.retain(|(entry_id, _)| entry_id != id);
+                removed |= list.len() != before;
+
+                if list.is_empty() {
+                    self.postings.remove(dimension);
+                }
+            }
+        }
+
+        removed
+    }
+
+    pub fn search(&self, query: &SparseVector, k: usize) -> Result<Vec<(String, f32)>, Box<dyn Error>> {
+        let mut scores: HashMap<&str, f32> = HashMap::new();
+
+        for (dimension, &query_value) in query.indices.iter().zip(&query.values) {
+            if let Some(list) = self.postings.get(dimension) {
+                for (id, value) in list {
+                    *scores.entry(id.as_str()).or_insert(0.0) += query_value * value;
+                }
+            }
+        }
+
+        let mut result: Vec<_> = scores
+            .into_iter()
+            .map(|(id, dot)| (id.to_string(), 1.0 - dot))
+            .collect();
+
+        result.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+        result.truncate(k);
+
+        Ok(result)
+    }
+
+    pub fn posting_count(&self) -> usize {
+        self.postings.values().map(|list| list.len()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sparse(indices: Vec<u32>, values: Vec<f32>) -> SparseVector {
+        SparseVector { indices, values }
+    }
+
+    #[test]
+    fn search_ranks_by_dot_product() {
+        let mut index = SparseIndex::new();
+        index.add_vector("a".to_string(), &sparse(vec![1, 5], vec![1.0, 0.5])).unwrap();
+        index.add_vector("b".to_string(), &sparse(vec![5, 9], vec![2.0, 1.0])).unwrap();
+        index.add_vector("c".to_string(), &sparse(vec![9], vec![3.0])).unwrap();
+
+        let results = index.search(&sparse(vec![1, 5], vec![1.0, 1.0]), 2).unwrap();
+        assert_eq!(results, vec![("b".to_string(), 1.0 - 2.0), ("a".to_string(), 1.0 - 1.5)]);
+    }
+
+    #[test]
+    fn removed_vectors_leave_no_postings() {
+        let mut index = SparseIndex::new();
+        let vector = sparse(vec![2, 3], vec![1.0, 1.0]);
+        index.add_vector("a".to_string(), &vector).unwrap();
+
+        assert!(index.remove_vector("a", &vector));
+        assert_eq!(index.posting_count(), 0);
+        assert!(index.search(&vector, 5).unwrap().is_empty());
+    }
+}
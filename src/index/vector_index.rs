@@ -1,15 +1,20 @@
+use crate::config::PerformanceConfig;
 use crate::index::hnsw::HNSWIndex;
-use crate::types::{CollectionConfig, Vector};
+use crate::index::sparse_index::SparseIndex;
+use crate::types::{CollectionConfig, ConnectivityReport, SparseVector, Vector};
+use crate::utils::cancellation::CancellationToken;
 use std::error::Error;
 
 pub struct VectorIndex {
     hnsw: HNSWIndex,
+    sparse: SparseIndex,
 }
 
 impl VectorIndex {
-    pub fn new(config: CollectionConfig) -> Self {
+    pub fn new(config: CollectionConfig, performance: &PerformanceConfig) -> Self {
         VectorIndex {
-            hnsw: HNSWIndex::new(config),
+            hnsw: HNSWIndex::new(config, performance),
+            sparse: SparseIndex::new(),
         }
     }
 
@@ -17,6 +22,14 @@ impl VectorIndex {
         self.hnsw.add_vector(id, vector)
     }
 
+    pub fn par_build(&mut self, vectors: Vec<(String, Vector)>) -> Result<(), Box<dyn Error>> {
+        self.hnsw.par_build(vectors)
+    }
+
+    pub fn add_sparse_vector(&mut self, id: String, vector: &SparseVector) -> Result<(), Box<dyn Error>> {
+        self.sparse.add_vector(id, vector)
+    }
+
     pub fn search(&self, query: Vector, limit: usize) -> Result<Vec<(String, f32)>, Box<dyn Error>> {
         self.hnsw.search(query, limit, None)
     }
@@ -25,11 +38,107 @@ impl VectorIndex {
         self.hnsw.search(query, limit, Some(ef))
     }
 
+    pub fn search_with_params(
+        &self,
+        query: Vector,
+        limit: usize,
+        ef: Option<usize>,
+        upper_layer_ef: usize,
+    ) -> Result<Vec<(String, f32)>, Box<dyn Error>> {
+        self.hnsw.search_with_beam(query, limit, ef, upper_layer_ef)
+    }
+
+    pub fn search_sparse(&self, query: &SparseVector, limit: usize) -> Result<Vec<(String, f32)>, Box<dyn Error>> {
+        self.sparse.search(query, limit)
+    }
+
     pub fn remove_vector(&mut self, id: &str) -> Result<bool, Box<dyn Error>> {
         self.hnsw.remove_vector(id)
     }
 
+    pub fn remove_vector_with_repair(&mut self, id: &str) -> Result<bool, Box<dyn Error>> {
+        self.hnsw.remove_vector_with_repair(id)
+    }
+
+    pub fn mark_deleted(&mut self, id: &str) -> bool {
+        self.hnsw.mark_deleted(id)
+    }
+
+    pub fn deleted_count(&self) -> usize {
+        self.hnsw.deleted_count()
+    }
+
+    pub fn deleted_ratio(&self) -> f32 {
+        self.hnsw.deleted_ratio()
+    }
+
+    pub fn optimize(&mut self) -> Result<(), Box<dyn Error>> {
+        self.hnsw.optimize()
+    }
+
+    /// An empty graph with this one's settings plus the live vectors to build it from; see
+    /// `install_compacted`.
+    pub fn compaction_seed(&self) -> (HNSWIndex, Vec<(String, Vector)>) {
+        self.hnsw.compaction_seed()
+    }
+
+    /// Swaps in a graph built from `compaction_seed`, first replaying onto it whatever
+    /// changed since when `changed` is set. Returns false, keeping the current graph, if
+    /// it was rebuilt with other construction parameters in the meantime.
+    pub fn install_compacted(&mut self, mut graph: HNSWIndex, changed: bool) -> Result<bool, Box<dyn Error>> {
+        if !graph.built_like(&self.hnsw) {
+            return Ok(false);
+        }
+        if changed {
+            graph.catch_up(&self.hnsw)?;
+        }
+        self.hnsw = graph;
+        Ok(true)
+    }
+
+    pub fn optimize_cancellable(&mut self, cancel: &CancellationToken) -> Result<(), Box<dyn Error>> {
+        self.hnsw.optimize_cancellable(cancel)
+    }
+
+    pub fn prune_connections(&mut self, max_degree: usize) -> Result<usize, Box<dyn Error>> {
+        self.hnsw.prune_connections(max_degree)
+    }
+
+    pub fn set_construction_params(&mut self, m: usize, ef_construction: usize) {
+        self.hnsw.set_construction_params(m, ef_construction)
+    }
+
+    pub fn reconfigure(&mut self, config: CollectionConfig) -> Result<(), Box<dyn Error>> {
+        self.hnsw.reconfigure(config)
+    }
+
+    pub fn clone_from(&mut self, source: &VectorIndex) -> Result<(), Box<dyn Error>> {
+        self.hnsw.clone_from(&source.hnsw)?;
+        self.sparse = source.sparse.clone();
+        Ok(())
+    }
+
+    pub fn remove_sparse_vector(&mut self, id: &str, vector: &SparseVector) -> bool {
+        self.sparse.remove_vector(id, vector)
+    }
+
+    pub fn connectivity_report(&self) -> ConnectivityReport {
+        self.hnsw.connectivity_report()
+    }
+
+    pub fn get_vector(&self, id: &str) -> Option<&Vector> {
+        self.hnsw.get_vector(id)
+    }
+
     pub fn get_stats(&self) -> (usize, usize) {
         self.hnsw.get_stats()
     }
-}
\ No newline at end of file
+
+    pub fn estimated_memory_bytes(&self) -> usize {
+        self.hnsw.estimated_memory_bytes()
+    }
+
+    pub fn vector_bytes(&self) -> usize {
+        self.hnsw.vector_bytes()
+    }
+}
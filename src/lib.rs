@@ -0,0 +1,8 @@
+pub mod config;
+pub mod core;
+pub mod error;
+pub mod flat_index;
+pub mod index;
+pub mod storage;
+pub mod types;
+pub mod utils;
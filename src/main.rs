@@ -1,17 +1,11 @@
+use solaris::core::database::Database;
 use std::error::Error;
 
-mod config;
-mod core;
-mod index;
-mod storage;
-mod types;
-mod utils;
-
 fn main() -> Result<(), Box<dyn Error>> {
     println!("Starting Solaris Vector Database...");
     
     // Criar uma instância do database
-    let mut db = core::database::Database::new("solaris_test".to_string());
+    let db = Database::new("solaris_test".to_string());
     
     let collection_name = "test_collection";
     db.create_collection(collection_name, 128)?;
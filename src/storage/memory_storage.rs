@@ -1,27 +1,74 @@
-use crate::types::{CollectionConfig, Vector, VectorDocument, VectorMetadata};
+use crate::types::{
+    CollectionConfig, IdType, IntVector, SparseVector, UpdateOutcome, Vector, VectorDocument, VectorMetadata,
+};
+use crate::utils::validation::parse_id;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::error::Error;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 pub struct MemoryStorage {
-    data: Arc<RwLock<HashMap<String, VectorDocument>>>,
+    data: Arc<RwLock<Documents>>,
     config: CollectionConfig,
+    size: AtomicUsize,
+    /// Logical clock for write order and last access, so ties within a second still order.
+    clock: AtomicU64,
 }
 
 impl MemoryStorage {
     pub fn new(config: CollectionConfig) -> Self {
         MemoryStorage {
-            data: Arc::new(RwLock::new(HashMap::new())),
+            data: Arc::new(RwLock::new(Documents::new(config.id_type))),
             config,
+            size: AtomicUsize::new(0),
+            clock: AtomicU64::new(0),
         }
     }
 
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn touch(&self, stored: &StoredDocument) {
+        stored.last_access.store(self.tick(), Ordering::Relaxed);
+    }
+
     pub fn store(
         &self,
         id: String,
         vector: Vector,
         metadata: Option<VectorMetadata>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.store_document(id, vector, None, None, metadata)
+    }
+
+    pub fn store_sparse(
+        &self,
+        id: String,
+        vector: SparseVector,
+        metadata: Option<VectorMetadata>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.store_document(id, Vec::new(), Some(vector), None, metadata)
+    }
+
+    pub fn store_integer(
+        &self,
+        id: String,
+        vector: IntVector,
+        metadata: Option<VectorMetadata>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.store_document(id, Vec::new(), None, Some(vector), metadata)
+    }
+
+    fn store_document(
+        &self,
+        id: String,
+        vector: Vector,
+        sparse_vector: Option<SparseVector>,
+        int_vector: Option<IntVector>,
+        metadata: Option<VectorMetadata>,
     ) -> Result<(), Box<dyn Error>> {
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)?
@@ -30,38 +77,147 @@ impl MemoryStorage {
         let document = VectorDocument {
             id: id.clone(),
             vector,
+            sparse_vector,
+            int_vector,
             metadata,
             timestamp,
         };
 
+        let document = self.stored(document)?;
         let mut data = self.data.write().map_err(|_| "Failed to acquire write lock")?;
-        data.insert(id, document);
+        self.size.fetch_add(document.size(), Ordering::Relaxed);
+        if let Some(previous) = data.insert(document) {
+            self.size.fetch_sub(previous.size(), Ordering::Relaxed);
+        }
         Ok(())
     }
 
     pub fn get(&self, id: &str) -> Result<Option<VectorDocument>, Box<dyn Error>> {
         let data = self.data.read().map_err(|_| "Failed to acquire read lock")?;
-        Ok(data.get(id).cloned())
+        Ok(data.get(id).inspect(|stored| self.touch(stored)).map(StoredDocument::decode))
+    }
+
+    pub fn contains(&self, id: &str) -> Result<bool, Box<dyn Error>> {
+        let data = self.data.read().map_err(|_| "Failed to acquire read lock")?;
+        Ok(data.contains_key(id))
     }
 
     pub fn get_vector(&self, id: &str) -> Result<Option<Vector>, Box<dyn Error>> {
         let data = self.data.read().map_err(|_| "Failed to acquire read lock")?;
-        Ok(data.get(id).map(|doc| doc.vector.clone()))
+        Ok(data.get(id).inspect(|stored| self.touch(stored)).map(|stored| stored.with(|doc| doc.vector.clone())))
     }
 
     pub fn get_metadata(&self, id: &str) -> Result<Option<VectorMetadata>, Box<dyn Error>> {
         let data = self.data.read().map_err(|_| "Failed to acquire read lock")?;
-        Ok(data.get(id).and_then(|doc| doc.metadata.clone()))
+        Ok(data
+            .get(id)
+            .inspect(|stored| self.touch(stored))
+            .and_then(|stored| stored.document.metadata.clone()))
+    }
+
+    pub fn with_document<T, F>(&self, id: &str, f: F) -> Result<Option<T>, Box<dyn Error>>
+    where
+        F: FnOnce(&VectorDocument) -> T,
+    {
+        let data = self.data.read().map_err(|_| "Failed to acquire read lock")?;
+        Ok(data.get(id).inspect(|stored| self.touch(stored)).map(|stored| stored.with(f)))
+    }
+
+    /// Like `with_document`, but skips decoding: `vector` may be empty (it is for compressed
+    /// and u64-keyed documents), so only read ids, metadata, timestamps and the other vector kinds.
+    pub fn with_metadata<T, F>(&self, id: &str, f: F) -> Result<Option<T>, Box<dyn Error>>
+    where
+        F: FnOnce(&VectorDocument) -> T,
+    {
+        let data = self.data.read().map_err(|_| "Failed to acquire read lock")?;
+        Ok(data.get(id).inspect(|stored| self.touch(stored)).map(|stored| stored.with_undecoded(f)))
+    }
+
+    pub fn get_many(&self, ids: &[String]) -> Result<Vec<VectorDocument>, Box<dyn Error>> {
+        let data = self.data.read().map_err(|_| "Failed to acquire read lock")?;
+        Ok(ids
+            .iter()
+            .filter_map(|id| data.get(id))
+            .inspect(|stored| self.touch(stored))
+            .map(StoredDocument::decode)
+            .collect())
+    }
+
+    pub fn get_timestamp(&self, id: &str) -> Result<Option<u64>, Box<dyn Error>> {
+        let data = self.data.read().map_err(|_| "Failed to acquire read lock")?;
+        Ok(data.get(id).map(|stored| stored.document.timestamp))
+    }
+
+    pub fn for_each_document<F>(&self, mut f: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&VectorDocument),
+    {
+        let data = self.data.read().map_err(|_| "Failed to acquire read lock")?;
+        data.values().for_each(|stored| stored.with(&mut f));
+        Ok(())
+    }
+
+    /// Like `for_each_document`, without decoding; see `with_metadata`.
+    pub fn for_each_metadata<F>(&self, mut f: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&VectorDocument),
+    {
+        let data = self.data.read().map_err(|_| "Failed to acquire read lock")?;
+        data.values().for_each(|stored| stored.with_undecoded(&mut f));
+        Ok(())
+    }
+
+    /// Like `for_each_document`, also passing each document's position in write order.
+    pub fn for_each_written<F>(&self, mut f: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(u64, &VectorDocument),
+    {
+        let data = self.data.read().map_err(|_| "Failed to acquire read lock")?;
+        data.values().for_each(|stored| stored.with(|doc| f(stored.written, doc)));
+        Ok(())
+    }
+
+    pub fn ids_older_than(&self, cutoff: u64) -> Result<Vec<String>, Box<dyn Error>> {
+        let data = self.data.read().map_err(|_| "Failed to acquire read lock")?;
+        Ok(data
+            .values()
+            .filter(|stored| stored.document.timestamp < cutoff)
+            .map(|stored| stored.id().into_owned())
+            .collect())
+    }
+
+    /// The document read least recently (writes count as reads), found by a full scan.
+    pub fn least_recently_accessed(&self) -> Result<Option<String>, Box<dyn Error>> {
+        let data = self.data.read().map_err(|_| "Failed to acquire read lock")?;
+        Ok(data
+            .values()
+            .min_by_key(|stored| stored.last_access.load(Ordering::Relaxed))
+            .map(|stored| stored.id().into_owned()))
+    }
+
+    /// The document written longest ago, found by a full scan.
+    pub fn oldest_written(&self) -> Result<Option<String>, Box<dyn Error>> {
+        let data = self.data.read().map_err(|_| "Failed to acquire read lock")?;
+        Ok(data
+            .values()
+            .min_by_key(|stored| stored.written)
+            .map(|stored| stored.id().into_owned()))
     }
 
     pub fn remove(&self, id: &str) -> Result<bool, Box<dyn Error>> {
         let mut data = self.data.write().map_err(|_| "Failed to acquire write lock")?;
-        Ok(data.remove(id).is_some())
+        match data.remove(id) {
+            Some(stored) => {
+                self.size.fetch_sub(stored.size(), Ordering::Relaxed);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
     }
 
     pub fn list_ids(&self) -> Result<Vec<String>, Box<dyn Error>> {
         let data = self.data.read().map_err(|_| "Failed to acquire read lock")?;
-        Ok(data.keys().cloned().collect())
+        Ok(data.ids().collect())
     }
 
     pub fn count(&self) -> Result<usize, Box<dyn Error>> {
@@ -71,21 +227,28 @@ impl MemoryStorage {
 
     pub fn get_all_documents(&self) -> Result<Vec<VectorDocument>, Box<dyn Error>> {
         let data = self.data.read().map_err(|_| "Failed to acquire read lock")?;
-        Ok(data.values().cloned().collect())
+        Ok(data.values().map(StoredDocument::decode).collect())
     }
 
     pub fn update_metadata(
         &self,
         id: &str,
         metadata: Option<VectorMetadata>,
-    ) -> Result<bool, Box<dyn Error>> {
+    ) -> Result<UpdateOutcome, Box<dyn Error>> {
         let mut data = self.data.write().map_err(|_| "Failed to acquire write lock")?;
-        if let Some(document) = data.get_mut(id) {
-            document.metadata = metadata;
-            Ok(true)
-        } else {
-            Ok(false)
+        let stored = match data.get_mut(id) {
+            Some(stored) => stored,
+            None => return Ok(UpdateOutcome::NotFound),
+        };
+
+        if stored.document.metadata == metadata {
+            return Ok(UpdateOutcome::Unchanged);
         }
+
+        self.size.fetch_sub(stored.size(), Ordering::Relaxed);
+        stored.document.metadata = metadata;
+        self.size.fetch_add(stored.size(), Ordering::Relaxed);
+        Ok(UpdateOutcome::Updated)
     }
 
     pub fn batch_insert(
@@ -96,7 +259,11 @@ impl MemoryStorage {
         let mut inserted = 0;
 
         for document in documents {
-            data.insert(document.id.clone(), document);
+            let document = self.stored(document)?;
+            self.size.fetch_add(document.size(), Ordering::Relaxed);
+            if let Some(previous) = data.insert(document) {
+                self.size.fetch_sub(previous.size(), Ordering::Relaxed);
+            }
             inserted += 1;
         }
 
@@ -106,24 +273,317 @@ impl MemoryStorage {
     pub fn clear(&self) -> Result<(), Box<dyn Error>> {
         let mut data = self.data.write().map_err(|_| "Failed to acquire write lock")?;
         data.clear();
+        self.size.store(0, Ordering::Relaxed);
         Ok(())
     }
 
     pub fn size_bytes(&self) -> Result<usize, Box<dyn Error>> {
-        let data = self.data.read().map_err(|_| "Failed to acquire read lock")?;
-        let mut size = 0;
-        
-        for document in data.values() {
-            size += document.id.len();
-            size += document.vector.len() * std::mem::size_of::<f32>();
-            if let Some(metadata) = &document.metadata {
-                for (key, value) in metadata {
-                    size += key.len() + value.len();
-                }
+        Ok(self.size.load(Ordering::Relaxed))
+    }
+
+    fn stored(&self, document: VectorDocument) -> Result<StoredDocument, Box<dyn Error>> {
+        let numeric_id = parse_id(&document.id, self.config.id_type)?;
+        Ok(StoredDocument::new(document, numeric_id, self.config.in_memory_compression, self.tick()))
+    }
+}
+
+/// Documents by id. Under `IdType::U64` only `numeric` is used, so ids cost no allocation.
+struct Documents {
+    text: HashMap<String, StoredDocument>,
+    numeric: HashMap<u64, StoredDocument>,
+    id_type: IdType,
+}
+
+impl Documents {
+    fn new(id_type: IdType) -> Self {
+        Documents {
+            text: HashMap::new(),
+            numeric: HashMap::new(),
+            id_type,
+        }
+    }
+
+    // Ids that do not parse under `IdType::U64` were never stored, so they are simply absent.
+    fn numeric_key(&self, id: &str) -> Option<u64> {
+        parse_id(id, self.id_type).ok().flatten()
+    }
+
+    fn get(&self, id: &str) -> Option<&StoredDocument> {
+        match self.id_type {
+            IdType::String => self.text.get(id),
+            IdType::U64 => self.numeric.get(&self.numeric_key(id)?),
+        }
+    }
+
+    fn get_mut(&mut self, id: &str) -> Option<&mut StoredDocument> {
+        match self.id_type {
+            IdType::String => self.text.get_mut(id),
+            IdType::U64 => {
+                let key = self.numeric_key(id)?;
+                self.numeric.get_mut(&key)
             }
-            size += std::mem::size_of::<u64>();
         }
-        
-        Ok(size)
     }
-}
\ No newline at end of file
+
+    fn contains_key(&self, id: &str) -> bool {
+        self.get(id).is_some()
+    }
+
+    fn insert(&mut self, document: StoredDocument) -> Option<StoredDocument> {
+        match document.numeric_id {
+            Some(key) => self.numeric.insert(key, document),
+            None => self.text.insert(document.document.id.clone(), document),
+        }
+    }
+
+    fn remove(&mut self, id: &str) -> Option<StoredDocument> {
+        match self.id_type {
+            IdType::String => self.text.remove(id),
+            IdType::U64 => self.numeric.remove(&self.numeric_key(id)?),
+        }
+    }
+
+    fn values(&self) -> impl Iterator<Item = &StoredDocument> {
+        self.text.values().chain(self.numeric.values())
+    }
+
+    fn ids(&self) -> impl Iterator<Item = String> + '_ {
+        self.text.keys().cloned().chain(self.numeric.keys().map(u64::to_string))
+    }
+
+    fn len(&self) -> usize {
+        self.text.len() + self.numeric.len()
+    }
+
+    fn clear(&mut self) {
+        self.text.clear();
+        self.numeric.clear();
+    }
+}
+
+struct StoredDocument {
+    document: VectorDocument,
+    /// Set under `IdType::U64`, in which case `document.id` is empty.
+    numeric_id: Option<u64>,
+    /// Set under in-memory compression, in which case `document.vector` is empty.
+    codes: Option<QuantizedVector>,
+    written: u64,
+    last_access: AtomicU64,
+}
+
+impl StoredDocument {
+    fn new(mut document: VectorDocument, numeric_id: Option<u64>, compress: bool, written: u64) -> Self {
+        if numeric_id.is_some() {
+            document.id = String::new();
+        }
+        let codes = (compress && !document.vector.is_empty())
+            .then(|| QuantizedVector::encode(&std::mem::take(&mut document.vector)));
+        StoredDocument {
+            document,
+            numeric_id,
+            codes,
+            written,
+            last_access: AtomicU64::new(written),
+        }
+    }
+
+    fn id(&self) -> Cow<'_, str> {
+        match self.numeric_id {
+            Some(id) => Cow::Owned(id.to_string()),
+            None => Cow::Borrowed(&self.document.id),
+        }
+    }
+
+    fn decode(&self) -> VectorDocument {
+        let mut document = self.document.clone();
+        if let Some(codes) = &self.codes {
+            document.vector = codes.decode();
+        }
+        if let Some(id) = self.numeric_id {
+            document.id = id.to_string();
+        }
+        document
+    }
+
+    fn with<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(&VectorDocument) -> T,
+    {
+        match (&self.codes, self.numeric_id) {
+            (None, None) => f(&self.document),
+            _ => f(&self.decode()),
+        }
+    }
+
+    /// Like `with`, but leaves a compressed vector empty instead of decoding it.
+    fn with_undecoded<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(&VectorDocument) -> T,
+    {
+        match self.numeric_id {
+            Some(id) => f(&VectorDocument {
+                id: id.to_string(),
+                vector: Vec::new(),
+                sparse_vector: self.document.sparse_vector.clone(),
+                int_vector: self.document.int_vector.clone(),
+                metadata: self.document.metadata.clone(),
+                timestamp: self.document.timestamp,
+            }),
+            None => f(&self.document),
+        }
+    }
+
+    fn size(&self) -> usize {
+        let id_size = self.numeric_id.map_or(0, |_| std::mem::size_of::<u64>());
+        document_size(&self.document) + id_size + self.codes.as_ref().map_or(0, QuantizedVector::size)
+    }
+}
+
+/// 8-bit scalar quantization over the vector's own range; decoding is off by at most
+/// half a step, `(max - min) / 510`.
+struct QuantizedVector {
+    min: f32,
+    step: f32,
+    codes: Vec<u8>,
+}
+
+impl QuantizedVector {
+    fn encode(vector: &Vector) -> Self {
+        let min = vector.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = vector.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let step = (max - min) / u8::MAX as f32;
+
+        let codes = vector
+            .iter()
+            .map(|x| if step > 0.0 { ((x - min) / step).round() as u8 } else { 0 })
+            .collect();
+        QuantizedVector { min, step, codes }
+    }
+
+    fn decode(&self) -> Vector {
+        self.codes.iter().map(|&code| self.min + code as f32 * self.step).collect()
+    }
+
+    fn size(&self) -> usize {
+        self.codes.len() + 2 * std::mem::size_of::<f32>()
+    }
+}
+
+fn document_size(document: &VectorDocument) -> usize {
+    let mut size = document.id.len();
+    size += document.vector.len() * std::mem::size_of::<f32>();
+    if let Some(sparse_vector) = &document.sparse_vector {
+        size += sparse_vector.indices.len() * std::mem::size_of::<u32>();
+        size += sparse_vector.values.len() * std::mem::size_of::<f32>();
+    }
+    if let Some(int_vector) = &document.int_vector {
+        size += int_vector.len() * std::mem::size_of::<u16>();
+    }
+    if let Some(metadata) = &document.metadata {
+        for (key, value) in metadata {
+            size += key.len() + value.len();
+        }
+    }
+    size + std::mem::size_of::<u64>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::gen::random_vectors;
+
+    fn storage(in_memory_compression: bool) -> MemoryStorage {
+        MemoryStorage::new(CollectionConfig {
+            dimension: 64,
+            in_memory_compression,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn compressed_vectors_decode_within_half_a_step() {
+        let storage = storage(true);
+        let original = random_vectors(1, 64, 1).remove(0);
+        storage.store("a".to_string(), original.clone(), None).unwrap();
+
+        let decoded = storage.get_vector("a").unwrap().unwrap();
+        // Components lie in [-1, 1).
+        let half_step = 2.0 / 510.0 + f32::EPSILON;
+        for (d, o) in decoded.iter().zip(&original) {
+            assert!((d - o).abs() <= half_step, "{} vs {}", d, o);
+        }
+    }
+
+    #[test]
+    fn compression_shrinks_size_bytes() {
+        let (plain, compressed) = (storage(false), storage(true));
+        for (i, vector) in random_vectors(10, 64, 2).into_iter().enumerate() {
+            plain.store(i.to_string(), vector.clone(), None).unwrap();
+            compressed.store(i.to_string(), vector, None).unwrap();
+        }
+
+        let (plain, compressed) = (plain.size_bytes().unwrap(), compressed.size_bytes().unwrap());
+        assert!(compressed * 3 < plain, "{} vs {}", compressed, plain);
+    }
+
+    #[test]
+    fn metadata_reads_see_the_stored_document_without_decoding() {
+        let storage = storage(true);
+        let metadata = vec![("kind".to_string(), "doc".to_string())];
+        storage.store("a".to_string(), random_vectors(1, 64, 3).remove(0), Some(metadata.clone())).unwrap();
+
+        let seen = storage.with_metadata("a", |doc| (doc.vector.len(), doc.metadata.clone())).unwrap();
+        assert_eq!(seen, Some((0, Some(metadata.clone()))));
+        let decoded = storage.with_document("a", |doc| doc.vector.len()).unwrap();
+        assert_eq!(decoded, Some(64));
+
+        let mut visited = Vec::new();
+        storage.for_each_metadata(|doc| visited.push((doc.id.clone(), doc.vector.is_empty()))).unwrap();
+        assert_eq!(visited, vec![("a".to_string(), true)]);
+    }
+
+    #[test]
+    fn u64_ids_are_keyed_as_integers_and_read_back_as_strings() {
+        let storage = MemoryStorage::new(CollectionConfig {
+            dimension: 2,
+            id_type: IdType::U64,
+            ..Default::default()
+        });
+        storage.store("7".to_string(), vec![1.0, 2.0], None).unwrap();
+        storage.store("12".to_string(), vec![3.0, 4.0], None).unwrap();
+        assert!(storage.store("x".to_string(), vec![0.0, 0.0], None).is_err());
+
+        let data = storage.data.read().unwrap();
+        assert_eq!((data.text.len(), data.numeric.len()), (0, 2));
+        assert!(data.values().all(|stored| stored.document.id.is_empty()));
+        drop(data);
+
+        assert_eq!(storage.get("7").unwrap().unwrap().id, "7");
+        assert_eq!(storage.with_metadata("12", |doc| doc.id.clone()).unwrap(), Some("12".to_string()));
+        assert!(storage.get("07").unwrap().is_none());
+        let mut ids = storage.list_ids().unwrap();
+        ids.sort();
+        assert_eq!(ids, vec!["12", "7"]);
+
+        assert!(storage.remove("7").unwrap());
+        assert_eq!(storage.count().unwrap(), 1);
+    }
+
+    #[test]
+    fn reads_refresh_access_order_but_not_write_order() {
+        let storage = storage(false);
+        for (i, vector) in random_vectors(3, 64, 4).into_iter().enumerate() {
+            storage.store(i.to_string(), vector, None).unwrap();
+        }
+        assert_eq!(storage.least_recently_accessed().unwrap().as_deref(), Some("0"));
+        assert_eq!(storage.oldest_written().unwrap().as_deref(), Some("0"));
+
+        storage.get("0").unwrap();
+        storage.get_vector("1").unwrap();
+        assert_eq!(storage.least_recently_accessed().unwrap().as_deref(), Some("2"));
+        assert_eq!(storage.oldest_written().unwrap().as_deref(), Some("0"));
+
+        storage.get_many(&["2".to_string()]).unwrap();
+        assert_eq!(storage.least_recently_accessed().unwrap().as_deref(), Some("0"));
+    }
+}
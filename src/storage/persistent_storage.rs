@@ -1,104 +1,719 @@
-use crate::types::{CollectionConfig, VectorDocument};
+use crate::config::{FlushPolicy, PersistenceFormat};
+use crate::error::SolarisError;
+use crate::types::{CollectionConfig, Vector, VectorDocument, VectorMetadata};
+use crate::utils::validation::sanitize_collection_name;
+#[cfg(feature = "std")]
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use serde_json;
+use std::collections::HashMap;
 use std::error::Error;
 use std::fs::{File, OpenOptions};
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+const LOAD_CHUNK_RECORDS: usize = 10_000;
+const MANIFEST_FILE: &str = "manifest.json";
+/// Version of the on-disk layout written by this build, stored in each data file's header.
+/// Bump it whenever the record encoding changes, and add the upgrade from the previous
+/// version to `MIGRATIONS`. Version 1 files have no header.
+pub const FORMAT_VERSION: u32 = 2;
+/// Starts the header of a bincode data file; as a record length it would be ~1.4 GB.
+const BINCODE_MAGIC: &[u8; 4] = b"SLRS";
+
+/// Rewrites a data file from version `n` to `n + 1`; `MIGRATIONS[n - 1]` upgrades version `n`.
+type Migration = fn(&Path, PersistenceFormat) -> Result<(), Box<dyn Error>>;
+const MIGRATIONS: [Migration; FORMAT_VERSION as usize - 1] = [add_file_header];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum LogRecord {
+    Put(VectorDocument),
+    Delete {
+        id: String,
+        timestamp: u64,
+    },
+    UpdateMetadata {
+        id: String,
+        metadata: Option<VectorMetadata>,
+        timestamp: u64,
+    },
+    /// Base for the `PutDelta` records that follow it in the file.
+    Reference(Vector),
+    /// `document.vector` is empty; component `i` is `reference[i] + delta[i] * scale`.
+    PutDelta {
+        document: VectorDocument,
+        scale: f32,
+        delta: Vec<i16>,
+    },
+}
+
+impl LogRecord {
+    fn id(&self) -> Option<&str> {
+        match self {
+            LogRecord::Put(document) | LogRecord::PutDelta { document, .. } => Some(&document.id),
+            LogRecord::Delete { id, .. } | LogRecord::UpdateMetadata { id, .. } => Some(id),
+            LogRecord::Reference(_) => None,
+        }
+    }
+
+    fn timestamp(&self) -> u64 {
+        match self {
+            LogRecord::Put(document) | LogRecord::PutDelta { document, .. } => document.timestamp,
+            LogRecord::Delete { timestamp, .. } | LogRecord::UpdateMetadata { timestamp, .. } => *timestamp,
+            LogRecord::Reference(_) => 0,
+        }
+    }
+
+    fn estimated_size(&self) -> usize {
+        let metadata_size = |metadata: &Option<VectorMetadata>| {
+            metadata.iter().flatten().map(|(key, value)| key.len() + value.len()).sum::<usize>()
+        };
+        let document_size = |document: &VectorDocument| {
+            document.id.len() + document.vector.len() * 4 + metadata_size(&document.metadata) + 8
+        };
+
+        match self {
+            LogRecord::Put(document) => document_size(document),
+            LogRecord::PutDelta { document, delta, .. } => document_size(document) + delta.len() * 2 + 4,
+            LogRecord::Delete { id, .. } => id.len() + 8,
+            LogRecord::UpdateMetadata { id, metadata, .. } => id.len() + metadata_size(metadata) + 8,
+            LogRecord::Reference(vector) => vector.len() * 4,
+        }
+    }
+}
+
+#[derive(Default)]
+struct WriteBuffer {
+    records: Vec<LogRecord>,
+    bytes: usize,
+    oldest: Option<Instant>,
+}
+
+impl WriteBuffer {
+    fn push(&mut self, record: LogRecord) {
+        self.bytes += record.estimated_size();
+        self.oldest.get_or_insert_with(Instant::now);
+        self.records.push(record);
+    }
+
+    fn take(&mut self) -> Vec<LogRecord> {
+        self.bytes = 0;
+        self.oldest = None;
+        std::mem::take(&mut self.records)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    fn due(&self, policy: &FlushPolicy) -> bool {
+        match policy {
+            FlushPolicy::ByCount(count) => self.records.len() >= *count,
+            FlushPolicy::ByBytes(bytes) => self.bytes >= *bytes,
+            FlushPolicy::ByInterval(interval) => self.oldest.is_some_and(|oldest| oldest.elapsed() >= *interval),
+            FlushPolicy::Any(policies) => policies.iter().any(|policy| self.due(policy)),
+        }
+    }
+}
+
+// Files written before log records existed hold bare documents, one per line.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonLine {
+    Record(LogRecord),
+    Legacy(VectorDocument),
+}
+
+/// First line of a JSONL data file; bincode files start with `BINCODE_MAGIC` and the version
+/// as a little-endian `u32` instead.
+#[derive(Serialize, Deserialize)]
+struct FileHeader {
+    format_version: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Manifest {
+    name: String,
+    format: PersistenceFormat,
+    // Manifests written before versioning have no version and hold version 1 data.
+    #[serde(default = "legacy_format_version")]
+    format_version: u32,
+}
+
+fn legacy_format_version() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionReport {
+    pub records_before: usize,
+    pub records_after: usize,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RestoreMode {
+    #[default]
+    Replace,
+    /// Appends the backup records; duplicates are resolved by the next `compact`.
+    Merge,
+    FailIfExists,
+}
 
 pub struct PersistentStorage {
     file_path: PathBuf,
     config: CollectionConfig,
-    buffer: Arc<RwLock<Vec<VectorDocument>>>,
-    buffer_size: usize,
+    format: PersistenceFormat,
+    buffer: Arc<RwLock<WriteBuffer>>,
+    flush_policy: FlushPolicy,
+    delta_encoding: bool,
+    reference: RwLock<Option<Vector>>,
 }
 
 impl PersistentStorage {
     pub fn new(config: CollectionConfig, data_dir: &Path) -> Result<Self, Box<dyn Error>> {
-        let file_path = data_dir.join(format!("{}.jsonl", config.name));
-        
-        if let Some(parent) = file_path.parent() {
-            std::fs::create_dir_all(parent)?;
+        Self::with_format(config, data_dir, PersistenceFormat::default())
+    }
+
+    pub fn with_format(
+        config: CollectionConfig,
+        data_dir: &Path,
+        format: PersistenceFormat,
+    ) -> Result<Self, Box<dyn Error>> {
+        let extension = extension(format);
+        let directory = Self::collection_directory(data_dir, &config.name)?;
+        std::fs::create_dir_all(&directory)?;
+
+        if let Some(existing) = Self::read_manifest(&directory)? {
+            Self::check_version(&directory, existing.format_version)?;
+            if existing.format != format {
+                Self::convert(&directory, &config.name, existing.format, format)?;
+            }
+        }
+
+        let file_path = directory.join(format!("data.{}", extension));
+        let legacy_path = data_dir.join(format!("{}.{}", config.name, extension));
+        if legacy_path.is_file() && !file_path.exists() {
+            std::fs::rename(&legacy_path, &file_path)?;
+            log::info!(
+                "Migrated '{}' to '{}'",
+                legacy_path.display(),
+                file_path.display()
+            );
         }
+        Self::upgrade(&file_path, format)?;
+        Self::write_manifest(&directory, &config.name, format)?;
 
         Ok(PersistentStorage {
             file_path,
             config,
-            buffer: Arc::new(RwLock::new(Vec::new())),
-            buffer_size: 1000,
+            format,
+            buffer: Arc::new(RwLock::new(WriteBuffer::default())),
+            flush_policy: FlushPolicy::default(),
+            delta_encoding: false,
+            reference: RwLock::new(None),
         })
     }
 
-    pub fn store(&self, document: VectorDocument) -> Result<(), Box<dyn Error>> {
-        let mut buffer = self.buffer.write().map_err(|_| "Failed to acquire write lock")?;
-        buffer.push(document);
+    /// Stores dense vectors as 16-bit quantized deltas against a per-file reference vector,
+    /// roughly halving their size. Lossy: each component is off by at most half of the
+    /// record's `scale` (its largest delta / 32767).
+    pub fn with_delta_encoding(mut self, enabled: bool) -> Self {
+        self.delta_encoding = enabled;
+        self
+    }
+
+    pub fn with_flush_policy(mut self, policy: FlushPolicy) -> Self {
+        self.flush_policy = policy;
+        self
+    }
+
+    /// `<data_dir>/<sanitized name>`, or `<sanitized name>-<hash>` when another collection
+    /// already owns the sanitized directory.
+    fn collection_directory(data_dir: &Path, name: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let sanitized = sanitize_collection_name(name);
+        let hashed = format!("{}-{:016x}", sanitized, fnv1a(name.as_bytes()));
+
+        let mut candidates = Vec::with_capacity(2);
+        if !sanitized.is_empty() {
+            candidates.push(data_dir.join(&sanitized));
+        }
+        candidates.push(data_dir.join(hashed));
 
-        if buffer.len() >= self.buffer_size {
-            self.flush_buffer(&mut buffer)?;
+        for directory in candidates {
+            match Self::read_manifest(&directory)? {
+                Some(manifest) if manifest.name != name => continue,
+                _ => return Ok(directory),
+            }
         }
 
+        Err(format!("No free storage directory for collection '{}'", name).into())
+    }
+
+    /// Refuses versions this build does not know rather than misreading their records.
+    fn check_version(path: &Path, found: u32) -> Result<(), Box<dyn Error>> {
+        if found == 0 || found > FORMAT_VERSION {
+            return Err(SolarisError::UnsupportedFormatVersion {
+                path: path.display().to_string(),
+                found,
+                supported: FORMAT_VERSION,
+            }
+            .into());
+        }
         Ok(())
     }
 
-    pub fn flush(&self) -> Result<(), Box<dyn Error>> {
-        let mut buffer = self.buffer.write().map_err(|_| "Failed to acquire write lock")?;
-        self.flush_buffer(&mut buffer)
+    /// Brings the data file at `path` to `FORMAT_VERSION`, if it exists.
+    fn upgrade(path: &Path, format: PersistenceFormat) -> Result<(), Box<dyn Error>> {
+        let found = Self::read_version(path, format)?;
+        Self::migrate(path, format, found)
     }
 
-    fn flush_buffer(&self, buffer: &mut Vec<VectorDocument>) -> Result<(), Box<dyn Error>> {
-        if buffer.is_empty() {
-            return Ok(());
+    /// Upgrades a data file from version `found` to `FORMAT_VERSION` one version at a time.
+    fn migrate(path: &Path, format: PersistenceFormat, found: u32) -> Result<(), Box<dyn Error>> {
+        Self::check_version(path, found)?;
+
+        for version in found..FORMAT_VERSION {
+            MIGRATIONS[version as usize - 1](path, format)?;
+            log::info!(
+                "Migrated '{}' from format version {} to {}",
+                path.display(),
+                version,
+                version + 1
+            );
+        }
+        Ok(())
+    }
+
+    /// The version in the file's header; missing and empty files count as current.
+    fn read_version(path: &Path, format: PersistenceFormat) -> Result<u32, Box<dyn Error>> {
+        if !path.exists() || std::fs::metadata(path)?.len() == 0 {
+            return Ok(FORMAT_VERSION);
+        }
+        let mut reader = BufReader::new(File::open(path)?);
+        Ok(Self::read_header(&mut reader, format)?.unwrap_or(1))
+    }
+
+    /// Consumes the header at the start of `reader`, if there is one.
+    fn read_header<R: BufRead>(reader: &mut R, format: PersistenceFormat) -> Result<Option<u32>, Box<dyn Error>> {
+        let start = reader.fill_buf()?;
+        match format {
+            PersistenceFormat::Jsonl => {
+                if !start.starts_with(b"{\"format_version\"") {
+                    return Ok(None);
+                }
+                let mut line = String::new();
+                reader.read_line(&mut line)?;
+                Ok(Some(serde_json::from_str::<FileHeader>(&line)?.format_version))
+            }
+            PersistenceFormat::Bincode => {
+                if !start.starts_with(BINCODE_MAGIC) {
+                    return Ok(None);
+                }
+                let mut header = [0u8; 8];
+                reader.read_exact(&mut header)?;
+                Ok(Some(u32::from_le_bytes([header[4], header[5], header[6], header[7]])))
+            }
+        }
+    }
+
+    fn write_header<W: Write>(writer: &mut W, format: PersistenceFormat) -> Result<(), Box<dyn Error>> {
+        match format {
+            PersistenceFormat::Jsonl => {
+                let header = FileHeader {
+                    format_version: FORMAT_VERSION,
+                };
+                writeln!(writer, "{}", serde_json::to_string(&header)?)?;
+            }
+            PersistenceFormat::Bincode => {
+                writer.write_all(BINCODE_MAGIC)?;
+                writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+            }
         }
+        Ok(())
+    }
 
+    /// Opens the data file for appending, writing the header first if the file is new.
+    fn open_append(&self) -> Result<BufWriter<File>, Box<dyn Error>> {
         let file = OpenOptions::new()
             .create(true)
             .append(true)
             .open(&self.file_path)?;
+        let empty = file.metadata()?.len() == 0;
 
         let mut writer = BufWriter::new(file);
+        if empty {
+            Self::write_header(&mut writer, self.format)?;
+        }
+        Ok(writer)
+    }
 
-        for document in buffer.drain(..) {
-            let json = serde_json::to_string(&document)?;
-            writeln!(writer, "{}", json)?;
+    /// Rewrites the `from` data file in the `to` format, then records `to` in the manifest
+    /// before dropping the old file, so a crash at any point leaves a file the manifest names.
+    fn convert(
+        directory: &Path,
+        name: &str,
+        from: PersistenceFormat,
+        to: PersistenceFormat,
+    ) -> Result<(), Box<dyn Error>> {
+        let source = directory.join(format!("data.{}", extension(from)));
+        if source.exists() {
+            let target = directory.join(format!("data.{}", extension(to)));
+            let temp = temp_path(&target, "tmp");
+            let mut writer = BufWriter::new(File::create(&temp)?);
+            Self::write_header(&mut writer, to)?;
+            for record in Self::read_records(&source, from)? {
+                Self::write_record(&mut writer, &record, to)?;
+            }
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+            drop(writer);
+            std::fs::rename(&temp, &target)?;
+        }
+
+        Self::write_manifest(directory, name, to)?;
+        if source.exists() {
+            std::fs::remove_file(&source)?;
+        }
+        log::info!("Converted '{}' from {:?} to {:?}", directory.display(), from, to);
+        Ok(())
+    }
+
+    fn write_manifest(directory: &Path, name: &str, format: PersistenceFormat) -> Result<(), Box<dyn Error>> {
+        let manifest = Manifest {
+            name: name.to_string(),
+            format,
+            format_version: FORMAT_VERSION,
+        };
+        std::fs::write(directory.join(MANIFEST_FILE), serde_json::to_string_pretty(&manifest)?)?;
+        Ok(())
+    }
+
+    fn read_manifest(directory: &Path) -> Result<Option<Manifest>, Box<dyn Error>> {
+        let path = directory.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&std::fs::read_to_string(path)?)?))
+    }
+
+    pub fn directory(&self) -> &Path {
+        self.file_path.parent().unwrap_or(&self.file_path)
+    }
+
+    /// Deletes the collection's directory, discarding any buffered records.
+    pub fn destroy(self) -> Result<(), Box<dyn Error>> {
+        self.buffer.write().map_err(|_| "Failed to acquire write lock")?.take();
+        std::fs::remove_dir_all(self.directory())?;
+        Ok(())
+    }
+
+    /// Flushes, then moves the collection's directory to the one `new_name` resolves to and
+    /// rewrites its manifest.
+    pub fn rename(&mut self, new_name: &str) -> Result<(), Box<dyn Error>> {
+        self.flush()?;
+
+        let data_dir = self
+            .directory()
+            .parent()
+            .ok_or("Storage directory has no parent")?
+            .to_path_buf();
+        let target = Self::collection_directory(&data_dir, new_name)?;
+        if target.exists() {
+            return Err(format!("Storage directory '{}' already exists", target.display()).into());
+        }
+
+        std::fs::rename(self.directory(), &target)?;
+        Self::write_manifest(&target, new_name, self.format)?;
+
+        let file_name = self.file_path.file_name().ok_or("Storage file has no name")?.to_owned();
+        self.file_path = target.join(file_name);
+        self.config.name = new_name.to_string();
+        Ok(())
+    }
+
+    pub fn store(&self, document: VectorDocument) -> Result<(), Box<dyn Error>> {
+        if !self.delta_encoding || document.vector.is_empty() {
+            return self.append(LogRecord::Put(document));
+        }
+
+        let mut reference = self.reference.write().map_err(|_| "Failed to acquire write lock")?;
+        let reference = match reference.as_ref() {
+            Some(reference) => reference,
+            None => {
+                self.append(LogRecord::Reference(document.vector.clone()))?;
+                reference.insert(document.vector.clone())
+            }
+        };
+
+        if reference.len() != document.vector.len() {
+            return self.append(LogRecord::Put(document));
+        }
+        self.append(encode_delta(document, reference))
+    }
+
+    pub fn delete(&self, id: &str) -> Result<(), Box<dyn Error>> {
+        self.append(LogRecord::Delete {
+            id: id.to_string(),
+            timestamp: now_seconds()?,
+        })
+    }
+
+    pub fn update_metadata(&self, id: &str, metadata: Option<VectorMetadata>) -> Result<(), Box<dyn Error>> {
+        self.append(LogRecord::UpdateMetadata {
+            id: id.to_string(),
+            metadata,
+            timestamp: now_seconds()?,
+        })
+    }
+
+    fn append(&self, record: LogRecord) -> Result<(), Box<dyn Error>> {
+        let mut buffer = self.buffer.write().map_err(|_| "Failed to acquire write lock")?;
+        buffer.push(record);
+
+        if buffer.due(&self.flush_policy) {
+            self.flush_buffer(&mut buffer, false)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&self) -> Result<(), Box<dyn Error>> {
+        let mut buffer = self.buffer.write().map_err(|_| "Failed to acquire write lock")?;
+        self.flush_buffer(&mut buffer, false)
+    }
+
+    /// Flushes buffered records and fsyncs the data file before returning.
+    pub fn sync(&self) -> Result<(), Box<dyn Error>> {
+        let mut buffer = self.buffer.write().map_err(|_| "Failed to acquire write lock")?;
+        self.flush_buffer(&mut buffer, true)
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(collection = %self.config.name, records = buffer.records.len()))
+    )]
+    fn flush_buffer(&self, buffer: &mut WriteBuffer, sync: bool) -> Result<(), Box<dyn Error>> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+
+        let mut writer = self.open_append()?;
+
+        for record in buffer.take() {
+            Self::write_record(&mut writer, &record, self.format)?;
         }
 
         writer.flush()?;
+        if sync {
+            writer.get_ref().sync_data()?;
+        }
         Ok(())
     }
 
+    /// Replays the log and returns the surviving documents in first-write order.
     pub fn load_all(&self) -> Result<Vec<VectorDocument>, Box<dyn Error>> {
-        if !self.file_path.exists() {
+        let records = self.load_records()?;
+        self.adopt_reference(&records)?;
+        Ok(Self::replay(records))
+    }
+
+    fn adopt_reference(&self, records: &[LogRecord]) -> Result<(), Box<dyn Error>> {
+        let last = records.iter().rev().find_map(|record| match record {
+            LogRecord::Reference(vector) => Some(vector.clone()),
+            _ => None,
+        });
+        if let Some(vector) = last {
+            *self.reference.write().map_err(|_| "Failed to acquire write lock")? = Some(vector);
+        }
+        Ok(())
+    }
+
+    fn load_records(&self) -> Result<Vec<LogRecord>, Box<dyn Error>> {
+        Self::read_records(&self.file_path, self.format)
+    }
+
+    fn read_records(path: &Path, format: PersistenceFormat) -> Result<Vec<LogRecord>, Box<dyn Error>> {
+        if !path.exists() {
             return Ok(Vec::new());
         }
+        Self::upgrade(path, format)?;
+
+        let file = File::open(path)?;
+        let mut reader = BufReader::new(file);
+        Self::read_header(&mut reader, format)?;
+
+        match format {
+            PersistenceFormat::Jsonl => Self::read_jsonl(reader),
+            PersistenceFormat::Bincode => Self::read_bincode(reader),
+        }
+    }
+
+    // A record only applies if it is at least as new as the last one applied to its id, so
+    // merged backups cannot roll documents back.
+    fn replay(records: Vec<LogRecord>) -> Vec<VectorDocument> {
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        let mut latest: Vec<(u64, Option<VectorDocument>)> = Vec::new();
+        let mut reference: Option<Vector> = None;
+
+        for record in records {
+            let record = match record {
+                LogRecord::Reference(vector) => {
+                    reference = Some(vector);
+                    continue;
+                }
+                LogRecord::PutDelta { document, scale, delta } => match &reference {
+                    Some(reference) => LogRecord::Put(decode_delta(document, reference, scale, &delta)),
+                    None => {
+                        log::warn!("Delta record for '{}' has no reference vector", document.id);
+                        continue;
+                    }
+                },
+                record => record,
+            };
+
+            let Some(id) = record.id() else {
+                continue;
+            };
+            let timestamp = record.timestamp();
+            let position = match positions.get(id) {
+                Some(&position) if timestamp < latest[position].0 => continue,
+                Some(&position) => position,
+                None => {
+                    positions.insert(id.to_string(), latest.len());
+                    latest.push((timestamp, None));
+                    latest.len() - 1
+                }
+            };
+
+            let entry = &mut latest[position];
+            entry.0 = timestamp;
+            match record {
+                LogRecord::Put(document) => entry.1 = Some(document),
+                LogRecord::Delete { .. } => entry.1 = None,
+                LogRecord::UpdateMetadata { metadata, .. } => {
+                    if let Some(document) = &mut entry.1 {
+                        document.metadata = metadata;
+                    }
+                }
+                LogRecord::Reference(_) | LogRecord::PutDelta { .. } => {}
+            }
+        }
 
-        let file = File::open(&self.file_path)?;
-        let reader = BufReader::new(file);
+        latest.into_iter().filter_map(|(_, document)| document).collect()
+    }
+
+    fn write_record<W: Write>(
+        writer: &mut W,
+        record: &LogRecord,
+        format: PersistenceFormat,
+    ) -> Result<(), Box<dyn Error>> {
+        match format {
+            PersistenceFormat::Jsonl => {
+                let json = serde_json::to_string(record)?;
+                writeln!(writer, "{}", json)?;
+            }
+            PersistenceFormat::Bincode => {
+                let bytes = bincode::serialize(record)?;
+                writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+                writer.write_all(&bytes)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_jsonl(reader: BufReader<File>) -> Result<Vec<LogRecord>, Box<dyn Error>> {
         let mut documents = Vec::new();
+        let mut lines = reader.lines();
+
+        loop {
+            let chunk = lines
+                .by_ref()
+                .take(LOAD_CHUNK_RECORDS)
+                .collect::<Result<Vec<String>, _>>()?;
+            if chunk.is_empty() {
+                break;
+            }
 
-        for line in reader.lines() {
-            let line = line?;
-            if !line.trim().is_empty() {
-                match serde_json::from_str::<VectorDocument>(&line) {
-                    Ok(document) => documents.push(document),
+            #[cfg(feature = "std")]
+            let lines = chunk.par_iter();
+            #[cfg(not(feature = "std"))]
+            let lines = chunk.iter();
+            let parsed: Vec<LogRecord> = lines
+                .filter(|line| !line.trim().is_empty())
+                .filter_map(|line| match serde_json::from_str::<JsonLine>(line) {
+                    Ok(JsonLine::Record(record)) => Some(record),
+                    Ok(JsonLine::Legacy(document)) => Some(LogRecord::Put(document)),
                     Err(e) => {
                         log::warn!("Failed to parse line in storage file: {}", e);
-                        continue;
+                        None
                     }
-                }
+                })
+                .collect();
+            documents.extend(parsed);
+        }
+
+        Ok(documents)
+    }
+
+    fn read_bincode(mut reader: BufReader<File>) -> Result<Vec<LogRecord>, Box<dyn Error>> {
+        let mut documents = Vec::new();
+        let mut records: Vec<Vec<u8>> = Vec::with_capacity(LOAD_CHUNK_RECORDS);
+        let mut length = [0u8; 4];
+
+        loop {
+            match reader.read_exact(&mut length) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+
+            let mut bytes = vec![0u8; u32::from_le_bytes(length) as usize];
+            if let Err(e) = reader.read_exact(&mut bytes) {
+                log::warn!("Truncated record in storage file: {}", e);
+                break;
+            }
+
+            records.push(bytes);
+            if records.len() >= LOAD_CHUNK_RECORDS {
+                documents.extend(Self::decode_bincode(&records));
+                records.clear();
             }
         }
 
+        documents.extend(Self::decode_bincode(&records));
         Ok(documents)
     }
 
+    fn decode_bincode(records: &[Vec<u8>]) -> Vec<LogRecord> {
+        #[cfg(feature = "std")]
+        let records = records.par_iter();
+        #[cfg(not(feature = "std"))]
+        let records = records.iter();
+        records
+            .filter_map(|bytes| match bincode::deserialize::<LogRecord>(bytes) {
+                Ok(record) => Some(record),
+                Err(e) => {
+                    log::warn!("Failed to decode record in storage file: {}", e);
+                    None
+                }
+            })
+            .collect()
+    }
+
     pub fn clear(&self) -> Result<(), Box<dyn Error>> {
         if self.file_path.exists() {
             std::fs::remove_file(&self.file_path)?;
         }
 
         let mut buffer = self.buffer.write().map_err(|_| "Failed to acquire write lock")?;
-        buffer.clear();
+        buffer.take();
+        drop(buffer);
 
+        *self.reference.write().map_err(|_| "Failed to acquire write lock")? = None;
         Ok(())
     }
 
@@ -112,35 +727,749 @@ impl PersistentStorage {
         Ok(())
     }
 
-    pub fn restore(&self, backup_path: &Path) -> Result<(), Box<dyn Error>> {
-        if backup_path.exists() {
-            std::fs::copy(backup_path, &self.file_path)?;
+    /// Backups from older versions are upgraded on a staged copy first; newer ones are refused
+    /// before anything is touched.
+    pub fn restore(&self, backup_path: &Path, mode: RestoreMode) -> Result<(), Box<dyn Error>> {
+        if !backup_path.exists() {
+            return Ok(());
+        }
+
+        let found = Self::read_version(backup_path, self.format)?;
+        Self::check_version(backup_path, found)?;
+        let staged = temp_path(&self.file_path, "restore");
+        std::fs::copy(backup_path, &staged)?;
+        let result = Self::migrate(&staged, self.format, found).and_then(|()| self.restore_staged(&staged, mode));
+        if staged.exists() {
+            let _ = std::fs::remove_file(&staged);
+        }
+        result
+    }
+
+    fn restore_staged(&self, staged: &Path, mode: RestoreMode) -> Result<(), Box<dyn Error>> {
+        match mode {
+            RestoreMode::Replace => {
+                let mut reference = self.reference.write().map_err(|_| "Failed to acquire write lock")?;
+                let mut buffer = self.buffer.write().map_err(|_| "Failed to acquire write lock")?;
+                buffer.take();
+                std::fs::rename(staged, &self.file_path)?;
+                *reference = None;
+            }
+            RestoreMode::Merge => {
+                self.flush()?;
+
+                let mut backup = BufReader::new(File::open(staged)?);
+                Self::read_header(&mut backup, self.format)?;
+                let mut file = self.open_append()?;
+                std::io::copy(&mut backup, &mut file)?;
+                file.flush()?;
+                drop(file);
+
+                // The backup may end with its own reference; re-assert ours for later deltas.
+                let reference = self.reference.read().map_err(|_| "Failed to acquire read lock")?;
+                if let Some(vector) = reference.as_ref() {
+                    self.append(LogRecord::Reference(vector.clone()))?;
+                }
+            }
+            RestoreMode::FailIfExists => {
+                let buffer = self.buffer.read().map_err(|_| "Failed to acquire read lock")?;
+                if !buffer.is_empty() || self.file_size()? > 0 {
+                    return Err(format!(
+                        "Refusing to restore over existing data in '{}'",
+                        self.file_path.display()
+                    )
+                    .into());
+                }
+                std::fs::rename(staged, &self.file_path)?;
+            }
         }
 
         Ok(())
     }
 
-    pub fn compact(&self) -> Result<usize, Box<dyn Error>> {
-        let documents = self.load_all()?;
-        
-        self.clear()?;
-        
-        let file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(&self.file_path)?;
+    /// Rewrites the log with one record per surviving document. The new log is written to
+    /// `data.<ext>.tmp`, synced and renamed over the old one, so a crash leaves either file
+    /// intact; writers wait on the buffer until the swap is done. The reference is held for the
+    /// whole rewrite, so no delta is encoded against one the new file does not start with.
+    pub fn compact(&self) -> Result<CompactionReport, Box<dyn Error>> {
+        // Same order as `store`: reference, then buffer.
+        let mut current = self.reference.write().map_err(|_| "Failed to acquire write lock")?;
+        let mut buffer = self.buffer.write().map_err(|_| "Failed to acquire write lock")?;
+        self.flush_buffer(&mut buffer, false)?;
 
-        let mut writer = BufWriter::new(file);
-        let mut written = 0;
+        let bytes_before = self.file_size()?;
+        let records = self.load_records()?;
+        let records_before = records.len();
+        let reference = match current.clone() {
+            Some(reference) => Some(reference),
+            None => records.iter().rev().find_map(|record| match record {
+                LogRecord::Reference(vector) => Some(vector.clone()),
+                _ => None,
+            }),
+        };
+        let latest = Self::replay(records);
+
+        let reference = if self.delta_encoding {
+            reference.or_else(|| latest.iter().find(|d| !d.vector.is_empty()).map(|d| d.vector.clone()))
+        } else {
+            None
+        };
 
-        for document in documents {
-            let json = serde_json::to_string(&document)?;
-            writeln!(writer, "{}", json)?;
-            written += 1;
+        let temp_path = temp_path(&self.file_path, "tmp");
+        if let Err(e) = self
+            .write_compacted(&temp_path, reference.as_ref(), &latest)
+            .and_then(|()| Ok(std::fs::rename(&temp_path, &self.file_path)?))
+        {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(e);
+        }
+        let bytes_after = self.file_size()?;
+        *current = reference;
+
+        Ok(CompactionReport {
+            records_before,
+            records_after: latest.len(),
+            bytes_before,
+            bytes_after,
+        })
+    }
+
+    fn write_compacted(
+        &self,
+        path: &Path,
+        reference: Option<&Vector>,
+        documents: &[VectorDocument],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        Self::write_header(&mut writer, self.format)?;
+
+        if let Some(vector) = reference {
+            Self::write_record(&mut writer, &LogRecord::Reference(vector.clone()), self.format)?;
+        }
+        for document in documents.iter().cloned() {
+            let record = match reference {
+                Some(vector) if vector.len() == document.vector.len() => encode_delta(document, vector),
+                _ => LogRecord::Put(document),
+            };
+            Self::write_record(&mut writer, &record, self.format)?;
         }
 
         writer.flush()?;
-        Ok(written)
+        writer.get_ref().sync_all()?;
+        Ok(())
+    }
+
+    fn file_size(&self) -> Result<u64, Box<dyn Error>> {
+        if self.file_path.exists() {
+            Ok(std::fs::metadata(&self.file_path)?.len())
+        } else {
+            Ok(0)
+        }
+    }
+}
+
+fn extension(format: PersistenceFormat) -> &'static str {
+    match format {
+        PersistenceFormat::Jsonl => "jsonl",
+        PersistenceFormat::Bincode => "bin",
+    }
+}
+
+/// `<path>.<suffix>`, next to `path` so it can be renamed over it.
+fn temp_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".");
+    temp.push(suffix);
+    PathBuf::from(temp)
+}
+
+/// 1 -> 2: prepends the file header; records are unchanged.
+fn add_file_header(path: &Path, format: PersistenceFormat) -> Result<(), Box<dyn Error>> {
+    let temp = temp_path(path, "tmp");
+    let mut writer = BufWriter::new(File::create(&temp)?);
+    PersistentStorage::write_header(&mut writer, format)?;
+    std::io::copy(&mut File::open(path)?, &mut writer)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    drop(writer);
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
+
+fn encode_delta(mut document: VectorDocument, reference: &Vector) -> LogRecord {
+    let vector = std::mem::take(&mut document.vector);
+    let max_delta = vector
+        .iter()
+        .zip(reference)
+        .map(|(x, r)| (x - r).abs())
+        .fold(0.0f32, f32::max);
+    let scale = max_delta / i16::MAX as f32;
+
+    let delta = vector
+        .iter()
+        .zip(reference)
+        .map(|(x, r)| if scale > 0.0 { ((x - r) / scale).round() as i16 } else { 0 })
+        .collect();
+
+    LogRecord::PutDelta { document, scale, delta }
+}
+
+fn decode_delta(mut document: VectorDocument, reference: &Vector, scale: f32, delta: &[i16]) -> VectorDocument {
+    document.vector = reference
+        .iter()
+        .zip(delta)
+        .map(|(r, &d)| r + d as f32 * scale)
+        .collect();
+    document
+}
+
+fn now_seconds() -> Result<u64, Box<dyn Error>> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl Drop for PersistentStorage {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            log::warn!("Failed to flush storage file on drop: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn config(name: &str) -> CollectionConfig {
+        CollectionConfig {
+            name: name.to_string(),
+            dimension: 3,
+            ..Default::default()
+        }
+    }
+
+    fn document(id: &str, vector: Vector, timestamp: u64) -> VectorDocument {
+        VectorDocument {
+            id: id.to_string(),
+            vector,
+            sparse_vector: None,
+            int_vector: None,
+            metadata: None,
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn compact_keeps_only_the_latest_version_of_each_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = PersistentStorage::new(config("docs"), dir.path()).unwrap();
+        for timestamp in 1..=3 {
+            storage.store(document("a", vec![timestamp as f32; 3], timestamp)).unwrap();
+        }
+        storage.store(document("b", vec![9.0; 3], 1)).unwrap();
+        storage.delete("b").unwrap();
+
+        let report = storage.compact().unwrap();
+        assert_eq!(report.records_before, 5);
+        assert_eq!(report.records_after, 1);
+        assert!(report.bytes_after < report.bytes_before);
+
+        let documents = storage.load_all().unwrap();
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0].vector, vec![3.0; 3]);
+    }
+
+    #[test]
+    fn compact_replaces_the_file_without_leaving_a_temp_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = PersistentStorage::with_format(config("docs"), dir.path(), PersistenceFormat::Bincode).unwrap();
+        storage.store(document("a", vec![1.0; 3], 1)).unwrap();
+        storage.store(document("a", vec![2.0; 3], 2)).unwrap();
+
+        storage.compact().unwrap();
+        storage.store(document("b", vec![3.0; 3], 3)).unwrap();
+        storage.flush().unwrap();
+
+        let files: Vec<_> = std::fs::read_dir(storage.directory())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert!(files.iter().all(|name| !name.ends_with(".tmp")), "{:?}", files);
+
+        let reopened = PersistentStorage::with_format(config("docs"), dir.path(), PersistenceFormat::Bincode).unwrap();
+        let ids: Vec<String> = reopened.load_all().unwrap().into_iter().map(|d| d.id).collect();
+        assert_eq!(ids, vec!["a", "b"]);
+    }
+
+    // Deltas here stay under ~300, so half a quantization step is below 0.005.
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 0.01, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn delta_encoded_vectors_round_trip_within_quantization() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = PersistentStorage::new(config("docs"), dir.path())
+            .unwrap()
+            .with_delta_encoding(true);
+        let vectors: Vec<Vector> = (0..20).map(|i| vec![0.5 + i as f32 * 0.01, -0.25, i as f32]).collect();
+        for (i, vector) in vectors.iter().enumerate() {
+            storage.store(document(&i.to_string(), vector.clone(), 1)).unwrap();
+        }
+        storage.flush().unwrap();
+
+        let documents = storage.load_all().unwrap();
+        assert_eq!(documents.len(), vectors.len());
+        for (document, vector) in documents.iter().zip(&vectors) {
+            assert_close(&document.vector, vector);
+        }
+    }
+
+    #[test]
+    fn deltas_stored_during_compaction_decode_after_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Arc::new(
+            PersistentStorage::new(config("docs"), dir.path())
+                .unwrap()
+                .with_delta_encoding(true)
+                .with_flush_policy(FlushPolicy::ByCount(1)),
+        );
+        let vector = |i: usize| vec![i as f32, 1.0 - i as f32 * 0.5, 2.0];
+
+        let writer = {
+            let storage = Arc::clone(&storage);
+            std::thread::spawn(move || {
+                for i in 0..200 {
+                    storage.store(document(&i.to_string(), vector(i), 1)).unwrap();
+                }
+            })
+        };
+        while !writer.is_finished() {
+            storage.compact().unwrap();
+        }
+        writer.join().unwrap();
+        storage.compact().unwrap();
+        storage.store(document("last", vector(200), 1)).unwrap();
+        drop(storage);
+
+        let reopened = PersistentStorage::new(config("docs"), dir.path()).unwrap();
+        let documents = reopened.load_all().unwrap();
+        assert_eq!(documents.len(), 201);
+        for document in &documents {
+            let i = document.id.parse().unwrap_or(200);
+            assert_close(&document.vector, &vector(i));
+        }
+    }
+
+    fn assert_unsupported(error: Box<dyn Error>, expected: u32) {
+        match error.downcast_ref::<SolarisError>() {
+            Some(SolarisError::UnsupportedFormatVersion { found, supported, .. }) => {
+                assert_eq!((*found, *supported), (expected, FORMAT_VERSION));
+            }
+            _ => panic!("expected UnsupportedFormatVersion, got {}", error),
+        }
+    }
+
+    fn write_bumped(path: &Path, format: PersistenceFormat) {
+        let mut bytes = Vec::new();
+        match format {
+            PersistenceFormat::Jsonl => writeln!(bytes, "{{\"format_version\":{}}}", FORMAT_VERSION + 1).unwrap(),
+            PersistenceFormat::Bincode => {
+                bytes.extend_from_slice(BINCODE_MAGIC);
+                bytes.extend_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+            }
+        }
+        std::fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    fn loading_a_newer_data_file_fails_with_its_version() {
+        for format in [PersistenceFormat::Jsonl, PersistenceFormat::Bincode] {
+            let dir = tempfile::tempdir().unwrap();
+            let storage = PersistentStorage::with_format(config("docs"), dir.path(), format).unwrap();
+            storage.store(document("a", vec![1.0; 3], 1)).unwrap();
+            storage.flush().unwrap();
+            write_bumped(&storage.file_path, format);
+
+            assert_unsupported(storage.load_all().unwrap_err(), FORMAT_VERSION + 1);
+            let reopened = PersistentStorage::with_format(config("docs"), dir.path(), format);
+            assert_unsupported(reopened.err().unwrap(), FORMAT_VERSION + 1);
+        }
+    }
+
+    #[test]
+    fn restoring_a_newer_backup_fails_and_keeps_the_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = PersistentStorage::new(config("docs"), dir.path()).unwrap();
+        storage.store(document("a", vec![1.0; 3], 1)).unwrap();
+        storage.flush().unwrap();
+        let backup = dir.path().join("backup.jsonl");
+        write_bumped(&backup, PersistenceFormat::Jsonl);
+
+        for mode in [RestoreMode::Replace, RestoreMode::Merge] {
+            assert_unsupported(storage.restore(&backup, mode).unwrap_err(), FORMAT_VERSION + 1);
+        }
+        assert_eq!(storage.load_all().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn headerless_version_1_files_are_migrated_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = serde_json::to_string(&LogRecord::Put(document("a", vec![1.0; 3], 1))).unwrap();
+        std::fs::write(dir.path().join("docs.jsonl"), format!("{}\n", legacy)).unwrap();
+
+        let storage = PersistentStorage::new(config("docs"), dir.path()).unwrap();
+        let mut reader = BufReader::new(File::open(&storage.file_path).unwrap());
+        let version = PersistentStorage::read_header(&mut reader, PersistenceFormat::Jsonl).unwrap();
+        assert_eq!(version, Some(FORMAT_VERSION));
+
+        let documents = storage.load_all().unwrap();
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0].vector, vec![1.0; 3]);
+    }
+
+    #[test]
+    fn version_1_backups_are_upgraded_on_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = PersistentStorage::with_format(config("docs"), dir.path(), PersistenceFormat::Bincode).unwrap();
+        let backup = dir.path().join("backup.bin");
+        let record = bincode::serialize(&LogRecord::Put(document("a", vec![2.0; 3], 1))).unwrap();
+        let mut bytes = (record.len() as u32).to_le_bytes().to_vec();
+        bytes.extend(record);
+        std::fs::write(&backup, bytes).unwrap();
+
+        storage.restore(&backup, RestoreMode::Merge).unwrap();
+        storage.store(document("b", vec![3.0; 3], 1)).unwrap();
+        storage.flush().unwrap();
+
+        let ids: Vec<String> = storage.load_all().unwrap().into_iter().map(|d| d.id).collect();
+        assert_eq!(ids, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn reopening_in_another_format_converts_the_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = PersistentStorage::new(config("docs"), dir.path()).unwrap();
+        storage.store(document("a", vec![1.0; 3], 1)).unwrap();
+        storage.store(document("b", vec![2.0; 3], 1)).unwrap();
+        drop(storage);
+
+        let storage = PersistentStorage::with_format(config("docs"), dir.path(), PersistenceFormat::Bincode).unwrap();
+        let ids: Vec<String> = storage.load_all().unwrap().into_iter().map(|d| d.id).collect();
+        assert_eq!(ids, vec!["a", "b"]);
+
+        let manifest = PersistentStorage::read_manifest(storage.directory()).unwrap().unwrap();
+        assert_eq!(manifest.format, PersistenceFormat::Bincode);
+        assert!(!storage.directory().join("data.jsonl").exists());
+    }
+
+    #[test]
+    fn colliding_sanitized_names_get_separate_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let upper = PersistentStorage::new(config("Docs"), dir.path()).unwrap();
+        let lower = PersistentStorage::new(config("docs"), dir.path()).unwrap();
+        assert_ne!(upper.directory(), lower.directory());
+
+        upper.store(document("upper", vec![1.0; 3], 1)).unwrap();
+        lower.store(document("lower", vec![2.0; 3], 1)).unwrap();
+        drop((upper, lower));
+
+        let reopened = PersistentStorage::new(config("Docs"), dir.path()).unwrap();
+        let ids: Vec<String> = reopened.load_all().unwrap().into_iter().map(|d| d.id).collect();
+        assert_eq!(ids, vec!["upper"]);
+    }
+
+    #[test]
+    fn bincode_round_trips_and_is_smaller_than_jsonl() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sizes = Vec::new();
+        for format in [PersistenceFormat::Jsonl, PersistenceFormat::Bincode] {
+            let root = dir.path().join(extension(format));
+            let storage = PersistentStorage::with_format(config("docs"), &root, format).unwrap();
+            let vectors = crate::utils::gen::random_vectors(20, 128, 1);
+            for (i, vector) in vectors.iter().enumerate() {
+                let mut stored = document(&i.to_string(), vector.clone(), i as u64);
+                stored.metadata = Some(vec![("source".to_string(), format!("{:?}", format))]);
+                storage.store(stored).unwrap();
+            }
+            storage.flush().unwrap();
+
+            let documents = storage.load_all().unwrap();
+            assert_eq!(documents.len(), 20);
+            for (document, vector) in documents.iter().zip(&vectors) {
+                assert_eq!(&document.vector, vector);
+                assert_eq!(document.metadata, Some(vec![("source".to_string(), format!("{:?}", format))]));
+            }
+
+            let data = storage.directory().join(format!("data.{}", extension(format)));
+            sizes.push(std::fs::metadata(data).unwrap().len());
+        }
+
+        let (jsonl, bincode) = (sizes[0], sizes[1]);
+        assert!(bincode * 2 < jsonl, "bincode {} bytes vs jsonl {}", bincode, jsonl);
+    }
+
+    fn contents(storage: &PersistentStorage) -> Vec<(String, Vector)> {
+        let mut documents: Vec<_> = storage.load_all().unwrap().into_iter().map(|d| (d.id, d.vector)).collect();
+        documents.sort_by(|a, b| a.0.cmp(&b.0));
+        documents
+    }
+
+    /// A backup holding `a` (timestamp 1) and `b`, with the live file since moved on to
+    /// a newer `a` (timestamp 3) and a new `c`.
+    fn diverged(dir: &Path) -> (PersistentStorage, PathBuf) {
+        let storage = PersistentStorage::new(config("docs"), dir).unwrap();
+        storage.store(document("a", vec![1.0; 3], 1)).unwrap();
+        storage.store(document("b", vec![2.0; 3], 1)).unwrap();
+        let backup = dir.join("backup.jsonl");
+        storage.backup(&backup).unwrap();
+        storage.store(document("a", vec![3.0; 3], 3)).unwrap();
+        storage.store(document("c", vec![4.0; 3], 3)).unwrap();
+        storage.flush().unwrap();
+        (storage, backup)
+    }
+
+    #[test]
+    fn replace_restore_discards_the_current_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let (storage, backup) = diverged(dir.path());
+
+        storage.restore(&backup, RestoreMode::Replace).unwrap();
+        assert_eq!(contents(&storage), vec![("a".to_string(), vec![1.0; 3]), ("b".to_string(), vec![2.0; 3])]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn merge_restore_keeps_the_newest_version_of_each_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let (storage, backup) = diverged(dir.path());
+
+        storage.restore(&backup, RestoreMode::Merge).unwrap();
+        let expected = vec![
+            ("a".to_string(), vec![3.0; 3]),
+            ("b".to_string(), vec![2.0; 3]),
+            ("c".to_string(), vec![4.0; 3]),
+        ];
+        assert_eq!(contents(&storage), expected);
+
+        let report = storage.compact().unwrap();
+        assert_eq!(report.records_after, 3);
+        assert_eq!(contents(&storage), expected);
+    }
+
+    #[test]
+    fn fail_if_exists_restore_only_fills_an_empty_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let (storage, backup) = diverged(dir.path());
+
+        assert!(storage.restore(&backup, RestoreMode::FailIfExists).is_err());
+        assert_eq!(contents(&storage).len(), 3);
+
+        let empty = PersistentStorage::new(config("fresh"), dir.path()).unwrap();
+        empty.restore(&backup, RestoreMode::FailIfExists).unwrap();
+        assert_eq!(contents(&empty), vec![("a".to_string(), vec![1.0; 3]), ("b".to_string(), vec![2.0; 3])]);
+    }
+
+    #[test]
+    fn restoring_a_missing_backup_changes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let (storage, _) = diverged(dir.path());
+
+        for mode in [RestoreMode::Replace, RestoreMode::Merge, RestoreMode::FailIfExists] {
+            storage.restore(&dir.path().join("missing.jsonl"), mode).unwrap();
+            assert_eq!(contents(&storage).len(), 3);
+        }
+    }
+
+    #[test]
+    fn chunked_loads_keep_record_order_across_chunk_boundaries() {
+        for format in [PersistenceFormat::Jsonl, PersistenceFormat::Bincode] {
+            let dir = tempfile::tempdir().unwrap();
+            let storage = PersistentStorage::with_format(config("docs"), dir.path(), format).unwrap();
+            let total = LOAD_CHUNK_RECORDS * 2 + LOAD_CHUNK_RECORDS / 2;
+            for i in 0..total {
+                storage.store(document(&i.to_string(), vec![i as f32, 0.0, 0.0], 1)).unwrap();
+            }
+            // Rewrites of early ids land in later chunks and must still win.
+            for i in 0..10 {
+                storage.store(document(&i.to_string(), vec![-1.0; 3], 2)).unwrap();
+            }
+            storage.delete("11").unwrap();
+            storage.flush().unwrap();
+
+            let documents = storage.load_all().unwrap();
+            assert_eq!(documents.len(), total - 1, "{:?}", format);
+            for document in documents {
+                let i: usize = document.id.parse().unwrap();
+                let expected = if i < 10 { vec![-1.0; 3] } else { vec![i as f32, 0.0, 0.0] };
+                assert_close(&document.vector, &expected);
+            }
+        }
+    }
+
+    #[test]
+    fn unreadable_jsonl_lines_are_skipped_without_losing_their_chunk() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = PersistentStorage::new(config("docs"), dir.path()).unwrap();
+        for i in 0..LOAD_CHUNK_RECORDS + 5 {
+            storage.store(document(&i.to_string(), vec![1.0; 3], 1)).unwrap();
+            if i == LOAD_CHUNK_RECORDS / 2 {
+                storage.flush().unwrap();
+                let mut file = OpenOptions::new().append(true).open(&storage.file_path).unwrap();
+                writeln!(file, "{{not json").unwrap();
+            }
+        }
+        storage.flush().unwrap();
+
+        assert_eq!(storage.load_all().unwrap().len(), LOAD_CHUNK_RECORDS + 5);
+    }
+
+    #[test]
+    fn deletes_and_metadata_updates_survive_a_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = now_seconds().unwrap();
+        {
+            let storage = PersistentStorage::new(config("docs"), dir.path()).unwrap();
+            for id in ["a", "b", "c", "d"] {
+                storage.store(document(id, vec![1.0; 3], now)).unwrap();
+            }
+            storage.update_metadata("a", Some(vec![("state".to_string(), "edited".to_string())])).unwrap();
+            storage.delete("b").unwrap();
+            storage.delete("c").unwrap();
+            storage.store(document("c", vec![2.0; 3], now)).unwrap();
+            storage.update_metadata("missing", Some(Vec::new())).unwrap();
+        }
+
+        let storage = PersistentStorage::new(config("docs"), dir.path()).unwrap();
+        let mut documents = storage.load_all().unwrap();
+        documents.sort_by(|x, y| x.id.cmp(&y.id));
+        let ids: Vec<&str> = documents.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, ["a", "c", "d"]);
+        assert_eq!(documents[0].metadata, Some(vec![("state".to_string(), "edited".to_string())]));
+        assert_eq!(documents[1].vector, vec![2.0; 3]);
+
+        let report = storage.compact().unwrap();
+        assert_eq!(report.records_after, 3);
+        let mut compacted = storage.load_all().unwrap();
+        compacted.sort_by(|x, y| x.id.cmp(&y.id));
+        assert_eq!(compacted.len(), 3);
+        assert_eq!(compacted[0].metadata, documents[0].metadata);
+    }
+
+    #[test]
+    fn older_records_do_not_roll_back_newer_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = PersistentStorage::new(config("docs"), dir.path()).unwrap();
+        let now = now_seconds().unwrap();
+        storage.delete("a").unwrap();
+        storage.store(document("a", vec![1.0; 3], now - 60)).unwrap();
+        storage.store(document("b", vec![1.0; 3], now + 60)).unwrap();
+        storage.delete("b").unwrap();
+        storage.flush().unwrap();
+
+        assert!(storage.load_all().unwrap().iter().map(|d| d.id.as_str()).eq(["b"]));
+    }
+
+    #[test]
+    fn sync_writes_buffered_records_to_the_data_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = PersistentStorage::new(config("docs"), dir.path()).unwrap();
+        let data = storage.directory().join("data.jsonl");
+        let on_disk = || std::fs::read_to_string(&data).unwrap_or_default();
+
+        storage.store(document("buffered", vec![1.0; 3], 1)).unwrap();
+        assert!(!on_disk().contains("buffered"));
+
+        storage.sync().unwrap();
+        assert!(on_disk().contains("buffered"));
+        storage.sync().unwrap();
+    }
+
+    #[test]
+    fn rename_moves_the_directory_and_keeps_appending() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = PersistentStorage::new(config("docs"), dir.path()).unwrap();
+        storage.store(document("a", vec![1.0; 3], 1)).unwrap();
+        let old_directory = storage.directory().to_path_buf();
+
+        storage.rename("papers").unwrap();
+        assert!(!old_directory.exists());
+        storage.store(document("b", vec![2.0; 3], 2)).unwrap();
+        storage.flush().unwrap();
+
+        let manifest = PersistentStorage::read_manifest(storage.directory()).unwrap().unwrap();
+        assert_eq!(manifest.name, "papers");
+        let reopened = PersistentStorage::new(config("papers"), dir.path()).unwrap();
+        let ids: Vec<String> = reopened.load_all().unwrap().into_iter().map(|d| d.id).collect();
+        assert_eq!(ids, vec!["a", "b"]);
+
+        let other = PersistentStorage::new(config("other"), dir.path()).unwrap();
+        assert!(storage.rename("other").is_err());
+        drop(other);
+    }
+
+    fn buffered(storage: &PersistentStorage) -> usize {
+        storage.buffer.read().unwrap().records.len()
+    }
+
+    #[test]
+    fn count_policy_flushes_when_the_buffer_fills() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = PersistentStorage::new(config("docs"), dir.path()).unwrap();
+        assert_eq!(storage.flush_policy, FlushPolicy::ByCount(1000));
+        for i in 0..999 {
+            storage.store(document(&i.to_string(), vec![1.0; 3], 1)).unwrap();
+        }
+        assert_eq!(buffered(&storage), 999);
+        storage.store(document("last", vec![1.0; 3], 1)).unwrap();
+        assert_eq!(buffered(&storage), 0);
+
+        let storage = PersistentStorage::new(config("small"), dir.path())
+            .unwrap()
+            .with_flush_policy(FlushPolicy::ByCount(3));
+        storage.store(document("a", vec![1.0; 3], 1)).unwrap();
+        storage.store(document("b", vec![1.0; 3], 1)).unwrap();
+        assert_eq!(buffered(&storage), 2);
+        storage.store(document("c", vec![1.0; 3], 1)).unwrap();
+        assert_eq!(buffered(&storage), 0);
+        assert_eq!(storage.load_all().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn byte_interval_and_combined_policies_flush_on_their_trigger() {
+        let dir = tempfile::tempdir().unwrap();
+        // Each record is estimated at 22 bytes: a 2-byte id, 12 bytes of vector and 8 of overhead.
+        let by_bytes = PersistentStorage::new(config("bytes"), dir.path())
+            .unwrap()
+            .with_flush_policy(FlushPolicy::ByBytes(50));
+        by_bytes.store(document("a1", vec![1.0; 3], 1)).unwrap();
+        by_bytes.store(document("a2", vec![1.0; 3], 1)).unwrap();
+        assert_eq!(buffered(&by_bytes), 2);
+        by_bytes.store(document("a3", vec![1.0; 3], 1)).unwrap();
+        assert_eq!(buffered(&by_bytes), 0);
+
+        let by_interval = PersistentStorage::new(config("interval"), dir.path())
+            .unwrap()
+            .with_flush_policy(FlushPolicy::ByInterval(Duration::from_millis(20)));
+        by_interval.store(document("a1", vec![1.0; 3], 1)).unwrap();
+        assert_eq!(buffered(&by_interval), 1);
+        std::thread::sleep(Duration::from_millis(30));
+        by_interval.store(document("a2", vec![1.0; 3], 1)).unwrap();
+        assert_eq!(buffered(&by_interval), 0);
+
+        let any = PersistentStorage::new(config("any"), dir.path())
+            .unwrap()
+            .with_flush_policy(FlushPolicy::Any(vec![FlushPolicy::ByCount(100), FlushPolicy::ByBytes(40)]));
+        any.store(document("a1", vec![1.0; 3], 1)).unwrap();
+        assert_eq!(buffered(&any), 1);
+        any.store(document("a2", vec![1.0; 3], 1)).unwrap();
+        assert_eq!(buffered(&any), 0);
+    }
+}
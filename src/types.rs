@@ -1,9 +1,21 @@
+use crate::utils::validation::ValidationError;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 
 pub type Vector = Vec<f32>;
+pub type IntVector = Vec<u16>;
 pub type VectorMetadata = Vec<(String, String)>;
 pub type SearchResult = (String, f32, Option<VectorMetadata>);
+/// A search result from a collection with `IdType::U64` ids.
+pub type NumericSearchResult = (u64, f32, Option<VectorMetadata>);
+/// A search result with the filter conditions it satisfied.
+pub type MatchedResult = (SearchResult, Vec<FilterCondition>);
+/// A document's metadata and insert timestamp.
+pub type DocumentMeta = (Option<VectorMetadata>, u64);
+/// Row ids, row-major vector data (`ids.len() * dimension` values) and the dimension.
+pub type VectorMatrix = (Vec<String>, Vec<f32>, usize);
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CollectionConfig {
@@ -13,6 +25,46 @@ pub struct CollectionConfig {
     pub max_elements: Option<usize>,
     pub ef_construction: usize,
     pub m: usize,
+    pub vector_type: VectorType,
+    pub ttl_seconds: Option<u64>,
+    pub compaction_threshold: f32,
+    /// Skips the NaN/Infinity scan on inserted vectors (dimension is still checked).
+    /// Only for trusted pipelines: a non-finite value that slips through corrupts
+    /// distance ordering for every search that touches it.
+    pub skip_value_validation: bool,
+    pub neighbor_selection: NeighborSelection,
+    pub max_search_limit: usize,
+    pub max_batch_size: usize,
+    /// Distance at or below which two vectors are treated as the same; `None` picks a
+    /// default for the metric.
+    pub epsilon: Option<f32>,
+    /// Keeps dense vectors in storage as 8-bit codes (about a quarter of the size), decoded on
+    /// read. The HNSW graph keeps full precision, so index searches are unaffected; reads and
+    /// exact scans see each component within half a quantization step.
+    pub in_memory_compression: bool,
+    /// With persistence, flushes and fsyncs the log after every write that stores a document
+    /// (inserts and updates), so it is on disk when the call returns. Costs one fsync per
+    /// write, typically capping throughput at a few hundred to a few thousand writes per second.
+    pub sync_on_insert: bool,
+    /// After pruning or delete repair, adds the reverse of every one-way HNSW link.
+    pub symmetric_links: bool,
+    /// Deletes unlink the node and reconnect its former neighbors to each other instead of
+    /// leaving a tombstone for compaction. Costs a neighbor selection per former neighbor on
+    /// every delete, in exchange for keeping recall up without waiting for a compaction.
+    pub repair_on_delete: bool,
+    /// `ef_construction` must be at least this multiple of `m`. Values above the floor but
+    /// under twice `m` are accepted with a warning, since they build noticeably worse graphs.
+    pub min_ef_construction_ratio: f32,
+    /// Clamps each component of inserted dense vectors into `(min, max)` instead of rejecting
+    /// out-of-range values. The clamped vector is what gets stored, indexed and returned.
+    pub clamp_range: Option<(f32, f32)>,
+    /// What an insert of a new id does once the collection holds `max_elements` documents.
+    pub eviction_policy: EvictionPolicy,
+    /// Sums distance terms sequentially with Kahan compensation instead of a parallel
+    /// reduction, so scores are bit-identical across runs and thread counts. Gives up
+    /// intra-vector parallelism, which is noticeable above ~10k dimensions.
+    pub deterministic_distance: bool,
+    pub id_type: IdType,
 }
 
 impl Default for CollectionConfig {
@@ -24,6 +76,23 @@ impl Default for CollectionConfig {
             max_elements: None,
             ef_construction: 200,
             m: 16,
+            vector_type: VectorType::Dense,
+            ttl_seconds: None,
+            compaction_threshold: 0.2,
+            skip_value_validation: false,
+            neighbor_selection: NeighborSelection::Heuristic,
+            max_search_limit: 10_000,
+            max_batch_size: 10_000,
+            epsilon: None,
+            in_memory_compression: false,
+            sync_on_insert: false,
+            symmetric_links: false,
+            repair_on_delete: false,
+            min_ef_construction_ratio: 1.0,
+            clamp_range: None,
+            eviction_policy: EvictionPolicy::Reject,
+            deterministic_distance: false,
+            id_type: IdType::String,
         }
     }
 }
@@ -33,32 +102,262 @@ pub enum DistanceMetric {
     Cosine,
     Euclidean,
     Manhattan,
+    /// Maximum inner product: distance is `1 - a·b`, so results come back in descending dot
+    /// product order. Only bounded (to [0, 2]) for unit vectors; otherwise it can be negative.
     DotProduct,
+    JensenShannon,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum NeighborSelection {
+    Simple,
+    Heuristic,
+}
+
+/// Choosing a victim scans every document, so evicting inserts are O(n).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Fail the insert with `CollectionFull`.
+    Reject,
+    /// Evict the document least recently read or written.
+    LruEvict,
+    /// Evict the document least recently written.
+    OldestEvict,
+}
+
+/// How a collection keys its documents.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum IdType {
+    #[default]
+    String,
+    /// Ids are `u64`s in canonical decimal form (`"42"`, not `"042"`), which storage keeps as
+    /// integers rather than strings. Use the `*_u64` methods, or pass `id.to_string()`.
+    U64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum VectorType {
+    Dense,
+    Sparse,
+    Integer,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SparseVector {
+    pub indices: Vec<u32>,
+    pub values: Vec<f32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct VectorDocument {
     pub id: String,
     pub vector: Vector,
+    pub sparse_vector: Option<SparseVector>,
+    pub int_vector: Option<IntVector>,
+    /// `None` means the document has no metadata; `Some(vec![])` means metadata was set and is
+    /// explicitly empty. Storage, persistence and replication keep the two apart; filters treat
+    /// both as having no keys.
     pub metadata: Option<VectorMetadata>,
     pub timestamp: u64,
 }
 
+impl VectorDocument {
+    pub fn is_pending(&self) -> bool {
+        self.vector.is_empty() && self.sparse_vector.is_none() && self.int_vector.is_none()
+    }
+}
+
+/// A multi-part key such as tenant and document id, ordered and hashed part by part.
+/// Collections store it as a plain id: the parts joined with `/`, with `/` and `\` inside a
+/// part escaped by `\`. Pass it anywhere an id is taken via `to_string()` (or `Into<String>`)
+/// and recover it from a result id with `parse()`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct CompositeId(Vec<String>);
+
+impl CompositeId {
+    pub fn new<I, S>(parts: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        CompositeId(parts.into_iter().map(Into::into).collect())
+    }
+
+    pub fn parts(&self) -> &[String] {
+        &self.0
+    }
+}
+
+impl fmt::Display for CompositeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, part) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("/")?;
+            }
+            for c in part.chars() {
+                if c == '/' || c == '\\' {
+                    f.write_str("\\")?;
+                }
+                write!(f, "{}", c)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for CompositeId {
+    type Err = ValidationError;
+
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        let mut parts = vec![String::new()];
+        let mut chars = id.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(escaped) => parts.last_mut().unwrap().push(escaped),
+                    None => return Err(ValidationError::InvalidCompositeId(id.to_string())),
+                },
+                '/' => parts.push(String::new()),
+                c => parts.last_mut().unwrap().push(c),
+            }
+        }
+        Ok(CompositeId(parts))
+    }
+}
+
+impl From<CompositeId> for String {
+    fn from(id: CompositeId) -> Self {
+        id.to_string()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct SearchQuery {
     pub vector: Vector,
     pub limit: usize,
     pub ef: Option<usize>,
+    /// Beam width on the layers above 0; wider beams can improve recall on poorly connected graphs.
+    #[serde(default = "default_upper_layer_ef")]
+    pub upper_layer_ef: usize,
+    /// Per-dimension weights for reranking the HNSW candidates; see
+    /// `utils::distance::weighted_distance`. Candidates are still gathered unweighted.
+    pub dimension_weights: Option<Vec<f32>>,
+    /// Half-open `(start, end)` slice of dimensions to rerank on, e.g. one modality of a
+    /// concatenated embedding. Like `dimension_weights`, candidates are gathered on the full
+    /// vector; with both set, weights outside the range are ignored.
+    pub dimension_range: Option<(usize, usize)>,
     pub filter: Option<MetadataFilter>,
+    pub metadata_fields: Option<Vec<String>>,
+    #[serde(default)]
+    pub sort_order: SortOrder,
+    pub dedup_by: Option<String>,
+    #[serde(default = "default_dedup_keep_missing")]
+    pub dedup_keep_missing: bool,
+    #[serde(default)]
+    pub estimate_confidence: bool,
+    /// Skips the collection's default filter for this query.
+    #[serde(default)]
+    pub ignore_default_filter: bool,
+}
+
+// Queries written before these fields existed get the behaviour they had then.
+fn default_upper_layer_ef() -> usize {
+    1
+}
+
+fn default_dedup_keep_missing() -> bool {
+    true
+}
+
+impl Default for SearchQuery {
+    fn default() -> Self {
+        Self {
+            vector: Vec::new(),
+            limit: 10,
+            ef: None,
+            upper_layer_ef: default_upper_layer_ef(),
+            dimension_weights: None,
+            dimension_range: None,
+            filter: None,
+            metadata_fields: None,
+            sort_order: SortOrder::default(),
+            dedup_by: None,
+            dedup_keep_missing: default_dedup_keep_missing(),
+            estimate_confidence: false,
+            ignore_default_filter: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SearchHit {
+    pub id: String,
+    pub distance: f32,
+    /// See `utils::distance::distance_to_similarity` for the per-metric transform.
+    pub similarity: f32,
+    pub metadata: Option<VectorMetadata>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum DistanceMatrix {
+    Full(Vec<Vec<f32>>),
+    /// Row-major entries above the diagonal: (0,1), (0,2), …, (0,n-1), (1,2), …
+    UpperTriangular { size: usize, values: Vec<f32> },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FederatedHit {
+    pub collection: String,
+    pub id: String,
+    pub score: f32,
+    pub metadata: Option<VectorMetadata>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SearchResponse {
+    pub results: Vec<SearchResult>,
+    /// True when more matches exist beyond `limit`; false when every match was returned.
+    pub truncated: bool,
+    /// Estimated recall of `results` against an exact scan (full for small collections,
+    /// strided sample otherwise). Only set when `SearchQuery.estimate_confidence` is on.
+    pub confidence: Option<f32>,
+}
+
+/// How `multi_query_search` combines the result lists of its queries.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum FusionMethod {
+    /// Distance to the closest query.
+    MinDistance,
+    /// Mean distance to all queries, including those that did not return the candidate.
+    MeanDistance,
+    /// Reciprocal rank fusion: the score is `sum(1 / (60 + rank))` over the lists a candidate
+    /// appears in, and higher is better, unlike the distance-based methods.
+    Rrf,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum SortOrder {
+    #[default]
+    BestFirst,
+    WorstFirst,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct MetadataFilter {
     pub conditions: Vec<FilterCondition>,
     pub operator: FilterOperator,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct FilterCondition {
     pub key: String,
     pub value: String,
@@ -66,12 +365,14 @@ pub struct FilterCondition {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum FilterOperator {
     And,
     Or,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum FilterOperation {
     Equals,
     NotEquals,
@@ -96,24 +397,196 @@ pub struct CollectionInfo {
     pub metric: DistanceMetric,
     pub vector_count: usize,
     pub size_bytes: usize,
+    /// Inserts, updates and deletes applied since the collection was opened.
+    pub operation_count: u64,
+    /// Unix time in seconds of the latest mutation; 0 if there has been none since opening.
+    pub last_modified: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct BatchInsertRequest {
     pub vectors: Vec<VectorDocument>,
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum ErrorCode {
+    DimensionMismatch,
+    InvalidVector,
+    InvalidDocument,
+    UnsupportedVectorType,
+    DuplicateId,
+    MemoryLimitExceeded,
+    CollectionFull,
+    Cancelled,
+    Internal,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct BatchFailure {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct BatchInsertResponse {
     pub inserted: usize,
-    pub failed: Vec<(String, String)>,
+    pub failed: Vec<(String, BatchFailure)>,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchValidationReport {
+    pub total: usize,
+    pub valid: usize,
+    pub invalid: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum ConflictPolicy {
+    Skip,
+    Overwrite,
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum UpsertOutcome {
+    Inserted,
+    Updated,
+    Skipped,
+    Failed(String),
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum UpdateOutcome {
+    Updated,
+    NotFound,
+    Unchanged,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchUpsertResponse {
+    pub outcomes: Vec<(String, UpsertOutcome)>,
+    pub inserted: usize,
+    pub updated: usize,
+    pub skipped: usize,
+    pub failed: usize,
     pub duration_ms: u64,
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum HealthState {
+    Healthy,
+    Degraded,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollectionHealth {
+    pub name: String,
+    pub lock_healthy: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthStatus {
+    pub state: HealthState,
+    pub collections: Vec<CollectionHealth>,
+    pub persistence_writable: Option<bool>,
+    pub memory_used_bytes: usize,
+    pub memory_limit_bytes: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FilterStats {
+    pub filtered_searches: u64,
+    pub candidates_fetched: u64,
+    pub candidates_passed: u64,
+    pub rolling_pass_rate: f32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecallStats {
+    pub measurements: u64,
+    pub last_recall: Option<f32>,
+    pub alerts: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectivityReport {
+    pub duplicate_edges: usize,
+    /// Links from A to B at a level where B does not link back to A.
+    pub asymmetric_edges: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct IndexStats {
     pub total_vectors: usize,
     pub index_size: usize,
     pub avg_search_time_ms: f64,
     pub memory_usage_mb: f64,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn composite_ids_round_trip_through_their_string_form() {
+        let ids = [
+            CompositeId::new(["tenant-a", "doc-1"]),
+            CompositeId::new(["a/b", "c\\d", ""]),
+            CompositeId::new(["only"]),
+            CompositeId::new([""]),
+        ];
+        for id in &ids {
+            assert_eq!(id.to_string().parse::<CompositeId>().unwrap(), *id);
+        }
+        assert_eq!(ids[0].to_string(), "tenant-a/doc-1");
+        assert_eq!(ids[1].to_string(), "a\\/b/c\\\\d/");
+        assert_eq!(String::from(ids[0].clone()), "tenant-a/doc-1");
+
+        assert!(matches!(
+            "tenant\\".parse::<CompositeId>(),
+            Err(ValidationError::InvalidCompositeId(id)) if id == "tenant\\"
+        ));
+    }
+
+    #[test]
+    fn composite_ids_order_part_by_part() {
+        let mut ids = [
+            CompositeId::new(["b", "1"]),
+            CompositeId::new(["a", "2"]),
+            CompositeId::new(["a", "10"]),
+            CompositeId::new(["a"]),
+        ];
+        ids.sort();
+        let parts: Vec<String> = ids.iter().map(|id| id.parts().join("|")).collect();
+        assert_eq!(parts, vec!["a", "a|10", "a|2", "b|1"]);
+
+        // Joining would make these collide; the escaped form keeps them apart.
+        let joined = CompositeId::new(["a/b", "c"]);
+        let split = CompositeId::new(["a", "b/c"]);
+        assert_ne!(joined, split);
+        assert_ne!(joined.to_string(), split.to_string());
+    }
+
+    #[test]
+    fn search_queries_without_the_newer_fields_still_deserialize() {
+        let query: SearchQuery = serde_json::from_str(
+            r#"{ "vector": [1.0, 0.0], "limit": 5, "ef": null, "filter": null }"#,
+        )
+        .unwrap();
+        let defaults = SearchQuery::default();
+        assert_eq!(query.vector, vec![1.0, 0.0]);
+        assert_eq!(query.limit, 5);
+        assert_eq!(query.upper_layer_ef, defaults.upper_layer_ef);
+        assert_eq!(query.sort_order, SortOrder::BestFirst);
+        assert_eq!(query.dedup_keep_missing, defaults.dedup_keep_missing);
+        assert!(!query.estimate_confidence);
+        assert!(!query.ignore_default_filter);
+        assert!(query.dimension_weights.is_none() && query.dimension_range.is_none());
+        assert!(query.metadata_fields.is_none() && query.dedup_by.is_none());
+    }
+}
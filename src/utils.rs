@@ -1,3 +1,5 @@
+pub mod cancellation;
 pub mod distance;
 pub mod filter;
+pub mod gen;
 pub mod validation;
\ No newline at end of file
@@ -0,0 +1,77 @@
+use crate::error::SolarisError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn check(&self) -> Result<(), SolarisError> {
+        if self.is_cancelled() {
+            Err(SolarisError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Parks the current thread until `deadline`, returning `false` if cancelled first.
+    /// `cancel` does not wake a parked thread by itself, so unpark the thread after cancelling.
+    pub fn park_until(&self, deadline: Instant) -> bool {
+        while !self.is_cancelled() {
+            let now = Instant::now();
+            if now >= deadline {
+                return true;
+            }
+            thread::park_timeout(deadline - now);
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn cancelling_one_clone_cancels_them_all() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(clone.check().is_ok());
+
+        token.cancel();
+        assert!(clone.is_cancelled());
+        assert!(matches!(clone.check(), Err(SolarisError::Cancelled)));
+    }
+
+    #[test]
+    fn park_until_wakes_early_only_when_cancelled() {
+        let token = CancellationToken::new();
+        assert!(token.park_until(Instant::now() + Duration::from_millis(10)));
+
+        let parked = {
+            let token = token.clone();
+            thread::spawn(move || token.park_until(Instant::now() + Duration::from_secs(60)))
+        };
+        thread::sleep(Duration::from_millis(20));
+        token.cancel();
+        parked.thread().unpark();
+        assert!(!parked.join().unwrap());
+    }
+}
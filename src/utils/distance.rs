@@ -1,19 +1,158 @@
-use crate::types::{DistanceMetric, Vector};
+use crate::config::NanHandling;
+use crate::error::SolarisError;
+use crate::types::{DistanceMetric, IntVector, SparseVector, Vector};
+#[cfg(feature = "std")]
 use rayon::prelude::*;
+use core::cmp::Ordering;
+
+fn kahan_sum<I: Iterator<Item = f32>>(values: I) -> f32 {
+    let mut sum = 0.0f32;
+    let mut compensation = 0.0f32;
+    for value in values {
+        let y = value - compensation;
+        let t = sum + y;
+        compensation = (t - sum) - y;
+        sum = t;
+    }
+    sum
+}
+
+/// Sums `term(a[i], b[i])`: in parallel with the `std` feature, sequentially without it,
+/// and with Kahan compensation in deterministic mode.
+#[cfg(feature = "std")]
+fn sum_terms<F>(a: &[f32], b: &[f32], term: F, deterministic: bool) -> f32
+where
+    F: Fn(f32, f32) -> f32 + Send + Sync,
+{
+    if deterministic {
+        return kahan_sum(a.iter().zip(b.iter()).map(|(&x, &y)| term(x, y)));
+    }
+    a.par_iter().zip(b.par_iter()).map(|(&x, &y)| term(x, y)).sum()
+}
+
+#[cfg(not(feature = "std"))]
+fn sum_terms<F>(a: &[f32], b: &[f32], term: F, deterministic: bool) -> f32
+where
+    F: Fn(f32, f32) -> f32,
+{
+    if deterministic {
+        return kahan_sum(a.iter().zip(b.iter()).map(|(&x, &y)| term(x, y)));
+    }
+    a.iter().zip(b.iter()).map(|(&x, &y)| term(x, y)).sum()
+}
+
+/// Ascending order with NaN after every real distance.
+pub fn compare_distances(a: f32, b: f32) -> Ordering {
+    a.is_nan()
+        .cmp(&b.is_nan())
+        .then_with(|| a.partial_cmp(&b).unwrap_or(Ordering::Equal))
+}
+
+pub fn check_nan_distances<'a, I>(scored: I, handling: NanHandling) -> Result<(), SolarisError>
+where
+    I: IntoIterator<Item = (&'a str, f32)>,
+{
+    if handling == NanHandling::SinkLast {
+        return Ok(());
+    }
+    match scored.into_iter().find(|(_, distance)| distance.is_nan()) {
+        Some((id, _)) => Err(SolarisError::NanDistance(id.to_string())),
+        None => Ok(()),
+    }
+}
 
 pub fn calculate_distance(a: &Vector, b: &Vector, metric: DistanceMetric) -> f32 {
+    calculate_distance_with(a, b, metric, false)
+}
+
+/// `calculate_distance`, summing sequentially with Kahan compensation when `deterministic`
+/// is set so scores are bit-identical across runs and thread counts.
+pub fn calculate_distance_with(a: &Vector, b: &Vector, metric: DistanceMetric, deterministic: bool) -> f32 {
+    match metric {
+        DistanceMetric::Cosine => cosine(a, b, deterministic),
+        DistanceMetric::Euclidean => sum_terms(a, b, |x, y| (x - y).powi(2), deterministic).sqrt(),
+        DistanceMetric::Manhattan => sum_terms(a, b, |x, y| (x - y).abs(), deterministic),
+        DistanceMetric::DotProduct => 1.0 - sum_terms(a, b, |x, y| x * y, deterministic),
+        DistanceMetric::JensenShannon => jensen_shannon_distance(a, b),
+    }
+}
+
+/// Maps a distance from `calculate_distance` to a similarity where larger is closer:
+/// - `Cosine`: `1 - d`, the cosine similarity in [-1, 1]
+/// - `DotProduct`: `1 - d`, the raw dot product
+/// - `JensenShannon`: `1 - d`, in [0, 1]
+/// - `Euclidean`, `Manhattan`: `1 / (1 + d)`, in (0, 1]
+pub fn distance_to_similarity(distance: f32, metric: DistanceMetric) -> f32 {
     match metric {
-        DistanceMetric::Cosine => cosine_distance(a, b),
-        DistanceMetric::Euclidean => euclidean_distance(a, b),
-        DistanceMetric::Manhattan => manhattan_distance(a, b),
-        DistanceMetric::DotProduct => dot_product_distance(a, b),
+        DistanceMetric::Cosine | DistanceMetric::DotProduct | DistanceMetric::JensenShannon => 1.0 - distance,
+        DistanceMetric::Euclidean | DistanceMetric::Manhattan => 1.0 / (1.0 + distance),
+    }
+}
+
+pub fn default_epsilon(metric: DistanceMetric) -> f32 {
+    match metric {
+        DistanceMetric::Cosine | DistanceMetric::JensenShannon => 1e-6,
+        DistanceMetric::Euclidean | DistanceMetric::Manhattan | DistanceMetric::DotProduct => 1e-5,
+    }
+}
+
+/// Dot product is not a distance (a vector is not closest to itself), so it is compared
+/// with Euclidean distance instead.
+pub fn within_epsilon(a: &Vector, b: &Vector, metric: DistanceMetric, epsilon: f32) -> bool {
+    let distance = match metric {
+        DistanceMetric::DotProduct => euclidean_distance(a, b),
+        _ => calculate_distance(a, b, metric),
+    };
+    distance <= epsilon
+}
+
+pub fn within_epsilon_int(a: &IntVector, b: &IntVector, metric: DistanceMetric, epsilon: f32) -> bool {
+    let metric = match metric {
+        DistanceMetric::DotProduct => DistanceMetric::Euclidean,
+        metric => metric,
+    };
+    calculate_int_distance(a, b, metric) <= epsilon
+}
+
+pub fn calculate_int_distance(a: &IntVector, b: &IntVector, metric: DistanceMetric) -> f32 {
+    match metric {
+        DistanceMetric::Cosine => {
+            let norm_a = int_dot_product(a, a) as f64;
+            let norm_b = int_dot_product(b, b) as f64;
+            if norm_a == 0.0 || norm_b == 0.0 {
+                return 1.0;
+            }
+            (1.0 - int_dot_product(a, b) as f64 / (norm_a.sqrt() * norm_b.sqrt())) as f32
+        }
+        DistanceMetric::Euclidean => {
+            let sum: u64 = a
+                .iter()
+                .zip(b.iter())
+                .map(|(&x, &y)| (x.abs_diff(y) as u64).pow(2))
+                .sum();
+            (sum as f64).sqrt() as f32
+        }
+        DistanceMetric::Manhattan => a
+            .iter()
+            .zip(b.iter())
+            .map(|(&x, &y)| x.abs_diff(y) as u64)
+            .sum::<u64>() as f32,
+        DistanceMetric::DotProduct => 1.0 - int_dot_product(a, b) as f32,
+        DistanceMetric::JensenShannon => jensen_shannon(
+            a.iter().map(|&x| x as f64),
+            b.iter().map(|&x| x as f64),
+        ),
     }
 }
 
 pub fn cosine_distance(a: &Vector, b: &Vector) -> f32 {
-    let dot_product = dot_product(a, b);
-    let norm_a = norm(a);
-    let norm_b = norm(b);
+    cosine(a, b, false)
+}
+
+fn cosine(a: &Vector, b: &Vector, deterministic: bool) -> f32 {
+    let dot_product = sum_terms(a, b, |x, y| x * y, deterministic);
+    let norm_a = sum_terms(a, a, |x, _| x * x, deterministic).sqrt();
+    let norm_b = sum_terms(b, b, |x, _| x * x, deterministic).sqrt();
     
     if norm_a == 0.0 || norm_b == 0.0 {
         return 1.0;
@@ -23,42 +162,125 @@ pub fn cosine_distance(a: &Vector, b: &Vector) -> f32 {
 }
 
 pub fn euclidean_distance(a: &Vector, b: &Vector) -> f32 {
-    a.par_iter()
-        .zip(b.par_iter())
-        .map(|(x, y)| (x - y).powi(2))
-        .sum::<f32>()
-        .sqrt()
+    calculate_distance_with(a, b, DistanceMetric::Euclidean, false)
 }
 
 pub fn manhattan_distance(a: &Vector, b: &Vector) -> f32 {
-    a.par_iter()
-        .zip(b.par_iter())
-        .map(|(x, y)| (x - y).abs())
-        .sum()
+    calculate_distance_with(a, b, DistanceMetric::Manhattan, false)
 }
 
+/// `1 - a·b`: monotonically decreasing in the dot product, so nearest-first ordering is
+/// largest-inner-product-first even for unnormalized vectors, where the value can be negative.
 pub fn dot_product_distance(a: &Vector, b: &Vector) -> f32 {
-    1.0 - dot_product(a, b)
+    calculate_distance_with(a, b, DistanceMetric::DotProduct, false)
+}
+
+/// `calculate_distance` with per-dimension weights, which scale the squared or absolute
+/// differences for Euclidean and Manhattan, the products (norms included) for DotProduct and
+/// Cosine, and the components before normalization for JensenShannon.
+pub fn weighted_distance(a: &Vector, b: &Vector, weights: &[f32], metric: DistanceMetric) -> f32 {
+    let terms = |term: fn(f32, f32) -> f32| -> f32 {
+        a.iter().zip(b.iter()).zip(weights).map(|((&x, &y), &w)| w * term(x, y)).sum()
+    };
+    
+    match metric {
+        DistanceMetric::Euclidean => terms(|x, y| (x - y).powi(2)).sqrt(),
+        DistanceMetric::Manhattan => terms(|x, y| (x - y).abs()),
+        DistanceMetric::DotProduct => 1.0 - terms(|x, y| x * y),
+        DistanceMetric::Cosine => {
+            let norm_a = terms(|x, _| x * x).sqrt();
+            let norm_b = terms(|_, y| y * y).sqrt();
+            if norm_a == 0.0 || norm_b == 0.0 {
+                return 1.0;
+            }
+            1.0 - terms(|x, y| x * y) / (norm_a * norm_b)
+        }
+        DistanceMetric::JensenShannon => jensen_shannon(
+            a.iter().zip(weights).map(|(&x, &w)| (w * x).max(0.0) as f64),
+            b.iter().zip(weights).map(|(&y, &w)| (w * y).max(0.0) as f64),
+        ),
+    }
+}
+
+/// Jensen-Shannon divergence (base 2, so bounded to [0, 1]) between the two vectors
+/// after normalizing each to sum to 1. Negative components are ignored.
+pub fn jensen_shannon_distance(a: &Vector, b: &Vector) -> f32 {
+    jensen_shannon(
+        a.iter().map(|&x| x.max(0.0) as f64),
+        b.iter().map(|&x| x.max(0.0) as f64),
+    )
+}
+
+fn jensen_shannon<A, B>(a: A, b: B) -> f32
+where
+    A: Iterator<Item = f64> + Clone,
+    B: Iterator<Item = f64> + Clone,
+{
+    let sum_a: f64 = a.clone().sum();
+    let sum_b: f64 = b.clone().sum();
+    if sum_a <= 0.0 || sum_b <= 0.0 {
+        return 1.0;
+    }
+
+    let mut divergence = 0.0;
+    for (x, y) in a.zip(b) {
+        let p = x / sum_a;
+        let q = y / sum_b;
+        let m = (p + q) / 2.0;
+        if p > 0.0 {
+            divergence += 0.5 * p * (p / m).log2();
+        }
+        if q > 0.0 {
+            divergence += 0.5 * q * (q / m).log2();
+        }
+    }
+
+    divergence as f32
+}
+
+pub fn int_dot_product(a: &IntVector, b: &IntVector) -> u64 {
+    a.iter().zip(b.iter()).map(|(&x, &y)| x as u64 * y as u64).sum()
 }
 
 pub fn dot_product(a: &Vector, b: &Vector) -> f32 {
-    a.par_iter()
-        .zip(b.par_iter())
-        .map(|(x, y)| x * y)
-        .sum()
+    sum_terms(a, b, |x, y| x * y, false)
+}
+
+pub fn sparse_dot_product(a: &SparseVector, b: &SparseVector) -> f32 {
+    let mut i = 0;
+    let mut j = 0;
+    let mut sum = 0.0;
+
+    while i < a.indices.len() && j < b.indices.len() {
+        match a.indices[i].cmp(&b.indices[j]) {
+            Ordering::Less => i += 1,
+            Ordering::Greater => j += 1,
+            Ordering::Equal => {
+                sum += a.values[i] * b.values[j];
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+
+    sum
+}
+
+pub fn sparse_dot_product_distance(a: &SparseVector, b: &SparseVector) -> f32 {
+    1.0 - sparse_dot_product(a, b)
 }
 
 pub fn norm(vector: &Vector) -> f32 {
-    vector.par_iter()
-        .map(|x| x * x)
-        .sum::<f32>()
-        .sqrt()
+    sum_terms(vector, vector, |x, _| x * x, false).sqrt()
 }
 
 pub fn normalize_vector(vector: &mut Vector) {
     let norm = norm(vector);
     if norm > 0.0 {
+        #[cfg(feature = "std")]
         vector.par_iter_mut().for_each(|x| *x /= norm);
+        #[cfg(not(feature = "std"))]
+        vector.iter_mut().for_each(|x| *x /= norm);
     }
 }
 
@@ -67,8 +289,170 @@ pub fn batch_distance_calculation(
     vectors: &[Vector], 
     metric: DistanceMetric
 ) -> Vec<f32> {
-    vectors
-        .par_iter()
-        .map(|v| calculate_distance(query, v, metric))
-        .collect()
-}
\ No newline at end of file
+    #[cfg(feature = "std")]
+    let vectors = vectors.par_iter();
+    #[cfg(not(feature = "std"))]
+    let vectors = vectors.iter();
+    
+    vectors.map(|v| calculate_distance(query, v, metric)).collect()
+}
+// Without `std` there is only the sequential path.
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::utils::gen::random_vectors;
+
+    #[test]
+    fn parallel_sums_agree_with_the_sequential_path() {
+        let vectors = random_vectors(20, 256, 7);
+        for metric in [
+            DistanceMetric::Cosine,
+            DistanceMetric::Euclidean,
+            DistanceMetric::Manhattan,
+            DistanceMetric::DotProduct,
+        ] {
+            for pair in vectors.chunks(2) {
+                let parallel = calculate_distance_with(&pair[0], &pair[1], metric, false);
+                let sequential = calculate_distance_with(&pair[0], &pair[1], metric, true);
+                assert!(
+                    (parallel - sequential).abs() <= 1e-4 * sequential.abs().max(1.0),
+                    "{:?}: {} != {}",
+                    metric,
+                    parallel,
+                    sequential
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn integer_distances_match_their_float_counterparts() {
+        let counts: Vec<IntVector> = vec![vec![3, 0, 7, 1], vec![0, 5, 2, 2], vec![1, 1, 1, 1]];
+        for metric in [DistanceMetric::Euclidean, DistanceMetric::Manhattan, DistanceMetric::JensenShannon] {
+            for a in &counts {
+                for b in &counts {
+                    let as_float = |v: &IntVector| v.iter().map(|&x| x as f32).collect::<Vector>();
+                    let expected = calculate_distance(&as_float(a), &as_float(b), metric);
+                    let actual = calculate_int_distance(a, b, metric);
+                    assert!((actual - expected).abs() < 1e-5, "{:?}: {} != {}", metric, actual, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn within_epsilon_separates_near_duplicates_from_distinct_vectors() {
+        let base = vec![1.0, 2.0, 3.0];
+        let near = vec![1.0, 2.0, 3.0 + 5e-4];
+        let far = vec![1.0, 2.0, 3.0 + 5e-2];
+        for metric in [DistanceMetric::Euclidean, DistanceMetric::Manhattan, DistanceMetric::DotProduct] {
+            assert!(within_epsilon(&base, &base, metric, 0.0), "{:?}", metric);
+            assert!(within_epsilon(&base, &near, metric, 1e-3), "{:?}", metric);
+            assert!(!within_epsilon(&base, &far, metric, 1e-3), "{:?}", metric);
+        }
+        assert!(within_epsilon(&base, &vec![2.0, 4.0, 6.0], DistanceMetric::Cosine, default_epsilon(DistanceMetric::Cosine)));
+        assert!(!within_epsilon(&base, &vec![3.0, 2.0, 1.0], DistanceMetric::Cosine, 1e-3));
+
+        let a: IntVector = vec![10, 20, 30];
+        assert!(within_epsilon_int(&a, &a, DistanceMetric::DotProduct, 0.0));
+        assert!(within_epsilon_int(&a, &vec![10, 20, 31], DistanceMetric::Manhattan, 1.0));
+        assert!(!within_epsilon_int(&a, &vec![10, 20, 32], DistanceMetric::Manhattan, 1.0));
+    }
+
+    #[test]
+    fn similarity_follows_the_documented_transform() {
+        let a = vec![1.0, 0.0];
+        let b = vec![0.0, 2.0];
+        let cosine = calculate_distance(&a, &b, DistanceMetric::Cosine);
+        assert!(distance_to_similarity(cosine, DistanceMetric::Cosine).abs() < 1e-6);
+        let same = calculate_distance(&a, &a, DistanceMetric::Cosine);
+        assert!((distance_to_similarity(same, DistanceMetric::Cosine) - 1.0).abs() < 1e-6);
+
+        let dot = calculate_distance(&vec![1.0, 2.0], &vec![3.0, 4.0], DistanceMetric::DotProduct);
+        assert!((distance_to_similarity(dot, DistanceMetric::DotProduct) - 11.0).abs() < 1e-5);
+
+        for metric in [DistanceMetric::Euclidean, DistanceMetric::Manhattan] {
+            assert_eq!(distance_to_similarity(0.0, metric), 1.0);
+            assert_eq!(distance_to_similarity(3.0, metric), 0.25);
+        }
+        assert_eq!(distance_to_similarity(0.25, DistanceMetric::JensenShannon), 0.75);
+    }
+
+    #[test]
+    fn kahan_summation_keeps_small_terms_a_naive_sum_drops() {
+        let values = std::iter::once(1.0f32).chain(std::iter::repeat_n(1e-8, 1_000_000));
+        let naive: f32 = std::iter::once(1.0f32).chain(std::iter::repeat_n(1e-8, 1_000_000)).sum();
+        assert_eq!(naive, 1.0);
+        assert!((kahan_sum(values) - 1.01).abs() < 1e-6);
+    }
+
+    #[test]
+    fn deterministic_distances_are_bit_identical_across_thread_counts() {
+        let vectors = random_vectors(8, 4096, 17);
+        let metrics = [
+            DistanceMetric::Cosine,
+            DistanceMetric::Euclidean,
+            DistanceMetric::Manhattan,
+            DistanceMetric::DotProduct,
+        ];
+        let scores = |threads: usize| -> Vec<u32> {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            pool.install(|| {
+                metrics
+                    .iter()
+                    .flat_map(|&metric| {
+                        vectors.chunks(2).map(move |pair| calculate_distance_with(&pair[0], &pair[1], metric, true).to_bits())
+                    })
+                    .collect()
+            })
+        };
+
+        let single = scores(1);
+        for threads in [2, 4, 8] {
+            for _ in 0..3 {
+                assert_eq!(scores(threads), single);
+            }
+        }
+    }
+
+    #[test]
+    fn nan_distances_sort_after_every_real_distance() {
+        let mut distances = [f32::NAN, 2.0, f32::INFINITY, 0.5, f32::NAN, -1.0];
+        distances.sort_by(|a, b| compare_distances(*a, *b));
+        assert_eq!(&distances[..4], [-1.0, 0.5, 2.0, f32::INFINITY]);
+        assert!(distances[4..].iter().all(|d| d.is_nan()));
+
+        let scored = [("a", 1.0), ("b", f32::NAN), ("c", 0.0)];
+        assert!(check_nan_distances(scored, NanHandling::SinkLast).is_ok());
+        assert!(matches!(check_nan_distances(scored, NanHandling::Error), Err(SolarisError::NanDistance(id)) if id == "b"));
+        assert!(check_nan_distances([("a", 1.0)], NanHandling::Error).is_ok());
+    }
+
+    #[test]
+    fn unit_weights_reproduce_the_unweighted_distance() {
+        let vectors: Vec<Vector> = random_vectors(10, 8, 31)
+            .into_iter()
+            .map(|v| v.into_iter().map(f32::abs).collect())
+            .collect();
+        let ones = [1.0; 8];
+        for metric in [
+            DistanceMetric::Cosine,
+            DistanceMetric::Euclidean,
+            DistanceMetric::Manhattan,
+            DistanceMetric::DotProduct,
+            DistanceMetric::JensenShannon,
+        ] {
+            for pair in vectors.chunks(2) {
+                let weighted = weighted_distance(&pair[0], &pair[1], &ones, metric);
+                let plain = calculate_distance_with(&pair[0], &pair[1], metric, true);
+                assert!((weighted - plain).abs() <= 1e-5, "{:?}: {} != {}", metric, weighted, plain);
+            }
+        }
+
+        // Zero weights drop a dimension entirely.
+        let a = vec![1.0, 10.0];
+        let b = vec![4.0, -10.0];
+        assert_eq!(weighted_distance(&a, &b, &[1.0, 0.0], DistanceMetric::Euclidean), 3.0);
+        assert_eq!(weighted_distance(&a, &b, &[2.0, 0.0], DistanceMetric::Manhattan), 6.0);
+    }
+}
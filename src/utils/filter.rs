@@ -1,9 +1,13 @@
 use crate::types::{FilterCondition, FilterOperation, FilterOperator, MetadataFilter, VectorDocument, VectorMetadata};
+#[cfg(feature = "std")]
 use rayon::prelude::*;
 
-pub fn apply_filter(documents: &[VectorDocument], filter: &MetadataFilter) -> Vec<&VectorDocument> {
+pub fn apply_filter<'a>(documents: &'a [VectorDocument], filter: &MetadataFilter) -> Vec<&'a VectorDocument> {
+    #[cfg(feature = "std")]
+    let documents = documents.par_iter();
+    #[cfg(not(feature = "std"))]
+    let documents = documents.iter();
     documents
-        .par_iter()
         .filter(|doc| evaluate_filter(doc, filter))
         .collect()
 }
@@ -24,21 +28,32 @@ pub fn evaluate_filter(document: &VectorDocument, filter: &MetadataFilter) -> bo
     }
 }
 
+/// The conditions `document` satisfies, in filter order. Unlike `evaluate_filter`, this
+/// says which branches of an `Or` filter matched.
+pub fn matching_conditions<'a>(document: &VectorDocument, filter: &'a MetadataFilter) -> Vec<&'a FilterCondition> {
+    filter.conditions
+        .iter()
+        .filter(|condition| evaluate_condition(document, condition))
+        .collect()
+}
+
+// A missing key fails every operation, `NotEquals` included, whether the document has
+// no metadata or empty metadata.
 fn evaluate_condition(document: &VectorDocument, condition: &FilterCondition) -> bool {
-    if let Some(metadata) = &document.metadata {
-        if let Some(value) = get_metadata_value(metadata, &condition.key) {
-            match condition.operation {
-                FilterOperation::Equals => value == condition.value,
-                FilterOperation::NotEquals => value != condition.value,
-                FilterOperation::Contains => value.contains(&condition.value),
-                FilterOperation::StartsWith => value.starts_with(&condition.value),
-                FilterOperation::EndsWith => value.ends_with(&condition.value),
-            }
-        } else {
-            false
-        }
-    } else {
-        false
+    let Some(value) = document
+        .metadata
+        .as_ref()
+        .and_then(|metadata| get_metadata_value(metadata, &condition.key))
+    else {
+        return false;
+    };
+
+    match condition.operation {
+        FilterOperation::Equals => value == condition.value,
+        FilterOperation::NotEquals => value != condition.value,
+        FilterOperation::Contains => value.contains(&condition.value),
+        FilterOperation::StartsWith => value.starts_with(&condition.value),
+        FilterOperation::EndsWith => value.ends_with(&condition.value),
     }
 }
 
@@ -48,9 +63,12 @@ fn get_metadata_value(metadata: &VectorMetadata, key: &str) -> Option<String> {
         .map(|(_, v)| v.clone())
 }
 
-pub fn filter_by_metadata_key(documents: &[VectorDocument], key: &str) -> Vec<&VectorDocument> {
+pub fn filter_by_metadata_key<'a>(documents: &'a [VectorDocument], key: &str) -> Vec<&'a VectorDocument> {
+    #[cfg(feature = "std")]
+    let documents = documents.par_iter();
+    #[cfg(not(feature = "std"))]
+    let documents = documents.iter();
     documents
-        .par_iter()
         .filter(|doc| {
             if let Some(metadata) = &doc.metadata {
                 metadata.iter().any(|(k, _)| k == key)
@@ -66,8 +84,11 @@ pub fn filter_by_timestamp_range(
     start: u64,
     end: u64,
 ) -> Vec<&VectorDocument> {
+    #[cfg(feature = "std")]
+    let documents = documents.par_iter();
+    #[cfg(not(feature = "std"))]
+    let documents = documents.iter();
     documents
-        .par_iter()
         .filter(|doc| doc.timestamp >= start && doc.timestamp <= end)
         .collect()
 }
@@ -81,4 +102,60 @@ pub fn create_simple_filter(key: String, value: String, operation: FilterOperati
         }],
         operator: FilterOperator::And,
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(metadata: Option<VectorMetadata>) -> VectorDocument {
+        VectorDocument {
+            id: "doc".to_string(),
+            vector: vec![0.0],
+            sparse_vector: None,
+            int_vector: None,
+            metadata,
+            timestamp: 0,
+        }
+    }
+
+    fn single(operation: FilterOperation, value: &str) -> MetadataFilter {
+        MetadataFilter {
+            conditions: vec![FilterCondition {
+                key: "lang".to_string(),
+                value: value.to_string(),
+                operation,
+            }],
+            operator: FilterOperator::And,
+        }
+    }
+
+    #[test]
+    fn absent_and_empty_metadata_fail_every_condition_alike() {
+        let absent = document(None);
+        let empty = document(Some(Vec::new()));
+        let other_key = document(Some(vec![("topic".to_string(), "rust".to_string())]));
+        let present = document(Some(vec![("lang".to_string(), "rust".to_string())]));
+
+        for (operation, value) in [
+            (FilterOperation::Equals, "rust"),
+            (FilterOperation::NotEquals, "go"),
+            (FilterOperation::Contains, "us"),
+            (FilterOperation::StartsWith, "ru"),
+            (FilterOperation::EndsWith, "st"),
+        ] {
+            let filter = single(operation, value);
+            assert!(evaluate_filter(&present, &filter), "{:?}", filter.conditions[0].operation);
+            for missing in [&absent, &empty, &other_key] {
+                assert!(!evaluate_filter(missing, &filter), "{:?} on {:?}", filter.conditions[0].operation, missing.metadata);
+                assert!(matching_conditions(missing, &filter).is_empty());
+            }
+        }
+
+        let no_conditions = MetadataFilter {
+            conditions: Vec::new(),
+            operator: FilterOperator::And,
+        };
+        assert!(evaluate_filter(&absent, &no_conditions));
+        assert!(evaluate_filter(&empty, &no_conditions));
+    }
+}
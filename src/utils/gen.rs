@@ -0,0 +1,91 @@
+use crate::types::{Vector, VectorMetadata};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const CLUSTER_SPREAD: f32 = 0.1;
+const CATEGORIES: [&str; 5] = ["news", "sports", "science", "finance", "travel"];
+
+/// `n` vectors with components drawn uniformly from [-1, 1).
+pub fn random_vectors(n: usize, dim: usize, seed: u64) -> Vec<Vector> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..n).map(|_| uniform_vector(&mut rng, dim)).collect()
+}
+
+/// `n` vectors spread over `clusters` uniformly placed centroids, assigned round-robin,
+/// with Gaussian noise of standard deviation 0.1 per component.
+pub fn clustered_vectors(n: usize, dim: usize, clusters: usize, seed: u64) -> Vec<Vector> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let centroids: Vec<Vector> = (0..clusters.max(1)).map(|_| uniform_vector(&mut rng, dim)).collect();
+
+    (0..n)
+        .map(|i| {
+            centroids[i % centroids.len()]
+                .iter()
+                .map(|&c| c + CLUSTER_SPREAD * gaussian(&mut rng))
+                .collect()
+        })
+        .collect()
+}
+
+/// `n` metadata lists with a `category` from a small fixed set, a numeric `score` in
+/// [0, 100) and a `group` in 0..10, so both equality and range filters have something to match.
+pub fn random_metadata(n: usize, seed: u64) -> Vec<VectorMetadata> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..n)
+        .map(|_| {
+            vec![
+                (
+                    "category".to_string(),
+                    CATEGORIES[rng.gen_range(0..CATEGORIES.len())].to_string(),
+                ),
+                ("score".to_string(), format!("{:.2}", rng.gen_range(0.0..100.0f32))),
+                ("group".to_string(), rng.gen_range(0..10usize).to_string()),
+            ]
+        })
+        .collect()
+}
+
+fn uniform_vector(rng: &mut StdRng, dim: usize) -> Vector {
+    (0..dim).map(|_| rng.gen_range(-1.0..1.0f32)).collect()
+}
+
+// Box-Muller; `1 - u` keeps the logarithm's argument in (0, 1].
+fn gaussian(rng: &mut StdRng) -> f32 {
+    let u: f32 = rng.gen();
+    let v: f32 = rng.gen();
+    (-2.0 * (1.0 - u).ln()).sqrt() * (std::f32::consts::TAU * v).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generators_are_reproducible_and_shaped_as_requested() {
+        let vectors = random_vectors(50, 7, 1);
+        assert_eq!(vectors, random_vectors(50, 7, 1));
+        assert_ne!(vectors, random_vectors(50, 7, 2));
+        assert_eq!(vectors.len(), 50);
+        assert!(vectors.iter().all(|v| v.len() == 7 && v.iter().all(|x| (-1.0..1.0).contains(x))));
+
+        let clustered = clustered_vectors(60, 5, 3, 1);
+        assert_eq!(clustered, clustered_vectors(60, 5, 3, 1));
+        assert_eq!(clustered.len(), 60);
+        assert!(clustered.iter().all(|v| v.len() == 5));
+        // Round-robin assignment: members of one cluster sit much closer than the centroids.
+        let spread = |a: &Vector, b: &Vector| a.iter().zip(b).map(|(x, y)| (x - y).powi(2)).sum::<f32>().sqrt();
+        let same: f32 = (0..57).map(|i| spread(&clustered[i], &clustered[i + 3])).sum::<f32>() / 57.0;
+        assert!(same < 0.5, "mean intra-cluster distance {}", same);
+
+        let metadata = random_metadata(40, 9);
+        assert_eq!(metadata, random_metadata(40, 9));
+        assert_eq!(metadata.len(), 40);
+        for entry in &metadata {
+            let keys: Vec<&str> = entry.iter().map(|(key, _)| key.as_str()).collect();
+            assert_eq!(keys, vec!["category", "score", "group"]);
+            assert!(CATEGORIES.contains(&entry[0].1.as_str()));
+            assert!((0.0..100.0).contains(&entry[1].1.parse::<f32>().unwrap()));
+            assert!(entry[2].1.parse::<usize>().unwrap() < 10);
+        }
+    }
+}
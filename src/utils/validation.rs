@@ -1,7 +1,15 @@
-use crate::types::{CollectionConfig, Vector, VectorDocument};
+use crate::types::{
+    CollectionConfig, IdType, IntVector, SparseVector, Vector, VectorDocument, VectorMetadata, VectorType,
+};
 use std::error::Error;
 use thiserror::Error;
 
+pub const DEFAULT_MAX_SEARCH_LIMIT: usize = 10_000;
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 10_000;
+pub const SEARCH_LIMIT_CEILING: usize = 1_000_000;
+pub const BATCH_SIZE_CEILING: usize = 1_000_000;
+pub const RECOMMENDED_EF_CONSTRUCTION_RATIO: f32 = 2.0;
+
 #[derive(Error, Debug)]
 pub enum ValidationError {
     #[error("Vector dimension mismatch: expected {expected}, got {actual}")]
@@ -28,8 +36,41 @@ pub enum ValidationError {
     #[error("Vector ID too long: maximum 256 characters")]
     IdTooLong,
     
+    #[error("Vector ID '{0}' is not a u64 in canonical decimal form")]
+    NonNumericId(String),
+    
     #[error("Too many metadata entries: maximum 100")]
     TooManyMetadataEntries,
+    
+    #[error("Sparse vector has {indices} indices but {values} values")]
+    SparseLengthMismatch { indices: usize, values: usize },
+    
+    #[error("Sparse vector indices must be strictly increasing")]
+    UnsortedSparseIndices,
+    
+    #[error("Sparse vector index {index} out of bounds for dimension {dimension}")]
+    SparseIndexOutOfBounds { index: u32, dimension: usize },
+    
+    #[error("Invalid caps: max_search_limit={max_search_limit}, max_batch_size={max_batch_size}")]
+    InvalidCaps { max_search_limit: usize, max_batch_size: usize },
+    
+    #[error("Invalid minimum ef_construction ratio: {0} (must be at least 1)")]
+    InvalidEfConstructionRatio(f32),
+    
+    #[error("Invalid composite id '{0}': trailing escape character")]
+    InvalidCompositeId(String),
+    
+    #[error("Invalid epsilon: {0}")]
+    InvalidEpsilon(f32),
+    
+    #[error("Invalid clamp range: {min}..={max}")]
+    InvalidClampRange { min: f32, max: f32 },
+    
+    #[error("Dimension weights must be finite and non-negative")]
+    InvalidDimensionWeights,
+    
+    #[error("Invalid dimension range {start}..{end} for dimension {dimension}")]
+    InvalidDimensionRange { start: usize, end: usize, dimension: usize },
 }
 
 pub fn validate_vector(vector: &Vector, expected_dimension: usize) -> Result<(), ValidationError> {
@@ -49,6 +90,71 @@ pub fn validate_vector(vector: &Vector, expected_dimension: usize) -> Result<(),
     Ok(())
 }
 
+pub fn validate_dimension_weights(weights: &[f32], expected_dimension: usize) -> Result<(), ValidationError> {
+    if weights.len() != expected_dimension {
+        return Err(ValidationError::DimensionMismatch {
+            expected: expected_dimension,
+            actual: weights.len(),
+        });
+    }
+
+    if weights.iter().any(|weight| !weight.is_finite() || *weight < 0.0) {
+        return Err(ValidationError::InvalidDimensionWeights);
+    }
+
+    Ok(())
+}
+
+pub fn validate_dimension_range(range: (usize, usize), dimension: usize) -> Result<(), ValidationError> {
+    let (start, end) = range;
+    if start >= end || end > dimension {
+        return Err(ValidationError::InvalidDimensionRange { start, end, dimension });
+    }
+
+    Ok(())
+}
+
+pub fn validate_sparse_vector(
+    vector: &SparseVector,
+    dimension: usize,
+) -> Result<(), ValidationError> {
+    if vector.indices.len() != vector.values.len() {
+        return Err(ValidationError::SparseLengthMismatch {
+            indices: vector.indices.len(),
+            values: vector.values.len(),
+        });
+    }
+
+    if vector.indices.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err(ValidationError::UnsortedSparseIndices);
+    }
+
+    if let Some(&index) = vector.indices.last() {
+        if index as usize >= dimension {
+            return Err(ValidationError::SparseIndexOutOfBounds { index, dimension });
+        }
+    }
+
+    for &value in &vector.values {
+        if !value.is_finite() {
+            return Err(ValidationError::InvalidValues);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn validate_int_vector(vector: &IntVector, expected_dimension: usize) -> Result<(), ValidationError> {
+    if vector.len() != expected_dimension {
+        return Err(ValidationError::DimensionMismatch {
+            expected: expected_dimension,
+            actual: vector.len(),
+        });
+    }
+
+    Ok(())
+}
+
 pub fn validate_vector_id(id: &str) -> Result<(), ValidationError> {
     if id.is_empty() {
         return Err(ValidationError::EmptyId);
@@ -61,22 +167,79 @@ pub fn validate_vector_id(id: &str) -> Result<(), ValidationError> {
     Ok(())
 }
 
+/// The id as a `u64` under `IdType::U64`, `None` under `IdType::String`.
+pub fn parse_id(id: &str, id_type: IdType) -> Result<Option<u64>, ValidationError> {
+    match id_type {
+        IdType::String => Ok(None),
+        IdType::U64 => id
+            .parse::<u64>()
+            .ok()
+            .filter(|number| number.to_string() == id)
+            .map(Some)
+            .ok_or_else(|| ValidationError::NonNumericId(id.to_string())),
+    }
+}
+
 pub fn validate_collection_config(config: &CollectionConfig) -> Result<(), ValidationError> {
     if config.name.is_empty() {
         return Err(ValidationError::EmptyCollectionName);
     }
 
-    if config.dimension == 0 || config.dimension > 10000 {
+    // Dense collections may leave the dimension at 0 to infer it from the first insert.
+    if (config.dimension == 0 && config.vector_type != VectorType::Dense) || config.dimension > 10000 {
         return Err(ValidationError::InvalidDimension(config.dimension));
     }
 
-    if config.m == 0 || config.m > 100 || config.ef_construction < config.m {
+    let ratio = config.min_ef_construction_ratio;
+    if !ratio.is_finite() || ratio < 1.0 {
+        return Err(ValidationError::InvalidEfConstructionRatio(ratio));
+    }
+
+    let ef_ratio = config.ef_construction as f32 / config.m.max(1) as f32;
+    if config.m == 0 || config.m > 100 || ef_ratio < ratio {
         return Err(ValidationError::InvalidHNSWParams {
             m: config.m,
             ef_construction: config.ef_construction,
         });
     }
 
+    if ef_ratio < RECOMMENDED_EF_CONSTRUCTION_RATIO {
+        log::warn!(
+            "Collection '{}': ef_construction={} is under {}x M={}; graph quality will suffer",
+            config.name,
+            config.ef_construction,
+            RECOMMENDED_EF_CONSTRUCTION_RATIO,
+            config.m
+        );
+    }
+
+    if let Some(epsilon) = config.epsilon {
+        if !epsilon.is_finite() || epsilon < 0.0 {
+            return Err(ValidationError::InvalidEpsilon(epsilon));
+        }
+    }
+
+    if let Some((min, max)) = config.clamp_range {
+        if !min.is_finite() || !max.is_finite() || min > max {
+            return Err(ValidationError::InvalidClampRange { min, max });
+        }
+    }
+
+    validate_caps(config.max_search_limit, config.max_batch_size)
+}
+
+pub fn validate_caps(max_search_limit: usize, max_batch_size: usize) -> Result<(), ValidationError> {
+    if max_search_limit == 0
+        || max_search_limit > SEARCH_LIMIT_CEILING
+        || max_batch_size == 0
+        || max_batch_size > BATCH_SIZE_CEILING
+    {
+        return Err(ValidationError::InvalidCaps {
+            max_search_limit,
+            max_batch_size,
+        });
+    }
+
     Ok(())
 }
 
@@ -84,18 +247,33 @@ pub fn validate_vector_document(
     document: &VectorDocument,
     expected_dimension: usize,
 ) -> Result<(), ValidationError> {
+    match (&document.sparse_vector, &document.int_vector) {
+        (Some(sparse_vector), _) => validate_sparse_vector(sparse_vector, expected_dimension)?,
+        (None, Some(int_vector)) => validate_int_vector(int_vector, expected_dimension)?,
+        (None, None) => validate_vector(&document.vector, expected_dimension)?,
+    }
+
+    validate_document_fields(document)
+}
+
+pub fn validate_document_fields(document: &VectorDocument) -> Result<(), ValidationError> {
     validate_vector_id(&document.id)?;
-    validate_vector(&document.vector, expected_dimension)?;
 
     if let Some(metadata) = &document.metadata {
-        if metadata.len() > 100 {
-            return Err(ValidationError::TooManyMetadataEntries);
-        }
+        validate_metadata(metadata)?;
+    }
 
-        for (key, _) in metadata {
-            if key.is_empty() {
-                return Err(ValidationError::EmptyMetadataKey);
-            }
+    Ok(())
+}
+
+pub fn validate_metadata(metadata: &VectorMetadata) -> Result<(), ValidationError> {
+    if metadata.len() > 100 {
+        return Err(ValidationError::TooManyMetadataEntries);
+    }
+
+    for (key, _) in metadata {
+        if key.is_empty() {
+            return Err(ValidationError::EmptyMetadataKey);
         }
     }
 
@@ -107,6 +285,7 @@ pub fn validate_search_params(
     expected_dimension: usize,
     limit: usize,
     ef: Option<usize>,
+    max_limit: usize,
 ) -> Result<(), Box<dyn Error>> {
     validate_vector(query_vector, expected_dimension)?;
 
@@ -114,16 +293,17 @@ pub fn validate_search_params(
         return Err("Search limit must be greater than 0".into());
     }
 
-    if limit > 10000 {
-        return Err("Search limit too large: maximum 10000".into());
+    if limit > max_limit {
+        return Err(format!("Search limit too large: maximum {}", max_limit).into());
     }
 
     if let Some(ef_value) = ef {
         if ef_value < limit {
             return Err("EF parameter must be greater than or equal to limit".into());
         }
-        if ef_value > 10000 {
-            return Err("EF parameter too large: maximum 10000".into());
+        let max_ef = max_limit.max(DEFAULT_MAX_SEARCH_LIMIT);
+        if ef_value > max_ef {
+            return Err(format!("EF parameter too large: maximum {}", max_ef).into());
         }
     }
 
@@ -137,14 +317,94 @@ pub fn sanitize_collection_name(name: &str) -> String {
         .to_lowercase()
 }
 
-pub fn validate_batch_size(size: usize) -> Result<(), Box<dyn Error>> {
+pub fn validate_batch_size(size: usize, max_size: usize) -> Result<(), Box<dyn Error>> {
     if size == 0 {
         return Err("Batch size must be greater than 0".into());
     }
 
-    if size > 10000 {
-        return Err("Batch size too large: maximum 10000".into());
+    if size > max_size {
+        return Err(format!("Batch size too large: maximum {}", max_size).into());
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    struct Recorder;
+
+    impl log::Log for Recorder {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::Level::Warn
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                WARNINGS.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn config(name: &str, m: usize, ef_construction: usize, ratio: f32) -> CollectionConfig {
+        CollectionConfig {
+            name: name.to_string(),
+            dimension: 4,
+            m,
+            ef_construction,
+            min_ef_construction_ratio: ratio,
+            ..Default::default()
+        }
+    }
+
+    fn warned(name: &str) -> bool {
+        let quoted = format!("'{}'", name);
+        WARNINGS.lock().unwrap().iter().any(|warning| warning.contains(&quoted))
+    }
+
+    #[test]
+    fn ef_construction_ratio_warns_below_twice_m_and_errors_below_the_floor() {
+        static RECORDER: Recorder = Recorder;
+        let _ = log::set_logger(&RECORDER);
+        log::set_max_level(log::LevelFilter::Warn);
+
+        assert!(validate_collection_config(&config("ratio-good", 16, 64, 1.0)).is_ok());
+        assert!(!warned("ratio-good"));
+
+        assert!(validate_collection_config(&config("ratio-low", 16, 16, 1.0)).is_ok());
+        assert!(warned("ratio-low"));
+
+        assert!(matches!(
+            validate_collection_config(&config("ratio-floor", 16, 31, 2.0)),
+            Err(ValidationError::InvalidHNSWParams { m: 16, ef_construction: 31 })
+        ));
+        assert!(validate_collection_config(&config("ratio-exact", 16, 32, 2.0)).is_ok());
+        assert!(!warned("ratio-exact"));
+        assert!(validate_collection_config(&config("ratio-strict", 8, 24, 4.0)).is_err());
+
+        for ratio in [0.5, f32::NAN, f32::INFINITY] {
+            assert!(matches!(
+                validate_collection_config(&config("ratio-invalid", 16, 64, ratio)),
+                Err(ValidationError::InvalidEfConstructionRatio(_))
+            ));
+        }
+        assert!(validate_collection_config(&config("ratio-under-m", 16, 15, 1.0)).is_err());
+    }
+
+    #[test]
+    fn dimension_ranges_must_be_non_empty_and_in_bounds() {
+        assert!(validate_dimension_range((0, 4), 4).is_ok());
+        assert!(validate_dimension_range((3, 4), 4).is_ok());
+        for (start, end) in [(2, 2), (3, 1), (0, 5)] {
+            assert!(matches!(
+                validate_dimension_range((start, end), 4),
+                Err(ValidationError::InvalidDimensionRange { dimension: 4, .. })
+            ));
+        }
+    }
+}